@@ -0,0 +1,96 @@
+// Feeds resting limit orders from one thread to a book owned by another, once through each way
+// of getting commands to the book thread, and prints the rate of each:
+//
+//     cargo run --release --example command_queue [orders]
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use trade_city::matching::clock::SystemClock;
+use trade_city::matching::handle::OrderbookHandle;
+use trade_city::matching::orderbook::{OrderBuilder, Orderbook, OrderbookConfig, Security, Side, TimeInForce};
+use trade_city::matching::spsc::{self, Command, TrySendError, WaitStrategy};
+use trade_city::matching::units::{Price, Qty};
+
+// the price levels the orders are spread over on each side
+const LEVELS: i64 = 100;
+const QUEUE_CAPACITY: usize = 4_096;
+
+fn report(queue: &str, count: usize, started: Instant) {
+    let elapsed = started.elapsed();
+    println!("{:<14} {:>9} in {:>8.1?} {:>12.0}/s", queue, count, elapsed, count as f64 / elapsed.as_secs_f64());
+}
+
+fn book(orders: usize) -> Orderbook {
+    let security = Arc::new(Security::new("XS0000000001", "COMMANDS"));
+    Orderbook::with_config(security, 1_000, OrderbookConfig::default().with_expected_orders(orders).with_expected_levels_per_side(LEVELS as usize), Box::new(SystemClock))
+}
+
+fn command(i: i64) -> Command {
+    let (side, limit) = if i % 2 == 0 { (Side::Buy, Price(999 - i / 2 % LEVELS)) } else { (Side::Sell, Price(1_001 + i / 2 % LEVELS)) };
+    Command::Place { side, limit: Some(limit), amount: Qty(1), time_in_force: TimeInForce::GoodTillCancel, account_id: None }
+}
+
+fn spsc_queue(orders: i64, wait: WaitStrategy) -> usize {
+    let (producer, consumer) = spsc::channel(QUEUE_CAPACITY);
+    let mut book = book(orders as usize);
+    let worker = thread::spawn(move || {
+        let mut placed = 0;
+        consumer.run(&mut book, wait, |_, result| if result.is_ok() { placed += 1 });
+        placed
+    });
+
+    for i in 0..orders {
+        let mut command = command(i);
+        // the ring is full until the book thread catches up, which it may need this core for
+        while let Err(TrySendError::Full(rejected)) = producer.try_send(command) {
+            command = rejected;
+            thread::yield_now();
+        }
+    }
+    drop(producer);
+    worker.join().expect("the book thread finished")
+}
+
+fn mpsc_channel(orders: i64) -> usize {
+    let (sender, receiver) = mpsc::channel::<Command>();
+    let mut book = book(orders as usize);
+    let worker = thread::spawn(move || receiver.iter().filter(|command| command.apply(&mut book).is_ok()).count());
+
+    for i in 0..orders {
+        sender.send(command(i)).expect("the book thread is running");
+    }
+    drop(sender);
+    worker.join().expect("the book thread finished")
+}
+
+fn orderbook_handle(orders: i64) -> usize {
+    let handle = OrderbookHandle::spawn(book(orders as usize));
+    let security = handle.execute(|book| book.security().clone()).wait().expect("the book thread is running");
+    let replies: Vec<_> = (0..orders)
+        .map(|i| {
+            let Command::Place { side, limit: Some(limit), amount, time_in_force, .. } = command(i) else { unreachable!() };
+            let order = OrderBuilder::new(side, &security).limit(limit).quantity(amount).tif(time_in_force).build().expect("the order is valid");
+            handle.send_place_order(order)
+        })
+        .collect();
+    replies.into_iter().filter_map(|reply| reply.wait().ok()).filter(Result::is_ok).count()
+}
+
+type Run = fn(i64) -> usize;
+
+fn main() {
+    let orders: i64 = std::env::args().nth(1).and_then(|arg| arg.parse().ok()).unwrap_or(1_000_000);
+    let queues: [(&str, Run); 4] = [
+        ("spsc busy-poll", |orders| spsc_queue(orders, WaitStrategy::BusyPoll)),
+        ("spsc park", |orders| spsc_queue(orders, WaitStrategy::Park)),
+        ("mpsc", mpsc_channel),
+        ("handle", orderbook_handle),
+    ];
+    for (queue, run) in queues {
+        let started = Instant::now();
+        let placed = run(orders);
+        report(queue, placed, started);
+    }
+}
@@ -0,0 +1,75 @@
+// Serves the depth of a simulated book over tcp. Every client gets the top levels as soon as it
+// connects and again whenever the top of the book changes:
+//
+//     cargo run --example depth_server --features async
+//     nc 127.0.0.1 7878
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_stream::StreamExt;
+use trade_city::matching::async_orderbook::AsyncOrderbook;
+use trade_city::matching::events::OrderbookEvent;
+use trade_city::matching::market_data::DepthSnapshot;
+use trade_city::matching::orderbook::{Order, Orderbook, Security};
+use trade_city::matching::units::{Price, Qty};
+
+const LEVELS: usize = 5;
+
+fn render(depth: &DepthSnapshot) -> String {
+    let mut text = format!("sequence {} last {}\n", depth.sequence(), depth.last_price());
+    for level in depth.asks().iter().rev() {
+        text.push_str(&format!("       {:>6} {:>6} ({})\n", level.price(), level.quantity(), level.order_count()));
+    }
+    for level in depth.bids() {
+        text.push_str(&format!("{:>6} {:>6}        ({})\n", level.quantity(), level.price(), level.order_count()));
+    }
+    text.push('\n');
+    text
+}
+
+async fn serve(book: AsyncOrderbook, mut socket: TcpStream) -> Result<(), Box<dyn std::error::Error>> {
+    // subscribe before the first snapshot so no change in between is missed
+    let mut events = book.events();
+    socket.write_all(render(&book.depth(LEVELS).await?).as_bytes()).await?;
+    while let Some(event) = events.next().await {
+        match event {
+            Ok(OrderbookEvent::BestPriceChanged { .. }) | Ok(OrderbookEvent::Trade { .. }) => {},
+            Ok(_) => continue,
+            // a client that cannot keep up skips the snapshots it missed
+            Err(_) => {},
+        }
+        socket.write_all(render(&book.depth(LEVELS).await?).as_bytes()).await?;
+    }
+    Ok(())
+}
+
+// Places a pseudo random order a few times a second.
+async fn simulate(book: AsyncOrderbook, security: Arc<Security>) {
+    let mut state: u64 = 42;
+    loop {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        let random = (state >> 33) as i64;
+        let order = if random % 2 == 0 { Order::buy(&security) } else { Order::sell(&security) };
+        let order = order.limit(Price(95 + (random >> 1) % 11)).quantity(Qty(1 + (random >> 5) % 20)).build().expect("the order is valid");
+        if book.place_order(order).await.is_err() { return; }
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let security = Arc::new(Security::new("DE0001234567", "Trade City"));
+    let book = AsyncOrderbook::spawn(Orderbook::new(security.clone(), 100), 1024);
+    tokio::spawn(simulate(book.clone(), security));
+
+    let listener = TcpListener::bind("127.0.0.1:7878").await?;
+    loop {
+        let (socket, _) = listener.accept().await?;
+        let book = book.clone();
+        tokio::spawn(async move {
+            let _ = serve(book, socket).await;
+        });
+    }
+}
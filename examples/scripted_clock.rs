@@ -0,0 +1,59 @@
+// Runs a scripted session on a manual clock and checks that every event, order and execution
+// carries the time the script set for its step:
+//
+//     cargo run --example scripted_clock
+use std::sync::Arc;
+
+use trade_city::matching::clock::ManualClock;
+use trade_city::matching::events::OrderbookEvent;
+use trade_city::matching::orderbook::{Order, Orderbook, OrderbookConfig, Security};
+use trade_city::matching::units::{OrderId, Price, Qty};
+
+enum Step {
+    Buy(i64, i64),
+    Sell(i64, i64),
+    Cancel(u64),
+}
+
+// nanoseconds into the session and what happens then
+const SCRIPT: [(u64, Step); 5] = [
+    (1_000, Step::Buy(99, 100)),
+    (2_500, Step::Sell(101, 50)),
+    (2_500, Step::Sell(99, 40)),
+    (7_250, Step::Buy(101, 20)),
+    (10_000, Step::Cancel(1)),
+];
+
+fn main() {
+    let security = Arc::new(Security::new("XS0000000002", "CLOCK"));
+    let clock = ManualClock::new(0);
+    let mut book = Orderbook::with_config(security.clone(), 100, OrderbookConfig::default(), Box::new(clock.clone()));
+    let events = book.subscribe_unbounded();
+
+    for (at, step) in &SCRIPT {
+        clock.set(*at);
+        let order = match *step {
+            Step::Buy(limit, quantity) => Some(Order::buy(&security).limit(Price(limit)).quantity(Qty(quantity))),
+            Step::Sell(limit, quantity) => Some(Order::sell(&security).limit(Price(limit)).quantity(Qty(quantity))),
+            Step::Cancel(order_id) => {
+                book.cancel_order(OrderId(order_id), None).expect("the order is open");
+                None
+            },
+        };
+        if let Some(order) = order {
+            let report = book.place_order(order.build().expect("the order is valid")).expect("the order is accepted");
+            for execution in report.executions() { assert_eq!(execution.timestamp(), *at, "execution of the step at {}", at); }
+            if let Some(order) = book.order(report.order_id()) { assert_eq!(order.timestamp(), *at, "order of the step at {}", at); }
+        }
+
+        let published: Vec<OrderbookEvent> = events.try_iter().collect();
+        assert!(!published.is_empty(), "the step at {} published nothing", at);
+        for event in &published {
+            assert_eq!(event.timestamp(), *at, "{:?}", event);
+        }
+        println!("{:>6} ns {:>2} events", at, published.len());
+    }
+    let trades: Vec<u64> = book.trade_tape().last_n_trades(usize::MAX).iter().map(|trade| trade.timestamp()).collect();
+    assert_eq!(trades, [2_500, 7_250]);
+    println!("every timestamp matches the script");
+}
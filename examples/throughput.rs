@@ -0,0 +1,87 @@
+// Places resting limit orders into a book, cancels them in random order and trades the rest
+// away, printing the rate of each phase, then does the same placing and cancelling with a plain
+// map of ids to orders for comparison:
+//
+//     cargo run --release --example throughput [orders]
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use trade_city::matching::clock::SystemClock;
+use trade_city::matching::orderbook::{Order, OrderBuilder, Orderbook, OrderbookConfig, Security, Side, TimeInForce};
+use trade_city::matching::units::{Price, Qty};
+
+// the price levels the orders are spread over on each side
+const LEVELS: i64 = 100;
+
+fn report(phase: &str, count: usize, started: Instant) {
+    let elapsed = started.elapsed();
+    println!("{:<8} {:>9} in {:>8.1?} {:>12.0}/s", phase, count, elapsed, count as f64 / elapsed.as_secs_f64());
+}
+
+// a fixed seed keeps runs comparable
+fn shuffle<T>(items: &mut [T]) {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+fn order(security: &Arc<Security>, i: i64) -> Order {
+    let order = if i % 2 == 0 { Order::buy(security).limit(Price(999 - i / 2 % LEVELS)) } else { Order::sell(security).limit(Price(1_001 + i / 2 % LEVELS)) };
+    order.quantity(Qty(1)).build().expect("the order is valid")
+}
+
+fn main() {
+    let orders: i64 = std::env::args().nth(1).and_then(|arg| arg.parse().ok()).unwrap_or(1_000_000);
+    let security = Arc::new(Security::new("XS0000000001", "THROUGHPUT"));
+    let config = OrderbookConfig::default().with_expected_orders(orders as usize).with_expected_levels_per_side(LEVELS as usize);
+    let mut book = Orderbook::with_config(security.clone(), 1_000, config, Box::new(SystemClock));
+
+    let started = Instant::now();
+    let mut order_ids = Vec::with_capacity(orders as usize);
+    for i in 0..orders {
+        let report = book.place_order(order(&security, i)).expect("the order rests");
+        order_ids.push(report.order_id());
+    }
+    report("place", order_ids.len(), started);
+
+    shuffle(&mut order_ids);
+    let cancelled = order_ids.len() / 2;
+    let started = Instant::now();
+    for &order_id in &order_ids[..cancelled] {
+        book.cancel_order(order_id, None).expect("the order is open");
+    }
+    report("cancel", cancelled, started);
+
+    let started = Instant::now();
+    let mut trades = 0;
+    for side in [Side::Buy, Side::Sell] {
+        let opposite_best = |book: &Orderbook| if side == Side::Buy { book.best_ask() } else { book.best_bid() };
+        while opposite_best(&book).is_some() {
+            let order = OrderBuilder::new(side, &security).quantity(Qty(100)).tif(TimeInForce::ImmediateOrCancel).build().expect("the order is valid");
+            let report = book.place_order(order).expect("the order trades");
+            trades += report.executions().len();
+        }
+    }
+    report("match", trades, started);
+
+    // the baseline keeps orders by id and nothing else, no levels, queues or matching
+    let started = Instant::now();
+    let mut map = HashMap::with_capacity(orders as usize);
+    for i in 0..orders {
+        map.insert(i, order(&security, i));
+    }
+    report("map put", map.len(), started);
+
+    let mut ids: Vec<i64> = (0..orders).collect();
+    shuffle(&mut ids);
+    let started = Instant::now();
+    for id in &ids[..cancelled] {
+        map.remove(id).expect("the order is in the map");
+    }
+    report("map del", cancelled, started);
+}
@@ -0,0 +1,9 @@
+pub mod account;
+pub mod borrow;
+pub mod corporate_action;
+pub mod error;
+pub mod ledger;
+pub mod pnl;
+pub mod registry;
+pub mod risk;
+pub mod settlement;
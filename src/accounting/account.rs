@@ -0,0 +1,84 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::borrow::Borrow;
+use super::pnl::PositionCost;
+
+// Cash and positions are in the units the books use, cash in price units times quantity.
+// Positions are signed, short positions are negative.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Account {
+    pub(crate) id: u64,
+    pub(crate) cash: i64,
+    // by ISIN
+    pub(crate) positions: HashMap<String, i64>,
+    // cost basis and realized profit by ISIN, for every security the account ever held
+    pub(crate) costs: HashMap<String, PositionCost>,
+    // whether the risk check lets the account sell more than it holds
+    pub(crate) short_selling: bool,
+    // what short positions borrowed by ISIN, oldest first
+    pub(crate) borrows: HashMap<String, VecDeque<Borrow>>,
+    // borrow fees charged so far by ISIN
+    pub(crate) borrow_fees: HashMap<String, i64>,
+    // what settlement lent the account to cover its shortfalls
+    pub(crate) borrowed: i64,
+}
+
+impl Account {
+    pub fn new(id: u64, cash: i64) -> Self {
+        Account { id, cash, positions: HashMap::new(), costs: HashMap::new(), short_selling: false, borrows: HashMap::new(), borrow_fees: HashMap::new(), borrowed: 0 }
+    }
+
+    pub fn with_short_selling(mut self) -> Account {
+        self.short_selling = true;
+        self
+    }
+
+    // A position of unknown cost, which counts as bought at 0.
+    pub fn with_position(mut self, isin: &str, quantity: i64) -> Account {
+        self.positions.insert(isin.to_string(), quantity);
+        self
+    }
+
+    pub fn with_position_at(mut self, isin: &str, quantity: i64, average_cost: i64) -> Account {
+        self.positions.insert(isin.to_string(), quantity);
+        self.costs.insert(isin.to_string(), PositionCost::new(quantity, average_cost));
+        self
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn cash(&self) -> i64 {
+        self.cash
+    }
+
+    pub fn position(&self, isin: &str) -> i64 {
+        self.positions.get(isin).copied().unwrap_or(0)
+    }
+
+    pub fn positions(&self) -> &HashMap<String, i64> {
+        &self.positions
+    }
+
+    pub fn cost(&self, isin: &str) -> PositionCost {
+        self.costs.get(isin).copied().unwrap_or_default()
+    }
+
+    pub fn short_selling(&self) -> bool {
+        self.short_selling
+    }
+
+    pub fn borrows(&self, isin: &str) -> impl Iterator<Item = &Borrow> {
+        self.borrows.get(isin).into_iter().flatten()
+    }
+
+    pub fn borrow_fees(&self, isin: &str) -> i64 {
+        self.borrow_fees.get(isin).copied().unwrap_or(0)
+    }
+
+    pub fn borrowed(&self) -> i64 {
+        self.borrowed
+    }
+}
@@ -0,0 +1,91 @@
+use std::collections::VecDeque;
+
+// Securities an account borrowed to deliver a short sale, at the price it sold them for and on
+// the business day it sold them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Borrow {
+    pub(crate) quantity: i64,
+    pub(crate) price: i64,
+    pub(crate) date: u32,
+}
+
+impl Borrow {
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn date(&self) -> u32 {
+        self.date
+    }
+
+    // what the borrowed securities were worth when they were borrowed
+    pub fn value(&self) -> i128 {
+        self.price as i128 * self.quantity as i128
+    }
+}
+
+// The short position of an account in one security with the borrows behind it, oldest first.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ShortPosition {
+    pub(crate) isin: String,
+    pub(crate) quantity: i64,
+    pub(crate) borrows: Vec<Borrow>,
+    pub(crate) accrued_fees: i64,
+}
+
+impl ShortPosition {
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    // positive, the quantity the account is short
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub fn borrows(&self) -> &[Borrow] {
+        &self.borrows
+    }
+
+    // the borrow fees charged for the security so far, including those of borrows already repaid
+    pub fn accrued_fees(&self) -> i64 {
+        self.accrued_fees
+    }
+}
+
+// Scales the quantities of the borrows by numerator / denominator, rounded toward zero, and
+// their prices the other way round, to the nearest unit.
+pub(crate) fn split(borrows: &mut VecDeque<Borrow>, numerator: i64, denominator: i64) {
+    for borrow in borrows.iter_mut() {
+        borrow.quantity = (borrow.quantity as i128 * numerator as i128 / denominator as i128).clamp(0, i64::MAX as i128) as i64;
+        borrow.price = ((borrow.price as i128 * denominator as i128 * 2 + numerator as i128) / (2 * numerator as i128)).clamp(0, i64::MAX as i128) as i64;
+    }
+    borrows.retain(|borrow| borrow.quantity > 0);
+}
+
+// Follows a fill of `quantity`, negative for sales, on a position: whatever takes the position
+// further below zero is borrowed at the price of the fill, and buys that cover repay the oldest
+// borrows first.
+pub(crate) fn fill(borrows: &mut VecDeque<Borrow>, position: i64, quantity: i64, price: i64, date: u32) {
+    let short_before = (-(position as i128)).max(0);
+    let short_after = (-(position as i128 + quantity as i128)).max(0);
+    let change = (short_after - short_before).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+    if change > 0 {
+        borrows.push_back(Borrow { quantity: change, price, date });
+    }
+
+    let mut covered = change.saturating_neg();
+    while covered > 0 {
+        let Some(oldest) = borrows.front_mut() else { break; };
+        let repaid = oldest.quantity.min(covered);
+        oldest.quantity -= repaid;
+        covered -= repaid;
+        if oldest.quantity == 0 { borrows.pop_front(); }
+    }
+}
@@ -0,0 +1,35 @@
+// What a corporate action paid to or charged an account for the position it held at ex.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Entitlement {
+    pub(crate) account_id: u64,
+    pub(crate) action_id: u64,
+    pub(crate) position: i64,
+    pub(crate) shares: i64,
+    pub(crate) cash: i64,
+}
+
+impl Entitlement {
+    pub fn account_id(&self) -> u64 {
+        self.account_id
+    }
+
+    pub fn action_id(&self) -> u64 {
+        self.action_id
+    }
+
+    // at trade date, short positions are negative
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    // what a split added to the position, negative for reverse splits and short positions
+    pub fn shares(&self) -> i64 {
+        self.shares
+    }
+
+    // negative for what short positions pay, the cash in lieu of fractions for splits
+    pub fn cash(&self) -> i64 {
+        self.cash
+    }
+}
@@ -0,0 +1,43 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccountingError {
+    UnknownAccount(u64),
+    DuplicateAccount(u64),
+    // an order without an account traded, its side of the trade could not be booked
+    NoAccount { order_id: i64 },
+    // the cash or the position of the account would leave the range of an i64
+    Overflow { account_id: u64 },
+    // the price source has no price for a security the account holds
+    NoPrice(String),
+    // a trade with fees needs an account to collect them
+    NoFeeAccount,
+    // a withdrawal of more cash than the account holds
+    InsufficientCash { account_id: u64, required: i64, available: i64 },
+    // a bust of a trade the registry did not book
+    UnknownTrade { isin: String, trade_id: u64 },
+    // the ledger does not add up to the cash or the positions of the account
+    LedgerMismatch { account_id: u64 },
+    // an asset of the ledger does not sum to zero over all accounts
+    UnbalancedLedger,
+}
+
+impl fmt::Display for AccountingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountingError::UnknownAccount(account_id) => write!(f, "Account {} does not exist", account_id),
+            AccountingError::DuplicateAccount(account_id) => write!(f, "Account {} already exists", account_id),
+            AccountingError::NoAccount { order_id } => write!(f, "Order {} traded without an account", order_id),
+            AccountingError::Overflow { account_id } => write!(f, "Booking would overflow account {}", account_id),
+            AccountingError::NoPrice(isin) => write!(f, "No price for security {}", isin),
+            AccountingError::NoFeeAccount => write!(f, "No account to collect fees"),
+            AccountingError::InsufficientCash { account_id, required, available } => write!(f, "Account {} holds {} cash, {} required", account_id, available, required),
+            AccountingError::UnknownTrade { isin, trade_id } => write!(f, "Trade {} of security {} was not booked", trade_id, isin),
+            AccountingError::LedgerMismatch { account_id } => write!(f, "Ledger does not match the balances of account {}", account_id),
+            AccountingError::UnbalancedLedger => write!(f, "Ledger does not sum to zero"),
+        }
+    }
+}
+
+impl Error for AccountingError {}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+
+// Who an entry moves cash or securities between.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LedgerAccount {
+    Account(u64),
+    // the world outside the exchange, opening balances and deposits come from it, withdrawals and
+    // lending go to it
+    External,
+    // the counterparty of every leg of deferred settlement, left with a balance by legs that failed
+    Clearing,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Asset {
+    Cash,
+    // by ISIN
+    Security(String),
+}
+
+// What caused an entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EntryCause {
+    // the balances an account was opened with
+    Opening,
+    Deposit,
+    Withdrawal,
+    // the cash and securities of a trade settled right away
+    Trade { trade_id: u64 },
+    // a fee paid or a rebate received for a trade
+    Fee { trade_id: u64 },
+    // the reversal of the cash, securities and fees of a busted trade
+    Bust { trade_id: u64 },
+    // the netted obligation of an account settled on a settlement date
+    Settlement { date: u32 },
+    // settlement lent an account what it was short
+    Borrowing { date: u32 },
+    // the daily fee for securities borrowed to sell short, charged when the day rolls over
+    BorrowFee { date: u32 },
+    // a dividend paid to holders, or by short sellers to whom they borrowed from
+    Dividend { action_id: u64 },
+    // the shares a split added or took away
+    Split { action_id: u64 },
+    // the fraction of a share a split left over, paid out in cash
+    CashInLieu { action_id: u64 },
+}
+
+// `amount` of `asset` moves from the credited account to the debited one.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LedgerEntry {
+    pub(crate) sequence: u64,
+    pub(crate) date: u32,
+    pub(crate) debit: LedgerAccount,
+    pub(crate) credit: LedgerAccount,
+    pub(crate) asset: Asset,
+    pub(crate) amount: i64,
+    pub(crate) cause: EntryCause,
+}
+
+impl LedgerEntry {
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn date(&self) -> u32 {
+        self.date
+    }
+
+    pub fn debit(&self) -> &LedgerAccount {
+        &self.debit
+    }
+
+    pub fn credit(&self) -> &LedgerAccount {
+        &self.credit
+    }
+
+    pub fn asset(&self) -> &Asset {
+        &self.asset
+    }
+
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+
+    pub fn cause(&self) -> &EntryCause {
+        &self.cause
+    }
+
+    // What the entry adds to the balance of `account`, 0 for accounts it does not touch.
+    pub fn change_for(&self, account: &LedgerAccount) -> i128 {
+        let mut change = 0;
+        if &self.debit == account { change += self.amount as i128; }
+        if &self.credit == account { change -= self.amount as i128; }
+        change
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrialBalanceLine {
+    pub account: LedgerAccount,
+    pub asset: Asset,
+    pub debits: i128,
+    pub credits: i128,
+}
+
+impl TrialBalanceLine {
+    pub fn balance(&self) -> i128 {
+        self.debits - self.credits
+    }
+}
+
+// The debits and credits of every account and asset up to the end of a day, by account and asset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrialBalance {
+    pub date: u32,
+    pub lines: Vec<TrialBalanceLine>,
+}
+
+impl TrialBalance {
+    // The balances of all accounts summed per asset.
+    pub fn totals(&self) -> BTreeMap<Asset, i128> {
+        let mut totals = BTreeMap::new();
+        for line in &self.lines { *totals.entry(line.asset.clone()).or_insert(0) += line.balance(); }
+        totals
+    }
+
+    // Whether every asset sums to zero over all accounts, which holds for every ledger that is
+    // only written by double entries.
+    pub fn is_balanced(&self) -> bool {
+        self.totals().values().all(|&total| total == 0)
+    }
+}
+
+// Every change to cash and positions of a registry as double entries, in the order they were
+// booked. Entries carry the business day they were booked on, the day of the last settlement
+// advance of the registry.
+#[derive(Clone, Debug, Default)]
+pub struct Ledger {
+    entries: Vec<LedgerEntry>,
+    date: u32,
+}
+
+impl Ledger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn date(&self) -> u32 {
+        self.date
+    }
+
+    pub(crate) fn set_date(&mut self, date: u32) {
+        self.date = self.date.max(date);
+    }
+
+    // Entries that would not move anything are left out, a negative amount moves the other way.
+    pub(crate) fn record(&mut self, debit: LedgerAccount, credit: LedgerAccount, asset: Asset, amount: i64, cause: EntryCause) {
+        if amount == 0 || debit == credit { return; }
+        let (debit, credit, amount) = if amount < 0 { (credit, debit, amount.saturating_neg()) } else { (debit, credit, amount) };
+        let sequence = self.entries.len() as u64;
+        self.entries.push(LedgerEntry { sequence, date: self.date, debit, credit, asset, amount, cause });
+    }
+
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // The entries touching the account that were booked on a day in `dates`.
+    pub fn entries_for<'a>(&'a self, account: &'a LedgerAccount, dates: impl RangeBounds<u32> + 'a) -> impl Iterator<Item = &'a LedgerEntry> + 'a {
+        self.entries.iter().filter(move |entry| (&entry.debit == account || &entry.credit == account) && dates.contains(&entry.date))
+    }
+
+    pub fn balance(&self, account: &LedgerAccount, asset: &Asset) -> i128 {
+        self.entries.iter().filter(|entry| &entry.asset == asset).map(|entry| entry.change_for(account)).sum()
+    }
+
+    // The trial balance over the entries booked on or before `date`.
+    pub fn trial_balance(&self, date: u32) -> TrialBalance {
+        let mut lines: BTreeMap<(LedgerAccount, Asset), (i128, i128)> = BTreeMap::new();
+        for entry in self.entries.iter().take_while(|entry| entry.date <= date) {
+            lines.entry((entry.debit.clone(), entry.asset.clone())).or_insert((0, 0)).0 += entry.amount as i128;
+            lines.entry((entry.credit.clone(), entry.asset.clone())).or_insert((0, 0)).1 += entry.amount as i128;
+        }
+        let lines = lines.into_iter().map(|((account, asset), (debits, credits))| TrialBalanceLine { account, asset, debits, credits }).collect();
+        TrialBalance { date, lines }
+    }
+
+    // The sum of every asset over all accounts, including the fee account, external and clearing.
+    pub fn is_balanced(&self) -> bool {
+        self.trial_balance(self.date).is_balanced()
+    }
+}
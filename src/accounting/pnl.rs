@@ -0,0 +1,134 @@
+// Cost and realized profit of one position. The cost basis is kept exactly, as the signed sum of
+// quantity times price of the fills the open position was built from, negative for a short
+// position. Only reducing a position divides: the part of the basis a fill closes is
+// basis * closed / |position|, rounded toward zero, and whatever the rounding leaves stays with
+// the rest of the position until it is closed completely, which takes the whole remaining basis.
+// Realized profit adds up exactly to proceeds minus cost over the life of a position.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PositionCost {
+    pub(crate) cost_basis: i64,
+    pub(crate) realized: i64,
+}
+
+impl PositionCost {
+    // a position taken over at a known average cost
+    pub fn new(position: i64, average_cost: i64) -> Self {
+        PositionCost { cost_basis: position.saturating_mul(average_cost), realized: 0 }
+    }
+
+    pub fn cost_basis(&self) -> i64 {
+        self.cost_basis
+    }
+
+    pub fn realized(&self) -> i64 {
+        self.realized
+    }
+
+    // The cost basis per unit of `position`, rounded down, None for a flat position.
+    pub fn average_cost(&self, position: i64) -> Option<i64> {
+        if position == 0 { return None; }
+        Some(((self.cost_basis as i128).abs() / (position as i128).abs()) as i64)
+    }
+
+    // What closing `position` at `price` would realize on top of what is realized already.
+    pub fn unrealized(&self, position: i64, price: i64) -> i128 {
+        position as i128 * price as i128 - self.cost_basis as i128
+    }
+
+    // The cost of `position` after a fill of `quantity` at `price`, positive for a buy and
+    // negative for a sell, or None if a figure leaves the range of an i64. A fill that flips the
+    // position closes the old side completely and opens the new side at the fill price.
+    pub fn fill(&self, position: i64, quantity: i64, price: i64) -> Option<PositionCost> {
+        let (position, mut basis, mut realized) = (position as i128, self.cost_basis as i128, self.realized as i128);
+        let (delta, price) = (quantity as i128, price as i128);
+
+        if position == 0 || position.signum() == delta.signum() {
+            basis += delta * price;
+        } else {
+            let closed = delta.abs().min(position.abs());
+            let removed = if closed == position.abs() { basis } else { basis * closed / position.abs() };
+            realized += position.signum() * closed * price - removed;
+            basis -= removed;
+
+            let opened = delta.abs() - closed;
+            if opened > 0 { basis = delta.signum() * opened * price; }
+        }
+
+        Some(PositionCost { cost_basis: i64::try_from(basis).ok()?, realized: i64::try_from(realized).ok()? })
+    }
+}
+
+// Profit and loss of one security of an account. Unrealized is None without a price.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PositionPnl {
+    pub(crate) isin: String,
+    pub(crate) position: i64,
+    pub(crate) average_cost: Option<i64>,
+    pub(crate) realized: i64,
+    pub(crate) unrealized: Option<i64>,
+    // the borrow fees charged for short positions in the security, not part of realized
+    pub(crate) borrow_cost: i64,
+}
+
+impl PositionPnl {
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn position(&self) -> i64 {
+        self.position
+    }
+
+    pub fn average_cost(&self) -> Option<i64> {
+        self.average_cost
+    }
+
+    pub fn realized(&self) -> i64 {
+        self.realized
+    }
+
+    pub fn unrealized(&self) -> Option<i64> {
+        self.unrealized
+    }
+
+    pub fn borrow_cost(&self) -> i64 {
+        self.borrow_cost
+    }
+}
+
+// Every security the account ever traded or held, in ISIN order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PnlReport {
+    pub(crate) account_id: u64,
+    pub(crate) positions: Vec<PositionPnl>,
+}
+
+impl PnlReport {
+    pub fn account_id(&self) -> u64 {
+        self.account_id
+    }
+
+    pub fn positions(&self) -> &[PositionPnl] {
+        &self.positions
+    }
+
+    pub fn position(&self, isin: &str) -> Option<&PositionPnl> {
+        self.positions.iter().find(|position| position.isin == isin)
+    }
+
+    pub fn realized(&self) -> i64 {
+        self.positions.iter().map(|position| position.realized).fold(0, i64::saturating_add)
+    }
+
+    pub fn borrow_cost(&self) -> i64 {
+        self.positions.iter().map(|position| position.borrow_cost).fold(0, i64::saturating_add)
+    }
+
+    // None if a position without price is open
+    pub fn unrealized(&self) -> Option<i64> {
+        self.positions.iter().filter(|position| position.position != 0).map(|position| position.unrealized).try_fold(0i64, |total, unrealized| unrealized.map(|unrealized| total.saturating_add(unrealized)))
+    }
+}
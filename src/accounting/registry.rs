@@ -0,0 +1,696 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::account::Account;
+use super::borrow::{self, ShortPosition};
+use super::corporate_action::Entitlement;
+use super::error::AccountingError;
+use super::ledger::{Asset, EntryCause, Ledger, LedgerAccount};
+use super::pnl::{PnlReport, PositionCost, PositionPnl};
+use super::settlement::{Obligation, SettlementEngine, SettlementReport};
+use crate::exchange::Exchange;
+use crate::matching::corporate_action::{CorporateAction, CorporateActionKind};
+use crate::matching::listener::{BookUpdate, CancelReason, ExecutionListener};
+use crate::matching::orderbook::Execution;
+use crate::matching::position::PositionProvider;
+
+// Where net worth takes the prices of positions from.
+pub trait PriceSource {
+    fn price(&self, isin: &str) -> Option<i64>;
+}
+
+impl<F: Fn(&str) -> Option<i64>> PriceSource for F {
+    fn price(&self, isin: &str) -> Option<i64> {
+        self(isin)
+    }
+}
+
+impl PriceSource for HashMap<String, i64> {
+    fn price(&self, isin: &str) -> Option<i64> {
+        self.get(isin).copied()
+    }
+}
+
+// the last price of every listed security
+impl PriceSource for Exchange {
+    fn price(&self, isin: &str) -> Option<i64> {
+        self.book(isin).map(|book| book.last_price())
+    }
+}
+
+// The mid price of every listed security, the last price for a book without both sides.
+pub struct MidPrices<'a>(pub &'a Exchange);
+
+impl PriceSource for MidPrices<'_> {
+    fn price(&self, isin: &str) -> Option<i64> {
+        self.0.book(isin).map(|book| book.mid_price().unwrap_or(book.last_price()))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AccountRegistry {
+    accounts: HashMap<u64, Account>,
+    // where the fees of trades go and rebates are paid from
+    fee_account: Option<u64>,
+    // None settles every trade the moment it happens
+    settlement: Option<SettlementEngine>,
+    // charged per day on the value of what short positions borrowed
+    borrow_fee_bps: i64,
+    ledger: Ledger,
+    // what each booked trade added to the cost basis and the realized profit of its accounts,
+    // taken off again when the trade is busted
+    trade_costs: HashMap<(String, u64), Vec<TradeCost>>,
+    pub(crate) errors: Vec<AccountingError>,
+}
+
+// account, cost basis and realized profit added by a trade
+type TradeCost = (u64, i64, i64);
+
+// What booking a trade changes, worked out before anything changes.
+struct Booking {
+    movements: Vec<(u64, i128, i64)>,
+    // account, trade date position, quantity, cost before and after
+    costs: Vec<(u64, i64, i64, PositionCost, PositionCost)>,
+    fee_account: Option<u64>,
+    ledger_notional: i64,
+}
+
+impl AccountRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A registry that settles trades a number of days after they happened, see SettlementEngine.
+    pub fn with_settlement(engine: SettlementEngine) -> Self {
+        AccountRegistry { settlement: Some(engine), ..Self::default() }
+    }
+
+    pub fn settlement(&self) -> Option<&SettlementEngine> {
+        self.settlement.as_ref()
+    }
+
+    // The daily fee for borrowed securities in basis points of their value at the time they were
+    // borrowed, charged by advance_settlement for every day that passed. Borrowing is free by
+    // default.
+    pub fn with_borrow_fee(mut self, bps_per_day: i64) -> AccountRegistry {
+        self.borrow_fee_bps = bps_per_day;
+        self
+    }
+
+    pub fn borrow_fee(&self) -> i64 {
+        self.borrow_fee_bps
+    }
+
+    // Short positions the account is opened with count as borrowed at their average cost.
+    pub fn open_account(&mut self, mut account: Account) -> Result<(), AccountingError> {
+        if self.accounts.contains_key(&account.id) { return Err(AccountingError::DuplicateAccount(account.id)); }
+        for (isin, &quantity) in &account.positions {
+            if quantity >= 0 { continue; }
+            let price = account.cost(isin).average_cost(quantity).unwrap_or(0);
+            borrow::fill(account.borrows.entry(isin.clone()).or_default(), 0, quantity, price, self.ledger.date());
+        }
+        let holder = LedgerAccount::Account(account.id);
+        self.ledger.record(holder.clone(), LedgerAccount::External, Asset::Cash, account.cash, EntryCause::Opening);
+        let mut positions: Vec<(&String, &i64)> = account.positions.iter().collect();
+        positions.sort();
+        for (isin, &quantity) in positions {
+            self.ledger.record(holder.clone(), LedgerAccount::External, Asset::Security(isin.clone()), quantity, EntryCause::Opening);
+        }
+        self.accounts.insert(account.id, account);
+        Ok(())
+    }
+
+    // Opens the account fees are collected in, which is not meant to trade itself.
+    pub fn open_fee_account(&mut self, account_id: u64) -> Result<(), AccountingError> {
+        self.open_account(Account::new(account_id, 0).with_short_selling())?;
+        self.fee_account = Some(account_id);
+        Ok(())
+    }
+
+    pub fn fee_account(&self) -> Option<u64> {
+        self.fee_account
+    }
+
+    // Fees collected minus rebates paid so far.
+    pub fn fee_revenue(&self) -> i64 {
+        self.fee_account.and_then(|account_id| self.accounts.get(&account_id)).map_or(0, |account| account.cash)
+    }
+
+    pub fn account(&self, account_id: u64) -> Option<&Account> {
+        self.accounts.get(&account_id)
+    }
+
+    pub fn accounts(&self) -> impl Iterator<Item = &Account> {
+        self.accounts.values()
+    }
+
+    // Adds cash from outside the exchange to the account and returns its new cash, a negative
+    // amount withdraws it.
+    pub fn deposit(&mut self, account_id: u64, amount: i64) -> Result<i64, AccountingError> {
+        if amount < 0 { return self.withdraw(account_id, amount.checked_neg().ok_or(AccountingError::Overflow { account_id })?); }
+        let account = self.accounts.get_mut(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+        account.cash = account.cash.checked_add(amount).ok_or(AccountingError::Overflow { account_id })?;
+        self.ledger.record(LedgerAccount::Account(account_id), LedgerAccount::External, Asset::Cash, amount, EntryCause::Deposit);
+        Ok(account.cash)
+    }
+
+    // Takes cash out of the exchange and returns what is left, never more than the account holds.
+    pub fn withdraw(&mut self, account_id: u64, amount: i64) -> Result<i64, AccountingError> {
+        if amount < 0 { return self.deposit(account_id, amount.checked_neg().ok_or(AccountingError::Overflow { account_id })?); }
+        let account = self.accounts.get_mut(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+        if amount > account.cash { return Err(AccountingError::InsufficientCash { account_id, required: amount, available: account.cash }); }
+        account.cash -= amount;
+        self.ledger.record(LedgerAccount::External, LedgerAccount::Account(account_id), Asset::Cash, amount, EntryCause::Withdrawal);
+        Ok(account.cash)
+    }
+
+    pub fn balance(&self, account_id: u64) -> Result<i64, AccountingError> {
+        self.accounts.get(&account_id).map(|account| account.cash).ok_or(AccountingError::UnknownAccount(account_id))
+    }
+
+    pub fn position(&self, account_id: u64, isin: &str) -> Result<i64, AccountingError> {
+        self.accounts.get(&account_id).map(|account| account.position(isin)).ok_or(AccountingError::UnknownAccount(account_id))
+    }
+
+    // Cash plus every position valued at the price of its security.
+    pub fn net_worth(&self, account_id: u64, prices: &impl PriceSource) -> Result<i64, AccountingError> {
+        let account = self.accounts.get(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+        let mut worth = account.cash as i128;
+        for (isin, &quantity) in &account.positions {
+            if quantity == 0 { continue; }
+            let price = prices.price(isin).ok_or_else(|| AccountingError::NoPrice(isin.clone()))?;
+            worth += price as i128 * quantity as i128;
+        }
+        i64::try_from(worth).map_err(|_| AccountingError::Overflow { account_id })
+    }
+
+    // Books a trade on the security: the buyer pays price times quantity to the seller and
+    // receives the quantity, and both pay their fee to the fee account. Nothing is booked unless
+    // every side can be booked. With deferred settlement cash and positions only move on the
+    // settlement date, costs, realized profit and borrows are booked on the trade date either way.
+    pub fn settle(&mut self, isin: &str, execution: &Execution) -> Result<(), AccountingError> {
+        let booking = self.plan(isin, execution, false)?;
+        self.book(isin, execution, booking, false)
+    }
+
+    // Books the reversal of a busted trade: the seller pays the price back to the buyer and gets
+    // the quantity back, and the fee account refunds both fees, all as entries of their own. With
+    // deferred settlement the reversal nets against the obligations like any trade. What the
+    // trade added to the cost basis and the realized profit of both accounts is taken off again,
+    // so a bust right after the trade leaves the costs as they were before it.
+    pub fn bust(&mut self, isin: &str, execution: &Execution) -> Result<(), AccountingError> {
+        let reversal = execution.reversed();
+        let booking = self.plan(isin, &reversal, true)?;
+        self.book(isin, &reversal, booking, true)
+    }
+
+    // Whether bust would book the reversal of the trade, without booking anything.
+    pub fn check_bust(&self, isin: &str, execution: &Execution) -> Result<(), AccountingError> {
+        self.plan(isin, &execution.reversed(), true).map(|_| ())
+    }
+
+    // Works out what booking the trade changes and checks every part of it, changing nothing.
+    fn plan(&self, isin: &str, execution: &Execution, bust: bool) -> Result<Booking, AccountingError> {
+        let buyer_id = execution.buying_account_id().ok_or(AccountingError::NoAccount { order_id: execution.buying_order_id() })?;
+        let seller_id = execution.selling_account_id().ok_or(AccountingError::NoAccount { order_id: execution.selling_order_id() })?;
+        for account_id in [buyer_id, seller_id] {
+            if !self.accounts.contains_key(&account_id) { return Err(AccountingError::UnknownAccount(account_id)); }
+        }
+        let (buyer_fee, seller_fee) = (execution.buyer_fee() as i128, execution.seller_fee() as i128);
+        let fee_account = match self.fee_account {
+            Some(account_id) => Some(account_id),
+            None if buyer_fee != 0 || seller_fee != 0 => return Err(AccountingError::NoFeeAccount),
+            None => None,
+        };
+
+        let (price, quantity) = (execution.price(), execution.amount());
+        let notional = price as i128 * quantity as i128;
+        let ledger_notional = i64::try_from(notional).map_err(|_| AccountingError::Overflow { account_id: buyer_id })?;
+        let mut movements = vec![(buyer_id, -notional - buyer_fee, quantity), (seller_id, notional - seller_fee, -quantity)];
+        if let Some(account_id) = fee_account { movements.push((account_id, buyer_fee + seller_fee, 0)); }
+
+        // a trade of an account with itself changes nothing but the fees
+        let mut costs = Vec::new();
+        let trade = (isin.to_string(), execution.trade_id());
+        let booked = self.trade_costs.get(&trade).filter(|_| bust);
+        if bust && booked.is_none() { return Err(AccountingError::UnknownTrade { isin: trade.0, trade_id: trade.1 }); }
+        if buyer_id != seller_id {
+            for (account_id, quantity) in [(buyer_id, quantity), (seller_id, -quantity)] {
+                let position = self.trade_date_position(account_id, isin);
+                let current = self.accounts[&account_id].cost(isin);
+                let overflow = AccountingError::Overflow { account_id };
+                let cost = match booked {
+                    Some(booked) => {
+                        let (basis, realized) = booked.iter().find(|(id, _, _)| *id == account_id).map_or((0, 0), |&(_, basis, realized)| (basis, realized));
+                        let cost_basis = current.cost_basis.checked_sub(basis).ok_or(overflow.clone())?;
+                        PositionCost { cost_basis, realized: current.realized.checked_sub(realized).ok_or(overflow)? }
+                    },
+                    None => current.fill(position, quantity, price).ok_or(overflow)?,
+                };
+                costs.push((account_id, position, quantity, current, cost));
+            }
+        }
+
+        match &self.settlement {
+            Some(engine) => { engine.net(isin, &movements)?; },
+            None => { self.balance_updates(isin, &movements)?; },
+        }
+        Ok(Booking { movements, costs, fee_account, ledger_notional })
+    }
+
+    fn book(&mut self, isin: &str, execution: &Execution, booking: Booking, bust: bool) -> Result<(), AccountingError> {
+        let Booking { movements, costs, fee_account, ledger_notional } = booking;
+        let (price, quantity, trade_id) = (execution.price(), execution.amount(), execution.trade_id());
+        match &mut self.settlement {
+            Some(engine) => engine.record(isin, &movements)?,
+            None => {
+                self.move_balances(isin, &movements)?;
+                let buyer = LedgerAccount::Account(movements[0].0);
+                let seller = LedgerAccount::Account(movements[1].0);
+                let (trade, fee) = match bust {
+                    true => (EntryCause::Bust { trade_id }, EntryCause::Bust { trade_id }),
+                    false => (EntryCause::Trade { trade_id }, EntryCause::Fee { trade_id }),
+                };
+                self.ledger.record(seller.clone(), buyer.clone(), Asset::Cash, ledger_notional, trade.clone());
+                self.ledger.record(buyer.clone(), seller.clone(), Asset::Security(isin.to_string()), quantity, trade);
+                if let Some(account_id) = fee_account {
+                    let fees = LedgerAccount::Account(account_id);
+                    self.ledger.record(fees.clone(), buyer, Asset::Cash, execution.buyer_fee(), fee.clone());
+                    self.ledger.record(fees, seller, Asset::Cash, execution.seller_fee(), fee);
+                }
+            },
+        }
+        let date = self.ledger.date();
+        let mut booked = Vec::with_capacity(costs.len());
+        for (account_id, position, quantity, before, cost) in costs {
+            let Some(account) = self.accounts.get_mut(&account_id) else { continue; };
+            account.costs.insert(isin.to_string(), cost);
+            borrow::fill(account.borrows.entry(isin.to_string()).or_default(), position, quantity, price, date);
+            if account.borrows.get(isin).is_some_and(|borrows| borrows.is_empty()) { account.borrows.remove(isin); }
+            booked.push((account_id, cost.cost_basis - before.cost_basis, cost.realized - before.realized));
+        }
+        match bust {
+            true => { self.trade_costs.remove(&(isin.to_string(), trade_id)); },
+            false => { self.trade_costs.insert((isin.to_string(), trade_id), booked); },
+        }
+        Ok(())
+    }
+
+    // Adds cash and quantity of the security to accounts, all or nothing. An account may appear
+    // more than once.
+    fn move_balances(&mut self, isin: &str, movements: &[(u64, i128, i64)]) -> Result<(), AccountingError> {
+        for (account_id, cash, position) in self.balance_updates(isin, movements)? {
+            let Some(account) = self.accounts.get_mut(&account_id) else { continue; };
+            account.cash = cash;
+            if position != 0 || account.positions.contains_key(isin) { account.positions.insert(isin.to_string(), position); }
+        }
+        Ok(())
+    }
+
+    // The cash and the position of the security move_balances would leave the accounts with.
+    fn balance_updates(&self, isin: &str, movements: &[(u64, i128, i64)]) -> Result<Vec<(u64, i64, i64)>, AccountingError> {
+        let mut balances: HashMap<u64, (i128, i128)> = HashMap::new();
+        for &(account_id, cash, quantity) in movements {
+            let account = self.accounts.get(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+            let balance = balances.entry(account_id).or_insert((account.cash as i128, account.position(isin) as i128));
+            balance.0 += cash;
+            balance.1 += quantity as i128;
+        }
+
+        let mut updates = Vec::with_capacity(balances.len());
+        for (account_id, (cash, position)) in balances {
+            let overflow = AccountingError::Overflow { account_id };
+            updates.push((account_id, i64::try_from(cash).map_err(|_| overflow.clone())?, i64::try_from(position).map_err(|_| overflow)?));
+        }
+        Ok(updates)
+    }
+
+    // The position including trades that are not settled yet.
+    pub fn trade_date_position(&self, account_id: u64, isin: &str) -> i64 {
+        let settled = self.accounts.get(&account_id).map_or(0, |account| account.position(isin));
+        settled.saturating_add(self.pending_position(account_id, isin))
+    }
+
+    // What unsettled trades will add to the position, 0 with instant settlement.
+    pub fn pending_position(&self, account_id: u64, isin: &str) -> i64 {
+        self.settlement.as_ref().map_or(0, |engine| engine.pending_position(account_id, isin))
+    }
+
+    // What unsettled trades will add to the cash of the account, negative if it owes more than it
+    // receives.
+    pub fn pending_cash(&self, account_id: u64) -> i64 {
+        self.settlement.as_ref().map_or(0, |engine| engine.pending_cash(account_id))
+    }
+
+    // The unsettled obligations of the account by settlement date and ISIN.
+    pub fn pending_obligations(&self, account_id: u64) -> Vec<Obligation> {
+        self.settlement.as_ref().map_or_else(Vec::new, |engine| engine.obligations_of(account_id))
+    }
+
+    // Settles every obligation due on or before `date` and makes `date` the trade date of the
+    // trades that follow and the day the ledger books on. With instant settlement there is
+    // nothing to settle.
+    pub fn advance_settlement(&mut self, date: u32) -> SettlementReport {
+        self.accrue_borrow_fees(date);
+        self.ledger.set_date(date);
+        match &mut self.settlement {
+            Some(engine) => engine.advance(date, &mut self.accounts, &mut self.ledger),
+            None => SettlementReport::empty(date),
+        }
+    }
+
+    // Charges the borrow fee for every day from the current day of the ledger to `date`, booked
+    // on `date`. Fees are rounded down per account and security.
+    fn accrue_borrow_fees(&mut self, date: u32) {
+        let days = date.saturating_sub(self.ledger.date());
+        if days == 0 || self.borrow_fee_bps == 0 { return; }
+        let mut ids: Vec<u64> = self.accounts.keys().copied().collect();
+        ids.sort();
+        for account_id in ids {
+            let account = self.accounts.get_mut(&account_id).expect("account ids are taken from the map");
+            let mut isins: Vec<String> = account.borrows.keys().cloned().collect();
+            isins.sort();
+            for isin in isins {
+                let value: i128 = account.borrows[&isin].iter().map(|borrow| borrow.value()).sum();
+                let fee = (value * self.borrow_fee_bps as i128 * days as i128).div_euclid(10_000).clamp(0, i64::MAX as i128) as i64;
+                if fee == 0 { continue; }
+                account.cash = account.cash.saturating_sub(fee);
+                let accrued = account.borrow_fees.entry(isin).or_insert(0);
+                *accrued = accrued.saturating_add(fee);
+                self.ledger.record(LedgerAccount::External, LedgerAccount::Account(account_id), Asset::Cash, fee, EntryCause::BorrowFee { date });
+            }
+        }
+    }
+
+    // The short positions of the account at trade date, in ISIN order.
+    pub fn short_positions(&self, account_id: u64) -> Result<Vec<ShortPosition>, AccountingError> {
+        let account = self.accounts.get(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+        let mut shorts: Vec<ShortPosition> = account.borrows.iter().filter(|(_, borrows)| !borrows.is_empty()).map(|(isin, borrows)| ShortPosition {
+            isin: isin.clone(),
+            quantity: self.trade_date_position(account_id, isin).saturating_neg().max(0),
+            borrows: borrows.iter().copied().collect(),
+            accrued_fees: account.borrow_fees(isin),
+        }).collect();
+        shorts.sort_by(|a, b| a.isin.cmp(&b.isin));
+        Ok(shorts)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    // Applies a corporate action to every account holding the security at trade date, which is
+    // meant to be called at the ex timestamp of the action. All or nothing, in the order of the
+    // account ids.
+    //
+    // A cash dividend credits holders and debits short positions the amount per share.
+    //
+    // A split multiplies positions by numerator / denominator, rounded toward zero. The fraction of
+    // a share that is left over is paid out as cash in lieu at the price of `prices` before the
+    // split, rounded down, so short positions pay for theirs. Borrows follow the short position,
+    // cost bases stay what they are. Unsettled trades are split as well, rounded toward zero, and
+    // get no cash in lieu.
+    pub fn apply_corporate_action(&mut self, action: &CorporateAction, prices: &impl PriceSource) -> Result<Vec<Entitlement>, AccountingError> {
+        let isin = action.isin();
+        let entitlements = self.entitlements(action, prices)?;
+
+        let action_id = action.id();
+        for entitlement in &entitlements {
+            let (account_id, holder) = (entitlement.account_id, LedgerAccount::Account(entitlement.account_id));
+            let Some(account) = self.accounts.get_mut(&account_id) else { continue; };
+            account.cash += entitlement.cash;
+            match action.kind() {
+                CorporateActionKind::CashDividend { .. } => {
+                    self.ledger.record(holder, LedgerAccount::External, Asset::Cash, entitlement.cash, EntryCause::Dividend { action_id });
+                },
+                CorporateActionKind::Split { .. } => {
+                    *account.positions.entry(isin.to_string()).or_insert(0) += entitlement.shares;
+                    self.ledger.record(holder.clone(), LedgerAccount::External, Asset::Security(isin.to_string()), entitlement.shares, EntryCause::Split { action_id });
+                    self.ledger.record(holder, LedgerAccount::External, Asset::Cash, entitlement.cash, EntryCause::CashInLieu { action_id });
+                },
+            }
+        }
+
+        if let CorporateActionKind::Split { numerator, denominator } = action.kind() {
+            if let Some(engine) = &mut self.settlement { engine.split(isin, numerator, denominator); }
+            let split_price = (prices.price(isin).unwrap_or(0) as i128 * denominator as i128 / numerator as i128).clamp(0, i64::MAX as i128) as i64;
+            let date = self.ledger.date();
+            let short_accounts: Vec<u64> = self.accounts.values().filter(|account| account.borrows.contains_key(isin)).map(|account| account.id).collect();
+            for account_id in short_accounts {
+                let short = -self.trade_date_position(account_id, isin).min(0);
+                let Some(account) = self.accounts.get_mut(&account_id) else { continue; };
+                let Some(borrows) = account.borrows.get_mut(isin) else { continue; };
+                borrow::split(borrows, numerator, denominator);
+                let borrowed: i64 = borrows.iter().map(|borrow| borrow.quantity()).sum();
+                borrow::fill(borrows, -borrowed, borrowed - short, split_price, date);
+                if borrows.is_empty() { account.borrows.remove(isin); }
+            }
+        }
+        Ok(entitlements)
+    }
+
+    // Checks that apply_corporate_action would succeed with the same prices, changing nothing.
+    pub fn check_corporate_action(&self, action: &CorporateAction, prices: &impl PriceSource) -> Result<(), AccountingError> {
+        self.entitlements(action, prices).map(|_| ())
+    }
+
+    // what every account is entitled to, checked for overflow
+    fn entitlements(&self, action: &CorporateAction, prices: &impl PriceSource) -> Result<Vec<Entitlement>, AccountingError> {
+        let isin = action.isin();
+        let mut ids: Vec<u64> = self.accounts.keys().copied().collect();
+        ids.sort();
+
+        let mut entitlements = Vec::new();
+        for account_id in ids {
+            let position = self.trade_date_position(account_id, isin);
+            let settled = self.accounts[&account_id].position(isin);
+            if position == 0 && settled == 0 { continue; }
+            let overflow = AccountingError::Overflow { account_id };
+            let (shares, cash) = match action.kind() {
+                CorporateActionKind::CashDividend { amount_per_share } => (0, position as i128 * amount_per_share as i128),
+                CorporateActionKind::Split { numerator, denominator } => {
+                    let scaled = settled as i128 * numerator as i128;
+                    let (whole, fraction) = (scaled / denominator as i128, scaled % denominator as i128);
+                    let cash = if fraction == 0 { 0 } else {
+                        let price = prices.price(isin).ok_or_else(|| AccountingError::NoPrice(isin.to_string()))?;
+                        (fraction * price as i128).div_euclid(numerator as i128)
+                    };
+                    (i64::try_from(whole - settled as i128).map_err(|_| overflow.clone())?, cash)
+                },
+            };
+            let cash = i64::try_from(cash).map_err(|_| overflow.clone())?;
+            if shares == 0 && cash == 0 { continue; }
+            self.accounts[&account_id].cash.checked_add(cash).ok_or(overflow.clone())?;
+            settled.checked_add(shares).ok_or(overflow)?;
+            entitlements.push(Entitlement { account_id, action_id: action.id(), position, shares, cash });
+        }
+        Ok(entitlements)
+    }
+
+    // Checks that the ledger sums to zero for every asset and that it adds up to the cash and
+    // positions of every account.
+    pub fn verify_ledger(&self) -> Result<(), AccountingError> {
+        let trial_balance = self.ledger.trial_balance(self.ledger.date());
+        if !trial_balance.is_balanced() { return Err(AccountingError::UnbalancedLedger); }
+
+        let mut balances: HashMap<(u64, Asset), i128> = HashMap::new();
+        for line in trial_balance.lines {
+            let LedgerAccount::Account(account_id) = line.account else { continue; };
+            if !self.accounts.contains_key(&account_id) { return Err(AccountingError::LedgerMismatch { account_id }); }
+            balances.insert((account_id, line.asset.clone()), line.balance());
+        }
+        let mut ids: Vec<u64> = self.accounts.keys().copied().collect();
+        ids.sort();
+        for account_id in ids {
+            let account = &self.accounts[&account_id];
+            let mismatch = AccountingError::LedgerMismatch { account_id };
+            if balances.remove(&(account_id, Asset::Cash)).unwrap_or(0) != account.cash as i128 { return Err(mismatch); }
+            for (isin, &quantity) in &account.positions {
+                if balances.remove(&(account_id, Asset::Security(isin.clone()))).unwrap_or(0) != quantity as i128 { return Err(mismatch); }
+            }
+        }
+        // whatever is left is a balance the account does not have
+        match balances.into_iter().find(|(_, balance)| *balance != 0) {
+            Some(((account_id, _), _)) => Err(AccountingError::LedgerMismatch { account_id }),
+            None => Ok(()),
+        }
+    }
+
+    // Realized and unrealized profit per security of the account, unrealized at the prices of
+    // `prices`. See pnl::PositionCost for how average costs are rounded.
+    pub fn pnl_report(&self, account_id: u64, prices: &impl PriceSource) -> Result<PnlReport, AccountingError> {
+        let account = self.accounts.get(&account_id).ok_or(AccountingError::UnknownAccount(account_id))?;
+        let mut isins: Vec<&String> = account.positions.keys().chain(account.costs.keys()).chain(account.borrow_fees.keys()).collect();
+        isins.sort();
+        isins.dedup();
+
+        let positions = isins.into_iter().map(|isin| {
+            let (position, cost) = (account.position(isin), account.cost(isin));
+            let unrealized = prices.price(isin).map(|price| cost.unrealized(position, price).clamp(i64::MIN as i128, i64::MAX as i128) as i64);
+            PositionPnl { isin: isin.clone(), position, average_cost: cost.average_cost(position), realized: cost.realized(), unrealized, borrow_cost: account.borrow_fees(isin) }
+        }).collect();
+        Ok(PnlReport { account_id, positions })
+    }
+
+    // Trades the listener could not book, the oldest first.
+    pub fn errors(&self) -> &[AccountingError] {
+        &self.errors
+    }
+
+    pub fn take_errors(&mut self) -> Vec<AccountingError> {
+        std::mem::take(&mut self.errors)
+    }
+}
+
+impl PositionProvider for AccountRegistry {
+    fn position(&self, account_id: u64, isin: &str) -> i64 {
+        self.accounts.get(&account_id).map_or(0, |account| account.position(isin))
+    }
+}
+
+// Books every execution of one book in a registry shared with the caller. The listener cannot
+// refuse a trade, executions that cannot be booked are collected in the errors of the registry.
+pub struct AccountingListener {
+    registry: Arc<Mutex<AccountRegistry>>,
+    isin: String,
+}
+
+impl AccountingListener {
+    pub fn new(registry: Arc<Mutex<AccountRegistry>>, isin: &str) -> Self {
+        AccountingListener { registry, isin: isin.to_string() }
+    }
+}
+
+impl ExecutionListener for AccountingListener {
+    fn on_execution(&mut self, execution: &Execution) {
+        let Ok(mut registry) = self.registry.lock() else { return; };
+        if let Err(error) = registry.settle(&self.isin, execution) { registry.errors.push(error); }
+    }
+
+    fn on_order_cancelled(&mut self, _order_id: i64, _reason: CancelReason) {}
+
+    fn on_book_update(&mut self, _update: &BookUpdate) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::risk::{RiskCheck, RiskChecker};
+    use crate::matching::events::OrderbookEvent;
+    use crate::matching::orderbook::{Order, OrderBuilder, OrderReport, Orderbook, Security, Side};
+    use crate::matching::units::{Price, Qty};
+    use std::sync::mpsc::Receiver;
+
+    const ISIN: &str = "XS0000000001";
+
+    fn trade(book: &mut Orderbook, buyer: u64, seller: u64, price: i64, quantity: i64) -> Execution {
+        let security = book.security().clone();
+        book.place_order(OrderBuilder::new(Side::Sell, &security).limit(Price(price)).quantity(Qty(quantity)).account(seller).build().unwrap()).unwrap();
+        book.place_order(OrderBuilder::new(Side::Buy, &security).limit(Price(price)).quantity(Qty(quantity)).account(buyer).build().unwrap()).unwrap();
+        book.executions().last().unwrap().clone()
+    }
+
+    fn registry() -> AccountRegistry {
+        let mut registry = AccountRegistry::new();
+        registry.open_account(Account::new(1, 100_000)).unwrap();
+        registry.open_account(Account::new(2, 0).with_position_at(ISIN, 1_000, 80)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn a_bust_takes_the_costs_of_the_trade_off_again() {
+        let mut book = Orderbook::new(Arc::new(Security::new(ISIN, "TEST")), 100);
+        let mut registry = registry();
+        let execution = trade(&mut book, 1, 2, 100, 10);
+        registry.settle(ISIN, &execution).unwrap();
+        assert_eq!(registry.account(2).unwrap().cost(ISIN), PositionCost { cost_basis: 79_200, realized: 200 });
+
+        registry.bust(ISIN, &execution).unwrap();
+        assert_eq!(registry.account(1).unwrap().cost(ISIN), PositionCost::default());
+        assert_eq!(registry.account(2).unwrap().cost(ISIN), PositionCost { cost_basis: 80_000, realized: 0 });
+        assert_eq!((registry.position(1, ISIN), registry.position(2, ISIN)), (Ok(0), Ok(1_000)));
+        assert_eq!((registry.account(1).unwrap().cash(), registry.account(2).unwrap().cash()), (100_000, 0));
+    }
+
+    #[test]
+    fn only_a_booked_trade_is_busted() {
+        let mut book = Orderbook::new(Arc::new(Security::new(ISIN, "TEST")), 100);
+        let mut registry = registry();
+        let execution = trade(&mut book, 1, 2, 100, 10);
+
+        let unknown = AccountingError::UnknownTrade { isin: ISIN.to_string(), trade_id: execution.trade_id() };
+        assert_eq!(registry.check_bust(ISIN, &execution), Err(unknown.clone()));
+        assert_eq!(registry.bust(ISIN, &execution), Err(unknown.clone()));
+        assert_eq!((registry.position(1, ISIN), registry.position(2, ISIN)), (Ok(0), Ok(1_000)));
+
+        registry.settle(ISIN, &execution).unwrap();
+        assert_eq!(registry.check_bust(ISIN, &execution), Ok(()));
+        registry.bust(ISIN, &execution).unwrap();
+        assert_eq!(registry.bust(ISIN, &execution), Err(unknown));
+    }
+
+    // what the exchange does with an order when it has a risk check
+    fn place(risk: &mut RiskChecker, book: &mut Orderbook, events: &Receiver<OrderbookEvent>, order: Order) -> OrderReport {
+        let reference_price = book.last_price();
+        risk.check(&order, reference_price).unwrap();
+        let report = book.place_order(order.clone()).unwrap();
+        for event in events.try_iter() { risk.on_event(&event, book); }
+        let open_quantity = book.order(report.order_id()).map_or(0, |order| order.remaining());
+        risk.reserve(&order, report.order_id().to_raw(), open_quantity, reference_price);
+        report
+    }
+
+    #[test]
+    fn reservations_shrink_with_every_partial_fill() {
+        let security = Arc::new(Security::new(ISIN, "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let events = book.subscribe_unbounded();
+        let mut risk = RiskChecker::new(Arc::new(Mutex::new(registry())));
+        let order = |side, account, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity)).account(account).build().unwrap();
+
+        place(&mut risk, &mut book, &events, order(Side::Buy, 1, 100, 50));
+        let sell = place(&mut risk, &mut book, &events, order(Side::Sell, 2, 105, 300)).order_id();
+        assert_eq!((risk.reserved_cash(1), risk.reserved_position(2, ISIN)), (5_000, 300));
+
+        // the buy is filled in two slices, what it no longer needs is released with each
+        place(&mut risk, &mut book, &events, order(Side::Sell, 2, 100, 20));
+        assert_eq!((risk.reserved_cash(1), risk.reserved_position(2, ISIN)), (3_000, 300));
+        place(&mut risk, &mut book, &events, order(Side::Sell, 2, 100, 30));
+        assert_eq!((risk.reserved_cash(1), risk.reserved_position(2, ISIN)), (0, 300));
+
+        // a buy of 40 takes part of the resting sell, which keeps the rest reserved
+        place(&mut risk, &mut book, &events, order(Side::Buy, 1, 105, 40));
+        assert_eq!((risk.reserved_cash(1), risk.reserved_position(2, ISIN)), (0, 260));
+        assert_eq!(risk.reservation_count(), 1);
+
+        book.cancel_order(sell, Some(2)).unwrap();
+        for event in events.try_iter() { risk.on_event(&event, &book); }
+        assert_eq!(risk.reserved_position(2, ISIN), 0);
+        assert_eq!(risk.reservation_count(), 0);
+    }
+
+    #[test]
+    fn a_dividend_pays_long_positions_and_charges_short_ones() {
+        let mut book = Orderbook::new(Arc::new(Security::new(ISIN, "TEST")), 100);
+        let mut registry = registry();
+        registry.open_account(Account::new(3, 50_000).with_short_selling()).unwrap();
+        registry.open_account(Account::new(4, 0).with_position_at(ISIN, 50, 90)).unwrap();
+        registry.open_account(Account::new(5, 1_000)).unwrap();
+        // 1 buys 10 from 3, who goes short, and the 50 of 4, who is left flat
+        for (seller, quantity) in [(3, 10), (4, 50)] {
+            let execution = trade(&mut book, 1, seller, 100, quantity);
+            registry.settle(ISIN, &execution).unwrap();
+        }
+        let cash: Vec<i64> = (1..=5).map(|account_id| registry.account(account_id).unwrap().cash()).collect();
+
+        let dividend = CorporateAction::new(1, ISIN, CorporateActionKind::CashDividend { amount_per_share: 2 }, 0);
+        let entitlements = registry.apply_corporate_action(&dividend, &|_: &str| Some(100)).unwrap();
+
+        let paid: Vec<(u64, i64, i64)> = entitlements.iter().map(|entitlement| (entitlement.account_id(), entitlement.position(), entitlement.cash())).collect();
+        assert_eq!(paid, [(1, 60, 120), (2, 1_000, 2_000), (3, -10, -20)]);
+        let changes: Vec<i64> = (1..=5).map(|account_id| registry.account(account_id).unwrap().cash() - cash[account_id as usize - 1]).collect();
+        assert_eq!(changes, [120, 2_000, -20, 0, 0]);
+        assert_eq!((1..=5).map(|account_id| registry.position(account_id, ISIN).unwrap()).collect::<Vec<_>>(), [60, 1_000, -10, 0, 0]);
+        assert_eq!(registry.verify_ledger(), Ok(()));
+    }
+}
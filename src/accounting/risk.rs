@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use super::registry::AccountRegistry;
+use crate::matching::error::RiskRejection;
+use crate::matching::events::OrderbookEvent;
+use crate::matching::orderbook::{Order, Orderbook, Side};
+use crate::matching::units::OrderId;
+
+// Consulted by the exchange for every order before it reaches a book. `reference_price` is the
+// last price of the book the order is for.
+pub trait RiskCheck {
+    fn check(&mut self, order: &Order, reference_price: i64) -> Result<(), RiskRejection>;
+
+    // The order passed the check and `open_quantity` of it is still open in the book after it was
+    // placed, resting or waiting for its stop.
+    fn reserve(&mut self, order: &Order, order_id: i64, open_quantity: i64, reference_price: i64);
+
+    // Every event of the book, for releasing what fills and cancellations no longer need. `book`
+    // is the book in its current state, which can be ahead of the event.
+    fn on_event(&mut self, event: &OrderbookEvent, book: &Orderbook);
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Reservation {
+    account_id: u64,
+    isin: String,
+    side: Side,
+    // per unit, the worst case price for buys
+    price: i64,
+    remaining: i64,
+}
+
+// Checks orders against the cash and positions of the accounts in a registry. Open buy orders
+// reserve their worst case notional, the limit or for orders without a limit the reference price
+// moved by the market collar, and open sell orders reserve the quantity they sell. Reservations
+// shrink with every fill and are gone once the order is filled or cancelled. Fills themselves are
+// booked by the accounting listener of the book. Trades that are not settled yet count with their
+// pending cash and quantity, so whatever a sale earns is available right away. Accounts that may
+// sell short are held to a short exposure limit: the short positions of the account including
+// what open sells would add, valued at the price they were borrowed at and the order at its worst
+// case price, may not exceed a multiple of its equity, the cash less the value of what it borrowed.
+pub struct RiskChecker {
+    registry: Arc<Mutex<AccountRegistry>>,
+    reservations: HashMap<i64, Reservation>,
+    market_collar_bps: i64,
+    short_limit_bps: i64,
+}
+
+impl RiskChecker {
+    pub fn new(registry: Arc<Mutex<AccountRegistry>>) -> Self {
+        RiskChecker { registry, reservations: HashMap::new(), market_collar_bps: 1_000, short_limit_bps: 10_000 }
+    }
+
+    // How far above the reference price a buy without a limit may trade, in basis points, 10% by
+    // default.
+    pub fn with_market_collar(mut self, bps: i64) -> RiskChecker {
+        self.market_collar_bps = bps;
+        self
+    }
+
+    // The short exposure an account may take in basis points of its equity, once its equity by
+    // default.
+    pub fn with_short_limit(mut self, bps: i64) -> RiskChecker {
+        self.short_limit_bps = bps;
+        self
+    }
+
+    // The cash open buy orders of the account hold back.
+    pub fn reserved_cash(&self, account_id: u64) -> i64 {
+        let reserved: i128 = self.reservations.values().filter(|reservation| reservation.account_id == account_id && reservation.side == Side::Buy)
+            .map(|reservation| reservation.price as i128 * reservation.remaining as i128).sum();
+        reserved.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+    }
+
+    // The quantity of the security open sell orders of the account hold back.
+    pub fn reserved_position(&self, account_id: u64, isin: &str) -> i64 {
+        self.reservations.values().filter(|reservation| reservation.account_id == account_id && reservation.side == Side::Sell && reservation.isin == isin)
+            .map(|reservation| reservation.remaining).sum()
+    }
+
+    pub fn reservation_count(&self) -> usize {
+        self.reservations.len()
+    }
+
+    fn worst_price(&self, order: &Order, reference_price: i64) -> i64 {
+        match order.order_limit() {
+            Some(limit) => limit,
+            None => {
+                let reference = reference_price.max(order.stop_price().unwrap_or(0)) as i128;
+                (reference * (10_000 + self.market_collar_bps as i128) / 10_000).clamp(0, i64::MAX as i128) as i64
+            },
+        }
+    }
+}
+
+impl RiskCheck for RiskChecker {
+    fn check(&mut self, order: &Order, reference_price: i64) -> Result<(), RiskRejection> {
+        let account_id = order.account_id().ok_or(RiskRejection::NoAccount)?;
+        let registry = self.registry.lock().map_err(|_| RiskRejection::UnknownAccount(account_id))?;
+        let account = registry.account(account_id).ok_or(RiskRejection::UnknownAccount(account_id))?;
+
+        match order.side() {
+            Side::Buy => {
+                let required = self.worst_price(order, reference_price) as i128 * order.amount() as i128;
+                let available = account.cash() as i128 + registry.pending_cash(account_id) as i128 - self.reserved_cash(account_id) as i128;
+                if required > available {
+                    let clamp = |value: i128| value.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+                    return Err(RiskRejection::InsufficientBuyingPower { required: clamp(required), available: clamp(available) });
+                }
+            },
+            Side::Sell => {
+                let isin = &order.security().isin;
+                let available = registry.trade_date_position(account_id, isin).saturating_sub(self.reserved_position(account_id, isin));
+                if order.amount() <= available { return Ok(()); }
+                if !account.short_selling() { return Err(RiskRejection::InsufficientPosition { required: order.amount(), available }); }
+
+                // the short position in the security once this and every open sell filled
+                let short = order.amount() as i128 - available as i128;
+                let price = reference_price.max(order.order_limit().unwrap_or(0)) as i128;
+                let (mut borrowed, mut borrowed_elsewhere) = (0i128, 0i128);
+                for (borrowed_isin, borrows) in &account.borrows {
+                    let value: i128 = borrows.iter().map(|borrow| borrow.value()).sum();
+                    if borrowed_isin == isin { borrowed += value; } else { borrowed_elsewhere += value; }
+                }
+                let exposure = borrowed_elsewhere + short * price;
+                // the proceeds of short sales are owed back
+                let equity = (account.cash() as i128 + registry.pending_cash(account_id) as i128 - borrowed - borrowed_elsewhere).max(0);
+                let limit = equity * self.short_limit_bps as i128 / 10_000;
+                if exposure > limit {
+                    let clamp = |value: i128| value.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+                    return Err(RiskRejection::ShortExposureLimit { exposure: clamp(exposure), limit: clamp(limit) });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn reserve(&mut self, order: &Order, order_id: i64, open_quantity: i64, reference_price: i64) {
+        let Some(account_id) = order.account_id() else { return; };
+        if open_quantity <= 0 { return; }
+        let price = self.worst_price(order, reference_price);
+        self.reservations.insert(order_id, Reservation { account_id, isin: order.security().isin.clone(), side: order.side(), price, remaining: open_quantity });
+    }
+
+    fn on_event(&mut self, event: &OrderbookEvent, book: &Orderbook) {
+        match event {
+            OrderbookEvent::Trade { execution, .. } | OrderbookEvent::TradeBusted { execution, .. } => {
+                for order_id in [execution.buying_order_id(), execution.selling_order_id()] {
+                    let Some(reservation) = self.reservations.get_mut(&order_id) else { continue; };
+                    match book.order(OrderId::from_raw(order_id)) {
+                        Some(order) => reservation.remaining = order.remaining(),
+                        None => { self.reservations.remove(&order_id); },
+                    }
+                }
+            },
+            OrderbookEvent::OrderCancelled { order_id, .. } => {
+                self.reservations.remove(order_id);
+            },
+            // orders dropped by the clear were not reported one by one
+            OrderbookEvent::BookCleared { .. } => {
+                let isin = &book.security().isin;
+                self.reservations.retain(|_, reservation| &reservation.isin != isin);
+            },
+            _ => {},
+        }
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::account::Account;
+use super::error::AccountingError;
+use super::ledger::{Asset, EntryCause, Ledger, LedgerAccount};
+
+// What happens to an obligation the cash of the account does not cover on the settlement date.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FailPolicy {
+    // neither cash nor securities of the account move, the obligation is dropped and reported,
+    // the counterparties still settle their legs
+    #[default]
+    FailLeg,
+    // the shortfall is lent to the account, its cash goes to 0 and its debt up by the shortfall
+    ForceBorrow,
+    // the obligation settles and the cash of the account goes negative
+    GoNegative,
+}
+
+// The net cash and quantity of one security an account receives on a settlement date, negative
+// for what it delivers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Obligation {
+    pub(crate) account_id: u64,
+    pub(crate) isin: String,
+    pub(crate) settlement_date: u32,
+    pub(crate) cash: i64,
+    pub(crate) quantity: i64,
+}
+
+impl Obligation {
+    pub fn account_id(&self) -> u64 {
+        self.account_id
+    }
+
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn settlement_date(&self) -> u32 {
+        self.settlement_date
+    }
+
+    pub fn cash(&self) -> i64 {
+        self.cash
+    }
+
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+}
+
+// An obligation the cash of its account did not cover, and what the policy did about it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SettlementFailure {
+    pub(crate) obligation: Obligation,
+    pub(crate) shortfall: i64,
+    pub(crate) policy: FailPolicy,
+}
+
+impl SettlementFailure {
+    pub fn obligation(&self) -> &Obligation {
+        &self.obligation
+    }
+
+    pub fn shortfall(&self) -> i64 {
+        self.shortfall
+    }
+
+    pub fn policy(&self) -> FailPolicy {
+        self.policy
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SettlementReport {
+    pub(crate) date: u32,
+    pub(crate) settled: Vec<Obligation>,
+    pub(crate) failures: Vec<SettlementFailure>,
+}
+
+impl SettlementReport {
+    pub(crate) fn empty(date: u32) -> Self {
+        SettlementReport { date, ..Self::default() }
+    }
+
+    pub fn date(&self) -> u32 {
+        self.date
+    }
+
+    // every obligation that moved cash and securities, including those settled by borrowing or
+    // going negative
+    pub fn settled(&self) -> &[Obligation] {
+        &self.settled
+    }
+
+    pub fn failures(&self) -> &[SettlementFailure] {
+        &self.failures
+    }
+}
+
+// settlement date, account and security of an obligation
+type ObligationKey = (u32, u64, String);
+
+// Holds trades back until their settlement date, `cycle_days` business days after the trade
+// date. Dates are business day numbers the caller counts, the trade date is the date of the last
+// advance. Obligations are netted per account, security and settlement date and settle in that
+// order, credits of an account before its debits.
+#[derive(Clone, Debug)]
+pub struct SettlementEngine {
+    cycle_days: u32,
+    policy: FailPolicy,
+    trade_date: u32,
+    obligations: BTreeMap<ObligationKey, Obligation>,
+}
+
+impl SettlementEngine {
+    pub fn new(cycle_days: u32, policy: FailPolicy) -> Self {
+        SettlementEngine { cycle_days, policy, trade_date: 0, obligations: BTreeMap::new() }
+    }
+
+    // T+2 with failing legs
+    pub fn t_plus_two() -> Self {
+        Self::new(2, FailPolicy::FailLeg)
+    }
+
+    pub fn cycle_days(&self) -> u32 {
+        self.cycle_days
+    }
+
+    pub fn policy(&self) -> FailPolicy {
+        self.policy
+    }
+
+    pub fn trade_date(&self) -> u32 {
+        self.trade_date
+    }
+
+    // Nets the movements of a trade into the obligations of its settlement date.
+    pub(crate) fn record(&mut self, isin: &str, movements: &[(u64, i128, i64)]) -> Result<(), AccountingError> {
+        for ((settlement_date, account_id, isin), cash, quantity) in self.net(isin, movements)? {
+            let obligation = self.obligations.entry((settlement_date, account_id, isin.clone())).or_insert(Obligation { account_id, isin, settlement_date, cash: 0, quantity: 0 });
+            obligation.cash = cash;
+            obligation.quantity = quantity;
+        }
+        Ok(())
+    }
+
+    // The obligations record would leave, an error if one would overflow.
+    pub(crate) fn net(&self, isin: &str, movements: &[(u64, i128, i64)]) -> Result<Vec<(ObligationKey, i64, i64)>, AccountingError> {
+        let settlement_date = self.trade_date.saturating_add(self.cycle_days);
+        let mut netted = Vec::with_capacity(movements.len());
+        for &(account_id, cash, quantity) in movements {
+            let key = (settlement_date, account_id, isin.to_string());
+            let (current_cash, current_quantity) = self.obligations.get(&key).map_or((0, 0), |obligation| (obligation.cash, obligation.quantity));
+            let overflow = AccountingError::Overflow { account_id };
+            let cash = i64::try_from(current_cash as i128 + cash).map_err(|_| overflow.clone())?;
+            let quantity = current_quantity.checked_add(quantity).ok_or(overflow)?;
+            netted.push((key, cash, quantity));
+        }
+        Ok(netted)
+    }
+
+    // Scales the quantities of the security still to be delivered, rounded toward zero.
+    pub(crate) fn split(&mut self, isin: &str, numerator: i64, denominator: i64) {
+        for obligation in self.obligations.values_mut().filter(|obligation| obligation.isin == isin) {
+            obligation.quantity = (obligation.quantity as i128 * numerator as i128 / denominator as i128).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        }
+    }
+
+    pub fn pending_position(&self, account_id: u64, isin: &str) -> i64 {
+        self.obligations.values().filter(|obligation| obligation.account_id == account_id && obligation.isin == isin).map(|obligation| obligation.quantity).sum()
+    }
+
+    pub fn pending_cash(&self, account_id: u64) -> i64 {
+        self.obligations.values().filter(|obligation| obligation.account_id == account_id).map(|obligation| obligation.cash).sum()
+    }
+
+    pub fn obligations_of(&self, account_id: u64) -> Vec<Obligation> {
+        self.obligations.values().filter(|obligation| obligation.account_id == account_id).cloned().collect()
+    }
+
+    pub fn obligations(&self) -> impl Iterator<Item = &Obligation> {
+        self.obligations.values()
+    }
+
+    // Every leg that settles is booked in the ledger against clearing.
+    pub(crate) fn advance(&mut self, date: u32, accounts: &mut HashMap<u64, Account>, ledger: &mut Ledger) -> SettlementReport {
+        self.trade_date = self.trade_date.max(date);
+        let later = self.obligations.split_off(&(date.saturating_add(1), 0, String::new()));
+        let mut due: Vec<Obligation> = std::mem::replace(&mut self.obligations, later).into_values().collect();
+        due.sort_by_key(|obligation| (obligation.settlement_date, obligation.account_id, obligation.cash < 0, obligation.isin.clone()));
+
+        let mut report = SettlementReport::empty(date);
+        for obligation in due {
+            let Some(account) = accounts.get_mut(&obligation.account_id) else { continue; };
+            let cash = account.cash as i128 + obligation.cash as i128;
+            if cash < 0 && obligation.cash < 0 {
+                let shortfall = (-cash).min(i64::MAX as i128) as i64;
+                report.failures.push(SettlementFailure { obligation: obligation.clone(), shortfall, policy: self.policy });
+                match self.policy {
+                    FailPolicy::FailLeg => continue,
+                    FailPolicy::ForceBorrow => {
+                        account.borrowed = account.borrowed.saturating_add(shortfall);
+                        account.cash = 0;
+                        ledger.record(LedgerAccount::Account(account.id), LedgerAccount::External, Asset::Cash, shortfall, EntryCause::Borrowing { date });
+                    },
+                    FailPolicy::GoNegative => account.cash = cash.max(i64::MIN as i128) as i64,
+                }
+            } else {
+                account.cash = cash.min(i64::MAX as i128) as i64;
+            }
+            if obligation.quantity != 0 {
+                let position = account.positions.entry(obligation.isin.clone()).or_insert(0);
+                *position = position.saturating_add(obligation.quantity);
+            }
+            let cause = EntryCause::Settlement { date };
+            ledger.record(LedgerAccount::Account(account.id), LedgerAccount::Clearing, Asset::Cash, obligation.cash, cause.clone());
+            ledger.record(LedgerAccount::Account(account.id), LedgerAccount::Clearing, Asset::Security(obligation.isin.clone()), obligation.quantity, cause);
+            report.settled.push(obligation);
+        }
+        report
+    }
+}
@@ -0,0 +1,871 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::Receiver;
+
+use crate::accounting::corporate_action::Entitlement;
+use crate::accounting::error::AccountingError;
+use crate::accounting::registry::{AccountRegistry, AccountingListener};
+use crate::accounting::risk::RiskCheck;
+use crate::accounting::settlement::SettlementReport;
+use crate::matching::auction::AuctionResult;
+use crate::matching::corporate_action::{AdjustedOrders, CorporateAction, CorporateActionKind, OpenOrderPolicy};
+use crate::matching::csv_export::{self, PriceFormat};
+use crate::matching::error::OrderbookError;
+use crate::matching::events::OrderbookEvent;
+use crate::matching::fees::FeeSchedule;
+use crate::matching::listener::CancelReason;
+use crate::matching::market_data::DepthSnapshot;
+use crate::matching::order_id::SharedOrderIdSequence;
+use crate::matching::orderbook::{CancelFilter, Execution, HaltReason, Order, OrderReport, Orderbook, PriceBands, ReportFlags, Security, SelfTradePolicy};
+use crate::matching::units::OrderId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExchangeError {
+    AlreadyListed(String),
+    UnknownSecurity(String),
+    Orderbook(OrderbookError),
+    // settlement needs the accounts, see Exchange::set_accounts
+    NoAccounts,
+    Accounting(AccountingError),
+    // a dividend that is not positive, or a split that does not change anything
+    InvalidCorporateAction,
+    UnknownSession(SessionToken),
+    // the kill switch of the account was pulled and it was not reinstated yet
+    AccountBlocked(u64),
+    // the account placed no order with the client order id, or the exchange forgot it already
+    UnknownClientOrder { account_id: u64, client_order_id: u64 },
+    // the account placed an order with the client order id on another security already
+    ClientOrderIdInUse { account_id: u64, client_order_id: u64, isin: String },
+}
+
+impl fmt::Display for ExchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExchangeError::AlreadyListed(isin) => write!(f, "Security {} is already listed", isin),
+            ExchangeError::UnknownSecurity(isin) => write!(f, "Security {} is not listed", isin),
+            ExchangeError::Orderbook(error) => write!(f, "{}", error),
+            ExchangeError::NoAccounts => write!(f, "No accounts attached to the exchange"),
+            ExchangeError::Accounting(error) => write!(f, "{}", error),
+            ExchangeError::InvalidCorporateAction => write!(f, "Invalid corporate action"),
+            ExchangeError::UnknownSession(token) => write!(f, "Session {} does not exist or has expired", token.id()),
+            ExchangeError::AccountBlocked(account_id) => write!(f, "Account {} is blocked by its kill switch", account_id),
+            ExchangeError::UnknownClientOrder { account_id, client_order_id } => write!(f, "Account {} has no order with client order id {}", account_id, client_order_id),
+            ExchangeError::ClientOrderIdInUse { account_id, client_order_id, isin } => {
+                write!(f, "Account {} used client order id {} for an order on {} already", account_id, client_order_id, isin)
+            },
+        }
+    }
+}
+
+impl std::error::Error for ExchangeError {}
+
+impl From<OrderbookError> for ExchangeError {
+    fn from(error: OrderbookError) -> Self {
+        ExchangeError::Orderbook(error)
+    }
+}
+
+impl From<AccountingError> for ExchangeError {
+    fn from(error: AccountingError) -> Self {
+        ExchangeError::Accounting(error)
+    }
+}
+
+// What applying a corporate action did to the book and the accounts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CorporateActionReport {
+    pub(crate) action: CorporateAction,
+    pub(crate) orders: AdjustedOrders,
+    pub(crate) entitlements: Vec<Entitlement>,
+}
+
+impl CorporateActionReport {
+    pub fn action(&self) -> &CorporateAction {
+        &self.action
+    }
+
+    pub fn orders(&self) -> &AdjustedOrders {
+        &self.orders
+    }
+
+    // empty without accounts
+    pub fn entitlements(&self) -> &[Entitlement] {
+        &self.entitlements
+    }
+}
+
+// Identifies a session registered with Exchange::register_session.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionToken(u64);
+
+impl SessionToken {
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Session {
+    account_id: u64,
+    last_heartbeat: u64,
+}
+
+// An order placed with a client order id, with the report it got back then.
+#[derive(Clone, Debug)]
+struct ClientOrder {
+    isin: String,
+    report: OrderReport,
+}
+
+// A session that timed out and the orders of its account that were cancelled because of it,
+// empty if another session of the account is still alive.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExpiredSession {
+    pub(crate) token: SessionToken,
+    pub(crate) account_id: u64,
+    pub(crate) cancelled: Vec<i64>,
+}
+
+impl ExpiredSession {
+    pub fn token(&self) -> SessionToken {
+        self.token
+    }
+
+    pub fn account_id(&self) -> u64 {
+        self.account_id
+    }
+
+    pub fn cancelled(&self) -> &[i64] {
+        &self.cancelled
+    }
+}
+
+// What one account may send to the exchange. The default throttles nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Throttle {
+    rate_limit: Option<(u32, u64)>,
+    max_open_orders: Option<usize>,
+    exempt_cancels: bool,
+}
+
+impl Throttle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // At most `messages` orders and cancels per `interval`, in the units of the book times, see
+    // Orderbook::set_time. The allowance of an account starts full at `messages` and refills
+    // continuously, so a quiet account may send a burst of `messages` at once.
+    pub fn with_rate_limit(mut self, messages: u32, interval: u64) -> Throttle {
+        self.rate_limit = Some((messages.max(1), interval.max(1)));
+        self
+    }
+
+    pub fn rate_limit(&self) -> Option<(u32, u64)> {
+        self.rate_limit
+    }
+
+    // At most `limit` open orders of the account per book.
+    pub fn with_max_open_orders(mut self, limit: usize) -> Throttle {
+        self.max_open_orders = Some(limit);
+        self
+    }
+
+    pub fn max_open_orders(&self) -> Option<usize> {
+        self.max_open_orders
+    }
+
+    // Cancels neither count against the rate limit nor are refused by it, so an account that
+    // runs out of messages can still take its orders off the book.
+    pub fn with_exempt_cancels(mut self) -> Throttle {
+        self.exempt_cancels = true;
+        self
+    }
+
+    pub fn exempt_cancels(&self) -> bool {
+        self.exempt_cancels
+    }
+}
+
+// The messages an account has left under its rate limit. The credit is counted in messages *
+// interval, so refills stay exact: a message takes `interval`, every unit of time adds `messages`.
+#[derive(Clone, Copy, Debug)]
+struct RateBucket {
+    credit: u128,
+    updated: u64,
+}
+
+impl RateBucket {
+    fn full((messages, interval): (u32, u64), now: u64) -> Self {
+        RateBucket { credit: messages as u128 * interval as u128, updated: now }
+    }
+
+    // the time until the next message is allowed if there is none left
+    fn take(&mut self, (messages, interval): (u32, u64), now: u64) -> Result<(), u64> {
+        let refill = now.saturating_sub(self.updated) as u128 * messages as u128;
+        self.credit = (self.credit + refill).min(messages as u128 * interval as u128);
+        self.updated = self.updated.max(now);
+        if self.credit < interval as u128 { return Err((interval as u128 - self.credit).div_ceil(messages as u128) as u64); }
+        self.credit -= interval as u128;
+        Ok(())
+    }
+}
+
+// The books of all listed securities. Every book draws its order ids from one sequence of the
+// exchange, so an order id identifies an order across all books.
+pub struct Exchange {
+    books: BTreeMap<String, Orderbook>,
+    order_ids: SharedOrderIdSequence,
+    fees: FeeSchedule,
+    risk: Option<Box<dyn RiskCheck + Send>>,
+    // the events of every book, for the risk check
+    risk_events: HashMap<String, Receiver<OrderbookEvent>>,
+    accounts: Option<Arc<Mutex<AccountRegistry>>>,
+    open_order_policy: OpenOrderPolicy,
+    next_action_id: u64,
+    sessions: BTreeMap<SessionToken, Session>,
+    next_session_id: u64,
+    session_timeout: u64,
+    blocked_accounts: BTreeSet<u64>,
+    self_trade_policy: SelfTradePolicy,
+    account_self_trade_policies: BTreeMap<u64, SelfTradePolicy>,
+    throttle: Throttle,
+    account_throttles: BTreeMap<u64, Throttle>,
+    rate_buckets: HashMap<u64, RateBucket>,
+    max_order_quantity: Option<i64>,
+    max_order_notional: Option<i64>,
+    // by account and client order id, with the keys in the order they were placed in
+    client_orders: HashMap<(u64, u64), ClientOrder>,
+    client_order_keys: VecDeque<(u64, u64)>,
+    client_order_retention: Option<usize>,
+}
+
+impl Exchange {
+    pub fn new() -> Self {
+        Exchange { books: BTreeMap::new(), order_ids: SharedOrderIdSequence::new(), fees: FeeSchedule::default(), risk: None, risk_events: HashMap::new(), accounts: None, open_order_policy: OpenOrderPolicy::default(), next_action_id: 1,
+            sessions: BTreeMap::new(), next_session_id: 1, session_timeout: u64::MAX, blocked_accounts: BTreeSet::new(),
+            self_trade_policy: SelfTradePolicy::default(), account_self_trade_policies: BTreeMap::new(),
+            throttle: Throttle::default(), account_throttles: BTreeMap::new(), rate_buckets: HashMap::new(),
+            max_order_quantity: None, max_order_notional: None,
+            client_orders: HashMap::new(), client_order_keys: VecDeque::new(), client_order_retention: None }
+    }
+
+    // Every order placed through the exchange passes the check before it reaches its book, a
+    // refused order is rejected by the book with OrderbookError::RiskRejected. The check follows
+    // fills and cancellations through the events of the books, including those of calls made on
+    // a book directly.
+    pub fn set_risk_check(&mut self, risk: Box<dyn RiskCheck + Send>) {
+        self.risk = Some(risk);
+        self.risk_events = self.books.iter_mut().map(|(isin, book)| (isin.clone(), book.subscribe_unbounded())).collect();
+    }
+
+    // Books the trades of every listed book and of the books listed from now on in the registry,
+    // through an AccountingListener that runs next to the listeners of the books. Whether trades
+    // settle right away or on a later day is up to the registry, see AccountRegistry::with_settlement.
+    pub fn set_accounts(&mut self, accounts: Arc<Mutex<AccountRegistry>>) {
+        for (isin, book) in &mut self.books { book.set_accounting_listener(Box::new(AccountingListener::new(accounts.clone(), isin))); }
+        self.accounts = Some(accounts);
+    }
+
+    pub fn accounts(&self) -> Option<&Arc<Mutex<AccountRegistry>>> {
+        self.accounts.as_ref()
+    }
+
+    // Settles the obligations of the accounts that are due on or before `date` and starts the
+    // next trade date, the end of day step of deferred settlement.
+    pub fn advance_settlement(&mut self, date: u32) -> Result<SettlementReport, ExchangeError> {
+        let accounts = self.accounts.as_ref().ok_or(ExchangeError::NoAccounts)?;
+        let mut registry = accounts.lock().map_err(|_| ExchangeError::NoAccounts)?;
+        Ok(registry.advance_settlement(date))
+    }
+
+    // What corporate actions do to open orders, Reprice by default.
+    pub fn set_open_order_policy(&mut self, policy: OpenOrderPolicy) {
+        self.open_order_policy = policy;
+    }
+
+    // Takes the security ex dividend: the open orders of the book are adjusted by the open order
+    // policy, then the accounts are paid the dividend for their positions, see
+    // AccountRegistry::apply_corporate_action. Subscribers of the book see the action as an event
+    // before the changes to the book.
+    pub fn apply_dividend(&mut self, isin: &str, amount_per_share: i64, ex_timestamp: u64) -> Result<CorporateActionReport, ExchangeError> {
+        if !self.books.contains_key(isin) { return Err(ExchangeError::UnknownSecurity(isin.to_string())); }
+        if amount_per_share <= 0 { return Err(ExchangeError::InvalidCorporateAction); }
+        let action = CorporateAction::new(self.next_action_id, isin, CorporateActionKind::CashDividend { amount_per_share }, ex_timestamp);
+        self.apply_corporate_action(action)
+    }
+
+    // Splits every share of the security into numerator / denominator shares: positions of the
+    // accounts and the orders and prices of the book are rescaled, see
+    // AccountRegistry::apply_corporate_action and Orderbook::apply_corporate_action for the
+    // rounding. The open order policy only decides whether orders are cancelled instead. The ex
+    // timestamp is the current time of the book.
+    pub fn apply_split(&mut self, isin: &str, numerator: i64, denominator: i64) -> Result<CorporateActionReport, ExchangeError> {
+        let book = self.books.get(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        if numerator <= 0 || denominator <= 0 || numerator == denominator { return Err(ExchangeError::InvalidCorporateAction); }
+        let action = CorporateAction::new(self.next_action_id, isin, CorporateActionKind::Split { numerator, denominator }, book.current_time());
+        self.apply_corporate_action(action)
+    }
+
+    // The accounts are checked first and booked after the book, at the price of the book before
+    // the action, so an action the journal of the book refuses changes nothing.
+    fn apply_corporate_action(&mut self, action: CorporateAction) -> Result<CorporateActionReport, ExchangeError> {
+        let price = self.book(action.isin()).map(|book| book.last_price());
+        let prices = |_: &str| price;
+        if let Some(accounts) = &self.accounts { accounts.lock().map_err(|_| ExchangeError::NoAccounts)?.check_corporate_action(&action, &prices)?; }
+
+        let book = self.books.get_mut(action.isin()).ok_or_else(|| ExchangeError::UnknownSecurity(action.isin().to_string()))?;
+        let orders = book.apply_corporate_action(&action, self.open_order_policy)?;
+        self.next_action_id += 1;
+        let entitlements = match &self.accounts {
+            Some(accounts) => accounts.lock().map_err(|_| ExchangeError::NoAccounts)?.apply_corporate_action(&action, &prices)?,
+            None => Vec::new(),
+        };
+        let book = &self.books[action.isin()];
+        if let (Some(risk), Some(events)) = (&mut self.risk, self.risk_events.get(action.isin())) {
+            for event in events.try_iter() { risk.on_event(&event, book); }
+        }
+        Ok(CorporateActionReport { action, orders, entitlements })
+    }
+
+    // Sets the fees of every listed book and of the books listed from now on. Without a schedule
+    // trading is free.
+    pub fn set_fee_schedule(&mut self, fees: FeeSchedule) {
+        for (isin, book) in &mut self.books { book.set_fees(fees.rates_for(isin)); }
+        self.fees = fees;
+    }
+
+    // The self trade policy of every listed book and of the books listed from now on. A book that
+    // cannot journal the policy keeps its own and stops the change there, see Orderbook::set_journal.
+    pub fn set_self_trade_policy(&mut self, policy: SelfTradePolicy) -> Result<(), ExchangeError> {
+        for book in self.books.values_mut() { book.set_self_trade_policy(policy)?; }
+        self.self_trade_policy = policy;
+        Ok(())
+    }
+
+    pub fn set_account_self_trade_policy(&mut self, account_id: u64, policy: SelfTradePolicy) -> Result<(), ExchangeError> {
+        for book in self.books.values_mut() { book.set_account_self_trade_policy(account_id, policy)?; }
+        self.account_self_trade_policies.insert(account_id, policy);
+        Ok(())
+    }
+
+    // The throttle of every account without one of its own. Orders refused by a throttle are
+    // rejected by their book with OrderbookError::RateLimited or OrderbookError::TooManyOpenOrders,
+    // which its metrics count like every reject. Orders and cancels without an account are not
+    // throttled.
+    pub fn set_throttle(&mut self, throttle: Throttle) {
+        self.throttle = throttle;
+    }
+
+    pub fn set_account_throttle(&mut self, account_id: u64, throttle: Throttle) {
+        self.account_throttles.insert(account_id, throttle);
+    }
+
+    pub fn throttle(&self, account_id: u64) -> Throttle {
+        self.account_throttles.get(&account_id).copied().unwrap_or(self.throttle)
+    }
+
+    // Every order and cancel of an account takes a message from its rate limit, also those that
+    // are rejected afterwards.
+    fn throttle_message(&mut self, isin: &str, account_id: u64, cancel: bool) -> Result<(), ExchangeError> {
+        let throttle = self.throttle(account_id);
+        let book = self.books.get_mut(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        if let Some(limit) = throttle.rate_limit.filter(|_| !(cancel && throttle.exempt_cancels)) {
+            let now = book.current_time();
+            let bucket = self.rate_buckets.entry(account_id).or_insert_with(|| RateBucket::full(limit, now));
+            if let Err(retry_after) = bucket.take(limit, now) { return Err(book.reject(OrderbookError::RateLimited { retry_after }).into()); }
+        }
+        if let Some(limit) = throttle.max_open_orders.filter(|_| !cancel) {
+            if book.open_order_count(account_id) >= limit { return Err(book.reject(OrderbookError::TooManyOpenOrders { limit }).into()); }
+        }
+        Ok(())
+    }
+
+    // The maximum order quantity and notional of the securities listed from now on that have none
+    // of their own, see Security::with_max_order_quantity and Security::with_max_order_notional.
+    pub fn set_max_order_size(&mut self, max_quantity: Option<i64>, max_notional: Option<i64>) {
+        self.max_order_quantity = max_quantity;
+        self.max_order_notional = max_notional;
+    }
+
+    pub fn fee_schedule(&self) -> &FeeSchedule {
+        &self.fees
+    }
+
+    pub fn list_security(&mut self, security: Security, starting_price: i64) -> Result<&mut Orderbook, ExchangeError> {
+        if self.books.contains_key(&security.isin) { return Err(ExchangeError::AlreadyListed(security.isin)); }
+
+        let isin = security.isin.clone();
+        let security = security.with_default_order_limits(self.max_order_quantity, self.max_order_notional);
+        let mut book = Orderbook::with_order_ids(Arc::new(security), starting_price, Box::new(self.order_ids.clone()));
+        book.set_fees(self.fees.rates_for(&isin));
+        book.set_self_trade_policy(self.self_trade_policy)?;
+        for (&account_id, &policy) in &self.account_self_trade_policies { book.set_account_self_trade_policy(account_id, policy)?; }
+        if self.risk.is_some() { self.risk_events.insert(isin.clone(), book.subscribe_unbounded()); }
+        if let Some(accounts) = &self.accounts { book.set_accounting_listener(Box::new(AccountingListener::new(accounts.clone(), &isin))); }
+        Ok(self.books.entry(isin).or_insert(book))
+    }
+
+    // Cancels all open orders of the security, which publishes their cancellations to the
+    // listener and the event subscribers of the book, and hands back the book. A book whose
+    // journal refuses the delisting stays listed with all its orders.
+    pub fn delist(&mut self, isin: &str) -> Result<Orderbook, ExchangeError> {
+        let mut book = self.books.remove(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        if let Err(error) = book.delist() {
+            self.books.insert(isin.to_string(), book);
+            return Err(error.into());
+        }
+        if let (Some(risk), Some(events)) = (&mut self.risk, self.risk_events.remove(isin)) {
+            for event in events.try_iter() { risk.on_event(&event, &book); }
+        }
+        Ok(book)
+    }
+
+    // Starts a session for the account at `now`, the time of its first heartbeat. An account may
+    // hold several sessions.
+    pub fn register_session(&mut self, account_id: u64, now: u64) -> SessionToken {
+        let token = SessionToken(self.next_session_id);
+        self.next_session_id += 1;
+        self.sessions.insert(token, Session { account_id, last_heartbeat: now });
+        token
+    }
+
+    pub fn heartbeat(&mut self, token: SessionToken, now: u64) -> Result<(), ExchangeError> {
+        let session = self.sessions.get_mut(&token).ok_or(ExchangeError::UnknownSession(token))?;
+        session.last_heartbeat = session.last_heartbeat.max(now);
+        Ok(())
+    }
+
+    // How long a session may go without a heartbeat, in the units of the timestamps the caller
+    // gives. Sessions never time out until it is set.
+    pub fn set_session_timeout(&mut self, timeout: u64) {
+        self.session_timeout = timeout;
+    }
+
+    // Ends every session whose last heartbeat is more than the timeout before `now`, in token
+    // order. Once an account has no session left, all its open orders in every book are cancelled
+    // with CancelReason::Disconnect. Expired tokens are unknown from then on.
+    pub fn expire_sessions(&mut self, now: u64) -> Result<Vec<ExpiredSession>, ExchangeError> {
+        let timeout = self.session_timeout;
+        let expired: Vec<(SessionToken, Session)> = self.sessions.iter()
+            .filter(|(_, session)| now.saturating_sub(session.last_heartbeat) > timeout).map(|(&token, &session)| (token, session)).collect();
+
+        let mut report = Vec::with_capacity(expired.len());
+        for (token, session) in expired {
+            self.sessions.remove(&token);
+            let alive = self.sessions.values().any(|other| other.account_id == session.account_id);
+            let cancelled = if alive { Vec::new() } else { self.cancel_account_orders(session.account_id, CancelReason::Disconnect)? };
+            report.push(ExpiredSession { token, account_id: session.account_id, cancelled });
+        }
+        Ok(report)
+    }
+
+    // Cancels every open order of the account in every book with CancelReason::KillSwitch and
+    // refuses its new orders until it is reinstated. Returns the cancelled ids by book in ISIN
+    // order, ascending within a book.
+    pub fn kill_switch(&mut self, account_id: u64) -> Result<Vec<i64>, ExchangeError> {
+        self.blocked_accounts.insert(account_id);
+        self.cancel_account_orders(account_id, CancelReason::KillSwitch)
+    }
+
+    // Lets the account place orders again, false if it was not blocked.
+    pub fn reinstate(&mut self, account_id: u64) -> bool {
+        self.blocked_accounts.remove(&account_id)
+    }
+
+    pub fn is_blocked(&self, account_id: u64) -> bool {
+        self.blocked_accounts.contains(&account_id)
+    }
+
+    // Every book is cleared of the orders of the account even if the journal of one fails, the
+    // first error is returned.
+    fn cancel_account_orders(&mut self, account_id: u64, reason: CancelReason) -> Result<Vec<i64>, ExchangeError> {
+        let (mut cancelled, mut error) = (Vec::new(), None);
+        for book in self.books.values_mut() {
+            match book.cancel_all_with_reason(CancelFilter::ByAccount(account_id), reason) {
+                Ok(order_ids) => cancelled.extend(order_ids),
+                Err(failure) => { error.get_or_insert(failure); },
+            }
+        }
+        match error {
+            Some(error) => Err(error.into()),
+            None => Ok(cancelled),
+        }
+    }
+
+    // An order with an account and a client order id the account already placed an order with is
+    // not placed again, the report of the first one is returned instead, as it was back then. That
+    // makes it safe to send an order again after the answer to it was lost. Client order ids are
+    // unique per account across all securities, an id used on another security is refused with
+    // ClientOrderIdInUse. Orders that were rejected are not remembered, sending them again places
+    // them.
+    pub fn place_order(&mut self, isin: &str, order: Order) -> Result<OrderReport, ExchangeError> {
+        let client_key = order.account_id().zip(order.client_order_id());
+        if let Some(((account_id, client_order_id), placed)) = client_key.and_then(|key| self.client_orders.get(&key).map(|placed| (key, placed))) {
+            if placed.isin != isin { return Err(ExchangeError::ClientOrderIdInUse { account_id, client_order_id, isin: placed.isin.clone() }); }
+            return Ok(placed.report.clone());
+        }
+        let report = self.place_new_order(isin, order)?;
+        if let Some(key) = client_key { self.remember_client_order(key, isin, &report); }
+        Ok(report)
+    }
+
+    fn place_new_order(&mut self, isin: &str, order: Order) -> Result<OrderReport, ExchangeError> {
+        if let Some(account_id) = order.account_id().filter(|account_id| self.blocked_accounts.contains(account_id)) {
+            return Err(ExchangeError::AccountBlocked(account_id));
+        }
+        if let Some(account_id) = order.account_id() { self.throttle_message(isin, account_id, false)?; }
+        let book = self.books.get_mut(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        let Some(risk) = &mut self.risk else { return Ok(book.place_order(order)?); };
+        let events = self.risk_events.get(isin);
+        let drain = |risk: &mut Box<dyn RiskCheck + Send>, book: &Orderbook| {
+            for event in events.into_iter().flat_map(|events| events.try_iter()) { risk.on_event(&event, book); }
+        };
+
+        drain(risk, book);
+        let reference_price = book.last_price();
+        if let Err(rejection) = risk.check(&order, reference_price) { return Err(book.reject(OrderbookError::RiskRejected(rejection)).into()); }
+
+        let checked = order.clone();
+        let report = book.place_order(order)?;
+        // the fills of the new order itself are already part of what is still open
+        drain(risk, book);
+        let open_quantity = book.order(report.order_id()).map_or(0, |order| order.remaining());
+        risk.reserve(&checked, report.order_id().to_raw(), open_quantity, reference_price);
+        Ok(report)
+    }
+
+    // Places the orders in the books of their securities, in the order given, with the same checks
+    // as place_order. Every book the batch reached publishes its book update once at the end, see
+    // Orderbook::place_orders.
+    pub fn place_orders(&mut self, batch: Vec<Order>) -> Vec<Result<OrderReport, ExchangeError>> {
+        let mut reports = Vec::with_capacity(batch.len());
+        let mut reached = BTreeSet::new();
+        for order in batch {
+            let isin = order.security().isin.clone();
+            if let Some(book) = self.books.get_mut(&isin) {
+                book.begin_batch();
+                reached.insert(isin.clone());
+            }
+            reports.push(self.place_order(&isin, order));
+        }
+        for isin in reached {
+            if let Some(book) = self.books.get_mut(&isin) { book.end_batch(); }
+        }
+        reports
+    }
+
+    fn remember_client_order(&mut self, key: (u64, u64), isin: &str, report: &OrderReport) {
+        self.client_orders.insert(key, ClientOrder { isin: isin.to_string(), report: report.clone() });
+        self.client_order_keys.push_back(key);
+        self.trim_client_orders();
+    }
+
+    fn trim_client_orders(&mut self) {
+        let Some(retention) = self.client_order_retention else { return; };
+        while self.client_order_keys.len() > retention {
+            if let Some(oldest) = self.client_order_keys.pop_front() { self.client_orders.remove(&oldest); }
+        }
+    }
+
+    // How many client order ids the exchange remembers at most, the oldest are forgotten first.
+    // All of them are kept until forget_client_order_ids by default.
+    pub fn set_client_order_retention(&mut self, retention: Option<usize>) {
+        self.client_order_retention = retention;
+        self.trim_client_orders();
+    }
+
+    // Forgets every client order id, for the start of the next session. Ids may be used again
+    // afterwards.
+    pub fn forget_client_order_ids(&mut self) {
+        self.client_orders.clear();
+        self.client_order_keys.clear();
+    }
+
+    // Cancels the order the account placed with the client order id, see cancel_order.
+    pub fn cancel_by_client_id(&mut self, account_id: u64, client_order_id: u64) -> Result<(), ExchangeError> {
+        let Some(placed) = self.client_orders.get(&(account_id, client_order_id)) else { return Err(ExchangeError::UnknownClientOrder { account_id, client_order_id }); };
+        let (isin, order_id) = (placed.isin.clone(), placed.report.order_id());
+        self.cancel_order(&isin, order_id, Some(account_id))
+    }
+
+    pub fn cancel_order(&mut self, isin: &str, order_id: OrderId, account_id: Option<u64>) -> Result<(), ExchangeError> {
+        if let Some(account_id) = account_id { self.throttle_message(isin, account_id, true)?; }
+        Ok(self.book_for(isin)?.cancel_order(order_id, account_id)?)
+    }
+
+    // Cancels an order without knowing its security, ids are unique across all books.
+    pub fn cancel(&mut self, order_id: OrderId, account_id: Option<u64>) -> Result<(), ExchangeError> {
+        match self.books.iter().find(|(_, book)| book.order(order_id).is_some()).map(|(isin, _)| isin.clone()) {
+            Some(isin) => self.cancel_order(&isin, order_id, account_id),
+            None => Err(ExchangeError::Orderbook(OrderbookError::UnknownOrder(order_id.to_raw()))),
+        }
+    }
+
+    // Cancels an order of any account, for the operator.
+    pub fn force_cancel(&mut self, order_id: OrderId) -> Result<(), ExchangeError> {
+        match self.books.values_mut().find(|book| book.order(order_id).is_some()) {
+            Some(book) => Ok(book.force_cancel(order_id)?),
+            None => Err(ExchangeError::Orderbook(OrderbookError::UnknownOrder(order_id.to_raw()))),
+        }
+    }
+
+    // Busts a trade of the security, see Orderbook::bust_trade, and books its reversal in the
+    // accounts, see AccountRegistry::bust. Trade ids are only unique within a book. A trade whose
+    // reversal the registry cannot book is not busted.
+    pub fn bust_trade(&mut self, isin: &str, trade_id: u64) -> Result<Execution, ExchangeError> {
+        let book = self.books.get_mut(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        // the lock is not held while the book runs its listeners, the accounting one takes it
+        if let Some(accounts) = &self.accounts {
+            let registry = accounts.lock().map_err(|_| ExchangeError::NoAccounts)?;
+            registry.check_bust(isin, book.bustable_trade(trade_id)?)?;
+        }
+        let execution = book.bust_trade(trade_id)?;
+        if let Some(accounts) = &self.accounts {
+            accounts.lock().map_err(|_| ExchangeError::NoAccounts)?.bust(isin, &execution)?;
+        }
+        if let (Some(risk), Some(events)) = (&mut self.risk, self.risk_events.get(isin)) {
+            for event in events.try_iter() { risk.on_event(&event, book); }
+        }
+        Ok(execution)
+    }
+
+    // Reports a trade between two accounts agreed outside the book, see Orderbook::report_trade.
+    // With accounts attached both have to exist, the trade is booked by the accounting listener
+    // of the book like a trade of the book.
+    pub fn report_trade(&mut self, isin: &str, buyer_account: u64, seller_account: u64, price: i64, quantity: i64, flags: ReportFlags) -> Result<Execution, ExchangeError> {
+        if let Some(accounts) = &self.accounts {
+            let registry = accounts.lock().map_err(|_| ExchangeError::NoAccounts)?;
+            for account_id in [buyer_account, seller_account] {
+                if registry.account(account_id).is_none() { return Err(ExchangeError::Accounting(AccountingError::UnknownAccount(account_id))); }
+            }
+        }
+        let book = self.books.get_mut(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))?;
+        let execution = book.report_trade(buyer_account, seller_account, price, quantity, flags)?;
+        if let (Some(risk), Some(events)) = (&mut self.risk, self.risk_events.get(isin)) {
+            for event in events.try_iter() { risk.on_event(&event, book); }
+        }
+        Ok(execution)
+    }
+
+    // Runs the next batch of the security, see Orderbook::run_batch.
+    pub fn run_batch(&mut self, isin: &str) -> Result<Option<AuctionResult>, ExchangeError> {
+        Ok(self.book_for(isin)?.run_batch()?)
+    }
+
+    // Halts trading in the security, see Orderbook::halt.
+    pub fn halt(&mut self, isin: &str, reason: HaltReason) -> Result<(), ExchangeError> {
+        Ok(self.book_for(isin)?.halt(reason)?)
+    }
+
+    pub fn resume(&mut self, isin: &str, through_auction: bool) -> Result<(), ExchangeError> {
+        Ok(self.book_for(isin)?.resume(through_auction)?)
+    }
+
+    // The price bands of the security, see Orderbook::set_price_bands.
+    pub fn set_price_bands(&mut self, isin: &str, bands: PriceBands) -> Result<(), ExchangeError> {
+        Ok(self.book_for(isin)?.set_price_bands(bands)?)
+    }
+
+    // Starts the closing auction of the security, see Orderbook::start_closing_auction.
+    pub fn start_closing_auction(&mut self, isin: &str) -> Result<(), ExchangeError> {
+        Ok(self.book_for(isin)?.start_closing_auction()?)
+    }
+
+    // Ends the call phase of the security with its auction, see Orderbook::uncross.
+    pub fn uncross(&mut self, isin: &str) -> Result<Option<AuctionResult>, ExchangeError> {
+        Ok(self.book_for(isin)?.uncross()?)
+    }
+
+    pub fn depth(&self, isin: &str, levels: usize) -> Result<DepthSnapshot, ExchangeError> {
+        self.book(isin).map(|book| book.depth(levels)).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))
+    }
+
+    // The security an open order belongs to.
+    pub fn security_of(&self, order_id: OrderId) -> Option<&Arc<Security>> {
+        self.books.values().find(|book| book.order(order_id).is_some()).map(|book| book.security())
+    }
+
+    pub fn book(&self, isin: &str) -> Option<&Orderbook> {
+        self.books.get(isin)
+    }
+
+    pub fn book_mut(&mut self, isin: &str) -> Option<&mut Orderbook> {
+        self.books.get_mut(isin)
+    }
+
+    fn book_for(&mut self, isin: &str) -> Result<&mut Orderbook, ExchangeError> {
+        self.books.get_mut(isin).ok_or_else(|| ExchangeError::UnknownSecurity(isin.to_string()))
+    }
+
+    // The listed securities in ISIN order.
+    pub fn securities(&self) -> impl Iterator<Item = &Arc<Security>> {
+        self.books.values().map(|book| book.security())
+    }
+
+    pub fn books(&self) -> impl Iterator<Item = &Orderbook> {
+        self.books.values()
+    }
+
+    pub fn books_mut(&mut self) -> impl Iterator<Item = &mut Orderbook> {
+        self.books.values_mut()
+    }
+
+    pub fn is_listed(&self, isin: &str) -> bool {
+        self.books.contains_key(isin)
+    }
+
+    pub fn len(&self) -> usize {
+        self.books.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
+    // The end of day report of csv_export over all listed securities, in ISIN order. With
+    // PriceFormat::OfSecurity every row has the decimals of its own security.
+    pub fn write_end_of_day_report<W: Write>(&self, w: W, prices: PriceFormat) -> io::Result<()> {
+        csv_export::write_end_of_day_report(w, self.books.values(), prices)
+    }
+}
+
+impl Default for Exchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounting::account::Account;
+    use crate::matching::listener::{ListenerEvent, VecListener};
+    use crate::matching::orderbook::{OrderBuilder, Side};
+    use crate::matching::units::{Price, Qty};
+
+    const ISIN: &str = "XS0000000001";
+
+    fn exchange() -> (Exchange, Arc<Security>) {
+        let mut exchange = Exchange::new();
+        let security = exchange.list_security(Security::new(ISIN, "TEST"), 100).unwrap().security().clone();
+        (exchange, security)
+    }
+
+    fn accounts() -> Arc<Mutex<AccountRegistry>> {
+        let mut registry = AccountRegistry::new();
+        registry.open_account(Account::new(1, 100_000)).unwrap();
+        registry.open_account(Account::new(2, 100_000).with_position(ISIN, 1_000)).unwrap();
+        Arc::new(Mutex::new(registry))
+    }
+
+    fn order(security: &Arc<Security>, side: Side, account_id: u64, price: i64, quantity: i64) -> Order {
+        OrderBuilder::new(side, security).limit(Price(price)).quantity(Qty(quantity)).account(account_id).build().unwrap()
+    }
+
+    fn executions(listener: &Arc<Mutex<VecListener>>) -> usize {
+        listener.lock().unwrap().events().iter().filter(|event| matches!(event, ListenerEvent::Execution(_))).count()
+    }
+
+    #[test]
+    fn accounting_runs_next_to_the_listener_of_the_book() {
+        let (mut exchange, security) = exchange();
+        let before = Arc::new(Mutex::new(VecListener::new()));
+        exchange.book_mut(ISIN).unwrap().set_listener(Box::new(before.clone()));
+        let accounts = accounts();
+        exchange.set_accounts(accounts.clone());
+        exchange.place_order(ISIN, order(&security, Side::Sell, 2, 100, 10)).unwrap();
+        exchange.place_order(ISIN, order(&security, Side::Buy, 1, 100, 4)).unwrap();
+
+        // a listener set after the accounts does not take their place either
+        let after = Arc::new(Mutex::new(VecListener::new()));
+        exchange.book_mut(ISIN).unwrap().set_listener(Box::new(after.clone()));
+        exchange.place_order(ISIN, order(&security, Side::Buy, 1, 100, 6)).unwrap();
+
+        assert_eq!((executions(&before), executions(&after)), (1, 1));
+        let registry = accounts.lock().unwrap();
+        assert_eq!((registry.position(1, ISIN), registry.position(2, ISIN)), (Ok(10), Ok(990)));
+    }
+
+    fn client_order(security: &Arc<Security>, client_order_id: u64, price: i64) -> Order {
+        OrderBuilder::new(Side::Buy, security).limit(Price(price)).quantity(Qty(10)).account(1).client_order_id(client_order_id).build().unwrap()
+    }
+
+    #[test]
+    fn an_order_sent_again_is_placed_once() {
+        let (mut exchange, security) = exchange();
+        let first = exchange.place_order(ISIN, client_order(&security, 7, 99)).unwrap();
+        let again = exchange.place_order(ISIN, client_order(&security, 7, 99)).unwrap();
+
+        assert_eq!(again.order_id(), first.order_id());
+        assert_eq!(exchange.book(ISIN).unwrap().open_order_count(1), 1);
+        let other = exchange.place_order(ISIN, client_order(&security, 8, 99)).unwrap();
+        assert_ne!(other.order_id(), first.order_id());
+    }
+
+    #[test]
+    fn a_client_order_id_belongs_to_one_security() {
+        let (mut exchange, security) = exchange();
+        let other = exchange.list_security(Security::new("XS0000000002", "OTHER"), 100).unwrap().security().clone();
+        exchange.place_order(ISIN, client_order(&security, 7, 99)).unwrap();
+
+        let refused = exchange.place_order("XS0000000002", client_order(&other, 7, 99)).unwrap_err();
+        assert_eq!(refused, ExchangeError::ClientOrderIdInUse { account_id: 1, client_order_id: 7, isin: ISIN.to_string() });
+        assert_eq!(exchange.book("XS0000000002").unwrap().open_order_count(1), 0);
+    }
+
+    #[test]
+    fn only_the_latest_client_order_ids_are_kept() {
+        let (mut exchange, security) = exchange();
+        exchange.set_client_order_retention(Some(2));
+        let first = exchange.place_order(ISIN, client_order(&security, 1, 97)).unwrap();
+        exchange.place_order(ISIN, client_order(&security, 2, 98)).unwrap();
+        exchange.place_order(ISIN, client_order(&security, 3, 99)).unwrap();
+
+        // the first id was forgotten, sending it again places a new order
+        assert_ne!(exchange.place_order(ISIN, client_order(&security, 1, 97)).unwrap().order_id(), first.order_id());
+        assert_eq!(exchange.book(ISIN).unwrap().open_order_count(1), 4);
+        assert_eq!(exchange.cancel_by_client_id(1, 2), Err(ExchangeError::UnknownClientOrder { account_id: 1, client_order_id: 2 }));
+
+        exchange.forget_client_order_ids();
+        assert_eq!(exchange.cancel_by_client_id(1, 3), Err(ExchangeError::UnknownClientOrder { account_id: 1, client_order_id: 3 }));
+    }
+
+    #[test]
+    fn an_order_is_cancelled_by_its_client_order_id() {
+        let (mut exchange, security) = exchange();
+        let placed = exchange.place_order(ISIN, client_order(&security, 7, 99)).unwrap();
+
+        assert_eq!(exchange.cancel_by_client_id(2, 7), Err(ExchangeError::UnknownClientOrder { account_id: 2, client_order_id: 7 }));
+        exchange.cancel_by_client_id(1, 7).unwrap();
+        assert!(exchange.book(ISIN).unwrap().order(placed.order_id()).is_none());
+        assert!(exchange.cancel_by_client_id(1, 7).is_err());
+    }
+
+    #[test]
+    fn a_trade_the_accounts_cannot_reverse_is_not_busted() {
+        let (mut exchange, security) = exchange();
+        exchange.place_order(ISIN, order(&security, Side::Sell, 2, 100, 10)).unwrap();
+        exchange.place_order(ISIN, order(&security, Side::Buy, 1, 100, 10)).unwrap();
+        let trade_id = exchange.book(ISIN).unwrap().executions()[0].trade_id();
+
+        // the trade happened before the accounts were attached, they never booked it
+        let accounts = accounts();
+        exchange.set_accounts(accounts.clone());
+        let refused = exchange.bust_trade(ISIN, trade_id).unwrap_err();
+        assert_eq!(refused, ExchangeError::Accounting(AccountingError::UnknownTrade { isin: ISIN.to_string(), trade_id }));
+        assert!(exchange.book(ISIN).unwrap().bustable_trade(trade_id).is_ok());
+
+        exchange.place_order(ISIN, order(&security, Side::Sell, 2, 100, 10)).unwrap();
+        exchange.place_order(ISIN, order(&security, Side::Buy, 1, 100, 10)).unwrap();
+        let booked = exchange.book(ISIN).unwrap().executions()[1].trade_id();
+        exchange.bust_trade(ISIN, booked).unwrap();
+        let registry = accounts.lock().unwrap();
+        assert_eq!((registry.position(1, ISIN), registry.position(2, ISIN)), (Ok(0), Ok(1_000)));
+        assert!(registry.errors.is_empty());
+    }
+}
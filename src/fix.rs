@@ -0,0 +1,393 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::matching::error::OrderbookError;
+use crate::matching::orderbook::{Execution, Order, OrderReport, OrderState, Orderbook, Security, Side, TimeInForce};
+use crate::matching::units::{OrderId, Price, Qty};
+
+// Field delimiter of the tag=value encoding.
+pub const SOH: u8 = 0x01;
+const BEGIN_STRING: &str = "FIX.4.4";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FixError {
+    // the message does not follow the tag=value layout, with the byte offset of the problem
+    Malformed(usize),
+    MissingTag(u32),
+    InvalidValue(u32),
+    BodyLength { declared: usize, actual: usize },
+    Checksum { declared: u8, actual: u8 },
+    UnsupportedMsgType(String),
+}
+
+impl fmt::Display for FixError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FixError::Malformed(offset) => write!(f, "Malformed FIX message at byte {}", offset),
+            FixError::MissingTag(tag) => write!(f, "Required tag {} is missing", tag),
+            FixError::InvalidValue(tag) => write!(f, "Tag {} has an invalid value", tag),
+            FixError::BodyLength { declared, actual } => write!(f, "BodyLength is {} but the body has {} bytes", declared, actual),
+            FixError::Checksum { declared, actual } => write!(f, "CheckSum is {:03} but the message sums to {:03}", declared, actual),
+            FixError::UnsupportedMsgType(msg_type) => write!(f, "MsgType {} is not supported", msg_type),
+        }
+    }
+}
+
+impl std::error::Error for FixError {}
+
+// The body of a FIX message: MsgType (35) and every field after it, in order. BeginString (8),
+// BodyLength (9) and CheckSum (10) are produced and checked by encode and parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FixMessage {
+    fields: Vec<(u32, String)>,
+}
+
+impl FixMessage {
+    pub fn new(msg_type: &str) -> FixMessage {
+        FixMessage { fields: vec![(35, msg_type.to_string())] }
+    }
+
+    pub fn with(mut self, tag: u32, value: impl ToString) -> FixMessage {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(35).unwrap_or("")
+    }
+
+    // the first occurrence of `tag`
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(field_tag, _)| *field_tag == tag).map(|(_, value)| value.as_str())
+    }
+
+    pub fn fields(&self) -> &[(u32, String)] {
+        &self.fields
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<FixMessage, FixError> {
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        // start offset of the field that is being read, used for body length and checksum
+        let mut field_starts = Vec::new();
+
+        while offset < bytes.len() {
+            let Some(end) = bytes[offset..].iter().position(|&byte| byte == SOH) else { return Err(FixError::Malformed(offset)); };
+            let field = std::str::from_utf8(&bytes[offset..offset + end]).map_err(|_| FixError::Malformed(offset))?;
+            let Some((tag, value)) = field.split_once('=') else { return Err(FixError::Malformed(offset)); };
+            let tag: u32 = tag.parse().map_err(|_| FixError::Malformed(offset))?;
+            field_starts.push(offset);
+            fields.push((tag, value.to_string()));
+            offset += end + 1;
+        }
+
+        if fields.len() < 4 { return Err(FixError::Malformed(bytes.len())); }
+        if fields[0] != (8, BEGIN_STRING.to_string()) { return Err(FixError::InvalidValue(8)); }
+        if fields[1].0 != 9 { return Err(FixError::MissingTag(9)); }
+        if fields[2].0 != 35 { return Err(FixError::MissingTag(35)); }
+        let Some(&(10, ref checksum)) = fields.last() else { return Err(FixError::MissingTag(10)); };
+
+        // the body runs from MsgType up to and including the delimiter in front of CheckSum
+        let checksum_start = field_starts[fields.len() - 1];
+        let declared: usize = fields[1].1.parse().map_err(|_| FixError::InvalidValue(9))?;
+        let actual = checksum_start - field_starts[2];
+        if declared != actual { return Err(FixError::BodyLength { declared, actual }); }
+
+        if checksum.len() != 3 { return Err(FixError::InvalidValue(10)); }
+        let declared: u8 = checksum.parse().map_err(|_| FixError::InvalidValue(10))?;
+        let actual = Self::checksum(&bytes[..checksum_start]);
+        if declared != actual { return Err(FixError::Checksum { declared, actual }); }
+
+        fields.drain(..2);
+        fields.pop();
+        Ok(FixMessage { fields })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (tag, value) in &self.fields {
+            body.extend_from_slice(format!("{}={}", tag, value).as_bytes());
+            body.push(SOH);
+        }
+
+        let mut message = format!("8={}\x019={}\x01", BEGIN_STRING, body.len()).into_bytes();
+        message.extend_from_slice(&body);
+        let checksum = Self::checksum(&message);
+        message.extend_from_slice(format!("10={:03}\x01", checksum).as_bytes());
+        message
+    }
+
+    fn checksum(bytes: &[u8]) -> u8 {
+        bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte))
+    }
+
+    fn required(&self, tag: u32) -> Result<&str, FixError> {
+        self.get(tag).ok_or(FixError::MissingTag(tag))
+    }
+
+    fn required_i64(&self, tag: u32) -> Result<i64, FixError> {
+        self.required(tag)?.parse().map_err(|_| FixError::InvalidValue(tag))
+    }
+}
+
+// What the translator remembers of an order it submitted, so fills of resting orders can be
+// reported to their owner as well.
+struct ClientOrder {
+    cl_ord_id: String,
+    side: Side,
+    quantity: i64,
+    cum_quantity: i64,
+    notional: i128,
+}
+
+// Turns NewOrderSingle (35=D) and OrderCancelRequest (35=F) into calls on the book and answers
+// with ExecutionReports (35=8), or an OrderCancelReject (35=9) for a cancel that failed. Prices
+// and quantities are whole numbers in the units of the book.
+pub struct FixTranslator {
+    book: Orderbook,
+    orders: HashMap<i64, ClientOrder>,
+    next_exec_id: u64,
+}
+
+impl FixTranslator {
+    pub fn new(book: Orderbook) -> FixTranslator {
+        FixTranslator { book, orders: HashMap::new(), next_exec_id: 1 }
+    }
+
+    pub fn book(&self) -> &Orderbook {
+        &self.book
+    }
+
+    pub fn book_mut(&mut self) -> &mut Orderbook {
+        &mut self.book
+    }
+
+    pub fn process(&mut self, bytes: &[u8]) -> Result<Vec<FixMessage>, FixError> {
+        self.handle(&FixMessage::parse(bytes)?)
+    }
+
+    pub fn handle(&mut self, message: &FixMessage) -> Result<Vec<FixMessage>, FixError> {
+        match message.msg_type() {
+            "D" => self.new_order_single(message),
+            "F" => self.order_cancel_request(message),
+            msg_type => Err(FixError::UnsupportedMsgType(msg_type.to_string())),
+        }
+    }
+
+    fn new_order_single(&mut self, message: &FixMessage) -> Result<Vec<FixMessage>, FixError> {
+        let cl_ord_id = message.required(11)?.to_string();
+        let side = match message.required(54)? {
+            "1" => Side::Buy,
+            "2" => Side::Sell,
+            _ => return Err(FixError::InvalidValue(54)),
+        };
+        let quantity = message.required_i64(38)?;
+        let (limit, stop_price) = match message.required(40)? {
+            "1" => (None, None),
+            "2" => (Some(self.required_price(message, 44)?), None),
+            "3" => (None, Some(self.required_price(message, 99)?)),
+            "4" => (Some(self.required_price(message, 44)?), Some(self.required_price(message, 99)?)),
+            _ => return Err(FixError::InvalidValue(40)),
+        };
+        let time_in_force = match message.get(59).unwrap_or("0") {
+            "0" => TimeInForce::Day,
+            "1" => TimeInForce::GoodTillCancel,
+            "3" => TimeInForce::ImmediateOrCancel,
+            "4" => TimeInForce::FillOrKill,
+            "7" => TimeInForce::AtTheClose,
+            _ => return Err(FixError::InvalidValue(59)),
+        };
+
+        // SecurityID (48) carries the ISIN, Symbol (55) may be either the name or the ISIN
+        let security = self.book.security();
+        let known = match message.get(48) {
+            Some(isin) => isin == security.isin,
+            None => message.required(55).is_ok_and(|symbol| symbol == security.name || symbol == security.isin),
+        };
+        if !known { return Ok(vec![self.rejected(message, &cl_ord_id, side, quantity, &OrderbookError::WrongSecurity)]); }
+
+        let mut order = Order::new(side, limit.map(Price), security, Qty(quantity), time_in_force);
+        if let Some(stop_price) = stop_price { order = order.with_stop_price(stop_price); }
+        if let Some(account_id) = Self::account(message)? { order = order.with_account(account_id); }
+
+        match self.book.place_order(order) {
+            Ok(report) => {
+                self.orders.insert(report.order_id().to_raw(), ClientOrder { cl_ord_id, side, quantity, cum_quantity: 0, notional: 0 });
+                Ok(self.reports(message, &report))
+            },
+            Err(error) => Ok(vec![self.rejected(message, &cl_ord_id, side, quantity, &error)]),
+        }
+    }
+
+    // Account (1), the numeric id of the account the order is placed or cancelled for
+    fn account(message: &FixMessage) -> Result<Option<u64>, FixError> {
+        message.get(1).map(|account| account.parse().map_err(|_| FixError::InvalidValue(1))).transpose()
+    }
+
+    fn order_cancel_request(&mut self, message: &FixMessage) -> Result<Vec<FixMessage>, FixError> {
+        let cl_ord_id = message.required(11)?.to_string();
+        // OrderID (37) wins over OrigClOrdID (41) when both are given
+        let order_id = match message.get(37) {
+            Some(order_id) => order_id.parse().map_err(|_| FixError::InvalidValue(37))?,
+            None => {
+                let orig_cl_ord_id = message.required(41)?;
+                self.orders.iter().find(|(_, order)| order.cl_ord_id == orig_cl_ord_id).map_or(-1, |(&order_id, _)| order_id)
+            },
+        };
+
+        // no order has id 0, the book reports it unknown like any other id it never gave out
+        if let Err(error) = self.book.cancel_order(OrderId::try_from(order_id).unwrap_or_default(), Self::account(message)?) {
+            let mut reject = Self::reply(message, "9")
+                .with(37, if order_id < 0 { "NONE".to_string() } else { order_id.to_string() })
+                .with(11, &cl_ord_id);
+            if let Some(orig_cl_ord_id) = message.get(41) { reject = reject.with(41, orig_cl_ord_id); }
+            reject = reject
+                .with(39, 8)
+                // other for orders of a different account, too late to cancel for orders the
+                // translator knows of, unknown order otherwise
+                .with(102, match error {
+                    OrderbookError::NotOwner(_) => 99,
+                    _ if self.orders.contains_key(&order_id) => 0,
+                    _ => 1,
+                })
+                .with(434, 1);
+            return Ok(vec![reject]);
+        }
+
+        if !self.orders.contains_key(&order_id) { return Ok(Vec::new()); }
+        let exec_id = self.exec_id();
+        let Some(order) = self.orders.remove(&order_id) else { return Ok(Vec::new()); };
+        let report = Self::reply(message, "8")
+            .with(37, order_id)
+            .with(11, &cl_ord_id)
+            .with(41, &order.cl_ord_id)
+            .with(17, exec_id)
+            .with(150, 4)
+            .with(39, 4)
+            .with(54, Self::side_code(order.side))
+            .with(38, order.quantity)
+            .with(14, order.cum_quantity)
+            .with(151, 0)
+            .with(6, Self::average_price(self.book.security(), &order));
+        Ok(vec![report])
+    }
+
+    // One report for the acknowledgement or every fill of the new order, one for each fill of a
+    // resting order the translator submitted, and one for a remainder that was cancelled.
+    fn reports(&mut self, request: &FixMessage, report: &OrderReport) -> Vec<FixMessage> {
+        let mut messages = Vec::new();
+        let order_id = report.order_id().to_raw();
+
+        for execution in report.executions() {
+            let passive_id = if execution.buying_order_id() == order_id { execution.selling_order_id() } else { execution.buying_order_id() };
+            if let Some(message) = self.fill_report(Some(request), order_id, execution) { messages.push(message); }
+            if let Some(message) = self.fill_report(None, passive_id, execution) { messages.push(message); }
+        }
+
+        let cancelled = matches!(report.state(), OrderState::Cancelled | OrderState::Killed);
+        if (report.executions().is_empty() || cancelled) && self.orders.contains_key(&order_id) {
+            let (exec_type, ord_status) = match report.state() {
+                OrderState::Cancelled | OrderState::Killed => (4, 4),
+                _ => (0, 0),
+            };
+            let exec_id = self.exec_id();
+            let order = &self.orders[&order_id];
+            messages.push(Self::reply(request, "8")
+                .with(37, order_id)
+                .with(11, &order.cl_ord_id)
+                .with(17, exec_id)
+                .with(150, exec_type)
+                .with(39, ord_status)
+                .with(54, Self::side_code(order.side))
+                .with(38, order.quantity)
+                .with(14, order.cum_quantity)
+                .with(151, if cancelled { 0 } else { report.remaining().get() })
+                .with(6, Self::average_price(self.book.security(), order)));
+            if cancelled { self.orders.remove(&order_id); }
+        }
+
+        messages
+    }
+
+    // Resting orders get their report without a request to answer, it names the instrument by ISIN.
+    fn fill_report(&mut self, request: Option<&FixMessage>, order_id: i64, execution: &Execution) -> Option<FixMessage> {
+        let header = match request {
+            Some(request) => Self::reply(request, "8"),
+            None => FixMessage::new("8").with(48, &self.book.security().isin).with(22, 4),
+        };
+        let exec_id = self.exec_id();
+        let order = self.orders.get_mut(&order_id)?;
+        order.cum_quantity += execution.amount();
+        order.notional += execution.price() as i128 * execution.amount() as i128;
+
+        // a remainder cancelled right after the fill is reported separately
+        let leaves = order.quantity - order.cum_quantity;
+        let ord_status = if leaves == 0 { 2 } else { 1 };
+        let message = header
+            .with(37, order_id)
+            .with(11, &order.cl_ord_id)
+            .with(17, exec_id)
+            .with(150, "F")
+            .with(39, ord_status)
+            .with(54, Self::side_code(order.side))
+            .with(38, order.quantity)
+            .with(31, self.book.security().to_display_price(execution.price()))
+            .with(32, execution.amount())
+            .with(14, order.cum_quantity)
+            .with(151, leaves)
+            .with(6, Self::average_price(self.book.security(), order));
+        if leaves == 0 { self.orders.remove(&order_id); }
+        Some(message)
+    }
+
+    fn rejected(&mut self, request: &FixMessage, cl_ord_id: &str, side: Side, quantity: i64, error: &OrderbookError) -> FixMessage {
+        let exec_id = self.exec_id();
+        Self::reply(request, "8")
+            .with(37, "NONE")
+            .with(11, cl_ord_id)
+            .with(17, exec_id)
+            .with(150, 8)
+            .with(39, 8)
+            .with(54, Self::side_code(side))
+            .with(38, quantity)
+            .with(14, 0)
+            .with(151, 0)
+            .with(6, self.book.security().to_display_price(0))
+            .with(58, error)
+    }
+
+    // A message back to the sender of `request`, with the comp ids swapped and the instrument
+    // of the book.
+    fn reply(request: &FixMessage, msg_type: &str) -> FixMessage {
+        let mut message = FixMessage::new(msg_type);
+        if let Some(sender) = request.get(56) { message = message.with(49, sender); }
+        if let Some(target) = request.get(49) { message = message.with(56, target); }
+        if let Some(symbol) = request.get(55) { message = message.with(55, symbol); }
+        if let Some(isin) = request.get(48) { message = message.with(48, isin).with(22, 4); }
+        message
+    }
+
+    fn exec_id(&mut self) -> u64 {
+        let exec_id = self.next_exec_id;
+        self.next_exec_id += 1;
+        exec_id
+    }
+
+    fn side_code(side: Side) -> u8 {
+        match side {
+            Side::Buy => 1,
+            Side::Sell => 2,
+        }
+    }
+
+    // rounded down to whole price units like the average of an OrderReport
+    fn average_price(security: &Security, order: &ClientOrder) -> String {
+        let average_price = if order.cum_quantity > 0 { (order.notional / order.cum_quantity as i128) as i64 } else { 0 };
+        security.to_display_price(average_price)
+    }
+
+    // Prices come with the decimals of the security, see Security::parse_price.
+    fn required_price(&self, message: &FixMessage, tag: u32) -> Result<i64, FixError> {
+        self.book.security().parse_price(message.required(tag)?).map_err(|_| FixError::InvalidValue(tag))
+    }
+}
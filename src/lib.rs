@@ -0,0 +1,5 @@
+pub mod accounting;
+pub mod exchange;
+#[cfg(feature = "fix")]
+pub mod fix;
+pub mod matching;
@@ -1,5 +1,3 @@
-mod matching;
-
 fn main() {
     println!("Hello World");
 }
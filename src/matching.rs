@@ -1 +1,27 @@
-pub mod orderbook;
\ No newline at end of file
+#[cfg(feature = "async")]
+pub mod async_orderbook;
+pub mod auction;
+pub mod candles;
+pub mod clock;
+pub mod corporate_action;
+pub mod csv_export;
+pub mod error;
+pub mod events;
+pub mod fees;
+pub mod handle;
+pub mod journal;
+pub mod listener;
+pub mod market_data;
+pub mod mdfeed;
+pub mod metrics;
+pub mod order_id;
+mod order_slab;
+pub mod orderbook;
+pub mod position;
+pub mod published;
+pub mod replay;
+pub mod settlement;
+pub mod snapshot;
+pub mod spsc;
+pub mod trade_tape;
+pub mod units;
\ No newline at end of file
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::thread;
+
+use tokio::sync::{broadcast, oneshot};
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+
+use super::events::OrderbookEvent;
+use super::handle::{HandleError, OrderbookHandle};
+use super::market_data::DepthSnapshot;
+use super::orderbook::{Order, OrderReport, OrderState, Orderbook};
+use super::units::OrderId;
+
+// A subscriber fell so far behind that the oldest events it had not read yet were overwritten.
+// The stream goes on with the oldest event still buffered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventsLagged {
+    pub(crate) missed: u64,
+}
+
+impl EventsLagged {
+    pub fn missed(&self) -> u64 {
+        self.missed
+    }
+}
+
+impl fmt::Display for EventsLagged {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Event subscriber lagged behind by {} events", self.missed)
+    }
+}
+
+impl std::error::Error for EventsLagged {}
+
+// The actor handle for async callers. Requests are awaited instead of blocking, events arrive as
+// a stream. The matching thread hands its events to a forwarding thread without ever waiting, and
+// every subscriber reads them from a ring of `event_capacity` events of its own, so a slow
+// subscriber only loses events itself instead of slowing down matching.
+#[derive(Clone)]
+pub struct AsyncOrderbook {
+    handle: OrderbookHandle,
+    events: broadcast::Sender<OrderbookEvent>,
+}
+
+impl AsyncOrderbook {
+    pub fn spawn(mut book: Orderbook, event_capacity: usize) -> AsyncOrderbook {
+        let (events, _) = broadcast::channel(event_capacity.max(1));
+        let receiver = book.subscribe_unbounded();
+        let forward = events.clone();
+        let name = format!("orderbook-events-{}", book.security().isin);
+        // ends once the book is dropped at shutdown
+        thread::Builder::new().name(name).spawn(move || {
+            for event in receiver {
+                let _ = forward.send(event);
+            }
+        }).expect("failed to spawn the event forwarder");
+
+        AsyncOrderbook { handle: OrderbookHandle::spawn(book), events }
+    }
+
+    // Runs `job` on the matching thread and awaits what it returned.
+    pub async fn execute<R: Send + 'static>(&self, job: impl FnOnce(&mut Orderbook) -> R + Send + 'static) -> Result<R, HandleError> {
+        let (sender, receiver) = oneshot::channel();
+        // the answer is taken from the oneshot, the blocking reply of the handle is not needed
+        let _ = self.handle.execute(move |book| { let _ = sender.send(job(book)); });
+        receiver.await.map_err(|_| HandleError::Stopped)
+    }
+
+    pub async fn place_order(&self, order: Order) -> Result<OrderReport, HandleError> {
+        Ok(self.execute(move |book| book.place_order(order)).await??)
+    }
+
+    pub async fn cancel_order(&self, order_id: OrderId, account_id: Option<u64>) -> Result<(), HandleError> {
+        Ok(self.execute(move |book| book.cancel_order(order_id, account_id)).await??)
+    }
+
+    pub async fn depth(&self, levels: usize) -> Result<DepthSnapshot, HandleError> {
+        self.execute(move |book| book.depth(levels)).await
+    }
+
+    pub async fn order_status(&self, order_id: OrderId) -> Result<Option<OrderState>, HandleError> {
+        self.execute(move |book| book.order_status(order_id)).await
+    }
+
+    // Every event published from now on.
+    pub fn events(&self) -> impl Stream<Item = Result<OrderbookEvent, EventsLagged>> + Unpin {
+        BroadcastStream::new(self.events.subscribe()).map(|event| event.map_err(|BroadcastStreamRecvError::Lagged(missed)| EventsLagged { missed }))
+    }
+
+    // The blocking handle on the same book, for shutdown or callers outside the runtime.
+    pub fn handle(&self) -> &OrderbookHandle {
+        &self.handle
+    }
+}
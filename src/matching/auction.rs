@@ -0,0 +1,96 @@
+use std::iter;
+
+use super::orderbook::Side;
+
+// The outcome of a call auction, every trade of the uncross happens at `price`. `imbalance` is
+// what is left over at that price, positive when buyers want more than sellers offer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuctionResult {
+    pub(crate) price: i64,
+    pub(crate) volume: i64,
+    pub(crate) imbalance: i64,
+}
+
+impl AuctionResult {
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    // the quantity that trades at the price
+    pub fn volume(&self) -> i64 {
+        self.volume
+    }
+
+    pub fn imbalance(&self) -> i64 {
+        self.imbalance
+    }
+}
+
+// What an auction would do if it uncrossed now: `paired_volume` trades at `price` and
+// `imbalance_quantity` of `imbalance_side` is left over, no side when both sides pair off exactly.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IndicativePrice {
+    pub(crate) price: i64,
+    pub(crate) paired_volume: i64,
+    pub(crate) imbalance_side: Option<Side>,
+    pub(crate) imbalance_quantity: i64,
+}
+
+impl IndicativePrice {
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn paired_volume(&self) -> i64 {
+        self.paired_volume
+    }
+
+    pub fn imbalance_side(&self) -> Option<Side> {
+        self.imbalance_side
+    }
+
+    pub fn imbalance_quantity(&self) -> i64 {
+        self.imbalance_quantity
+    }
+}
+
+impl From<AuctionResult> for IndicativePrice {
+    fn from(result: AuctionResult) -> Self {
+        let imbalance_side = match result.imbalance {
+            0 => None,
+            imbalance if imbalance > 0 => Some(Side::Buy),
+            _ => Some(Side::Sell),
+        };
+        IndicativePrice { price: result.price, paired_volume: result.volume, imbalance_side, imbalance_quantity: result.imbalance.saturating_abs() }
+    }
+}
+
+// The price at which the most quantity trades, given the quantity bid and offered per level best
+// price first and the quantity of market orders on each side. Of the prices that trade the same
+// quantity the one with the smallest imbalance wins, then the one closest to the reference price,
+// then the lowest. The reference price is a candidate as well, so it is chosen whenever it lies
+// inside a range of equally good prices. Without anything that would trade the auction clears at
+// the reference price with no volume.
+pub(crate) fn equilibrium(bids: &[(i64, i64)], asks: &[(i64, i64)], market_buy: i64, market_sell: i64, reference: i64) -> AuctionResult {
+    let cumulative = |levels: &[(i64, i64)]| -> Vec<i128> {
+        iter::once(0).chain(levels.iter().scan(0i128, |sum, &(_, quantity)| {
+            *sum += quantity as i128;
+            Some(*sum)
+        })).collect()
+    };
+    let (bid_sums, ask_sums) = (cumulative(bids), cumulative(asks));
+    let demand = |price: i64| market_buy as i128 + bid_sums[bids.partition_point(|&(bid, _)| bid >= price)];
+    let supply = |price: i64| market_sell as i128 + ask_sums[asks.partition_point(|&(ask, _)| ask <= price)];
+    let clamp = |value: i128| value.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+
+    let candidates = bids.iter().chain(asks).map(|&(price, _)| price).chain(iter::once(reference));
+    let best = candidates.map(|price| (price, demand(price).min(supply(price)), demand(price) - supply(price)))
+        .min_by_key(|&(price, volume, imbalance)| (-volume, imbalance.abs(), (price as i128 - reference as i128).abs(), price));
+
+    match best {
+        Some((price, volume, imbalance)) if volume > 0 => AuctionResult { price, volume: clamp(volume), imbalance: clamp(imbalance) },
+        _ => AuctionResult { price: reference, volume: 0, imbalance: clamp(demand(reference) - supply(reference)) },
+    }
+}
@@ -0,0 +1,118 @@
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyIntervals {
+    // intervals without trades produce no candle
+    #[default]
+    Skip,
+    // intervals without trades produce a flat candle at the previous close with no volume
+    CarryForward,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candle {
+    start: u64,
+    open: i64,
+    high: i64,
+    low: i64,
+    close: i64,
+    volume: i64,
+    trade_count: usize,
+}
+
+impl Candle {
+    fn flat(start: u64, price: i64) -> Self {
+        Candle { start, open: price, high: price, low: price, close: price, volume: 0, trade_count: 0 }
+    }
+
+    // engine timestamp at which the interval starts, a multiple of the interval length
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn open(&self) -> i64 {
+        self.open
+    }
+
+    pub fn high(&self) -> i64 {
+        self.high
+    }
+
+    pub fn low(&self) -> i64 {
+        self.low
+    }
+
+    pub fn close(&self) -> i64 {
+        self.close
+    }
+
+    pub fn volume(&self) -> i64 {
+        self.volume
+    }
+
+    pub fn trade_count(&self) -> usize {
+        self.trade_count
+    }
+}
+
+// Collects the trades of a book and buckets them into candles of any interval on request. Engine
+// time is expected not to run backwards, as the book's clock is driven by the caller.
+#[derive(Clone, Debug, Default)]
+pub struct CandleAggregator {
+    trades: Vec<(u64, i64, i64)>,
+    empty_intervals: EmptyIntervals,
+}
+
+impl CandleAggregator {
+    pub fn new(empty_intervals: EmptyIntervals) -> Self {
+        CandleAggregator { trades: Vec::new(), empty_intervals }
+    }
+
+    pub fn record(&mut self, timestamp: u64, price: i64, quantity: i64) {
+        self.trades.push((timestamp, price, quantity));
+    }
+
+    // forgets the trades, keeping the setting for empty intervals and the buffer
+    pub(crate) fn clear(&mut self) {
+        self.trades.clear();
+    }
+
+    // Candles of `interval` time units, starting with the interval that contains `since` and
+    // ending with the interval of the latest trade.
+    pub fn candles(&self, interval: u64, since: u64) -> Vec<Candle> {
+        let mut candles: Vec<Candle> = Vec::new();
+        if interval == 0 { return candles; }
+
+        let first_start = since - since % interval;
+        let first_index = self.trades.partition_point(|&(timestamp, _, _)| timestamp < first_start);
+        let mut previous_close = first_index.checked_sub(1).map(|index| self.trades[index].1);
+
+        for &(timestamp, price, quantity) in &self.trades[first_index..] {
+            let start = timestamp - timestamp % interval;
+
+            match candles.last_mut() {
+                Some(candle) if candle.start == start => {
+                    candle.high = candle.high.max(price);
+                    candle.low = candle.low.min(price);
+                    candle.close = price;
+                },
+                _ => {
+                    if let (EmptyIntervals::CarryForward, Some(close)) = (self.empty_intervals, previous_close) {
+                        let mut empty_start = candles.last().map_or(first_start, |candle| candle.start + interval);
+                        while empty_start < start {
+                            candles.push(Candle::flat(empty_start, close));
+                            empty_start += interval;
+                        }
+                    }
+                    candles.push(Candle::flat(start, price));
+                },
+            }
+
+            if let Some(candle) = candles.last_mut() {
+                candle.volume += quantity;
+                candle.trade_count += 1;
+            }
+            previous_close = Some(price);
+        }
+
+        candles
+    }
+}
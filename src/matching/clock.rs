@@ -0,0 +1,96 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Nanoseconds, since the Unix epoch for the system clock and from wherever a script starts for a
+// manual one.
+pub type Timestamp = u64;
+
+// Where a book takes the time with which it stamps orders, executions and events and against
+// which it checks expiries, see Orderbook::set_clock. Matching never reads the time of the
+// operating system on its own.
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+// The wall clock of the operating system, for live books.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        // a clock set before 1970 reads as the epoch
+        SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_nanos() as Timestamp)
+    }
+}
+
+// A clock that only moves when it is told to, for tests and simulations. Clones share the same
+// time, so a script keeps one and hands another to the book.
+#[derive(Clone, Debug, Default)]
+pub struct ManualClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new(start: Timestamp) -> Self {
+        ManualClock { now: Arc::new(AtomicU64::new(start)) }
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, nanos: u64) {
+        self.now.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matching::events::OrderbookEvent;
+    use crate::matching::orderbook::{OrderBuilder, Orderbook, OrderbookConfig, Security, Side};
+    use crate::matching::units::{Price, Qty};
+
+    #[test]
+    fn a_scripted_session_is_stamped_with_the_script_times() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let clock = ManualClock::new(1_000);
+        let mut book = Orderbook::with_config(security.clone(), 100, OrderbookConfig::new(), Box::new(clock.clone()));
+        let events = book.subscribe_unbounded();
+        let limit = |side, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity));
+
+        let mut stamps = Vec::new();
+        let mut step = |at: Timestamp, action: &mut dyn FnMut(&mut Orderbook)| {
+            clock.set(at);
+            action(&mut book);
+            let stamped: Vec<u64> = events.try_iter().map(|event: OrderbookEvent| event.timestamp()).collect();
+            assert!(!stamped.is_empty());
+            stamps.push((at, stamped));
+        };
+        let mut ask = None;
+        step(1_000, &mut |book| ask = Some(book.place_order(limit(Side::Sell, 101, 5).build().unwrap()).unwrap().order_id()));
+        let mut bid = None;
+        step(2_500, &mut |book| bid = Some(book.place_order(limit(Side::Buy, 99, 3).build().unwrap()).unwrap().order_id()));
+        step(4_000, &mut |book| book.cancel_order(bid.unwrap(), None).unwrap());
+        step(7_000, &mut |book| { book.place_order(limit(Side::Buy, 101, 2).build().unwrap()).unwrap(); });
+        let mut expiring = None;
+        step(7_500, &mut |book| expiring = Some(book.place_order(limit(Side::Buy, 98, 1).expires_at(8_000).build().unwrap()).unwrap().order_id()));
+        // the expiry is stamped with the time it is purged at, not the one the order expired at
+        step(9_000, &mut |book| assert_eq!(book.purge_expired(9_000).unwrap(), vec![expiring.unwrap().to_raw()]));
+
+        for (at, stamped) in &stamps {
+            assert!(stamped.iter().all(|timestamp| timestamp == at), "{:?} at {}", stamped, at);
+        }
+        assert_eq!(book.order(ask.unwrap()).unwrap().timestamp(), 1_000);
+        assert_eq!(book.executions()[0].timestamp(), 7_000);
+        assert_eq!(book.trade_tape().last_n_trades(1)[0].timestamp(), 7_000);
+        assert_eq!(book.best_bid(), None);
+    }
+}
@@ -0,0 +1,71 @@
+// A change to a security decided by its issuer, effective at the ex timestamp. Ids are assigned by
+// the exchange, one sequence over all securities.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CorporateAction {
+    pub(crate) id: u64,
+    pub(crate) isin: String,
+    pub(crate) kind: CorporateActionKind,
+    pub(crate) ex_timestamp: u64,
+}
+
+impl CorporateAction {
+    pub fn new(id: u64, isin: &str, kind: CorporateActionKind, ex_timestamp: u64) -> Self {
+        CorporateAction { id, isin: isin.to_string(), kind, ex_timestamp }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn kind(&self) -> CorporateActionKind {
+        self.kind
+    }
+
+    pub fn ex_timestamp(&self) -> u64 {
+        self.ex_timestamp
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CorporateActionKind {
+    // holders receive and short sellers pay the amount for every share, in price units
+    CashDividend { amount_per_share: i64 },
+    // every share becomes numerator / denominator shares, a reverse split if that is less than one
+    Split { numerator: i64, denominator: i64 },
+}
+
+// The resting orders a corporate action repriced and cancelled, by ascending id.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AdjustedOrders {
+    pub(crate) repriced: Vec<i64>,
+    pub(crate) cancelled: Vec<i64>,
+}
+
+impl AdjustedOrders {
+    pub fn repriced(&self) -> &[i64] {
+        &self.repriced
+    }
+
+    pub fn cancelled(&self) -> &[i64] {
+        &self.cancelled
+    }
+}
+
+// What a corporate action does to the open limit orders of its security.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OpenOrderPolicy {
+    // limits move by the price adjustment of the action, orders keep their place in the queue
+    #[default]
+    Reprice,
+    // every resting limit order is cancelled
+    Cancel,
+    // orders stay as they are
+    Keep,
+}
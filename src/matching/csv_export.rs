@@ -0,0 +1,79 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use super::orderbook::{Orderbook, Security};
+use super::units::format_price;
+
+// How prices are written. Raw keeps the integer price units of the book, Decimal places the
+// decimal point `decimals` digits from the right, so 10050 with 2 decimals becomes 100.50.
+// OfSecurity writes every price with the decimals of its own security, for exports that cover
+// books of securities with different decimals.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PriceFormat {
+    #[default]
+    Raw,
+    Decimal(u8),
+    OfSecurity,
+}
+
+impl PriceFormat {
+    // The decimals the security quotes its prices with.
+    pub fn for_security(security: &Security) -> Self {
+        PriceFormat::Decimal(security.price_decimals())
+    }
+
+    // OfSecurity needs the security to know its decimals, here its prices are written raw.
+    pub fn format(&self, price: i64) -> String {
+        match *self {
+            PriceFormat::Decimal(decimals) => format_price(price, decimals),
+            PriceFormat::Raw | PriceFormat::OfSecurity => price.to_string(),
+        }
+    }
+
+    // a price of a security with `decimals` decimals
+    pub(crate) fn format_in(&self, decimals: u8, price: i64) -> String {
+        match *self {
+            PriceFormat::OfSecurity => format_price(price, decimals),
+            PriceFormat::Raw | PriceFormat::Decimal(_) => self.format(price),
+        }
+    }
+}
+
+// Quotes a field that contains a delimiter, a quote or a line break, doubling the quotes inside.
+pub(crate) fn escape(field: &str) -> Cow<'_, str> {
+    if !field.contains([',', '"', '\n', '\r']) { return Cow::Borrowed(field); }
+    Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+}
+
+pub(crate) fn write_row<W: Write>(w: &mut W, fields: &[&str]) -> io::Result<()> {
+    for (index, field) in fields.iter().enumerate() {
+        if index > 0 { w.write_all(b",")?; }
+        w.write_all(escape(field).as_bytes())?;
+    }
+    w.write_all(b"\n")
+}
+
+// One row per book with the session figures: open, high, low and close are empty for a security
+// that did not trade, close is the last price, which is the reference price without trades. The
+// closing auction columns are empty for a book that did not run one.
+// end_of_session starts new statistics, so the report is written before it.
+pub fn write_end_of_day_report<'a, W: Write>(mut w: W, books: impl IntoIterator<Item = &'a Orderbook>, prices: PriceFormat) -> io::Result<()> {
+    write_row(&mut w, &["isin", "name", "open", "high", "low", "close", "volume", "trade_count", "closing_auction_price", "closing_auction_volume"])?;
+    for book in books {
+        let stats = book.stats();
+        let optional = |price: Option<i64>| price.map_or(String::new(), |price| prices.format_in(book.security().price_decimals(), price));
+        write_row(&mut w, &[
+            &book.security().isin,
+            &book.security().name,
+            &optional(stats.open()),
+            &optional(stats.high()),
+            &optional(stats.low()),
+            &prices.format_in(book.security().price_decimals(), stats.last_price()),
+            &stats.volume().to_string(),
+            &stats.trade_count().to_string(),
+            &optional(stats.closing_auction().map(|result| result.price())),
+            &stats.closing_auction().map_or(String::new(), |result| result.volume().to_string()),
+        ])?;
+    }
+    w.flush()
+}
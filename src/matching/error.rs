@@ -0,0 +1,300 @@
+use std::error::Error;
+use std::fmt;
+
+use super::orderbook::Side;
+
+// Errors only carry ids, prices and reason codes, so rejecting an order never allocates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderbookError {
+    InvalidAmount,
+    InvalidLimit,
+    InvalidStopPrice,
+    InvalidTrailingOffset,
+    InvalidPeg,
+    // midpoint orders cannot be stops, pegged, icebergs, post only, fill or kill, at the close or
+    // have a minimum quantity
+    InvalidMidpointPeg,
+    ReduceOnlyUnavailable,
+    ReduceOnlyWouldIncrease,
+    InvalidDisplayQuantity,
+    InvalidMinQuantity { min_quantity: i64, amount: i64 },
+    OrderExpired,
+    StopLimitTooFar { stop_price: i64, limit: i64 },
+    RejectedPostOnlyWouldCross { limit: i64, best_opposite: i64 },
+    AmendBelowExecuted { order_id: i64, executed: i64 },
+    UnknownOrder(i64),
+    // the order belongs to a different account than the one that asked to cancel it
+    NotOwner(i64),
+    DuplicateOrderId(i64),
+    WrongSecurity,
+    // the journal could not log the command, so it was not executed
+    JournalWrite(#[cfg_attr(feature = "serde", serde(with = "error_kind"))] std::io::ErrorKind),
+    // a snapshot can only be restored into a book without orders
+    BookNotEmpty,
+    InvalidSnapshot(SnapshotProblem),
+    // refused by the pre-trade risk check of the exchange
+    RiskRejected(RiskRejection),
+    // the book is closed, see Orderbook::close
+    MarketClosed,
+    // orders at the close are only accepted during the closing auction
+    ClosingAuctionOnly,
+    // trading is halted, see Orderbook::halt
+    MarketHalted,
+    // prices have to be multiples of the tick size of the security
+    PriceNotOnTick { price: i64, tick_size: i64 },
+    // quantities have to be multiples of the lot size of the security
+    QuantityNotInLots { quantity: i64, lot_size: i64 },
+    // the bid of a quote has to be below its ask
+    InvalidQuote { bid_price: i64, ask_price: i64 },
+    // a side of the quote would trade with the opposite best price, see QuotePolicy
+    QuoteWouldCross { side: Side, price: i64, best_opposite: i64 },
+    // the quote was replaced or cancelled already
+    UnknownQuote(i64),
+    // the account sent more messages than its rate limit allows, see Exchange::set_throttle. A
+    // message is allowed again `retry_after` after the time of the book.
+    RateLimited { retry_after: u64 },
+    // the account already has `limit` orders open in the book
+    TooManyOpenOrders { limit: usize },
+    // the order is larger than the security allows, see Security::with_max_order_quantity
+    QuantityAboveMaximum { quantity: i64, max_quantity: i64 },
+    // the order is worth more than the security allows, see Security::with_max_order_notional
+    NotionalAboveMaximum { notional: i64, max_notional: i64 },
+    // no trade with the id is known to the book
+    UnknownTrade(u64),
+    TradeAlreadyBusted(u64),
+    // a reported trade is outside the static price band, see ReportFlags::with_negotiated_outside_band
+    OutsidePriceBand { price: i64, reference_price: i64 },
+    // resting the order would take the quantity of the level at `price` beyond what a level holds
+    LevelFull { price: i64 },
+}
+
+// What made a snapshot unfit for restoring, see Orderbook::restore.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SnapshotProblem {
+    NothingLeft(i64),
+    ListedTwice(i64),
+    NotLimitOrder { order_id: i64, side: Side },
+    NotMarketOrder(i64),
+    NotPendingStop(i64),
+    NotMidpointOrder(i64),
+    // the quantity of the level at the price does not fit an i64
+    LevelFull(i64),
+    // the orders do not make up a consistent book, see Orderbook::check_invariants
+    Inconsistent,
+}
+
+impl fmt::Display for SnapshotProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotProblem::NothingLeft(order_id) => write!(f, "Order {} has nothing left to trade", order_id),
+            SnapshotProblem::ListedTwice(order_id) => write!(f, "Order {} is listed twice", order_id),
+            SnapshotProblem::NotLimitOrder { order_id, side } => write!(f, "Order {} is not a {:?} limit order", order_id, side),
+            SnapshotProblem::NotMarketOrder(order_id) => write!(f, "Order {} is not a market order", order_id),
+            SnapshotProblem::NotPendingStop(order_id) => write!(f, "Order {} is not a pending stop", order_id),
+            SnapshotProblem::NotMidpointOrder(order_id) => write!(f, "Order {} is not a midpoint order", order_id),
+            SnapshotProblem::LevelFull(price) => write!(f, "The level at {} holds more than fits an i64", price),
+            SnapshotProblem::Inconsistent => write!(f, "The orders do not make up a consistent book"),
+        }
+    }
+}
+
+// Why a price could not be read, see Security::parse_price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParsePriceError {
+    Empty,
+    // the byte offset of the first character that is not part of a decimal number
+    InvalidCharacter(usize),
+    // the price has significant digits beyond the decimals of the security
+    TooManyDecimals { decimals: u8 },
+    Overflow,
+}
+
+impl fmt::Display for ParsePriceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParsePriceError::Empty => write!(f, "The price is empty"),
+            ParsePriceError::InvalidCharacter(offset) => write!(f, "The price has an invalid character at byte {}", offset),
+            ParsePriceError::TooManyDecimals { decimals } => write!(f, "The price has more than {} decimals", decimals),
+            ParsePriceError::Overflow => write!(f, "The price is too large"),
+        }
+    }
+}
+
+impl Error for ParsePriceError {}
+
+// Why a deserialized security was refused. Tick and lot size divide every price and quantity of
+// the book, so neither may be below 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InvalidSecurity {
+    TickSize(i64),
+    LotSize(i64),
+}
+
+impl fmt::Display for InvalidSecurity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidSecurity::TickSize(tick_size) => write!(f, "The tick size {} is below 1", tick_size),
+            InvalidSecurity::LotSize(lot_size) => write!(f, "The lot size {} is below 1", lot_size),
+        }
+    }
+}
+
+impl Error for InvalidSecurity {}
+
+// Why the pre-trade risk check refused an order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RiskRejection {
+    NoAccount,
+    UnknownAccount(u64),
+    // cash minus what open buys reserve does not cover the worst case notional of the order
+    InsufficientBuyingPower { required: i64, available: i64 },
+    // the position minus what open sells reserve does not cover the order, and shorting is off
+    InsufficientPosition { required: i64, available: i64 },
+    // the short positions of the account would be worth more than its equity allows
+    ShortExposureLimit { exposure: i64, limit: i64 },
+}
+
+impl RiskRejection {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RiskRejection::NoAccount => "no_account",
+            RiskRejection::UnknownAccount(_) => "unknown_account",
+            RiskRejection::InsufficientBuyingPower { .. } => "insufficient_buying_power",
+            RiskRejection::InsufficientPosition { .. } => "insufficient_position",
+            RiskRejection::ShortExposureLimit { .. } => "short_exposure_limit",
+        }
+    }
+}
+
+impl fmt::Display for RiskRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RiskRejection::NoAccount => write!(f, "Order has no account"),
+            RiskRejection::UnknownAccount(account_id) => write!(f, "Account {} does not exist", account_id),
+            RiskRejection::InsufficientBuyingPower { required, available } => write!(f, "Order needs buying power of {} but only {} is available", required, available),
+            RiskRejection::InsufficientPosition { required, available } => write!(f, "Order sells {} but only {} is held", required, available),
+            RiskRejection::ShortExposureLimit { exposure, limit } => write!(f, "Order would take short exposure to {} above the limit of {}", exposure, limit),
+        }
+    }
+}
+
+impl fmt::Display for OrderbookError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderbookError::InvalidAmount => write!(f, "Order amount must be greater than zero"),
+            OrderbookError::InvalidLimit => write!(f, "Limit must be greater than zero"),
+            OrderbookError::InvalidStopPrice => write!(f, "Stop price must be greater than zero"),
+            OrderbookError::InvalidTrailingOffset => write!(f, "Trailing offset must be greater than zero"),
+            OrderbookError::InvalidPeg => write!(f, "A pegged order needs a non-negative offset and a limit as cap and cannot be a stop"),
+            OrderbookError::InvalidMidpointPeg => write!(f, "A midpoint order cannot be a stop, pegged, an iceberg, post only, fill or kill or at the close, or have a minimum quantity"),
+            OrderbookError::ReduceOnlyUnavailable => write!(f, "Reduce only orders need an account and a position provider"),
+            OrderbookError::ReduceOnlyWouldIncrease => write!(f, "Reduce only order would increase the position of its account"),
+            OrderbookError::OrderExpired => write!(f, "Order has already expired"),
+            OrderbookError::InvalidDisplayQuantity => write!(f, "Display quantity must be positive, not exceed the order amount and requires a limit"),
+            OrderbookError::InvalidMinQuantity { min_quantity, amount } => write!(f, "Minimum quantity {} must be positive and not exceed the order amount {}", min_quantity, amount),
+            OrderbookError::StopLimitTooFar { stop_price, limit } => write!(f, "Limit {} is too far away from stop price {}", limit, stop_price),
+            OrderbookError::RejectedPostOnlyWouldCross { limit, best_opposite } => write!(f, "Post only order with limit {} would cross the opposite best price {}", limit, best_opposite),
+            OrderbookError::AmendBelowExecuted { order_id, executed } => write!(f, "Order {} cannot be amended below its executed amount of {}", order_id, executed),
+            OrderbookError::UnknownOrder(order_id) => write!(f, "Order {} does not exist or is already filled", order_id),
+            OrderbookError::NotOwner(order_id) => write!(f, "Order {} belongs to a different account", order_id),
+            OrderbookError::DuplicateOrderId(order_id) => write!(f, "Order id {} is already in use", order_id),
+            OrderbookError::WrongSecurity => write!(f, "Order is for a different security than the orderbook"),
+            OrderbookError::JournalWrite(kind) => write!(f, "Journal write failed: {}", kind),
+            OrderbookError::BookNotEmpty => write!(f, "The orderbook already has orders"),
+            OrderbookError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {}", reason),
+            OrderbookError::RiskRejected(rejection) => write!(f, "Risk check failed: {}", rejection),
+            OrderbookError::MarketClosed => write!(f, "The market is closed"),
+            OrderbookError::ClosingAuctionOnly => write!(f, "Orders at the close are only accepted during the closing auction"),
+            OrderbookError::MarketHalted => write!(f, "Trading is halted"),
+            OrderbookError::PriceNotOnTick { price, tick_size } => write!(f, "Price {} is not a multiple of the tick size {}", price, tick_size),
+            OrderbookError::QuantityNotInLots { quantity, lot_size } => write!(f, "Quantity {} is not a multiple of the lot size {}", quantity, lot_size),
+            OrderbookError::InvalidQuote { bid_price, ask_price } => write!(f, "Quote bid {} must be below its ask {}", bid_price, ask_price),
+            OrderbookError::QuoteWouldCross { side, price, best_opposite } => write!(f, "Quote {:?} at {} would cross the opposite best price {}", side, price, best_opposite),
+            OrderbookError::UnknownQuote(quote_id) => write!(f, "Quote {} is not the current quote of its account", quote_id),
+            OrderbookError::RateLimited { retry_after } => write!(f, "Message rate limit exceeded, retry after {}", retry_after),
+            OrderbookError::TooManyOpenOrders { limit } => write!(f, "Account already has the maximum of {} open orders", limit),
+            OrderbookError::QuantityAboveMaximum { quantity, max_quantity } => write!(f, "Quantity {} is above the maximum order quantity {}", quantity, max_quantity),
+            OrderbookError::NotionalAboveMaximum { notional, max_notional } => write!(f, "Notional {} is above the maximum order notional {}", notional, max_notional),
+            OrderbookError::UnknownTrade(trade_id) => write!(f, "Trade {} does not exist", trade_id),
+            OrderbookError::TradeAlreadyBusted(trade_id) => write!(f, "Trade {} is already busted", trade_id),
+            OrderbookError::OutsidePriceBand { price, reference_price } => write!(f, "Price {} is outside the price band around {}", price, reference_price),
+            OrderbookError::LevelFull { price } => write!(f, "The level at {} cannot hold the quantity of the order", price),
+        }
+    }
+}
+
+impl Error for OrderbookError {}
+
+impl OrderbookError {
+    // A stable name for the kind of error without its details, usable as a metrics label.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            OrderbookError::InvalidAmount => "invalid_amount",
+            OrderbookError::InvalidLimit => "invalid_limit",
+            OrderbookError::InvalidStopPrice => "invalid_stop_price",
+            OrderbookError::InvalidTrailingOffset => "invalid_trailing_offset",
+            OrderbookError::InvalidPeg => "invalid_peg",
+            OrderbookError::InvalidMidpointPeg => "invalid_midpoint_peg",
+            OrderbookError::ReduceOnlyUnavailable => "reduce_only_unavailable",
+            OrderbookError::ReduceOnlyWouldIncrease => "reduce_only_would_increase",
+            OrderbookError::InvalidDisplayQuantity => "invalid_display_quantity",
+            OrderbookError::InvalidMinQuantity { .. } => "invalid_min_quantity",
+            OrderbookError::OrderExpired => "order_expired",
+            OrderbookError::StopLimitTooFar { .. } => "stop_limit_too_far",
+            OrderbookError::RejectedPostOnlyWouldCross { .. } => "post_only_would_cross",
+            OrderbookError::AmendBelowExecuted { .. } => "amend_below_executed",
+            OrderbookError::UnknownOrder(_) => "unknown_order",
+            OrderbookError::NotOwner(_) => "not_owner",
+            OrderbookError::DuplicateOrderId(_) => "duplicate_order_id",
+            OrderbookError::WrongSecurity => "wrong_security",
+            OrderbookError::JournalWrite(_) => "journal_write",
+            OrderbookError::BookNotEmpty => "book_not_empty",
+            OrderbookError::InvalidSnapshot(_) => "invalid_snapshot",
+            OrderbookError::RiskRejected(rejection) => rejection.code(),
+            OrderbookError::MarketClosed => "market_closed",
+            OrderbookError::ClosingAuctionOnly => "closing_auction_only",
+            OrderbookError::MarketHalted => "market_halted",
+            OrderbookError::PriceNotOnTick { .. } => "price_not_on_tick",
+            OrderbookError::QuantityNotInLots { .. } => "quantity_not_in_lots",
+            OrderbookError::InvalidQuote { .. } => "invalid_quote",
+            OrderbookError::QuoteWouldCross { .. } => "quote_would_cross",
+            OrderbookError::UnknownQuote(_) => "unknown_quote",
+            OrderbookError::RateLimited { .. } => "rate_limited",
+            OrderbookError::TooManyOpenOrders { .. } => "too_many_open_orders",
+            OrderbookError::QuantityAboveMaximum { .. } => "quantity_above_maximum",
+            OrderbookError::NotionalAboveMaximum { .. } => "notional_above_maximum",
+            OrderbookError::UnknownTrade(_) => "unknown_trade",
+            OrderbookError::TradeAlreadyBusted(_) => "trade_already_busted",
+            OrderbookError::OutsidePriceBand { .. } => "outside_price_band",
+            OrderbookError::LevelFull { .. } => "level_full",
+        }
+    }
+}
+
+// io::ErrorKind has no serde support of its own, it is written as its description.
+#[cfg(feature = "serde")]
+mod error_kind {
+    use std::io::ErrorKind;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const KINDS: [ErrorKind; 14] = [
+        ErrorKind::NotFound, ErrorKind::PermissionDenied, ErrorKind::AlreadyExists, ErrorKind::WouldBlock,
+        ErrorKind::InvalidInput, ErrorKind::InvalidData, ErrorKind::TimedOut, ErrorKind::WriteZero,
+        ErrorKind::Interrupted, ErrorKind::Unsupported, ErrorKind::UnexpectedEof, ErrorKind::OutOfMemory,
+        ErrorKind::StorageFull, ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: Serializer>(kind: &ErrorKind, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&kind.to_string())
+    }
+
+    // kinds this side does not know come back as Other
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<ErrorKind, D::Error> {
+        let description = String::deserialize(deserializer)?;
+        Ok(KINDS.into_iter().find(|kind| kind.to_string() == description).unwrap_or(ErrorKind::Other))
+    }
+}
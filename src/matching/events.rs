@@ -0,0 +1,181 @@
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+
+use super::auction::AuctionResult;
+use super::corporate_action::CorporateAction;
+use super::error::OrderbookError;
+use super::listener::CancelReason;
+use super::orderbook::{CancelFilter, ClearPolicy, Execution, PriceBand, SessionState, Side};
+
+// Market data and order events of one book. The sequence starts at 1 and increases by one per
+// event of the book, so a subscriber sees from a gap that it lost events. The timestamp is the
+// engine time of the book when the event happened.
+//
+// With the serde feature an event is written as an object keyed by the variant name, e.g.
+// {"OrderAccepted":{"sequence":1,"timestamp":0,"order_id":7}}. Field and variant names are the
+// ones below and stay stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderbookEvent {
+    OrderAccepted { sequence: u64, timestamp: u64, order_id: i64 },
+    OrderRejected { sequence: u64, timestamp: u64, reason: OrderbookError },
+    Trade { sequence: u64, timestamp: u64, execution: Execution },
+    OrderCancelled { sequence: u64, timestamp: u64, order_id: i64, reason: CancelReason },
+    // a limit order now shows `quantity` at the back of the level at `price`, also sent for every
+    // new slice of an iceberg
+    OrderAdded { sequence: u64, timestamp: u64, order_id: i64, side: Side, price: i64, quantity: i64 },
+    // the visible quantity of a resting order went down to `quantity` without a trade, it keeps its place
+    OrderReduced { sequence: u64, timestamp: u64, order_id: i64, quantity: i64 },
+    // a resting order left its level to be re-priced, it comes back with OrderAdded or trades
+    OrderRemoved { sequence: u64, timestamp: u64, order_id: i64 },
+    // quantity and order count of a level after it changed, both 0 once the level is gone
+    LevelChanged { sequence: u64, timestamp: u64, side: Side, price: i64, quantity: i64, order_count: usize },
+    BestPriceChanged { sequence: u64, timestamp: u64, best_bid: Option<i64>, best_ask: Option<i64> },
+    // the security went ex, published before the orders it cancels and the levels it moves. With
+    // OpenOrderPolicy::Reprice every resting limit order that is not cancelled moved down by the
+    // price adjustment of the action and kept its place in the queue.
+    CorporateAction { sequence: u64, timestamp: u64, action: CorporateAction },
+    // published after the OrderCancelled events of a cancel_all, `count` orders were cancelled
+    MassCancelled { sequence: u64, timestamp: u64, filter: CancelFilter, count: usize },
+    // published before the trades a crossed book makes when continuous trading starts
+    SessionChanged { sequence: u64, timestamp: u64, previous: SessionState, state: SessionState },
+    // published after the trades of an auction and before the book is updated
+    AuctionUncrossed { sequence: u64, timestamp: u64, result: AuctionResult },
+    // a trade of the order at `price` would have left `band`, published before the session changes
+    // to the volatility auction that lasts until `ends_at`
+    VolatilityInterruption { sequence: u64, timestamp: u64, order_id: i64, band: PriceBand, price: i64, reference_price: i64, last_price: i64, ends_at: u64 },
+    // an account replaced its quote, published after the cancellations of the previous quote and
+    // before the events of the two new orders
+    QuoteUpdated { sequence: u64, timestamp: u64, account_id: u64, quote_id: i64, bid_order_id: i64, bid_price: i64, bid_quantity: i64, ask_order_id: i64, ask_price: i64, ask_quantity: i64 },
+    // published before the cancellations of the orders of the quote
+    QuoteCancelled { sequence: u64, timestamp: u64, account_id: u64, quote_id: i64 },
+    // the indicative auction price changed while the book collects orders for an auction, see
+    // Orderbook::indicative. Published after the level changes of the call that changed it.
+    IndicativePrice { sequence: u64, timestamp: u64, price: i64, paired_volume: i64, imbalance_side: Option<Side>, imbalance_quantity: i64 },
+    // the trade was busted, see Orderbook::bust_trade. Published before the orders it re-opens
+    // come back.
+    TradeBusted { sequence: u64, timestamp: u64, execution: Execution },
+    // the book was cleared and is back to how a new book starts, see Orderbook::clear. No levels
+    // are left and both best prices are None, the `count` orders it held are gone. Only published
+    // after the OrderCancelled events of the orders if the policy cancels them.
+    BookCleared { sequence: u64, timestamp: u64, policy: ClearPolicy, count: usize },
+}
+
+impl OrderbookEvent {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            OrderbookEvent::OrderAccepted { sequence, .. }
+            | OrderbookEvent::OrderRejected { sequence, .. }
+            | OrderbookEvent::Trade { sequence, .. }
+            | OrderbookEvent::OrderCancelled { sequence, .. }
+            | OrderbookEvent::OrderAdded { sequence, .. }
+            | OrderbookEvent::OrderReduced { sequence, .. }
+            | OrderbookEvent::OrderRemoved { sequence, .. }
+            | OrderbookEvent::LevelChanged { sequence, .. }
+            | OrderbookEvent::BestPriceChanged { sequence, .. }
+            | OrderbookEvent::CorporateAction { sequence, .. }
+            | OrderbookEvent::MassCancelled { sequence, .. }
+            | OrderbookEvent::SessionChanged { sequence, .. }
+            | OrderbookEvent::AuctionUncrossed { sequence, .. }
+            | OrderbookEvent::VolatilityInterruption { sequence, .. }
+            | OrderbookEvent::QuoteUpdated { sequence, .. }
+            | OrderbookEvent::QuoteCancelled { sequence, .. }
+            | OrderbookEvent::IndicativePrice { sequence, .. }
+            | OrderbookEvent::TradeBusted { sequence, .. }
+            | OrderbookEvent::BookCleared { sequence, .. } => *sequence,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            OrderbookEvent::OrderAccepted { timestamp, .. }
+            | OrderbookEvent::OrderRejected { timestamp, .. }
+            | OrderbookEvent::Trade { timestamp, .. }
+            | OrderbookEvent::OrderCancelled { timestamp, .. }
+            | OrderbookEvent::OrderAdded { timestamp, .. }
+            | OrderbookEvent::OrderReduced { timestamp, .. }
+            | OrderbookEvent::OrderRemoved { timestamp, .. }
+            | OrderbookEvent::LevelChanged { timestamp, .. }
+            | OrderbookEvent::BestPriceChanged { timestamp, .. }
+            | OrderbookEvent::CorporateAction { timestamp, .. }
+            | OrderbookEvent::MassCancelled { timestamp, .. }
+            | OrderbookEvent::SessionChanged { timestamp, .. }
+            | OrderbookEvent::AuctionUncrossed { timestamp, .. }
+            | OrderbookEvent::VolatilityInterruption { timestamp, .. }
+            | OrderbookEvent::QuoteUpdated { timestamp, .. }
+            | OrderbookEvent::QuoteCancelled { timestamp, .. }
+            | OrderbookEvent::IndicativePrice { timestamp, .. }
+            | OrderbookEvent::TradeBusted { timestamp, .. }
+            | OrderbookEvent::BookCleared { timestamp, .. } => *timestamp,
+        }
+    }
+}
+
+// What happens when a subscriber does not keep up and its buffer is full. Matching never waits
+// for a subscriber.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // the event is not delivered to that subscriber, which notices the gap in the sequence
+    #[default]
+    DropNewest,
+    // the subscriber is disconnected, its receiver reports the disconnect once it is drained
+    Disconnect,
+}
+
+enum Subscriber {
+    Bounded(SyncSender<OrderbookEvent>),
+    Unbounded(Sender<OrderbookEvent>),
+}
+
+pub(crate) struct EventPublisher {
+    subscribers: Vec<Subscriber>,
+    capacity: usize,
+    policy: OverflowPolicy,
+    sequence: u64,
+}
+
+impl EventPublisher {
+    pub(crate) fn new() -> Self {
+        EventPublisher { subscribers: Vec::new(), capacity: 1024, policy: OverflowPolicy::default(), sequence: 0 }
+    }
+
+    pub(crate) fn subscribe(&mut self) -> Receiver<OrderbookEvent> {
+        let (sender, receiver) = mpsc::sync_channel(self.capacity);
+        self.subscribers.push(Subscriber::Bounded(sender));
+        receiver
+    }
+
+    pub(crate) fn subscribe_unbounded(&mut self) -> Receiver<OrderbookEvent> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(Subscriber::Unbounded(sender));
+        receiver
+    }
+
+    pub(crate) fn configure(&mut self, capacity: usize, policy: OverflowPolicy) {
+        self.capacity = capacity;
+        self.policy = policy;
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        !self.subscribers.is_empty()
+    }
+
+    // Builds the event from its sequence and sends it to every subscriber. Events are only
+    // numbered while someone listens.
+    pub(crate) fn publish(&mut self, event: impl FnOnce(u64) -> OrderbookEvent) {
+        if self.subscribers.is_empty() { return; }
+
+        self.sequence += 1;
+        let event = event(self.sequence);
+        let policy = self.policy;
+
+        // dropped receivers are removed, matching goes on without them
+        self.subscribers.retain(|subscriber| match subscriber {
+            Subscriber::Bounded(sender) => match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => policy == OverflowPolicy::DropNewest,
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+            Subscriber::Unbounded(sender) => sender.send(event.clone()).is_ok(),
+        });
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+// The fees of one book in basis points of the notional. Fees are rounded down to whole price
+// units, so a rebate is rounded away from zero. A positive fee is at least the minimum fee, a
+// rebate is never raised to it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeeRates {
+    pub(crate) maker_bps: i64,
+    pub(crate) taker_bps: i64,
+    pub(crate) minimum_fee: i64,
+}
+
+impl FeeRates {
+    // a negative maker rate pays a rebate to the resting order
+    pub fn new(maker_bps: i64, taker_bps: i64) -> Self {
+        FeeRates { maker_bps, taker_bps, minimum_fee: 0 }
+    }
+
+    pub fn with_minimum_fee(mut self, minimum_fee: i64) -> FeeRates {
+        self.minimum_fee = minimum_fee;
+        self
+    }
+
+    pub fn maker_bps(&self) -> i64 {
+        self.maker_bps
+    }
+
+    pub fn taker_bps(&self) -> i64 {
+        self.taker_bps
+    }
+
+    pub fn minimum_fee(&self) -> i64 {
+        self.minimum_fee
+    }
+
+    // The maker and the taker fee of a trade of `amount` at `price`.
+    pub fn fees(&self, price: i64, amount: i64) -> (i64, i64) {
+        let notional = price as i128 * amount as i128;
+        (self.fee(notional, self.maker_bps), self.fee(notional, self.taker_bps))
+    }
+
+    fn fee(&self, notional: i128, bps: i64) -> i64 {
+        if bps == 0 { return 0; }
+        let fee = (notional * bps as i128).div_euclid(10_000).clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        if fee >= 0 { fee.max(self.minimum_fee) } else { fee }
+    }
+}
+
+// The fees of an exchange, the same rates for all securities unless overridden for one of them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FeeSchedule {
+    default: FeeRates,
+    overrides: HashMap<String, FeeRates>,
+}
+
+impl FeeSchedule {
+    pub fn new(default: FeeRates) -> Self {
+        FeeSchedule { default, overrides: HashMap::new() }
+    }
+
+    pub fn with_override(mut self, isin: &str, rates: FeeRates) -> FeeSchedule {
+        self.overrides.insert(isin.to_string(), rates);
+        self
+    }
+
+    pub fn rates_for(&self, isin: &str) -> FeeRates {
+        self.overrides.get(isin).copied().unwrap_or(self.default)
+    }
+}
@@ -0,0 +1,149 @@
+use std::fmt;
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use super::error::OrderbookError;
+use super::market_data::DepthSnapshot;
+use super::orderbook::{Order, OrderReport, OrderState, Orderbook};
+use super::snapshot::BookSnapshot;
+use super::units::OrderId;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum HandleError {
+    // the worker thread is gone, after a shutdown or because it panicked
+    Stopped,
+    Orderbook(OrderbookError),
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HandleError::Stopped => write!(f, "The orderbook worker has stopped"),
+            HandleError::Orderbook(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for HandleError {}
+
+impl From<OrderbookError> for HandleError {
+    fn from(error: OrderbookError) -> Self {
+        HandleError::Orderbook(error)
+    }
+}
+
+// The answer to one request, delivered once the worker got to it.
+pub struct Reply<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Reply<T> {
+    // Blocks until the worker answered.
+    pub fn wait(self) -> Result<T, HandleError> {
+        self.receiver.recv().map_err(|_| HandleError::Stopped)
+    }
+
+    // The answer if it is already there, None while the request is still queued.
+    pub fn try_get(&self) -> Result<Option<T>, HandleError> {
+        match self.receiver.try_recv() {
+            Ok(value) => Ok(Some(value)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(HandleError::Stopped),
+        }
+    }
+}
+
+type Job = Box<dyn FnOnce(&mut Orderbook) + Send>;
+
+enum Request {
+    Run(Job),
+    Shutdown(SyncSender<BookSnapshot>),
+}
+
+// A handle on an orderbook owned by a thread of its own. The thread runs the requests of all
+// clones of the handle one after the other in the order they arrived, so the book sees exactly
+// the sequence of commands it would see from a single caller.
+#[derive(Clone)]
+pub struct OrderbookHandle {
+    requests: Sender<Request>,
+    worker: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
+
+impl OrderbookHandle {
+    // Moves the book to a new thread. The thread ends after shutdown or once every handle is
+    // dropped.
+    pub fn spawn(book: Orderbook) -> OrderbookHandle {
+        let (requests, receiver) = mpsc::channel();
+        let name = format!("orderbook-{}", book.security().isin);
+        let worker = thread::Builder::new().name(name).spawn(move || Self::run(book, receiver)).expect("failed to spawn the orderbook worker");
+        OrderbookHandle { requests, worker: Arc::new(Mutex::new(Some(worker))) }
+    }
+
+    fn run(mut book: Orderbook, receiver: Receiver<Request>) {
+        while let Ok(request) = receiver.recv() {
+            match request {
+                Request::Run(job) => job(&mut book),
+                Request::Shutdown(reply) => {
+                    // whatever was sent before the worker stopped taking requests is still executed
+                    for request in receiver.try_iter() {
+                        if let Request::Run(job) = request { job(&mut book); }
+                    }
+                    let _ = reply.send(book.snapshot());
+                    return;
+                },
+            }
+        }
+    }
+
+    // Runs `job` on the worker thread and hands back what it returned. Every other request is
+    // built on this, it is public for everything the typed requests do not cover.
+    pub fn execute<R: Send + 'static>(&self, job: impl FnOnce(&mut Orderbook) -> R + Send + 'static) -> Reply<R> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        // a stopped worker drops the sender with the job, which the reply reports as Stopped
+        let _ = self.requests.send(Request::Run(Box::new(move |book| { let _ = sender.send(job(book)); })));
+        Reply { receiver }
+    }
+
+    pub fn send_place_order(&self, order: Order) -> Reply<Result<OrderReport, OrderbookError>> {
+        self.execute(move |book| book.place_order(order))
+    }
+
+    pub fn send_cancel_order(&self, order_id: OrderId, account_id: Option<u64>) -> Reply<Result<(), OrderbookError>> {
+        self.execute(move |book| book.cancel_order(order_id, account_id))
+    }
+
+    pub fn send_depth(&self, levels: usize) -> Reply<DepthSnapshot> {
+        self.execute(move |book| book.depth(levels))
+    }
+
+    pub fn send_order_status(&self, order_id: OrderId) -> Reply<Option<OrderState>> {
+        self.execute(move |book| book.order_status(order_id))
+    }
+
+    pub fn place_order(&self, order: Order) -> Result<OrderReport, HandleError> {
+        Ok(self.send_place_order(order).wait()??)
+    }
+
+    pub fn cancel_order(&self, order_id: OrderId, account_id: Option<u64>) -> Result<(), HandleError> {
+        Ok(self.send_cancel_order(order_id, account_id).wait()??)
+    }
+
+    pub fn depth(&self, levels: usize) -> Result<DepthSnapshot, HandleError> {
+        self.send_depth(levels).wait()
+    }
+
+    pub fn order_status(&self, order_id: OrderId) -> Result<Option<OrderState>, HandleError> {
+        self.send_order_status(order_id).wait()
+    }
+
+    // Stops the worker once it executed every request queued so far and returns the final state
+    // of the book. Requests of other clones made afterwards fail with Stopped.
+    pub fn shutdown(&self) -> Result<BookSnapshot, HandleError> {
+        let (sender, receiver) = mpsc::sync_channel(1);
+        self.requests.send(Request::Shutdown(sender)).map_err(|_| HandleError::Stopped)?;
+        let snapshot = receiver.recv().map_err(|_| HandleError::Stopped)?;
+        if let Some(worker) = self.worker.lock().ok().and_then(|mut worker| worker.take()) { let _ = worker.join(); }
+        Ok(snapshot)
+    }
+}
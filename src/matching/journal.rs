@@ -0,0 +1,679 @@
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use super::corporate_action::{CorporateAction, CorporateActionKind, OpenOrderPolicy};
+use super::error::OrderbookError;
+use super::market_data::crc32_update;
+use super::orderbook::{CancelFilter, ClearPolicy, HaltReason, MarketRemainder, MatchingAlgorithm, OcoPolicy, Order, OrderbookConfig, PostOnlyPolicy, PriceBands, QuotePolicy, ReportFlags, Security, SelfTradePolicy, SessionState, Side, TimeInForce};
+use super::units::{Price, Qty};
+
+// When the journal asks the operating system to put appended records on disk.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SyncPolicy {
+    // every command is on disk before it touches the book
+    #[default]
+    Always,
+    // after every n commands, a crash loses at most the last n - 1
+    EveryN(u32),
+    // flushing is left to the operating system
+    Never,
+}
+
+#[derive(Debug)]
+pub enum JournalError {
+    Io(io::Error),
+    // a record in the middle of the log does not match its checksum or cannot be decoded
+    Corrupt { segment: PathBuf, offset: u64 },
+    // replaying a command did not give the same result as when it was logged
+    Diverged { segment: PathBuf, offset: u64 },
+    // the snapshot recovery started from could not be restored
+    Restore(OrderbookError),
+}
+
+impl fmt::Display for JournalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JournalError::Io(error) => write!(f, "journal i/o failed: {}", error),
+            JournalError::Corrupt { segment, offset } => write!(f, "corrupt journal record in {} at offset {}", segment.display(), offset),
+            JournalError::Diverged { segment, offset } => write!(f, "replay of the journal record in {} at offset {} diverged", segment.display(), offset),
+            JournalError::Restore(error) => write!(f, "snapshot restore failed: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for JournalError {}
+
+impl From<io::Error> for JournalError {
+    fn from(error: io::Error) -> Self {
+        JournalError::Io(error)
+    }
+}
+
+// A command as it was given to the book. Orders are logged as the caller built them, together
+// with the id the book assigned, so replay can check it ends up with the same ids.
+#[derive(Clone, Debug)]
+pub(crate) enum JournalEntry {
+    Place { order_id: i64, order: Box<Order> },
+    PlaceOco { primary_id: i64, secondary_id: i64, orders: Box<(Order, Order)> },
+    Cancel { order_id: i64 },
+    ForceCancel { order_id: i64 },
+    CancelAll { filter: CancelFilter },
+    SetSelfTradePolicy { account_id: Option<u64>, policy: SelfTradePolicy },
+    SetSessionState { state: SessionState },
+    Amend { order_id: i64, new_limit: Option<i64>, new_amount: i64 },
+    SetTime { now: u64 },
+    PurgeExpired { now: u64 },
+    EndOfSession,
+    SetOcoPolicy { policy: OcoPolicy },
+    SetMaxStopLimitGap { max_gap: Option<i64> },
+    SetPriceBands { bands: PriceBands },
+    Halt { reason: HaltReason },
+    Resume { through_auction: bool },
+    SetConfig { config: OrderbookConfig },
+    RunBatch,
+    Quote { account_id: u64, bid_id: i64, ask_id: i64, bid: (i64, i64), ask: (i64, i64) },
+    CancelQuote { account_id: u64, quote_id: i64 },
+    BustTrade { trade_id: u64 },
+    ReportTrade { buyer_account: u64, seller_account: u64, price: i64, quantity: i64, flags: ReportFlags },
+    Clear { policy: ClearPolicy },
+    Delist,
+    CorporateAction { action: Box<CorporateAction>, policy: OpenOrderPolicy },
+}
+
+// Append only log of the commands of one book. Every record is framed as
+// [payload length: u32 LE][CRC32 of the payload: u32 LE][payload], so a record cut short by a
+// crash is recognised and dropped on recovery. The active segment lives at the journal path,
+// rotated segments next to it as <path>.1, <path>.2 and so on, oldest first.
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    entries: u64,
+    next_segment: u32,
+    sync_policy: SyncPolicy,
+    max_segment_size: Option<u64>,
+    unsynced: u32,
+    // the first write error, after which the journal refuses all commands
+    failed: Option<io::ErrorKind>,
+}
+
+impl Journal {
+    // Opens the journal at `path`, appending to a segment that is already there.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Journal> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        let segments = segment_paths(&path);
+        let mut entries = 0;
+        for segment in segments.iter().chain([&path]) {
+            entries += record_offsets(&fs::read(segment)?).len() as u64;
+        }
+        let next_segment = segments.len() as u32 + 1;
+        Ok(Journal { path, file, size, entries, next_segment, sync_policy: SyncPolicy::default(), max_segment_size: None, unsynced: 0, failed: None })
+    }
+
+    pub fn with_sync_policy(mut self, sync_policy: SyncPolicy) -> Journal {
+        self.sync_policy = sync_policy;
+        self
+    }
+
+    // Starts a new segment once the active one has grown to `max_segment_size` bytes.
+    pub fn with_rotation(mut self, max_segment_size: u64) -> Journal {
+        self.max_segment_size = Some(max_segment_size);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // commands written to the journal over all its segments
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    // the error of the write that failed, the book refuses commands while it has a failed journal
+    pub fn failed(&self) -> Option<io::ErrorKind> {
+        self.failed
+    }
+
+    pub(crate) fn append(&mut self, entry: &JournalEntry) -> Result<(), io::ErrorKind> {
+        if let Some(kind) = self.failed { return Err(kind); }
+
+        let result = self.write_record(&encode_entry(entry));
+        if let Err(error) = &result { self.failed = Some(error.kind()); }
+        result.map_err(|error| error.kind())
+    }
+
+    fn write_record(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut record = Vec::with_capacity(payload.len() + 8);
+        record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        record.extend_from_slice(&(!crc32_update(!0, payload)).to_le_bytes());
+        record.extend_from_slice(payload);
+        self.file.write_all(&record)?;
+        self.size += record.len() as u64;
+        self.entries += 1;
+
+        self.unsynced += 1;
+        let sync = match self.sync_policy {
+            SyncPolicy::Always => true,
+            SyncPolicy::EveryN(n) => self.unsynced >= n,
+            SyncPolicy::Never => false,
+        };
+        if sync {
+            self.file.sync_data()?;
+            self.unsynced = 0;
+        }
+
+        if self.max_segment_size.is_some_and(|max_size| self.size >= max_size) { self.rotate()?; }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.sync_data()?;
+        fs::rename(&self.path, segment_path(&self.path, self.next_segment))?;
+        self.next_segment += 1;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.unsynced = 0;
+        Ok(())
+    }
+}
+
+fn segment_path(path: &Path, segment: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", segment));
+    PathBuf::from(name)
+}
+
+// Start offsets of the complete records in a segment.
+fn record_offsets(bytes: &[u8]) -> Vec<usize> {
+    let mut offsets = Vec::new();
+    let mut offset = 0;
+    while bytes.len() - offset >= 8 {
+        let length = record_length(bytes, offset);
+        if bytes.len() - offset - 8 < length { break; }
+        offsets.push(offset);
+        offset += 8 + length;
+    }
+    offsets
+}
+
+fn record_length(bytes: &[u8], offset: usize) -> usize {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap()) as usize
+}
+
+// The rotated segments of a journal, oldest first.
+fn segment_paths(path: &Path) -> Vec<PathBuf> {
+    (1..).map(|segment| segment_path(path, segment)).take_while(|segment| segment.exists()).collect()
+}
+
+// All entries of a journal in the order they were written, each with the segment and offset of its
+// record. A record cut short at the end of the active segment is the remains of a crash and is
+// cut off the file, so appending can go on behind the last complete record.
+pub(crate) fn read_journal(path: &Path, security: &Arc<Security>) -> Result<Vec<(JournalEntry, PathBuf, u64)>, JournalError> {
+    let mut entries = Vec::new();
+    let mut segments = segment_paths(path);
+    if path.exists() { segments.push(path.to_path_buf()); }
+
+    for segment in segments {
+        let bytes = fs::read(&segment)?;
+        let offsets = record_offsets(&bytes);
+        let end = offsets.last().map_or(0, |&offset| offset + 8 + record_length(&bytes, offset));
+        if end < bytes.len() {
+            if segment != path { return Err(JournalError::Corrupt { segment, offset: end as u64 }); }
+            OpenOptions::new().write(true).open(&segment)?.set_len(end as u64)?;
+        }
+
+        for offset in offsets {
+            let corrupt = || JournalError::Corrupt { segment: segment.clone(), offset: offset as u64 };
+            let checksum = u32::from_le_bytes(bytes[offset + 4..offset + 8].try_into().unwrap());
+            let payload = &bytes[offset + 8..offset + 8 + record_length(&bytes, offset)];
+            if !crc32_update(!0, payload) != checksum { return Err(corrupt()); }
+
+            let entry = decode_entry(payload, security).ok_or_else(corrupt)?;
+            entries.push((entry, segment.clone(), offset as u64));
+        }
+    }
+
+    Ok(entries)
+}
+
+fn encode_entry(entry: &JournalEntry) -> Vec<u8> {
+    let mut buf = Vec::new();
+    match entry {
+        JournalEntry::Place { order_id, order } => {
+            buf.push(1);
+            put_i64(&mut buf, *order_id);
+            encode_order(&mut buf, order);
+        },
+        JournalEntry::PlaceOco { primary_id, secondary_id, orders } => {
+            buf.push(2);
+            put_i64(&mut buf, *primary_id);
+            encode_order(&mut buf, &orders.0);
+            put_i64(&mut buf, *secondary_id);
+            encode_order(&mut buf, &orders.1);
+        },
+        JournalEntry::Cancel { order_id } => {
+            buf.push(3);
+            put_i64(&mut buf, *order_id);
+        },
+        JournalEntry::Amend { order_id, new_limit, new_amount } => {
+            buf.push(4);
+            put_i64(&mut buf, *order_id);
+            put_opt_i64(&mut buf, *new_limit);
+            put_i64(&mut buf, *new_amount);
+        },
+        JournalEntry::SetTime { now } => {
+            buf.push(5);
+            put_i64(&mut buf, *now as i64);
+        },
+        JournalEntry::PurgeExpired { now } => {
+            buf.push(6);
+            put_i64(&mut buf, *now as i64);
+        },
+        JournalEntry::EndOfSession => buf.push(7),
+        JournalEntry::SetOcoPolicy { policy } => {
+            buf.push(8);
+            buf.push(match policy {
+                OcoPolicy::CancelOnFill => 0,
+                OcoPolicy::ReduceProportionally => 1,
+            });
+        },
+        JournalEntry::SetMaxStopLimitGap { max_gap } => {
+            buf.push(9);
+            put_opt_i64(&mut buf, *max_gap);
+        },
+        JournalEntry::ForceCancel { order_id } => {
+            buf.push(10);
+            put_i64(&mut buf, *order_id);
+        },
+        JournalEntry::CancelAll { filter } => {
+            buf.push(11);
+            match *filter {
+                CancelFilter::All => buf.push(0),
+                CancelFilter::ByAccount(account_id) => {
+                    buf.push(1);
+                    put_i64(&mut buf, account_id as i64);
+                },
+                CancelFilter::BySide(side) => buf.push(match side {
+                    Side::Buy => 2,
+                    Side::Sell => 3,
+                }),
+                CancelFilter::ByPriceRange(low, high) => {
+                    buf.push(4);
+                    put_i64(&mut buf, low);
+                    put_i64(&mut buf, high);
+                },
+            }
+        },
+        JournalEntry::SetSelfTradePolicy { account_id, policy } => {
+            buf.push(12);
+            put_opt_i64(&mut buf, account_id.map(|account_id| account_id as i64));
+            buf.push(match policy {
+                SelfTradePolicy::Allow => 0,
+                SelfTradePolicy::CancelNewest => 1,
+                SelfTradePolicy::CancelOldest => 2,
+                SelfTradePolicy::CancelBoth => 3,
+            });
+        },
+        JournalEntry::SetSessionState { state } => {
+            buf.push(13);
+            buf.push(match state {
+                SessionState::PreOpen => 0,
+                SessionState::Continuous => 1,
+                SessionState::Auction => 2,
+                SessionState::Closed => 3,
+                SessionState::ClosingAuction => 4,
+                SessionState::VolatilityAuction => 5,
+                SessionState::Halted => 6,
+            });
+        },
+        JournalEntry::SetPriceBands { bands } => {
+            buf.push(14);
+            put_opt_i64(&mut buf, bands.static_bps());
+            put_opt_i64(&mut buf, bands.dynamic_bps());
+            put_i64(&mut buf, bands.auction_duration() as i64);
+        },
+        JournalEntry::Halt { reason } => {
+            buf.push(15);
+            buf.push(match reason {
+                HaltReason::NewsPending => 0,
+                HaltReason::Regulatory => 1,
+                HaltReason::Technical => 2,
+                HaltReason::Operator => 3,
+            });
+        },
+        JournalEntry::Resume { through_auction } => {
+            buf.push(16);
+            buf.push(*through_auction as u8);
+        },
+        JournalEntry::SetConfig { config } => {
+            buf.push(17);
+            put_opt_i64(&mut buf, config.market_collar_bps());
+            match config.matching_algorithm() {
+                MatchingAlgorithm::PriceTimePriority => buf.push(0),
+                MatchingAlgorithm::ProRata { min_allocation } => {
+                    buf.push(1);
+                    put_i64(&mut buf, min_allocation);
+                },
+            }
+            buf.push(config.batch_auctions() as u8);
+            buf.push(match config.quote_policy() {
+                QuotePolicy::RejectCrossing => 0,
+                QuotePolicy::Trade => 1,
+            });
+        },
+        JournalEntry::RunBatch => buf.push(18),
+        JournalEntry::Quote { account_id, bid_id, ask_id, bid, ask } => {
+            buf.push(19);
+            put_i64(&mut buf, *account_id as i64);
+            put_i64(&mut buf, *bid_id);
+            put_i64(&mut buf, *ask_id);
+            for value in [bid.0, bid.1, ask.0, ask.1] { put_i64(&mut buf, value); }
+        },
+        JournalEntry::CancelQuote { account_id, quote_id } => {
+            buf.push(20);
+            put_i64(&mut buf, *account_id as i64);
+            put_i64(&mut buf, *quote_id);
+        },
+        JournalEntry::BustTrade { trade_id } => {
+            buf.push(21);
+            put_i64(&mut buf, *trade_id as i64);
+        },
+        JournalEntry::ReportTrade { buyer_account, seller_account, price, quantity, flags } => {
+            buf.push(22);
+            put_i64(&mut buf, *buyer_account as i64);
+            put_i64(&mut buf, *seller_account as i64);
+            put_i64(&mut buf, *price);
+            put_i64(&mut buf, *quantity);
+            buf.push(flags.sets_last_price() as u8 | (flags.negotiated_outside_band() as u8) << 1);
+        },
+        JournalEntry::Clear { policy } => {
+            buf.push(23);
+            buf.push(policy.cancels_orders() as u8 | (policy.resets_statistics() as u8) << 1 | (policy.resets_tape() as u8) << 2 | (policy.resets_order_ids() as u8) << 3);
+        },
+        JournalEntry::Delist => buf.push(24),
+        // the isin is the one of the book
+        JournalEntry::CorporateAction { action, policy } => {
+            buf.push(25);
+            put_i64(&mut buf, action.id() as i64);
+            match action.kind() {
+                CorporateActionKind::CashDividend { amount_per_share } => {
+                    buf.push(0);
+                    put_i64(&mut buf, amount_per_share);
+                },
+                CorporateActionKind::Split { numerator, denominator } => {
+                    buf.push(1);
+                    put_i64(&mut buf, numerator);
+                    put_i64(&mut buf, denominator);
+                },
+            }
+            put_i64(&mut buf, action.ex_timestamp() as i64);
+            buf.push(match policy {
+                OpenOrderPolicy::Reprice => 0,
+                OpenOrderPolicy::Cancel => 1,
+                OpenOrderPolicy::Keep => 2,
+            });
+        },
+    }
+    buf
+}
+
+fn decode_entry(payload: &[u8], security: &Arc<Security>) -> Option<JournalEntry> {
+    let mut reader = Reader { bytes: payload, position: 0 };
+    let entry = match reader.u8()? {
+        1 => JournalEntry::Place { order_id: reader.i64()?, order: Box::new(decode_order(&mut reader, security)?) },
+        2 => {
+            let (primary_id, primary) = (reader.i64()?, decode_order(&mut reader, security)?);
+            let (secondary_id, secondary) = (reader.i64()?, decode_order(&mut reader, security)?);
+            JournalEntry::PlaceOco { primary_id, secondary_id, orders: Box::new((primary, secondary)) }
+        },
+        3 => JournalEntry::Cancel { order_id: reader.i64()? },
+        4 => JournalEntry::Amend { order_id: reader.i64()?, new_limit: reader.opt_i64()?, new_amount: reader.i64()? },
+        5 => JournalEntry::SetTime { now: reader.i64()? as u64 },
+        6 => JournalEntry::PurgeExpired { now: reader.i64()? as u64 },
+        7 => JournalEntry::EndOfSession,
+        8 => JournalEntry::SetOcoPolicy { policy: match reader.u8()? {
+            0 => OcoPolicy::CancelOnFill,
+            1 => OcoPolicy::ReduceProportionally,
+            _ => return None,
+        } },
+        9 => JournalEntry::SetMaxStopLimitGap { max_gap: reader.opt_i64()? },
+        10 => JournalEntry::ForceCancel { order_id: reader.i64()? },
+        11 => JournalEntry::CancelAll { filter: match reader.u8()? {
+            0 => CancelFilter::All,
+            1 => CancelFilter::ByAccount(reader.i64()? as u64),
+            2 => CancelFilter::BySide(Side::Buy),
+            3 => CancelFilter::BySide(Side::Sell),
+            4 => CancelFilter::ByPriceRange(reader.i64()?, reader.i64()?),
+            _ => return None,
+        } },
+        12 => JournalEntry::SetSelfTradePolicy { account_id: reader.opt_i64()?.map(|account_id| account_id as u64), policy: match reader.u8()? {
+            0 => SelfTradePolicy::Allow,
+            1 => SelfTradePolicy::CancelNewest,
+            2 => SelfTradePolicy::CancelOldest,
+            3 => SelfTradePolicy::CancelBoth,
+            _ => return None,
+        } },
+        13 => JournalEntry::SetSessionState { state: match reader.u8()? {
+            0 => SessionState::PreOpen,
+            1 => SessionState::Continuous,
+            2 => SessionState::Auction,
+            3 => SessionState::Closed,
+            4 => SessionState::ClosingAuction,
+            5 => SessionState::VolatilityAuction,
+            6 => SessionState::Halted,
+            _ => return None,
+        } },
+        14 => {
+            let (static_bps, dynamic_bps) = (reader.opt_i64()?, reader.opt_i64()?);
+            let mut bands = PriceBands::new(reader.i64()? as u64);
+            if let Some(bps) = static_bps { bands = bands.with_static_band(bps); }
+            if let Some(bps) = dynamic_bps { bands = bands.with_dynamic_band(bps); }
+            JournalEntry::SetPriceBands { bands }
+        },
+        15 => JournalEntry::Halt { reason: match reader.u8()? {
+            0 => HaltReason::NewsPending,
+            1 => HaltReason::Regulatory,
+            2 => HaltReason::Technical,
+            3 => HaltReason::Operator,
+            _ => return None,
+        } },
+        16 => JournalEntry::Resume { through_auction: match reader.u8()? {
+            0 => false,
+            1 => true,
+            _ => return None,
+        } },
+        17 => {
+            let mut config = OrderbookConfig::new();
+            if let Some(bps) = reader.opt_i64()? { config = config.with_market_collar(bps); }
+            config = config.with_matching_algorithm(match reader.u8()? {
+                0 => MatchingAlgorithm::PriceTimePriority,
+                1 => MatchingAlgorithm::ProRata { min_allocation: reader.i64()? },
+                _ => return None,
+            });
+            if reader.u8()? == 1 { config = config.with_batch_auctions(); }
+            config = config.with_quote_policy(match reader.u8()? {
+                0 => QuotePolicy::RejectCrossing,
+                1 => QuotePolicy::Trade,
+                _ => return None,
+            });
+            JournalEntry::SetConfig { config }
+        },
+        18 => JournalEntry::RunBatch,
+        19 => {
+            let (account_id, bid_id, ask_id) = (reader.i64()? as u64, reader.i64()?, reader.i64()?);
+            let (bid, ask) = ((reader.i64()?, reader.i64()?), (reader.i64()?, reader.i64()?));
+            JournalEntry::Quote { account_id, bid_id, ask_id, bid, ask }
+        },
+        20 => JournalEntry::CancelQuote { account_id: reader.i64()? as u64, quote_id: reader.i64()? },
+        21 => JournalEntry::BustTrade { trade_id: reader.i64()? as u64 },
+        22 => {
+            let (buyer_account, seller_account, price, quantity) = (reader.i64()? as u64, reader.i64()? as u64, reader.i64()?, reader.i64()?);
+            let bits = reader.u8()?;
+            if bits > 3 { return None; }
+            let mut flags = ReportFlags::new();
+            if bits & 1 != 0 { flags = flags.with_last_price(); }
+            if bits & 2 != 0 { flags = flags.with_negotiated_outside_band(); }
+            JournalEntry::ReportTrade { buyer_account, seller_account, price, quantity, flags }
+        },
+        23 => {
+            let bits = reader.u8()?;
+            if bits > 15 { return None; }
+            let mut policy = ClearPolicy::new();
+            if bits & 1 != 0 { policy = policy.with_cancellations(); }
+            if bits & 2 != 0 { policy = policy.with_statistics_reset(); }
+            if bits & 4 != 0 { policy = policy.with_tape_reset(); }
+            if bits & 8 != 0 { policy = policy.with_order_id_reset(); }
+            JournalEntry::Clear { policy }
+        },
+        24 => JournalEntry::Delist,
+        25 => {
+            let id = reader.i64()? as u64;
+            let kind = match reader.u8()? {
+                0 => CorporateActionKind::CashDividend { amount_per_share: reader.i64()? },
+                1 => CorporateActionKind::Split { numerator: reader.i64()?, denominator: reader.i64()? },
+                _ => return None,
+            };
+            let action = Box::new(CorporateAction::new(id, &security.isin, kind, reader.i64()? as u64));
+            let policy = match reader.u8()? {
+                0 => OpenOrderPolicy::Reprice,
+                1 => OpenOrderPolicy::Cancel,
+                2 => OpenOrderPolicy::Keep,
+                _ => return None,
+            };
+            JournalEntry::CorporateAction { action, policy }
+        },
+        _ => return None,
+    };
+    // trailing bytes mean the record is not what it claims to be
+    if reader.position != payload.len() { return None; }
+    Some(entry)
+}
+
+fn encode_order(buf: &mut Vec<u8>, order: &Order) {
+    buf.push(match order.side() {
+        Side::Buy => 0,
+        Side::Sell => 1,
+    });
+    put_opt_i64(buf, order.order_limit());
+    put_i64(buf, order.amount());
+    buf.push(match order.time_in_force() {
+        TimeInForce::GoodTillCancel => 0,
+        TimeInForce::ImmediateOrCancel => 1,
+        TimeInForce::FillOrKill => 2,
+        TimeInForce::Day => 3,
+        TimeInForce::AtTheClose => 4,
+    });
+    put_opt_i64(buf, order.stop_price());
+    put_opt_i64(buf, order.trailing_offset());
+    put_opt_i64(buf, order.peg_offset());
+    put_opt_i64(buf, order.min_quantity());
+    buf.push(match order.market_remainder() {
+        MarketRemainder::RestAsMarket => 0,
+        MarketRemainder::ConvertToLimit => 1,
+        MarketRemainder::CancelRemainder => 2,
+    });
+    put_opt_i64(buf, order.account_id().map(|account_id| account_id as i64));
+    buf.push(order.is_reduce_only() as u8);
+    put_opt_i64(buf, order.display_quantity());
+    buf.push(match order.post_only() {
+        None => 0,
+        Some(PostOnlyPolicy::Reject) => 1,
+        Some(PostOnlyPolicy::Reprice) => 2,
+    });
+    put_opt_i64(buf, order.expires_at().map(|expires_at| expires_at as i64));
+    buf.push(order.is_continuous_only() as u8);
+    buf.push(order.is_midpoint() as u8);
+    put_opt_i64(buf, order.client_order_id().map(|client_order_id| client_order_id as i64));
+}
+
+fn decode_order(reader: &mut Reader, security: &Arc<Security>) -> Option<Order> {
+    let side = match reader.u8()? {
+        0 => Side::Buy,
+        1 => Side::Sell,
+        _ => return None,
+    };
+    let order_limit = reader.opt_i64()?;
+    let amount = reader.i64()?;
+    let time_in_force = match reader.u8()? {
+        0 => TimeInForce::GoodTillCancel,
+        1 => TimeInForce::ImmediateOrCancel,
+        2 => TimeInForce::FillOrKill,
+        3 => TimeInForce::Day,
+        4 => TimeInForce::AtTheClose,
+        _ => return None,
+    };
+    let mut order = Order::new(side, order_limit.map(Price), security, Qty(amount), time_in_force);
+
+    if let Some(stop_price) = reader.opt_i64()? { order = order.with_stop_price(stop_price); }
+    if let Some(offset) = reader.opt_i64()? { order = order.with_trailing_stop(offset); }
+    if let Some(offset) = reader.opt_i64()? { order = order.with_peg(offset); }
+    if let Some(min_quantity) = reader.opt_i64()? { order = order.with_min_quantity(min_quantity); }
+    order = order.with_market_remainder(match reader.u8()? {
+        0 => MarketRemainder::RestAsMarket,
+        1 => MarketRemainder::ConvertToLimit,
+        2 => MarketRemainder::CancelRemainder,
+        _ => return None,
+    });
+    if let Some(account_id) = reader.opt_i64()? { order = order.with_account(account_id as u64); }
+    if reader.u8()? == 1 { order = order.with_reduce_only(); }
+    if let Some(display_quantity) = reader.opt_i64()? { order = order.with_display_quantity(display_quantity); }
+    match reader.u8()? {
+        0 => {},
+        1 => order = order.with_post_only(PostOnlyPolicy::Reject),
+        2 => order = order.with_post_only(PostOnlyPolicy::Reprice),
+        _ => return None,
+    }
+    if let Some(expires_at) = reader.opt_i64()? { order = order.with_expiry(expires_at as u64); }
+    if reader.u8()? == 1 { order = order.with_continuous_only(); }
+    if reader.u8()? == 1 { order = order.with_midpoint_peg(); }
+    if let Some(client_order_id) = reader.opt_i64()? { order = order.with_client_order_id(client_order_id as u64); }
+
+    Some(order)
+}
+
+fn put_i64(buf: &mut Vec<u8>, value: i64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_opt_i64(buf: &mut Vec<u8>, value: Option<i64>) {
+    match value {
+        Some(value) => {
+            buf.push(1);
+            put_i64(buf, value);
+        },
+        None => buf.push(0),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.bytes.get(self.position)?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn i64(&mut self) -> Option<i64> {
+        let bytes = self.bytes.get(self.position..self.position + 8)?;
+        self.position += 8;
+        Some(i64::from_le_bytes(bytes.try_into().ok()?))
+    }
+
+    fn opt_i64(&mut self) -> Option<Option<i64>> {
+        match self.u8()? {
+            0 => Some(None),
+            1 => Some(Some(self.i64()?)),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use std::sync::{Arc, Mutex};
+
+use super::orderbook::Execution;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CancelReason {
+    // cancel_order was called for the order
+    Requested,
+    // the operator of the exchange cancelled the order with force_cancel
+    Operator,
+    // selected by a cancel_all filter
+    MassCancel,
+    // the session of the account timed out, see Exchange::expire_sessions
+    Disconnect,
+    // the kill switch of the account was pulled
+    KillSwitch,
+    // the order met an order of its own account, see SelfTradePolicy
+    SelfTrade,
+    // a continuous only order when the closing auction started
+    ContinuousOnly,
+    // the expiry time of the order was reached
+    Expired,
+    // a day order at the end of the session
+    EndOfSession,
+    // the other leg of its oco pair traded or was cancelled
+    OcoSibling,
+    // the account submitted a new quote in place of the one the order belonged to
+    QuoteReplaced,
+    // the quote the order belonged to was cancelled with cancel_quote
+    QuoteCancelled,
+    // the part of an immediate or cancel order, or of a market order, that found no liquidity
+    Unfilled,
+    // a fill or kill or minimum quantity order that could not be filled as required
+    Killed,
+    // a reduce only order that would increase the position of its account
+    ReduceOnly,
+    // the security was delisted
+    Delisted,
+    // a corporate action of the security, see OpenOrderPolicy
+    CorporateAction,
+    // the book was cleared, see Orderbook::clear
+    Cleared,
+}
+
+// The top of the book after a change, published once per place, amend or cancel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookUpdate {
+    pub(crate) sequence: u64,
+    pub(crate) best_bid: Option<i64>,
+    pub(crate) best_ask: Option<i64>,
+    pub(crate) last_price: i64,
+}
+
+impl BookUpdate {
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn best_bid(&self) -> Option<i64> {
+        self.best_bid
+    }
+
+    pub fn best_ask(&self) -> Option<i64> {
+        self.best_ask
+    }
+
+    pub fn last_price(&self) -> i64 {
+        self.last_price
+    }
+}
+
+// Called synchronously by the book while it processes an order: every execution the moment it
+// happens, every order that leaves the book without being filled, and the top of the book once
+// the call is done.
+pub trait ExecutionListener {
+    fn on_execution(&mut self, execution: &Execution);
+    fn on_order_cancelled(&mut self, order_id: i64, reason: CancelReason);
+    fn on_book_update(&mut self, update: &BookUpdate);
+}
+
+// Lets the caller keep a handle on a listener that is owned by the book.
+impl<T: ExecutionListener> ExecutionListener for Arc<Mutex<T>> {
+    fn on_execution(&mut self, execution: &Execution) {
+        if let Ok(mut listener) = self.lock() { listener.on_execution(execution); }
+    }
+
+    fn on_order_cancelled(&mut self, order_id: i64, reason: CancelReason) {
+        if let Ok(mut listener) = self.lock() { listener.on_order_cancelled(order_id, reason); }
+    }
+
+    fn on_book_update(&mut self, update: &BookUpdate) {
+        if let Ok(mut listener) = self.lock() { listener.on_book_update(update); }
+    }
+}
+
+pub struct NoopListener;
+
+impl ExecutionListener for NoopListener {
+    fn on_execution(&mut self, _execution: &Execution) {}
+
+    fn on_order_cancelled(&mut self, _order_id: i64, _reason: CancelReason) {}
+
+    fn on_book_update(&mut self, _update: &BookUpdate) {}
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ListenerEvent {
+    Execution(Execution),
+    OrderCancelled(i64, CancelReason),
+    BookUpdate(BookUpdate),
+}
+
+// Records every callback in the order it was made.
+#[derive(Clone, Debug, Default)]
+pub struct VecListener {
+    events: Vec<ListenerEvent>,
+}
+
+impl VecListener {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> &[ListenerEvent] {
+        &self.events
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+}
+
+impl ExecutionListener for VecListener {
+    fn on_execution(&mut self, execution: &Execution) {
+        self.events.push(ListenerEvent::Execution(execution.clone()));
+    }
+
+    fn on_order_cancelled(&mut self, order_id: i64, reason: CancelReason) {
+        self.events.push(ListenerEvent::OrderCancelled(order_id, reason));
+    }
+
+    fn on_book_update(&mut self, update: &BookUpdate) {
+        self.events.push(ListenerEvent::BookUpdate(*update));
+    }
+}
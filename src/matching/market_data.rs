@@ -0,0 +1,324 @@
+use super::auction::AuctionResult;
+use super::orderbook::{Halt, LevelOrders, Order, SessionState, Side};
+use super::units::{Price, Qty};
+
+// Aggregated view of one price level. Only the visible quantity is counted, the hidden part of
+// icebergs stays out of market data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepthLevel {
+    pub(crate) price: i64,
+    pub(crate) quantity: i64,
+    pub(crate) order_count: usize,
+}
+
+impl DepthLevel {
+    pub fn price(&self) -> Price {
+        Price(self.price)
+    }
+
+    pub fn quantity(&self) -> Qty {
+        Qty(self.quantity)
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.order_count
+    }
+}
+
+// The top levels of both sides, best price first. The sequence grows with every change of the
+// book, so a consumer that sees it jump by more than one has missed an update.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DepthSnapshot {
+    pub(crate) bids: Vec<DepthLevel>,
+    pub(crate) asks: Vec<DepthLevel>,
+    pub(crate) last_price: i64,
+    pub(crate) sequence: u64,
+    pub(crate) checksum: u32,
+    // quotes outside continuous trading may be crossed, nothing trades on them until the book opens
+    pub(crate) session_state: SessionState,
+    pub(crate) halt: Option<Halt>,
+}
+
+impl DepthSnapshot {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn bids(&self) -> &[DepthLevel] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[DepthLevel] {
+        &self.asks
+    }
+
+    pub fn last_price(&self) -> i64 {
+        self.last_price
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // book_checksum over the levels of the snapshot
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        self.session_state
+    }
+
+    // the halt going on, the quotes are indicative only while it lasts
+    pub fn halt(&self) -> Option<Halt> {
+        self.halt
+    }
+}
+
+// CRC32 (IEEE) of the levels in a canonical form, so a consumer can check a locally maintained book
+// against the one of the engine. The asks are taken best price first, followed by the bids best
+// price first, and every level contributes the ASCII text "<price>:<quantity>;" with both
+// numbers in plain decimal. Only visible quantity is part of a level.
+pub fn book_checksum(bids: &[DepthLevel], asks: &[DepthLevel]) -> u32 {
+    let mut crc = !0u32;
+    for level in asks.iter().chain(bids) {
+        crc = crc32_update(crc, format!("{}:{};", level.price, level.quantity).as_bytes());
+    }
+    !crc
+}
+
+// One step of the reflected CRC32 (IEEE), the caller starts with !0 and inverts the result.
+pub(crate) fn crc32_update(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    crc
+}
+
+// One resting order as seen from outside the book. `queue_position` counts from 0 at the front of
+// the price level, `timestamp` is the engine time at which the order joined the back of its queue.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderView {
+    pub(crate) order_id: i64,
+    pub(crate) side: Side,
+    pub(crate) price: i64,
+    pub(crate) remaining: i64,
+    pub(crate) visible: i64,
+    pub(crate) queue_position: usize,
+    pub(crate) timestamp: u64,
+}
+
+impl OrderView {
+    pub fn order_id(&self) -> i64 {
+        self.order_id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn remaining(&self) -> i64 {
+        self.remaining
+    }
+
+    // the part of the remainder that is shown, less than the remainder only for icebergs
+    pub fn visible(&self) -> i64 {
+        self.visible
+    }
+
+    pub fn queue_position(&self) -> usize {
+        self.queue_position
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+// Every resting limit order of both sides in matching priority: best price first and in queue
+// order within a price.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookView {
+    pub(crate) bids: Vec<OrderView>,
+    pub(crate) asks: Vec<OrderView>,
+}
+
+impl BookView {
+    pub fn bids(&self) -> &[OrderView] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[OrderView] {
+        &self.asks
+    }
+}
+
+// Trading statistics of the current session, updated by executions only.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionStats {
+    pub(crate) starting_price: i64,
+    pub(crate) last_price: i64,
+    pub(crate) open: Option<i64>,
+    pub(crate) high: Option<i64>,
+    pub(crate) low: Option<i64>,
+    pub(crate) volume: i64,
+    pub(crate) turnover: i128,
+    pub(crate) trade_count: u64,
+    pub(crate) closing_auction: Option<AuctionResult>,
+}
+
+impl SessionStats {
+    pub(crate) fn new(starting_price: i64) -> Self {
+        SessionStats { starting_price, last_price: starting_price, ..Default::default() }
+    }
+
+    pub(crate) fn record(&mut self, price: i64, amount: i64) {
+        self.last_price = price;
+        self.open.get_or_insert(price);
+        self.high = Some(self.high.map_or(price, |high| high.max(price)));
+        self.low = Some(self.low.map_or(price, |low| low.min(price)));
+        self.volume += amount;
+        self.turnover += price as i128 * amount as i128;
+        self.trade_count += 1;
+    }
+
+    pub fn starting_price(&self) -> i64 {
+        self.starting_price
+    }
+
+    pub fn last_price(&self) -> i64 {
+        self.last_price
+    }
+
+    // the price of the first trade of the session
+    pub fn open(&self) -> Option<i64> {
+        self.open
+    }
+
+    pub fn high(&self) -> Option<i64> {
+        self.high
+    }
+
+    pub fn low(&self) -> Option<i64> {
+        self.low
+    }
+
+    pub fn volume(&self) -> i64 {
+        self.volume
+    }
+
+    // the sum of price times quantity over all trades of the session
+    pub fn turnover(&self) -> i128 {
+        self.turnover
+    }
+
+    pub fn trade_count(&self) -> u64 {
+        self.trade_count
+    }
+
+    // the result of the closing auction, the official closing price, once it ran
+    pub fn closing_auction(&self) -> Option<AuctionResult> {
+        self.closing_auction
+    }
+
+    pub fn change(&self) -> i64 {
+        self.last_price - self.starting_price
+    }
+
+    // the change against the starting price in percent, None if the starting price is zero
+    pub fn change_percent(&self) -> Option<f64> {
+        if self.starting_price == 0 { return None; }
+        Some(self.change() as f64 * 100.0 / self.starting_price as f64)
+    }
+}
+
+// A price level borrowed from the book. Quantity and order count are computed when asked for,
+// and holding a LevelRef keeps the book borrowed immutably.
+#[derive(Clone, Copy)]
+pub struct LevelRef<'a> {
+    pub(crate) price: i64,
+    pub(crate) quantity: i64,
+    pub(crate) order_count: usize,
+    pub(crate) orders: LevelOrders<'a>,
+}
+
+impl<'a> LevelRef<'a> {
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    // the visible quantity of the level, hidden iceberg quantity is not included
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.order_count
+    }
+
+    // the orders of the level in queue order
+    pub fn orders(&self) -> impl Iterator<Item = &'a Order> + 'a {
+        self.orders
+    }
+
+    pub fn to_depth_level(&self) -> DepthLevel {
+        DepthLevel { price: self.price, quantity: self.quantity(), order_count: self.order_count() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::matching::orderbook::{OrderBuilder, Orderbook, Security};
+
+    #[test]
+    fn the_crc_is_the_ieee_one() {
+        assert_eq!(!crc32_update(!0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(book_checksum(&[], &[]), 0);
+    }
+
+    #[test]
+    fn the_checksum_of_a_small_book_is_the_one_worked_out_by_hand() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let limit = |side, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity));
+        book.place_order(limit(Side::Sell, 101, 2).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 101, 3).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 102, 3).build().unwrap()).unwrap();
+        // only the 4 shown of the iceberg count
+        book.place_order(limit(Side::Buy, 99, 10).display_quantity(Qty(4)).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Buy, 98, 2).build().unwrap()).unwrap();
+
+        // CRC32 of "101:5;102:3;99:4;98:2;"
+        assert_eq!(book.checksum(2), 0xDED8_F192);
+        assert_eq!(book.depth(2).checksum(), 0xDED8_F192);
+        // CRC32 of "101:5;99:4;"
+        assert_eq!(book.checksum(1), 0x51FE_56FE);
+        assert_eq!(book.depth(1).checksum(), 0x51FE_56FE);
+    }
+
+    #[test]
+    fn the_checksum_follows_the_levels_in_canonical_order() {
+        let bids = [DepthLevel { price: 99, quantity: 4, order_count: 1 }];
+        let asks = [DepthLevel { price: 101, quantity: 5, order_count: 2 }, DepthLevel { price: 102, quantity: 3, order_count: 1 }];
+        // CRC32 of "101:5;102:3;99:4;", the order count is not part of it
+        assert_eq!(book_checksum(&bids, &asks), 0x95D7_1257);
+        let counted = [DepthLevel { price: 101, quantity: 5, order_count: 5 }, asks[1]];
+        assert_eq!(book_checksum(&bids, &counted), 0x95D7_1257);
+        assert_ne!(book_checksum(&bids, &[asks[1], asks[0]]), 0x95D7_1257);
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use super::events::OrderbookEvent;
+use super::market_data::{book_checksum, DepthLevel};
+use super::orderbook::Side;
+
+// Order level (L3) market data in fixed size little endian messages. Every message starts with
+// the same 17 byte header:
+//
+//   offset 0   u8   message type
+//   offset 1   u64  sequence, gapless within one feed and starting at 1
+//   offset 9   u64  engine timestamp
+//
+// followed by the body of its type:
+//
+//   'A' AddOrder       42 bytes   17 order id i64, 25 side u8 (b'B' or b'S'), 26 price i64, 34 quantity i64
+//   'E' OrderExecuted  49 bytes   17 order id i64, 25 executed quantity i64, 33 price i64, 41 trade id u64
+//   'X' OrderCancel    33 bytes   17 order id i64, 25 cancelled quantity i64
+//   'P' Trade          57 bytes   17 buying order id i64, 25 selling order id i64, 33 price i64,
+//                                 41 quantity i64, 49 trade id u64
+//
+// Quantities are visible quantities. An order that has no visible quantity left is off the book,
+// the next slice of an iceberg comes with a new AddOrder for the same order id. Trade reports
+// executions of orders that were never displayed, such as parked market orders.
+pub const HEADER_SIZE: usize = 17;
+pub const ADD_ORDER_SIZE: usize = 42;
+pub const ORDER_EXECUTED_SIZE: usize = 49;
+pub const ORDER_CANCEL_SIZE: usize = 33;
+pub const TRADE_SIZE: usize = 57;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedMessage {
+    AddOrder { sequence: u64, timestamp: u64, order_id: i64, side: Side, price: i64, quantity: i64 },
+    OrderExecuted { sequence: u64, timestamp: u64, order_id: i64, quantity: i64, price: i64, trade_id: u64 },
+    OrderCancel { sequence: u64, timestamp: u64, order_id: i64, quantity: i64 },
+    Trade { sequence: u64, timestamp: u64, buying_order_id: i64, selling_order_id: i64, price: i64, quantity: i64, trade_id: u64 },
+}
+
+#[derive(Debug)]
+pub enum FeedError {
+    Io(io::Error),
+    // the bytes end inside a message
+    Truncated { offset: usize },
+    UnknownMessageType { offset: usize, message_type: u8 },
+    InvalidSide { offset: usize },
+    // a message was lost between the previous one and this one
+    SequenceGap { expected: u64, received: u64 },
+}
+
+impl fmt::Display for FeedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeedError::Io(error) => write!(f, "feed i/o failed: {}", error),
+            FeedError::Truncated { offset } => write!(f, "feed ends inside the message at offset {}", offset),
+            FeedError::UnknownMessageType { offset, message_type } => write!(f, "unknown message type {} at offset {}", message_type, offset),
+            FeedError::InvalidSide { offset } => write!(f, "invalid side in the message at offset {}", offset),
+            FeedError::SequenceGap { expected, received } => write!(f, "expected sequence {} but received {}", expected, received),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+impl From<io::Error> for FeedError {
+    fn from(error: io::Error) -> Self {
+        FeedError::Io(error)
+    }
+}
+
+impl FeedMessage {
+    pub fn sequence(&self) -> u64 {
+        match self {
+            FeedMessage::AddOrder { sequence, .. }
+            | FeedMessage::OrderExecuted { sequence, .. }
+            | FeedMessage::OrderCancel { sequence, .. }
+            | FeedMessage::Trade { sequence, .. } => *sequence,
+        }
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            FeedMessage::AddOrder { timestamp, .. }
+            | FeedMessage::OrderExecuted { timestamp, .. }
+            | FeedMessage::OrderCancel { timestamp, .. }
+            | FeedMessage::Trade { timestamp, .. } => *timestamp,
+        }
+    }
+
+    // Appends the message to `out`, which only allocates when it has to grow.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let (message_type, sequence, timestamp) = match *self {
+            FeedMessage::AddOrder { sequence, timestamp, .. } => (b'A', sequence, timestamp),
+            FeedMessage::OrderExecuted { sequence, timestamp, .. } => (b'E', sequence, timestamp),
+            FeedMessage::OrderCancel { sequence, timestamp, .. } => (b'X', sequence, timestamp),
+            FeedMessage::Trade { sequence, timestamp, .. } => (b'P', sequence, timestamp),
+        };
+        out.push(message_type);
+        out.extend_from_slice(&sequence.to_le_bytes());
+        out.extend_from_slice(&timestamp.to_le_bytes());
+
+        match *self {
+            FeedMessage::AddOrder { order_id, side, price, quantity, .. } => {
+                out.extend_from_slice(&order_id.to_le_bytes());
+                out.push(match side {
+                    Side::Buy => b'B',
+                    Side::Sell => b'S',
+                });
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+            },
+            FeedMessage::OrderExecuted { order_id, quantity, price, trade_id, .. } => {
+                out.extend_from_slice(&order_id.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&trade_id.to_le_bytes());
+            },
+            FeedMessage::OrderCancel { order_id, quantity, .. } => {
+                out.extend_from_slice(&order_id.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+            },
+            FeedMessage::Trade { buying_order_id, selling_order_id, price, quantity, trade_id, .. } => {
+                out.extend_from_slice(&buying_order_id.to_le_bytes());
+                out.extend_from_slice(&selling_order_id.to_le_bytes());
+                out.extend_from_slice(&price.to_le_bytes());
+                out.extend_from_slice(&quantity.to_le_bytes());
+                out.extend_from_slice(&trade_id.to_le_bytes());
+            },
+        }
+    }
+
+    // Reads the message at the start of `bytes` and returns it with its size. `offset` is only used
+    // to point at the message in errors.
+    pub fn decode(bytes: &[u8], offset: usize) -> Result<(FeedMessage, usize), FeedError> {
+        let Some(&message_type) = bytes.first() else { return Err(FeedError::Truncated { offset }); };
+        let size = match message_type {
+            b'A' => ADD_ORDER_SIZE,
+            b'E' => ORDER_EXECUTED_SIZE,
+            b'X' => ORDER_CANCEL_SIZE,
+            b'P' => TRADE_SIZE,
+            _ => return Err(FeedError::UnknownMessageType { offset, message_type }),
+        };
+        if bytes.len() < size { return Err(FeedError::Truncated { offset }); }
+
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let i64_at = |at: usize| i64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        let (sequence, timestamp) = (u64_at(1), u64_at(9));
+
+        let message = match message_type {
+            b'A' => {
+                let side = match bytes[25] {
+                    b'B' => Side::Buy,
+                    b'S' => Side::Sell,
+                    _ => return Err(FeedError::InvalidSide { offset }),
+                };
+                FeedMessage::AddOrder { sequence, timestamp, order_id: i64_at(17), side, price: i64_at(26), quantity: i64_at(34) }
+            },
+            b'E' => FeedMessage::OrderExecuted { sequence, timestamp, order_id: i64_at(17), quantity: i64_at(25), price: i64_at(33), trade_id: u64_at(41) },
+            b'X' => FeedMessage::OrderCancel { sequence, timestamp, order_id: i64_at(17), quantity: i64_at(25) },
+            _ => FeedMessage::Trade {
+                sequence,
+                timestamp,
+                buying_order_id: i64_at(17),
+                selling_order_id: i64_at(25),
+                price: i64_at(33),
+                quantity: i64_at(41),
+                trade_id: u64_at(49),
+            },
+        };
+        Ok((message, size))
+    }
+}
+
+// Turns the events of one book into feed messages. It keeps the visible quantity of every
+// displayed order, which tells executions of displayed orders from those of hidden ones and
+// gives cancels their quantity.
+#[derive(Debug, Default)]
+pub struct FeedEncoder {
+    sequence: u64,
+    displayed: HashMap<i64, i64>,
+}
+
+impl FeedEncoder {
+    pub fn new() -> Self {
+        FeedEncoder::default()
+    }
+
+    // Appends the messages for `event` to `out` and returns how many there were. Events without
+    // an effect on single orders, like level and best price changes, produce none. A cleared book
+    // cancels every displayed order that was not cancelled on its own before.
+    pub fn encode(&mut self, event: &OrderbookEvent, out: &mut Vec<u8>) -> usize {
+        let timestamp = event.timestamp();
+        if let OrderbookEvent::BookCleared { .. } = event {
+            let mut cleared: Vec<(i64, i64)> = self.displayed.drain().collect();
+            cleared.sort_unstable();
+            for &(order_id, quantity) in &cleared {
+                self.emit(FeedMessage::OrderCancel { sequence: 0, timestamp, order_id, quantity }, out);
+            }
+            return cleared.len();
+        }
+
+        let message = match *event {
+            OrderbookEvent::OrderAdded { order_id, side, price, quantity, .. } => {
+                self.displayed.insert(order_id, quantity);
+                Some(FeedMessage::AddOrder { sequence: 0, timestamp, order_id, side, price, quantity })
+            },
+            OrderbookEvent::Trade { ref execution, .. } => {
+                let (price, quantity, trade_id) = (execution.price(), execution.amount(), execution.trade_id());
+                let resting_id = [execution.buying_order_id(), execution.selling_order_id()].into_iter().find(|order_id| self.displayed.contains_key(order_id));
+                match resting_id {
+                    Some(order_id) => {
+                        self.reduce(order_id, quantity);
+                        Some(FeedMessage::OrderExecuted { sequence: 0, timestamp, order_id, quantity, price, trade_id })
+                    },
+                    None => Some(FeedMessage::Trade {
+                        sequence: 0,
+                        timestamp,
+                        buying_order_id: execution.buying_order_id(),
+                        selling_order_id: execution.selling_order_id(),
+                        price,
+                        quantity,
+                        trade_id,
+                    }),
+                }
+            },
+            OrderbookEvent::OrderReduced { order_id, quantity, .. } => {
+                let cancelled = self.displayed.get(&order_id).map_or(0, |&displayed| displayed - quantity);
+                self.reduce(order_id, cancelled);
+                (cancelled > 0).then_some(FeedMessage::OrderCancel { sequence: 0, timestamp, order_id, quantity: cancelled })
+            },
+            OrderbookEvent::OrderCancelled { order_id, .. } | OrderbookEvent::OrderRemoved { order_id, .. } => {
+                self.displayed.remove(&order_id).map(|quantity| FeedMessage::OrderCancel { sequence: 0, timestamp, order_id, quantity })
+            },
+            _ => None,
+        };
+
+        let Some(message) = message else { return 0; };
+        self.emit(message, out);
+        1
+    }
+
+    fn emit(&mut self, mut message: FeedMessage, out: &mut Vec<u8>) {
+        self.sequence += 1;
+        match &mut message {
+            FeedMessage::AddOrder { sequence, .. }
+            | FeedMessage::OrderExecuted { sequence, .. }
+            | FeedMessage::OrderCancel { sequence, .. }
+            | FeedMessage::Trade { sequence, .. } => *sequence = self.sequence,
+        }
+        message.encode(out);
+    }
+
+    fn reduce(&mut self, order_id: i64, quantity: i64) {
+        let Some(displayed) = self.displayed.get_mut(&order_id) else { return; };
+        *displayed -= quantity;
+        if *displayed <= 0 { self.displayed.remove(&order_id); }
+    }
+}
+
+// An order by order book rebuilt from feed messages alone.
+#[derive(Debug, Default)]
+pub struct FeedBook {
+    orders: HashMap<i64, (Side, i64, i64)>,
+    bids: BTreeMap<i64, VecDeque<i64>>,
+    asks: BTreeMap<i64, VecDeque<i64>>,
+    last_sequence: u64,
+}
+
+impl FeedBook {
+    pub fn new() -> Self {
+        FeedBook::default()
+    }
+
+    pub fn apply(&mut self, message: &FeedMessage) -> Result<(), FeedError> {
+        let expected = self.last_sequence + 1;
+        if message.sequence() != expected { return Err(FeedError::SequenceGap { expected, received: message.sequence() }); }
+        self.last_sequence = expected;
+
+        match *message {
+            FeedMessage::AddOrder { order_id, side, price, quantity, .. } => {
+                self.orders.insert(order_id, (side, price, quantity));
+                self.levels_mut(side).entry(price).or_default().push_back(order_id);
+            },
+            FeedMessage::OrderExecuted { order_id, quantity, .. } | FeedMessage::OrderCancel { order_id, quantity, .. } => {
+                let Some(order) = self.orders.get_mut(&order_id) else { return Ok(()); };
+                order.2 -= quantity;
+                let (side, price, remaining) = *order;
+                if remaining > 0 { return Ok(()); }
+
+                self.orders.remove(&order_id);
+                let levels = self.levels_mut(side);
+                if let Some(level) = levels.get_mut(&price) {
+                    level.retain(|&queued_id| queued_id != order_id);
+                    if level.is_empty() { levels.remove(&price); }
+                }
+            },
+            FeedMessage::Trade { .. } => {},
+        }
+        Ok(())
+    }
+
+    fn levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, VecDeque<i64>> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn depth_level(&self, price: i64, level: &VecDeque<i64>) -> DepthLevel {
+        let quantity = level.iter().filter_map(|order_id| self.orders.get(order_id)).map(|order| order.2).sum();
+        DepthLevel { price, quantity, order_count: level.len() }
+    }
+
+    // best price first, like Orderbook::depth
+    pub fn bids(&self) -> Vec<DepthLevel> {
+        self.bids.iter().rev().map(|(&price, level)| self.depth_level(price, level)).collect()
+    }
+
+    pub fn asks(&self) -> Vec<DepthLevel> {
+        self.asks.iter().map(|(&price, level)| self.depth_level(price, level)).collect()
+    }
+
+    // book_checksum over all levels, equal to the one of the source book from the same depth
+    pub fn checksum(&self) -> u32 {
+        book_checksum(&self.bids(), &self.asks())
+    }
+
+    // the visible quantities of the orders at `price` in queue order
+    pub fn orders_at(&self, side: Side, price: i64) -> Vec<(i64, i64)> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        levels.get(&price).map_or(Vec::new(), |level| level.iter().filter_map(|&order_id| Some((order_id, self.orders.get(&order_id)?.2))).collect())
+    }
+
+    pub fn last_sequence(&self) -> u64 {
+        self.last_sequence
+    }
+}
+
+// Reads a feed file written from FeedEncoder output and rebuilds the book it describes.
+pub fn replay_feed(path: impl AsRef<Path>) -> Result<FeedBook, FeedError> {
+    let bytes = fs::read(path)?;
+    let mut book = FeedBook::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let (message, size) = FeedMessage::decode(&bytes[offset..], offset)?;
+        book.apply(&message)?;
+        offset += size;
+    }
+    Ok(book)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::matching::orderbook::{OrderBuilder, Orderbook, Security};
+    use crate::matching::units::{Price, Qty};
+
+    #[test]
+    fn every_message_round_trips_at_its_documented_size() {
+        let messages = [
+            (FeedMessage::AddOrder { sequence: 1, timestamp: 10, order_id: 7, side: Side::Sell, price: 101, quantity: 5 }, ADD_ORDER_SIZE),
+            (FeedMessage::OrderExecuted { sequence: 2, timestamp: 11, order_id: 7, quantity: 2, price: 101, trade_id: 1 }, ORDER_EXECUTED_SIZE),
+            (FeedMessage::OrderCancel { sequence: 3, timestamp: 12, order_id: 7, quantity: 3 }, ORDER_CANCEL_SIZE),
+            (FeedMessage::Trade { sequence: 4, timestamp: 13, buying_order_id: 8, selling_order_id: 9, price: -4, quantity: 1, trade_id: 2 }, TRADE_SIZE),
+        ];
+        let mut out = Vec::new();
+        for (message, size) in messages {
+            out.clear();
+            message.encode(&mut out);
+            assert_eq!(out.len(), size);
+            assert_eq!(FeedMessage::decode(&out, 0).unwrap(), (message, size));
+        }
+    }
+
+    #[test]
+    fn the_bytes_follow_the_documented_layout() {
+        let mut out = Vec::new();
+        FeedMessage::AddOrder { sequence: 0x0102, timestamp: 3, order_id: 7, side: Side::Buy, price: 101, quantity: -1 }.encode(&mut out);
+        assert_eq!(out[0], b'A');
+        assert_eq!(out[1..9], [2, 1, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(out[9..17], [3, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(out[17..25], [7, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(out[25], b'B');
+        assert_eq!(out[26..34], [101, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(out[34..42], [0xFF; 8]);
+    }
+
+    #[test]
+    fn broken_bytes_are_errors() {
+        let mut out = Vec::new();
+        FeedMessage::OrderCancel { sequence: 1, timestamp: 0, order_id: 7, quantity: 3 }.encode(&mut out);
+        assert!(matches!(FeedMessage::decode(&out[..ORDER_CANCEL_SIZE - 1], 5), Err(FeedError::Truncated { offset: 5 })));
+        assert!(matches!(FeedMessage::decode(&[], 0), Err(FeedError::Truncated { offset: 0 })));
+        out[0] = b'Z';
+        assert!(matches!(FeedMessage::decode(&out, 0), Err(FeedError::UnknownMessageType { offset: 0, message_type: b'Z' })));
+
+        out.clear();
+        FeedMessage::AddOrder { sequence: 1, timestamp: 0, order_id: 7, side: Side::Buy, price: 101, quantity: 1 }.encode(&mut out);
+        out[25] = b'?';
+        assert!(matches!(FeedMessage::decode(&out, 0), Err(FeedError::InvalidSide { offset: 0 })));
+
+        let mut book = FeedBook::new();
+        let skipped = FeedMessage::OrderCancel { sequence: 2, timestamp: 0, order_id: 7, quantity: 3 };
+        assert!(matches!(book.apply(&skipped), Err(FeedError::SequenceGap { expected: 1, received: 2 })));
+    }
+
+    #[test]
+    fn a_replayed_feed_file_rebuilds_the_book() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let events = book.subscribe_unbounded();
+        let limit = |side, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity));
+        book.place_order(limit(Side::Sell, 101, 5).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 101, 4).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 103, 9).display_quantity(Qty(3)).build().unwrap()).unwrap();
+        let cancelled = book.place_order(limit(Side::Buy, 98, 2).build().unwrap()).unwrap().order_id();
+        book.place_order(limit(Side::Buy, 99, 6).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Buy, 101, 7).build().unwrap()).unwrap();
+        book.cancel_order(cancelled, None).unwrap();
+        book.place_order(OrderBuilder::new(Side::Buy, &security).quantity(Qty(5)).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 99, 1).build().unwrap()).unwrap();
+
+        let mut encoder = FeedEncoder::new();
+        let mut feed = Vec::new();
+        let count: usize = events.try_iter().map(|event| encoder.encode(&event, &mut feed)).sum();
+        let path = std::env::temp_dir().join(format!("trade-city-feed-{}", std::process::id()));
+        fs::write(&path, &feed).unwrap();
+        let replayed = replay_feed(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let depth = book.depth(usize::MAX);
+        assert_eq!(replayed.bids(), depth.bids());
+        assert_eq!(replayed.asks(), depth.asks());
+        assert_eq!(replayed.checksum(), depth.checksum());
+        assert_eq!(replayed.last_sequence(), count as u64);
+        // the market order took the first slice of the iceberg, the feed shows the next one
+        assert_eq!(replayed.orders_at(Side::Sell, 103), vec![(3, 3)]);
+        assert!(replayed.orders_at(Side::Buy, 98).is_empty());
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use super::error::OrderbookError;
+use super::orderbook::Side;
+
+// Where the book takes the time it measures latencies with, in nanoseconds from an arbitrary
+// origin. Only differences between two readings are used.
+pub trait LatencyClock {
+    fn now_ns(&self) -> u64;
+}
+
+// The monotonic clock of the operating system.
+#[derive(Clone, Copy, Debug)]
+pub struct InstantClock {
+    origin: Instant,
+}
+
+impl InstantClock {
+    pub fn new() -> Self {
+        InstantClock { origin: Instant::now() }
+    }
+}
+
+impl Default for InstantClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LatencyClock for InstantClock {
+    fn now_ns(&self) -> u64 {
+        self.origin.elapsed().as_nanos() as u64
+    }
+}
+
+// A clock that only moves when it is told to. Clones share the same time, so a test keeps one
+// and hands another to the book.
+#[derive(Clone, Debug, Default)]
+pub struct ManualLatencyClock {
+    now: Arc<AtomicU64>,
+}
+
+impl ManualLatencyClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, now_ns: u64) {
+        self.now.store(now_ns, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self, ns: u64) {
+        self.now.fetch_add(ns, Ordering::Relaxed);
+    }
+}
+
+impl LatencyClock for ManualLatencyClock {
+    fn now_ns(&self) -> u64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+// Called synchronously by the book: the latency of every place_order and place_oco call, every
+// order accepted or rejected, every execution, and the number of price levels of both sides at
+// the end of every call that changed the book. Every method does nothing unless implemented, so
+// an exporter only implements what it exports.
+pub trait Metrics {
+    fn record_order_latency(&mut self, _ns: u64) {}
+    fn incr_orders_accepted(&mut self) {}
+    fn incr_orders_rejected(&mut self, _reason: &OrderbookError) {}
+    fn incr_trades(&mut self) {}
+    fn gauge_book_depth(&mut self, _side: Side, _levels: usize) {}
+}
+
+// Lets the caller keep a handle on metrics that are owned by the book.
+impl<T: Metrics> Metrics for Arc<Mutex<T>> {
+    fn record_order_latency(&mut self, ns: u64) {
+        if let Ok(mut metrics) = self.lock() { metrics.record_order_latency(ns); }
+    }
+
+    fn incr_orders_accepted(&mut self) {
+        if let Ok(mut metrics) = self.lock() { metrics.incr_orders_accepted(); }
+    }
+
+    fn incr_orders_rejected(&mut self, reason: &OrderbookError) {
+        if let Ok(mut metrics) = self.lock() { metrics.incr_orders_rejected(reason); }
+    }
+
+    fn incr_trades(&mut self) {
+        if let Ok(mut metrics) = self.lock() { metrics.incr_trades(); }
+    }
+
+    fn gauge_book_depth(&mut self, side: Side, levels: usize) {
+        if let Ok(mut metrics) = self.lock() { metrics.gauge_book_depth(side, levels); }
+    }
+}
+
+pub struct NoopMetrics;
+
+impl Metrics for NoopMetrics {}
+
+// Upper bounds of the latency buckets in nanoseconds, the last bucket takes everything slower.
+pub const LATENCY_BUCKETS_NS: [u64; 12] = [
+    250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 1_000_000, 10_000_000,
+];
+
+// Latencies counted per bucket, not cumulative. counts()[i] are the latencies up to
+// LATENCY_BUCKETS_NS[i] and above the previous bound, the extra last count the ones above all
+// bounds.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    pub(crate) counts: [u64; LATENCY_BUCKETS_NS.len() + 1],
+    pub(crate) count: u64,
+    pub(crate) sum_ns: u64,
+    pub(crate) max_ns: u64,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, ns: u64) {
+        let bucket = LATENCY_BUCKETS_NS.iter().position(|&bound| ns <= bound).unwrap_or(LATENCY_BUCKETS_NS.len());
+        self.counts[bucket] += 1;
+        self.count += 1;
+        self.sum_ns = self.sum_ns.saturating_add(ns);
+        self.max_ns = self.max_ns.max(ns);
+    }
+
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum_ns(&self) -> u64 {
+        self.sum_ns
+    }
+
+    pub fn max_ns(&self) -> u64 {
+        self.max_ns
+    }
+}
+
+// Everything InMemoryMetrics counted so far.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    pub orders_accepted: u64,
+    pub orders_rejected: u64,
+    // rejections by OrderbookError::kind
+    pub rejections: BTreeMap<&'static str, u64>,
+    pub trades: u64,
+    pub bid_levels: usize,
+    pub ask_levels: usize,
+    pub order_latency: LatencyHistogram,
+}
+
+// Keeps counters in memory, to be read with snapshot() and exported from there.
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryMetrics {
+    current: MetricsSnapshot,
+}
+
+impl InMemoryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        self.current.clone()
+    }
+
+    pub fn reset(&mut self) {
+        self.current = MetricsSnapshot::default();
+    }
+}
+
+impl Metrics for InMemoryMetrics {
+    fn record_order_latency(&mut self, ns: u64) {
+        self.current.order_latency.record(ns);
+    }
+
+    fn incr_orders_accepted(&mut self) {
+        self.current.orders_accepted += 1;
+    }
+
+    fn incr_orders_rejected(&mut self, reason: &OrderbookError) {
+        self.current.orders_rejected += 1;
+        *self.current.rejections.entry(reason.kind()).or_insert(0) += 1;
+    }
+
+    fn incr_trades(&mut self) {
+        self.current.trades += 1;
+    }
+
+    fn gauge_book_depth(&mut self, side: Side, levels: usize) {
+        match side {
+            Side::Buy => self.current.bid_levels = levels,
+            Side::Sell => self.current.ask_levels = levels,
+        }
+    }
+}
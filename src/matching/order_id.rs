@@ -0,0 +1,75 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+pub trait OrderIdGenerator {
+    fn next_order_id(&mut self) -> i64;
+
+    // Called when a book is restored, so ids handed out before the snapshot are not handed out
+    // again. Generators that cannot skip ahead keep their state.
+    fn advance_past(&mut self, _order_id: i64) {}
+
+    // Called when a book is cleared together with its order ids, see ClearPolicy. Generators that
+    // other books draw from as well keep counting.
+    fn reset(&mut self) {}
+}
+
+// Hands out ids for a single book, starting at 1.
+pub struct OrderIdSequence {
+    next_order_id: i64,
+}
+
+impl OrderIdSequence {
+    pub fn new() -> Self {
+        OrderIdSequence { next_order_id: 1 }
+    }
+}
+
+impl Default for OrderIdSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderIdGenerator for OrderIdSequence {
+    fn next_order_id(&mut self) -> i64 {
+        let order_id = self.next_order_id;
+        self.next_order_id += 1;
+        order_id
+    }
+
+    fn advance_past(&mut self, order_id: i64) {
+        self.next_order_id = self.next_order_id.max(order_id + 1);
+    }
+
+    fn reset(&mut self) {
+        self.next_order_id = 1;
+    }
+}
+
+// Clones share one counter, so several books can draw from an exchange-wide sequence.
+#[derive(Clone)]
+pub struct SharedOrderIdSequence {
+    next_order_id: Arc<AtomicI64>,
+}
+
+impl SharedOrderIdSequence {
+    pub fn new() -> Self {
+        SharedOrderIdSequence { next_order_id: Arc::new(AtomicI64::new(1)) }
+    }
+}
+
+impl Default for SharedOrderIdSequence {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OrderIdGenerator for SharedOrderIdSequence {
+    fn next_order_id(&mut self) -> i64 {
+        self.next_order_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn advance_past(&mut self, order_id: i64) {
+        self.next_order_id.fetch_max(order_id + 1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Index;
+
+// the ids the window always has room for beyond the ones it holds
+const WINDOW_SLACK: usize = 1024;
+
+// Where an entry of a slab lives. The generation tells a handle to an entry apart from a handle
+// to whatever took its slot after it was removed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct SlotHandle {
+    index: u32,
+    generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    entry: Option<(i64, T)>,
+}
+
+// The open orders of a book in one vector, keyed by order id. Removed slots are reused through a
+// free list, each reuse bumping the generation of the slot, so a stale handle finds nothing
+// instead of the order that took its place. Order ids are handed out in ascending order by the
+// id generator of the book, so the handles of the ids from the oldest open order on are kept in a
+// window indexed by id and a lookup by id is an index into it, handles go straight to the slot.
+// Open orders far older than the rest, and ids far away from the window, are kept in a map
+// instead, so an order left resting for a long time does not keep the window growing.
+pub(crate) struct OrderSlab<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+    // the handle of id first_id + i at i, None for ids that are not in the slab
+    window: VecDeque<Option<SlotHandle>>,
+    first_id: i64,
+    // the entries in the window
+    windowed: usize,
+    outside: HashMap<i64, SlotHandle>,
+}
+
+impl<T> OrderSlab<T> {
+    pub(crate) fn new() -> Self {
+        Self::with_capacity(0)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        OrderSlab { slots: Vec::with_capacity(capacity), free: Vec::with_capacity(capacity), window: VecDeque::with_capacity(capacity), first_id: 0, windowed: 0, outside: HashMap::new() }
+    }
+
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional.saturating_sub(self.free.len()));
+        self.free.reserve(self.slots.capacity() - self.free.len());
+        self.window.reserve(additional);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.windowed + self.outside.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn handle(&self, id: &i64) -> Option<SlotHandle> {
+        let offset = id.checked_sub(self.first_id).filter(|&offset| offset >= 0);
+        if let Some(handle) = offset.and_then(|offset| self.window.get(offset as usize).copied().flatten()) { return Some(handle); }
+        if self.outside.is_empty() { return None; }
+        self.outside.get(id).copied()
+    }
+
+    pub(crate) fn contains_key(&self, id: &i64) -> bool {
+        self.handle(id).is_some()
+    }
+
+    fn map_id(&mut self, id: i64, handle: SlotHandle) {
+        if self.window.is_empty() { self.first_id = id; }
+        let room = (self.window.len() + WINDOW_SLACK) as i128;
+        let offset = id as i128 - self.first_id as i128;
+        if offset < 0 {
+            if -offset > room { self.outside.insert(id, handle); return; }
+            for _ in 0..-offset { self.window.push_front(None); }
+            self.first_id = id;
+        } else if offset - self.window.len() as i128 > room {
+            // the generator jumped ahead, the window starts over at the id
+            self.move_outside(self.window.len());
+            self.first_id = id;
+        }
+        let offset = (id - self.first_id) as usize;
+        if offset >= self.window.len() { self.window.resize(offset + 1, None); }
+        self.window[offset] = Some(handle);
+        self.windowed += 1;
+
+        // mostly empty after orders that stay open, the older half moves out of the window
+        if self.window.len() > 4 * self.windowed + WINDOW_SLACK { self.move_outside(self.window.len() / 2); }
+    }
+
+    fn unmap_id(&mut self, id: &i64) -> Option<SlotHandle> {
+        let offset = id.checked_sub(self.first_id).filter(|&offset| offset >= 0);
+        let Some(handle) = offset.and_then(|offset| self.window.get_mut(offset as usize)).and_then(Option::take) else { return self.outside.remove(id); };
+        self.windowed -= 1;
+        self.trim();
+        Some(handle)
+    }
+
+    // Moves the first `count` ids of the window to the map.
+    fn move_outside(&mut self, count: usize) {
+        for (offset, handle) in self.window.drain(..count).enumerate() {
+            let Some(handle) = handle else { continue; };
+            self.outside.insert(self.first_id + offset as i64, handle);
+            self.windowed -= 1;
+        }
+        self.first_id = self.first_id.wrapping_add(count as i64);
+        self.trim();
+    }
+
+    // The window starts at the oldest id it holds. Past the last id an empty window wraps, the next
+    // id starts it over anyway.
+    fn trim(&mut self) {
+        while self.window.front().is_some_and(Option::is_none) {
+            self.window.pop_front();
+            self.first_id = self.first_id.wrapping_add(1);
+        }
+    }
+
+    // Puts the entry in the slot of the id if it has one, returning what was there before.
+    pub(crate) fn insert(&mut self, id: i64, value: T) -> Option<T> {
+        if let Some(handle) = self.handle(&id) {
+            let slot = &mut self.slots[handle.index as usize];
+            return slot.entry.replace((id, value)).map(|(_, previous)| previous);
+        }
+
+        let handle = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.entry = Some((id, value));
+                SlotHandle { index, generation: slot.generation }
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, entry: Some((id, value)) });
+                SlotHandle { index: self.slots.len() as u32 - 1, generation: 0 }
+            },
+        };
+        self.map_id(id, handle);
+        None
+    }
+
+    pub(crate) fn remove(&mut self, id: &i64) -> Option<T> {
+        let handle = self.unmap_id(id)?;
+        let slot = &mut self.slots[handle.index as usize];
+        let (_, value) = slot.entry.take()?;
+        slot.generation = slot.generation.wrapping_add(1);
+        self.free.push(handle.index);
+        Some(value)
+    }
+
+    pub(crate) fn get(&self, id: &i64) -> Option<&T> {
+        self.get_at(self.handle(id)?)
+    }
+
+    pub(crate) fn get_mut(&mut self, id: &i64) -> Option<&mut T> {
+        self.get_at_mut(self.handle(id)?)
+    }
+
+    // None once the entry the handle was taken from has been removed.
+    pub(crate) fn get_at(&self, handle: SlotHandle) -> Option<&T> {
+        let slot = self.slots.get(handle.index as usize).filter(|slot| slot.generation == handle.generation)?;
+        slot.entry.as_ref().map(|(_, value)| value)
+    }
+
+    pub(crate) fn get_at_mut(&mut self, handle: SlotHandle) -> Option<&mut T> {
+        let slot = self.slots.get_mut(handle.index as usize).filter(|slot| slot.generation == handle.generation)?;
+        slot.entry.as_mut().map(|(_, value)| value)
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            if slot.entry.take().is_some() {
+                slot.generation = slot.generation.wrapping_add(1);
+                self.free.push(index as u32);
+            }
+        }
+        self.window.clear();
+        self.windowed = 0;
+        self.outside.clear();
+    }
+
+    // In slot order, which is not the order the entries were inserted in.
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&i64, &T)> + '_ {
+        self.slots.iter().filter_map(|slot| slot.entry.as_ref().map(|(id, value)| (id, value)))
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &i64> + '_ {
+        self.iter().map(|(id, _)| id)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &T> + '_ {
+        self.iter().map(|(_, value)| value)
+    }
+}
+
+impl<T> Default for OrderSlab<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> Index<&i64> for OrderSlab<T> {
+    type Output = T;
+
+    fn index(&self, id: &i64) -> &T {
+        self.get(id).expect("no entry for the id")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_stale_handle_finds_nothing() {
+        let mut slab = OrderSlab::new();
+        slab.insert(1, "first");
+        let handle = slab.handle(&1).unwrap();
+        assert_eq!(slab.remove(&1), Some("first"));
+        slab.insert(2, "second");
+
+        assert_eq!(slab.handle(&2).map(|handle| handle.index), Some(handle.index));
+        assert_eq!(slab.get_at(handle), None);
+        assert_eq!((slab.get(&1), slab.get(&2)), (None, Some(&"second")));
+    }
+
+    #[test]
+    fn ids_far_apart_and_out_of_order_are_found() {
+        let mut slab = OrderSlab::new();
+        let ids = [5, 3, 1_000_000, -7, 4, i64::MAX, 6];
+        for id in ids { assert_eq!(slab.insert(id, -id), None); }
+
+        assert_eq!(slab.len(), ids.len());
+        for id in ids { assert_eq!(slab.get(&id), Some(&-id)); }
+        assert!(!slab.contains_key(&2) && !slab.contains_key(&999_999));
+        for id in ids { assert_eq!(slab.remove(&id), Some(-id)); }
+        assert!(slab.is_empty());
+    }
+
+    #[test]
+    fn an_order_left_open_does_not_grow_the_window() {
+        let mut slab = OrderSlab::new();
+        slab.insert(1, 1);
+        for id in 2..100_000 {
+            slab.insert(id, id);
+            if id > 11 { slab.remove(&(id - 10)); }
+        }
+
+        assert!(slab.window.len() < 2 * WINDOW_SLACK);
+        assert_eq!((slab.len(), slab.get(&1)), (11, Some(&1)));
+        assert_eq!(slab.remove(&1), Some(1));
+        assert_eq!(slab.keys().count(), 10);
+    }
+}
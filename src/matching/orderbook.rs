@@ -1,233 +1,5665 @@
-use std::collections::{VecDeque, HashMap};
+use std::collections::{btree_map, BTreeMap, VecDeque, HashMap, HashSet};
+use std::ops::Bound;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::mpsc::Receiver;
 
-pub struct Orderbook<'a> {
-    security: &'a Security,
+use super::auction::{self, AuctionResult, IndicativePrice};
+use super::candles::CandleAggregator;
+use super::corporate_action::{AdjustedOrders, CorporateAction, CorporateActionKind, OpenOrderPolicy};
+use super::error::{InvalidSecurity, OrderbookError, ParsePriceError, SnapshotProblem};
+use super::events::{EventPublisher, OrderbookEvent, OverflowPolicy};
+use super::fees::FeeRates;
+use super::journal::{self, Journal, JournalEntry, JournalError};
+use super::listener::{BookUpdate, CancelReason, ExecutionListener};
+use super::market_data::{book_checksum, BookView, DepthLevel, DepthSnapshot, LevelRef, OrderView, SessionStats};
+use super::clock::{Clock, Timestamp};
+use super::metrics::{InstantClock, LatencyClock, Metrics};
+use super::order_id::{OrderIdGenerator, OrderIdSequence};
+use super::order_slab::{OrderSlab, SlotHandle};
+use super::position::PositionProvider;
+use super::published::{SnapshotPublisher, SnapshotReader};
+use super::settlement::{ExecutionSink, SettlementInstruction, SinkError};
+use super::snapshot::{BookSnapshot, SnapshotOcoLink, SnapshotOrder, SnapshotQuote};
+use super::trade_tape::{TradeTape, TradeWindow};
+use super::units::{self, OrderId, Price, Qty};
+
+pub struct Orderbook {
+    security: Arc<Security>,
     starting_price: i64,
     current_market_price: i64,
-    best_bid: i64,
-    best_ask: i64,
-    worst_bid: i64,
-    worst_ask: i64,
-    order_map: HashMap<i64, Order<'a>>,
+    order_map: OrderSlab<Order>,
     buy_at_market_orders: VecDeque<i64>,
     sell_at_market_orders: VecDeque<i64>,
-    buy_limit_orders: VecDeque<VecDeque<i64>>,
-    sell_limit_orders: VecDeque<VecDeque<i64>>,
+    // the price levels by price, the best bid is the last key and the best ask the first
+    buy_levels: BTreeMap<i64, PriceLevel>,
+    sell_levels: BTreeMap<i64, PriceLevel>,
     number_buy_limit_orders: u32,
     number_sell_limit_orders: u32,
+    buy_stop_orders: VecDeque<i64>,
+    sell_stop_orders: VecDeque<i64>,
+    // midpoint orders in time priority, outside the levels, see Order::with_midpoint_peg
+    buy_midpoint_orders: VecDeque<i64>,
+    sell_midpoint_orders: VecDeque<i64>,
+    pegged_orders: Vec<i64>,
+    oco_links: HashMap<i64, OcoLink>,
+    // oco legs cancelled during the current match, see notify_met_cancelled
+    met_cancelled: Vec<i64>,
+    next_oco_link_id: i64,
+    oco_policy: OcoPolicy,
+    quotes: HashMap<u64, Quote>,
+    next_quote_id: i64,
+    max_stop_limit_gap: Option<i64>,
+    self_trade_policy: SelfTradePolicy,
+    account_self_trade_policies: HashMap<u64, SelfTradePolicy>,
+    session_state: SessionState,
+    config: OrderbookConfig,
+    price_bands: PriceBands,
+    band_reference: i64,
+    dynamic_reference: i64,
+    volatility_trigger: Option<(PriceBand, i64)>,
+    interruption_ends_at: Option<u64>,
+    halt: Option<Halt>,
+    position_provider: Option<Box<dyn PositionProvider + Send>>,
+    position_changes: HashMap<u64, i64>,
+    current_time: u64,
+    sequence: u64,
+    stats: SessionStats,
+    executions: Vec<Execution>,
+    busted_trades: HashSet<u64>,
+    // off-book trades that do not count in the statistics, see ReportFlags
+    unpriced_trades: HashSet<u64>,
+    // the last trade before the session started, the statistics count the trades after it
+    session_start_trade: u64,
+    // limit orders filled in this session by id, so a bust can re-open them
+    filled_orders: HashMap<i64, Order>,
+    trade_tape: TradeTape,
+    price_samples: Vec<(u64, i64)>,
+    candles: Option<CandleAggregator>,
+    display_levels: usize,
+    listener: Option<Box<dyn ExecutionListener + Send>>,
+    // the accounting of the exchange, called before the listener and never replaced by it
+    accounting: Option<Box<dyn ExecutionListener + Send>>,
+    metrics: Option<Box<dyn Metrics + Send>>,
+    execution_sink: Option<Box<dyn ExecutionSink + Send>>,
+    fees: FeeRates,
+    pending_settlements: Vec<SettlementInstruction>,
+    sink_error: Option<SinkError>,
+    latency_clock: Box<dyn LatencyClock + Send>,
+    // without one the book time only moves with set_time
+    clock: Option<Box<dyn Clock + Send>>,
+    events: EventPublisher,
+    snapshots: Option<SnapshotPublisher>,
+    touched_levels: Vec<(Side, i64)>,
+    // set while a batch of orders is placed, the book update waits for the end of the batch
+    batching: bool,
+    published_best: (Option<i64>, Option<i64>),
+    published_indicative: Option<IndicativePrice>,
+    journal: Option<Journal>,
+    last_order_id: i64,
+    order_ids: Box<dyn OrderIdGenerator + Send>,
 }
 
-impl <'a> Orderbook<'a> {
-    pub fn new(security: &'a Security, starting_price: i64) -> Self {
+impl Orderbook {
+    pub fn new(security: Arc<Security>, starting_price: i64) -> Self {
+        Self::with_order_ids(security, starting_price, Box::new(OrderIdSequence::new()))
+    }
+
+    pub fn with_order_ids(security: Arc<Security>, starting_price: i64, order_ids: Box<dyn OrderIdGenerator + Send>) -> Self {
         Orderbook {
+            trade_tape: TradeTape::for_security(&security),
             security,
             starting_price,
             current_market_price: starting_price,
-            best_bid: 0,
-            best_ask: 0,
-            worst_bid: 0,
-            worst_ask: 0,
-            order_map: HashMap::new(),
+            order_map: OrderSlab::new(),
             buy_at_market_orders: VecDeque::new(),
             sell_at_market_orders: VecDeque::new(),
-            buy_limit_orders: VecDeque::new(),
-            sell_limit_orders: VecDeque::new(),
+            buy_levels: BTreeMap::new(),
+            sell_levels: BTreeMap::new(),
             number_buy_limit_orders: 0,
-            number_sell_limit_orders: 0
+            number_sell_limit_orders: 0,
+            buy_stop_orders: VecDeque::new(),
+            buy_midpoint_orders: VecDeque::new(),
+            sell_midpoint_orders: VecDeque::new(),
+            sell_stop_orders: VecDeque::new(),
+            pegged_orders: Vec::new(),
+            oco_links: HashMap::new(),
+            met_cancelled: Vec::new(),
+            next_oco_link_id: 1,
+            oco_policy: OcoPolicy::default(),
+            quotes: HashMap::new(),
+            next_quote_id: 1,
+            max_stop_limit_gap: None,
+            self_trade_policy: SelfTradePolicy::default(),
+            account_self_trade_policies: HashMap::new(),
+            session_state: SessionState::default(),
+            config: OrderbookConfig::default(),
+            price_bands: PriceBands::default(),
+            band_reference: starting_price,
+            dynamic_reference: starting_price,
+            volatility_trigger: None,
+            interruption_ends_at: None,
+            halt: None,
+            position_provider: None,
+            position_changes: HashMap::new(),
+            current_time: 0,
+            sequence: 0,
+            stats: SessionStats::new(starting_price),
+            executions: Vec::new(),
+            busted_trades: HashSet::new(),
+            unpriced_trades: HashSet::new(),
+            session_start_trade: 0,
+            filled_orders: HashMap::new(),
+            price_samples: Vec::new(),
+            candles: None,
+            display_levels: 10,
+            listener: None,
+            accounting: None,
+            metrics: None,
+            execution_sink: None,
+            fees: FeeRates::default(),
+            pending_settlements: Vec::new(),
+            sink_error: None,
+            latency_clock: Box::new(InstantClock::new()),
+            clock: None,
+            events: EventPublisher::new(),
+            snapshots: None,
+            touched_levels: Vec::new(),
+            batching: false,
+            published_best: (None, None),
+            published_indicative: None,
+            journal: None,
+            last_order_id: 0,
+            order_ids,
         }
     }
 
-    fn insert_order(&mut self, order: &mut Order<'a>) -> Result<(i64, MatchingSignal), String> {
-        if order.amount <= 0 { return Err("Order amount must be greater than zero".to_string()); }
+    // A book sized up front for the orders and levels the config expects, so the first orders do
+    // not pay for growing its storage. The config is applied as with set_config, the clock as
+    // with set_clock.
+    pub fn with_config(security: Arc<Security>, starting_price: i64, config: OrderbookConfig, clock: Box<dyn Clock + Send>) -> Self {
+        let mut book = Self::new(security, starting_price);
+        book.config = config;
+        book.set_clock(clock);
+        book.reserve_additional(config.expected_orders);
+        book.touched_levels.reserve(config.expected_levels_per_side);
+        book
+    }
+
+    // Makes room for `additional` more open orders and as many trades. Every trade fills at least
+    // one of its orders, so a book never has more trades than orders it took. Price levels are
+    // kept in a BTreeMap, which allocates its nodes as levels appear and cannot be sized ahead.
+    pub fn reserve_additional(&mut self, additional: usize) {
+        self.order_map.reserve(additional);
+        self.trade_tape.reserve(additional);
+        self.executions.reserve(additional);
+    }
 
-        let new_order_id: i64 = 1;
-        order.order_id = new_order_id;
-        let order_limit = order.order_limit;
-        let is_buy_order = order.is_buy_order;
-        self.buy_at_market_orders.len();
-
-        if let Some(limit) = order_limit {
-            if limit <= 0 { return Err("Limit must be greater than zero".to_string()); }
-
-            if is_buy_order {
-                if limit > self.best_bid {
-                    let mut queue = VecDeque::new();
-                    queue.push_back(new_order_id);
-                    self.buy_limit_orders.push_front(queue);
-                    self.number_buy_limit_orders += 1;
-                    self.best_bid = limit;
-                    return Ok((new_order_id, MatchingSignal::NewHighestBid),);
-                }
-
-                if limit < self.worst_bid {
-                    if self.worst_bid * 12 < self.current_market_price * 10 { return Err("Limit is too far away from current market price.".to_string()); }
-                    let mut queue = VecDeque::new();
-                    queue.push_back(new_order_id);
-                    self.buy_limit_orders.push_back(queue);
-                    self.number_buy_limit_orders += 1;
-                    self.worst_bid = limit;
-                    return Ok((new_order_id, MatchingSignal::NoOperation));
-                }
-
-                let index = limit - self.best_bid;
-                if let Some(subqueue) = self.buy_limit_orders.get_mut(index.try_into().unwrap()) {
-                    subqueue.push_back(new_order_id);    
-                } else {
-                    let mut queue = VecDeque::new();
-                    queue.push_back(new_order_id);
-                    self.buy_limit_orders.insert(index.try_into().unwrap(), queue);
-                }
-                
-                return Ok((new_order_id, if index == 0 { MatchingSignal::NewHighestBid } else { MatchingSignal::NoOperation } ));
-            }
-
-            if limit < self.best_ask {
-                let mut queue = VecDeque::new();
-                queue.push_back(new_order_id);
-                self.sell_limit_orders.push_front(queue);
-                self.number_sell_limit_orders += 1;
-                self.best_ask = limit;
-                return Ok((new_order_id, MatchingSignal::NewLowestAsk));
-            }
-
-            if limit > self.worst_ask {
-                let mut queue = VecDeque::new();
-                queue.push_back(new_order_id);
-                self.sell_limit_orders.push_back(queue);
-                self.number_sell_limit_orders += 1;
-                self.worst_ask = limit;
-                return Ok((new_order_id, MatchingSignal::NoOperation));
-            }
-
-            let index = limit - self.best_ask;
-            if let Some(subqueue) = self.sell_limit_orders.get_mut(index.try_into().unwrap()) {
-                subqueue.push_back(new_order_id);
-            } else {
-                let mut queue = VecDeque::new();
-                queue.push_back(new_order_id);
-                self.sell_limit_orders.insert(index.try_into().unwrap(), queue);
+    // Validates the order. Nothing is added to the book here, so a marketable order never shows up
+    // as the best price before it traded.
+    fn accept_order(&mut self, order: &mut Order) -> Result<(), OrderbookError> {
+        self.check_order_size(order.side, order.order_limit.or(order.stop_price), order.amount)?;
+        if self.session_state == SessionState::Closed { return Err(OrderbookError::MarketClosed); }
+        if self.session_state == SessionState::Halted { return Err(OrderbookError::MarketHalted); }
+        if order.time_in_force == TimeInForce::AtTheClose && self.session_state != SessionState::ClosingAuction { return Err(OrderbookError::ClosingAuctionOnly); }
+        order.validate()?;
+        if order.is_expired(self.current_time) { return Err(OrderbookError::OrderExpired); }
+        if let Some(limit) = order.order_limit.filter(|_| !order.midpoint) { self.check_level_room(order.side, limit, 0, order.amount)?; }
+
+        if order.reduce_only {
+            if order.account_id.is_none() || self.position_provider.is_none() { return Err(OrderbookError::ReduceOnlyUnavailable); }
+            if self.reduce_only_cap(order) == Some(0) { return Err(OrderbookError::ReduceOnlyWouldIncrease); }
+        }
+
+        if let Some(stop_price) = order.stop_price {
+            // a buy stop limit far below its stop (or a sell stop limit far above it) could never execute once triggered
+            let gap = match (order.side, order.order_limit) {
+                (Side::Buy, Some(limit)) => stop_price - limit,
+                (Side::Sell, Some(limit)) => limit - stop_price,
+                (_, None) => 0,
+            };
+            if self.max_stop_limit_gap.is_some_and(|max_gap| gap > max_gap) {
+                return Err(OrderbookError::StopLimitTooFar { stop_price, limit: order.order_limit.unwrap_or(stop_price) });
             }
+        }
+
+        Ok(())
+    }
+
+    // Levels cache the sum of the visible quantity of their orders, which has to stay within an
+    // i64. `leaving` is what an order that is about to be replaced shows at the level already.
+    fn check_level_room(&self, side: Side, price: i64, leaving: i64, quantity: i64) -> Result<(), OrderbookError> {
+        let resting = self.price_levels(side).get(&price).map_or(0, |level| level.quantity) - leaving;
+        match resting.checked_add(quantity) {
+            Some(_) => Ok(()),
+            None => Err(OrderbookError::LevelFull { price }),
+        }
+    }
+
+    // The maximum quantity and notional of the security. Market orders are valued at the collar, or
+    // at the last price without one.
+    fn check_order_size(&self, side: Side, price: Option<i64>, amount: i64) -> Result<(), OrderbookError> {
+        let price = price.or_else(|| self.config.collar_price(side, self.current_market_price)).unwrap_or(self.current_market_price);
+        self.security.check_order_size(Some(price), amount)
+    }
+
+    fn assign_order_id(&mut self, order: &mut Order) -> Result<i64, OrderbookError> {
+        let new_order_id = self.order_ids.next_order_id();
+        self.last_order_id = self.last_order_id.max(new_order_id);
+        if self.order_map.contains_key(&new_order_id) { return Err(OrderbookError::DuplicateOrderId(new_order_id)); }
+        order.order_id = new_order_id;
+
+        Ok(new_order_id)
+    }
+
+    // Decides how an accepted order has to be matched against the current book.
+    fn matching_signal(&self, order: &Order) -> MatchingSignal {
+        let side = order.side;
+
+        match order.order_limit {
+            None => match side {
+                Side::Buy => MatchingSignal::BuyAtMarket,
+                Side::Sell => MatchingSignal::SellAtMarket,
+            },
+            Some(limit) => {
+                let opposite = side.opposite();
+                let crosses = !self.at_market_orders(opposite).is_empty()
+                    || self.best_price(opposite).is_some_and(|best| !side.improves(best, limit));
+
+                match (crosses, side) {
+                    (true, Side::Buy) => MatchingSignal::NewHighestBid,
+                    (true, Side::Sell) => MatchingSignal::NewLowestAsk,
+                    (false, _) => MatchingSignal::NoOperation,
+                }
+            },
+        }
+    }
+
+    // Rests a limit order in its price level, behind all orders already waiting at that price.
+    fn insert_order(&mut self, mut order: Order) {
+        let Some(limit) = order.order_limit else { return; };
+        order.replenish();
+        order.timestamp = self.current_time;
+        let order_id = order.order_id;
+        let side = order.side;
+        let (timestamp, quantity) = (self.current_time, order.visible_remaining());
+        self.order_map.insert(order_id, order);
+        self.touched_levels.push((side, limit));
+        self.events.publish(|sequence| OrderbookEvent::OrderAdded { sequence, timestamp, order_id, side, price: limit, quantity });
+
+        let (levels, order_map) = self.levels_and_orders(side);
+        levels.entry(limit).or_default().push_back(order_map, order_id);
+        *self.number_limit_orders_mut(side) += 1;
+    }
+
+    // Keeps the cached quantity of a level in step with a change of the visible quantity of one
+    // of its orders.
+    fn adjust_level(&mut self, side: Side, price: i64, change: i64) {
+        if let Some(level) = self.price_levels_mut(side).get_mut(&price) { level.quantity += change; }
+    }
+
+    // The visible quantity of the orders of a level.
+    fn level_quantity(&self, level: &PriceLevel) -> i64 {
+        level.orders(&self.order_map).map(|order| order.visible_remaining()).sum()
+    }
+
+    // Debug builds recount the levels a call touched, so a cached quantity or order count that went
+    // out of step fails right at the call that broke it.
+    #[cfg(debug_assertions)]
+    fn recount_levels(&self, touched_levels: &[(Side, i64)]) {
+        for &(side, price) in touched_levels {
+            let Some(level) = self.price_levels(side).get(&price) else { continue; };
+            debug_assert_eq!(level.quantity, self.level_quantity(level), "cached quantity of the {:?} level at {}", side, price);
+            debug_assert_eq!(level.len(), level.orders(&self.order_map).count(), "cached order count of the {:?} level at {}", side, price);
+        }
+    }
+
+    fn best_price(&self, side: Side) -> Option<i64> {
+        self.ladder(side, None).next().map(|(price, _)| price)
+    }
+
+    fn price_levels(&self, side: Side) -> &BTreeMap<i64, PriceLevel> {
+        match side {
+            Side::Buy => &self.buy_levels,
+            Side::Sell => &self.sell_levels,
+        }
+    }
+
+    fn price_levels_mut(&mut self, side: Side) -> &mut BTreeMap<i64, PriceLevel> {
+        match side {
+            Side::Buy => &mut self.buy_levels,
+            Side::Sell => &mut self.sell_levels,
+        }
+    }
+
+    // The levels of a side next to the order map, for queue changes that link orders.
+    fn levels_and_orders(&mut self, side: Side) -> (&mut BTreeMap<i64, PriceLevel>, &mut OrderSlab<Order>) {
+        match side {
+            Side::Buy => (&mut self.buy_levels, &mut self.order_map),
+            Side::Sell => (&mut self.sell_levels, &mut self.order_map),
+        }
+    }
+
+    // The levels of a side best price first, down to and including `through`.
+    fn ladder(&self, side: Side, through: Option<i64>) -> Ladder<'_> {
+        let bounds = match (side, through) {
+            (_, None) => (Bound::Unbounded, Bound::Unbounded),
+            (Side::Buy, Some(price)) => (Bound::Included(price), Bound::Unbounded),
+            (Side::Sell, Some(price)) => (Bound::Unbounded, Bound::Included(price)),
+        };
+        Ladder { range: self.price_levels(side).range(bounds), side }
+    }
+
+    // The best level of a side for matching.
+    fn best_level_mut(&mut self, side: Side) -> Option<(i64, &mut PriceLevel)> {
+        let levels = self.price_levels_mut(side);
+        let (&price, level) = match side {
+            Side::Buy => levels.iter_mut().next_back(),
+            Side::Sell => levels.iter_mut().next(),
+        }?;
+        Some((price, level))
+    }
+
+    fn at_market_orders(&self, side: Side) -> &VecDeque<i64> {
+        match side {
+            Side::Buy => &self.buy_at_market_orders,
+            Side::Sell => &self.sell_at_market_orders,
+        }
+    }
+
+    fn at_market_orders_mut(&mut self, side: Side) -> &mut VecDeque<i64> {
+        match side {
+            Side::Buy => &mut self.buy_at_market_orders,
+            Side::Sell => &mut self.sell_at_market_orders,
+        }
+    }
+
+    fn midpoint_orders(&self, side: Side) -> &VecDeque<i64> {
+        match side {
+            Side::Buy => &self.buy_midpoint_orders,
+            Side::Sell => &self.sell_midpoint_orders,
+        }
+    }
+
+    fn midpoint_orders_mut(&mut self, side: Side) -> &mut VecDeque<i64> {
+        match side {
+            Side::Buy => &mut self.buy_midpoint_orders,
+            Side::Sell => &mut self.sell_midpoint_orders,
+        }
+    }
 
-            return Ok((new_order_id, if index == 0 { MatchingSignal::NewLowestAsk } else { MatchingSignal::NoOperation }));
+    fn number_limit_orders_mut(&mut self, side: Side) -> &mut u32 {
+        match side {
+            Side::Buy => &mut self.number_buy_limit_orders,
+            Side::Sell => &mut self.number_sell_limit_orders,
         }
+    }
+
+    // Cancels an order on behalf of `account_id`, which has to be the account the order was placed
+    // with, None for orders placed without one.
+    pub fn cancel_order(&mut self, order_id: OrderId, account_id: Option<u64>) -> Result<(), OrderbookError> {
+        self.tick();
+        let order_id = order_id.to_raw();
+        if let Some(order) = self.order_map.get(&order_id) {
+            if order.account_id != account_id { return Err(OrderbookError::NotOwner(order_id)); }
+            self.log(JournalEntry::Cancel { order_id })?;
+        }
+        self.cancel_with_reason(order_id, CancelReason::Requested)
+    }
 
-        Ok((new_order_id, if is_buy_order { MatchingSignal::BuyAtMarket } else { MatchingSignal::SellAtMarket }))
+    // Cancels an order whatever account it belongs to, for the operator of the exchange.
+    pub fn force_cancel(&mut self, order_id: OrderId) -> Result<(), OrderbookError> {
+        self.tick();
+        let order_id = order_id.to_raw();
+        if self.order_map.contains_key(&order_id) { self.log(JournalEntry::ForceCancel { order_id })?; }
+        self.cancel_with_reason(order_id, CancelReason::Operator)
     }
 
-    pub fn cancel_order(&mut self, order_id: i64) -> Result<(), String> {
+    fn cancel_with_reason(&mut self, order_id: i64, reason: CancelReason) -> Result<(), OrderbookError> {
         // The order needs to be removed from the order map as well as from the order queues.
-        // With the help of the order metadata it should be relatively easy to find the order and remove it. 
-        //
-        // Other tasks: Decrement counter, new best bid, new worst bid, new best ask, new worst bid
-        
-        todo!()
-        
-    }
-
-    pub fn place_order(&mut self, mut order: Order<'a>) -> Result<i64, String> {
-        match self.insert_order(&mut order) {
-            Ok((order_id, matching_signal)) => {
-                match matching_signal {
-                    MatchingSignal::BuyAtMarket => {
-                        // try to match order directly
-                        self.match_against_sell_side_at_market(&mut order);
-                    },
-                    MatchingSignal::SellAtMarket => {
-                        // try to match order directly
-                        self.match_against_buy_side_at_market(&mut order);
-                    },
-                    MatchingSignal::NewHighestBid => {
-                        // try to match any orders on the sell side
-                        self.match_against_sell_side(&mut order);
-                    },
-                    MatchingSignal::NewLowestAsk => {
-                        // try to match any orders on the sell side
-                        self.match_against_buy_side(&mut order);
-                    },
-                    MatchingSignal::NoOperation => {
-                        self.order_map.insert(order_id, order);
-                        // No further actions
-                    },
+        // Filled orders have already left the order map, so they are reported like unknown ones.
+        // Executions of a partially filled order stay in the trade history, only the open remainder is removed.
+        let Some(mut order) = self.unlink_order(order_id) else { return Err(OrderbookError::UnknownOrder(order_id)); };
+        order.close(OrderState::Cancelled, reason);
+        self.sequence += 1;
+        self.notify_cancelled(order_id, reason);
+        self.cancel_oco_sibling(order_id);
+
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+        Ok(())
+    }
+
+    // Cancelling one leg of an oco pair cancels the other one as well.
+    fn cancel_oco_sibling(&mut self, order_id: i64) {
+        let Some(link) = self.oco_links.remove(&order_id) else { return; };
+        self.oco_links.remove(&link.sibling);
+        if let Some(mut sibling) = self.unlink_order(link.sibling) {
+            sibling.close(OrderState::Cancelled, CancelReason::OcoSibling);
+            self.notify_cancelled(link.sibling, CancelReason::OcoSibling);
+        }
+    }
+
+    fn notify_cancelled(&mut self, order_id: i64, reason: CancelReason) {
+        if let Some(accounting) = &mut self.accounting { accounting.on_order_cancelled(order_id, reason); }
+        if let Some(listener) = &mut self.listener { listener.on_order_cancelled(order_id, reason); }
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::OrderCancelled { sequence, timestamp, order_id, reason });
+    }
+
+    // A resting order cancelled while an order matches against it, its oco sibling goes once the
+    // match is done, see execute_order.
+    fn notify_met_cancelled(&mut self, order_id: i64, reason: CancelReason) {
+        self.notify_cancelled(order_id, reason);
+        if self.oco_links.contains_key(&order_id) { self.met_cancelled.push(order_id); }
+    }
+
+    fn notify_removed(&mut self, order_id: i64) {
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::OrderRemoved { sequence, timestamp, order_id });
+    }
+
+    fn notify_execution(&mut self, execution: &Execution) {
+        if let Some(accounting) = &mut self.accounting { accounting.on_execution(execution); }
+        if let Some(listener) = &mut self.listener { listener.on_execution(execution); }
+        if let Some(metrics) = &mut self.metrics { metrics.incr_trades(); }
+        if self.execution_sink.is_some() {
+            self.pending_settlements.push(SettlementInstruction {
+                trade_id: execution.trade_id,
+                isin: self.security.isin.clone(),
+                buyer_account: execution.buying_account_id,
+                seller_account: execution.selling_account_id,
+                buying_order_id: execution.buying_order_id,
+                selling_order_id: execution.selling_order_id,
+                quantity: execution.amount,
+                price: execution.price,
+                buyer_fee: execution.buyer_fee(),
+                seller_fee: execution.seller_fee(),
+                timestamp: self.current_time,
+            });
+        }
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::Trade { sequence, timestamp, execution: execution.clone() });
+    }
+
+    // Runs at the end of every call that changed the book: the levels it touched and a new best
+    // price are published, in ladder order, followed by the book update of the listener.
+    fn notify_book_update(&mut self) {
+        if self.batching { return; }
+        let mut touched_levels = std::mem::take(&mut self.touched_levels);
+        #[cfg(debug_assertions)]
+        self.recount_levels(&touched_levels);
+        if self.events.has_subscribers() {
+            touched_levels.sort_by_key(|&(side, price)| (side == Side::Sell, if side == Side::Buy { -price } else { price }));
+            touched_levels.dedup();
+
+            let timestamp = self.current_time;
+            for (side, price) in touched_levels.drain(..) {
+                let (quantity, order_count) = self.price_levels(side).get(&price).map_or((0, 0), |level| (level.quantity, level.len()));
+                self.events.publish(|sequence| OrderbookEvent::LevelChanged { sequence, timestamp, side, price, quantity, order_count });
+            }
+
+            let best = (self.best_bid(), self.best_ask());
+            if best != self.published_best {
+                self.published_best = best;
+                self.events.publish(|sequence| OrderbookEvent::BestPriceChanged { sequence, timestamp, best_bid: best.0, best_ask: best.1 });
+            }
+
+            let indicative = self.indicative();
+            if indicative != self.published_indicative {
+                self.published_indicative = indicative;
+                if let Some(IndicativePrice { price, paired_volume, imbalance_side, imbalance_quantity }) = indicative {
+                    self.events.publish(|sequence| OrderbookEvent::IndicativePrice { sequence, timestamp, price, paired_volume, imbalance_side, imbalance_quantity });
                 }
-            
-                return Ok(order_id)
+            }
+        }
+        touched_levels.clear();
+        self.touched_levels = touched_levels;
+
+        let update = BookUpdate { sequence: self.sequence, best_bid: self.best_bid(), best_ask: self.best_ask(), last_price: self.current_market_price };
+        if let Some(accounting) = &mut self.accounting { accounting.on_book_update(&update); }
+        if let Some(listener) = &mut self.listener { listener.on_book_update(&update); }
+        if !self.pending_settlements.is_empty() { let _ = self.flush_settlements(); }
+        if self.snapshots.as_ref().is_some_and(|snapshots| snapshots.is_due(self.sequence)) { self.publish_snapshot(); }
+
+        if self.metrics.is_some() {
+            let (bid_levels, ask_levels) = (self.levels(Side::Buy).count(), self.levels(Side::Sell).count());
+            if let Some(metrics) = &mut self.metrics {
+                metrics.gauge_book_depth(Side::Buy, bid_levels);
+                metrics.gauge_book_depth(Side::Sell, ask_levels);
+            }
+        }
+    }
+
+    pub(crate) fn reject(&mut self, reason: OrderbookError) -> OrderbookError {
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::OrderRejected { sequence, timestamp, reason });
+        if let Some(metrics) = &mut self.metrics { metrics.incr_orders_rejected(&reason); }
+        reason
+    }
+
+    // The price a pegged order has to rest at: `offset` behind the best price of its side, but never
+    // beyond its cap. Pegged orders are ignored as reference, so pegs never follow each other or
+    // themselves. Without any other order on its side a peg rests at its cap.
+    fn pegged_price(&self, order: &Order, cap: i64) -> i64 {
+        let offset = order.peg_offset.unwrap_or(0);
+        let reference = self.ladder(order.side, None)
+            .find(|(_, level)| level.orders(&self.order_map).any(|resting| resting.peg_offset.is_none()))
+            .map(|(price, _)| price);
+
+        match (order.side, reference) {
+            (Side::Buy, Some(reference)) => (reference - offset).min(cap).max(self.security.tick_size),
+            (Side::Sell, Some(reference)) => (reference + offset).max(cap),
+            (_, None) => cap,
+        }
+    }
+
+    // Moves pegged orders whose reference price changed to their new level, at the back of its queue.
+    fn reprice_pegged_orders(&mut self) {
+        self.pegged_orders.retain(|order_id| self.order_map.contains_key(order_id));
+
+        for order_id in self.pegged_orders.clone() {
+            let Some(order) = self.order_map.get(&order_id) else { continue; };
+            let (Some(cap), Some(limit)) = (order.peg_cap, order.order_limit) else { continue; };
+            let price = self.pegged_price(order, cap);
+            if price == limit { continue; }
+
+            let side = order.side;
+            self.remove_from_levels(order_id, side, limit);
+            self.notify_removed(order_id);
+            let Some(mut order) = self.order_map.remove(&order_id) else { continue; };
+            order.order_limit = Some(price);
+            self.insert_order(order);
+        }
+    }
+
+    // Takes an order out of the order map and whichever queue it is waiting in.
+    fn unlink_order(&mut self, order_id: i64) -> Option<Order> {
+        let order = self.order_map.get(&order_id)?;
+        let (side, limit, pending_stop, midpoint) = (order.side, order.order_limit, order.is_pending_stop(), order.midpoint);
+
+        if pending_stop {
+            let queue = match side {
+                Side::Buy => &mut self.buy_stop_orders,
+                Side::Sell => &mut self.sell_stop_orders,
+            };
+            queue.retain(|&queued_id| queued_id != order_id);
+        } else if midpoint {
+            self.midpoint_orders_mut(side).retain(|&queued_id| queued_id != order_id);
+        } else if let Some(limit) = limit {
+            self.remove_from_levels(order_id, side, limit);
+        } else {
+            self.at_market_orders_mut(side).retain(|&queued_id| queued_id != order_id);
+        }
+
+        self.order_map.remove(&order_id)
+    }
+
+    // Changes limit and total amount of a resting order while keeping its id. Reducing the amount
+    // keeps the place in the queue. A new price or a larger amount loses time priority: the order
+    // is taken out and submitted again as if it was new, so it may trade if it became marketable.
+    // Pending stops are amended in place, their queue is ordered by stop price only.
+    pub fn amend_order(&mut self, order_id: OrderId, new_limit: Option<Price>, new_amount: Qty) -> Result<OrderReport, OrderbookError> {
+        self.tick();
+        let (order_id, new_limit, new_amount) = (order_id.to_raw(), new_limit.map(Price::get), new_amount.get());
+        self.position_changes.clear();
+        let Some(current) = self.order_map.get(&order_id) else { return Err(OrderbookError::UnknownOrder(order_id)); };
+        self.check_order_size(current.side, new_limit.or(current.stop_price), new_amount)?;
+        if self.session_state == SessionState::Closed { return Err(OrderbookError::MarketClosed); }
+        if self.session_state == SessionState::Halted { return Err(OrderbookError::MarketHalted); }
+        if new_amount <= current.amount_executed {
+            return Err(OrderbookError::AmendBelowExecuted { order_id, executed: current.amount_executed });
+        }
+        if new_limit.is_some_and(|limit| limit <= 0) { return Err(OrderbookError::InvalidLimit); }
+        let (tick_size, lot_size) = (self.security.tick_size, self.security.lot_size);
+        if let Some(price) = new_limit.filter(|limit| limit % tick_size != 0) { return Err(OrderbookError::PriceNotOnTick { price, tick_size }); }
+        if new_amount % lot_size != 0 { return Err(OrderbookError::QuantityNotInLots { quantity: new_amount, lot_size }); }
+        if current.display_quantity.is_some() && new_limit.is_none() { return Err(OrderbookError::InvalidDisplayQuantity); }
+        if let Some(limit) = new_limit.filter(|_| !current.midpoint) {
+            let resting_here = if current.queue.is_some() && current.order_limit == Some(limit) { current.visible_remaining() } else { 0 };
+            self.check_level_room(current.side, limit, resting_here, new_amount - current.amount_executed)?;
+        }
+
+        let mut amended = current.clone();
+        let (limit, amount, visible) = (current.order_limit, current.amount, current.visible_remaining());
+        self.log(JournalEntry::Amend { order_id, new_limit, new_amount })?;
+        amended.order_limit = new_limit;
+        amended.amount = new_amount;
+        if amended.display_quantity.is_some() { amended.displayed = amended.displayed.min(amended.remaining()); }
+        self.sequence += 1;
+
+        if amended.is_pending_stop() || (new_limit == limit && new_amount <= amount) {
+            let report = OrderReport::new(&amended, Vec::new());
+            let resting = !amended.is_pending_stop() && amended.order_limit.is_some() && !amended.midpoint;
+            let (timestamp, quantity) = (self.current_time, amended.visible_remaining());
+            if let (true, Some(limit)) = (resting, amended.order_limit) {
+                let change = quantity - visible;
+                self.touched_levels.push((amended.side, limit));
+                self.adjust_level(amended.side, limit, change);
+            }
+            self.order_map.insert(order_id, amended);
+            if resting { self.events.publish(|sequence| OrderbookEvent::OrderReduced { sequence, timestamp, order_id, quantity }); }
+            self.notify_book_update();
+            return Ok(report);
+        }
+
+        self.apply_post_only(&mut amended)?;
+        // the copy still holds the place of the order in its queue
+        amended.queue = None;
+        let removed = self.unlink_order(order_id);
+        if removed.is_some_and(|order| !order.is_pending_stop() && order.order_limit.is_some() && !order.midpoint) { self.notify_removed(order_id); }
+
+        let executions = self.execute_order(&mut amended);
+        self.trigger_stop_orders();
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+
+        Ok(OrderReport::new(&amended, executions))
+    }
+
+    pub fn place_order(&mut self, order: Order) -> Result<OrderReport, OrderbookError> {
+        self.tick();
+        let started = self.latency_start();
+        let result = self.place(order);
+        self.record_latency(started);
+        result
+    }
+
+    // Places the orders one after the other, as many calls to place_order would, so an order can
+    // trade against what the orders before it left in the book. Executions, cancels and rejects
+    // are reported as they happen, the levels the batch touched and the top of the book once at
+    // the end.
+    pub fn place_orders(&mut self, batch: Vec<Order>) -> Vec<Result<OrderReport, OrderbookError>> {
+        self.begin_batch();
+        let reports = batch.into_iter().map(|order| self.place_order(order)).collect();
+        self.end_batch();
+        reports
+    }
+
+    pub(crate) fn begin_batch(&mut self) {
+        self.batching = true;
+    }
+
+    pub(crate) fn end_batch(&mut self) {
+        if !self.batching { return; }
+        self.batching = false;
+        self.notify_book_update();
+    }
+
+    fn place(&mut self, mut order: Order) -> Result<OrderReport, OrderbookError> {
+        // the order is logged the way the caller built it, before anything from it reaches the book
+        let logged = self.journal.is_some().then(|| Box::new(order.clone()));
+        match self.prepare_order(&mut order) {
+            Ok(order_id) => {
+                if let Some(logged) = logged { self.log(JournalEntry::Place { order_id, order: logged })?; }
+                Ok(self.submit_order(order))
             },
-            Err(error_text) => Err(error_text),
+            Err(error) => Err(self.reject(error)),
         }
     }
 
-    fn match_against_buy_side_at_market(&mut self, order: &mut Order<'a>) {
-        // move to order map if no matching is possible or the order is not fully executed
-        // first match with limit orders in order of the price and queue location on the buy side
+    // Only reads the clock when someone collects the latencies.
+    fn latency_start(&self) -> Option<u64> {
+        self.metrics.as_ref().map(|_| self.latency_clock.now_ns())
+    }
+
+    fn record_latency(&mut self, started: Option<u64>) {
+        let Some(started) = started else { return; };
+        let elapsed = self.latency_clock.now_ns().saturating_sub(started);
+        if let Some(metrics) = &mut self.metrics { metrics.record_order_latency(elapsed); }
+    }
 
-        // match order and send to the accounting module
+    // Everything that can reject an order happens here, before the order touches the book.
+    fn prepare_order(&mut self, order: &mut Order) -> Result<i64, OrderbookError> {
+        self.check_order(order)?;
+        self.assign_order_id(order)
     }
 
-    fn match_against_sell_side_at_market(&mut self, order: &mut Order<'a>) {
-        // move to order map if no matching is possible or the order is not fully executed
-        // first match with limit orders in order of the price and queue location on the sell side
+    // Orders placed together are all checked before any of them takes an id, so a rejected pair
+    // does not use up ids that journal replay would not use.
+    fn check_order(&mut self, order: &mut Order) -> Result<(), OrderbookError> {
+        self.position_changes.clear();
+        if order.security.isin != self.security.isin { return Err(OrderbookError::WrongSecurity); }
+        if let Some(offset) = order.trailing_offset { order.stop_price = Some(Self::trailing_stop_price(order.side, self.current_market_price, offset)); }
+        if order.peg_offset.is_some() {
+            order.peg_cap = order.order_limit;
+            order.order_limit = order.peg_cap.map(|cap| self.pegged_price(order, cap));
+        }
+        self.apply_post_only(order)?;
 
-        // match order and send to the accounting module
+        self.accept_order(order)
     }
 
-    fn match_against_buy_side(&mut self, order: &mut Order<'a>) {
-        // move to order map if no matching is possible or the order is not fully executed
-        // first match with at markets order in order of the queue location on the buy side
-        // then match with limit orders which are higher or equal than this one
+    fn submit_order(&mut self, mut order: Order) -> OrderReport {
+        self.sequence += 1;
+        let (timestamp, order_id) = (self.current_time, order.order_id);
+        self.events.publish(|sequence| OrderbookEvent::OrderAccepted { sequence, timestamp, order_id });
+        if let Some(metrics) = &mut self.metrics { metrics.incr_orders_accepted(); }
+        if order.is_pending_stop() && !self.stop_triggered(&order) {
+            // the stop waits for the market to reach its trigger price
+            self.insert_stop_order(order.clone());
+            self.notify_book_update();
+            return OrderReport::new(&order, Vec::new());
+        }
+
+        order.triggered = order.stop_price.is_some();
+        if order.peg_offset.is_some() { self.pegged_orders.push(order.order_id); }
+        let executions = self.execute_order(&mut order);
+        self.trigger_stop_orders();
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+
+        OrderReport::new(&order, executions)
+    }
 
-        // match order and send to the accounting module
+    // Places two linked orders, typically a limit and a stop, of which only one may execute. Once
+    // one leg trades the other is cancelled, or reduced in proportion to the fill under
+    // OcoPolicy::ReduceProportionally. Both orders are validated before any of them is placed.
+    pub fn place_oco(&mut self, primary: Order, secondary: Order) -> Result<OcoReport, OrderbookError> {
+        self.tick();
+        let started = self.latency_start();
+        let result = self.place_linked(primary, secondary);
+        self.record_latency(started);
+        result
     }
 
-    fn match_against_sell_side(&mut self, order: &mut Order<'a>) {
-        // move to order map if no matching is possible or the order is not fully executed
-        // first match with market order in order of the queue location on the sell side
-        // then match with limit orders which are lower or equal price than this one
+    fn place_linked(&mut self, mut primary: Order, mut secondary: Order) -> Result<OcoReport, OrderbookError> {
+        let logged = self.journal.is_some().then(|| Box::new((primary.clone(), secondary.clone())));
+        self.check_order(&mut primary).map_err(|error| self.reject(error))?;
+        self.check_order(&mut secondary).map_err(|error| self.reject(error))?;
+        self.assign_order_id(&mut primary).map_err(|error| self.reject(error))?;
+        self.assign_order_id(&mut secondary).map_err(|error| self.reject(error))?;
+        if let Some(orders) = logged { self.log(JournalEntry::PlaceOco { primary_id: primary.order_id, secondary_id: secondary.order_id, orders })?; }
 
-        // match order and send to the accounting module 
+        let link_id = self.next_oco_link_id;
+        self.next_oco_link_id += 1;
+        let (primary_id, secondary_id) = (primary.order_id, secondary.order_id);
+        self.oco_links.insert(primary_id, OcoLink { link_id, sibling: secondary_id, amount: primary.amount, executed: 0 });
+        self.oco_links.insert(secondary_id, OcoLink { link_id, sibling: primary_id, amount: secondary.amount, executed: 0 });
+
+        let mut primary_report = self.submit_order(primary);
+
+        // the primary may already have traded enough to cancel or shrink the secondary
+        let allowed = self.oco_allowed_remaining(secondary_id).unwrap_or(0);
+        let secondary_report = if allowed == 0 {
+            if let Some(link) = self.oco_links.remove(&secondary_id) { self.oco_links.remove(&link.sibling); }
+            secondary.close(OrderState::Cancelled, CancelReason::OcoSibling);
+            self.notify_cancelled(secondary_id, CancelReason::OcoSibling);
+            OrderReport::new(&secondary, Vec::new())
+        } else {
+            secondary.amount = secondary.amount.min(allowed);
+            self.submit_order(secondary)
+        };
+        primary_report.sync(self.order_map.get(&primary_id));
+
+        Ok(OcoReport { link_id, primary: primary_report, secondary: secondary_report })
     }
-}
 
-enum MatchingSignal {
-    BuyAtMarket,
-    SellAtMarket,
-    NewHighestBid,
-    NewLowestAsk,
-    NoOperation
-}
+    // How much of an oco leg may still be open given what its sibling executed so far.
+    fn oco_allowed_remaining(&self, order_id: i64) -> Option<i64> {
+        let link = self.oco_links.get(&order_id)?;
+        let sibling = self.oco_links.get(&link.sibling)?;
 
-pub struct Order<'a> {
-    order_id: i64,
-    is_buy_order: bool,
-    order_limit: Option<i64>,
-    security: &'a Security,
-    amount: i64,
-    amount_executed: i64
-}
+        let allowed = match self.oco_policy {
+            OcoPolicy::CancelOnFill if sibling.executed > 0 => 0,
+            OcoPolicy::CancelOnFill => link.amount,
+            OcoPolicy::ReduceProportionally => (link.amount as i128 * (sibling.amount - sibling.executed) as i128 / sibling.amount as i128) as i64,
+        };
+        Some(allowed)
+    }
 
-impl <'a> Order<'a> {
-    pub fn new(is_buy_order: bool, order_limit: Option<i64>, security: &Security, amount: i64) -> Order {
-        Order {
-            order_id: -1,
-            is_buy_order,
-            order_limit,
-            security,
-            amount,
-            amount_executed: 0,
+    // Runs after every match: books the fills of oco legs and cancels or reduces their siblings.
+    fn apply_oco_fills(&mut self, executions: &[Execution]) {
+        let mut siblings = Vec::new();
+        for execution in executions {
+            for order_id in [execution.buying_order_id, execution.selling_order_id] {
+                let Some(link) = self.oco_links.get_mut(&order_id) else { continue; };
+                link.executed += execution.amount;
+                if !siblings.contains(&link.sibling) { siblings.push(link.sibling); }
+            }
+        }
+
+        for sibling_id in siblings {
+            let Some(allowed) = self.oco_allowed_remaining(sibling_id) else { continue; };
+
+            if allowed == 0 {
+                // the links go first so the cancellation does not cascade back to the filled leg
+                if let Some(link) = self.oco_links.remove(&sibling_id) { self.oco_links.remove(&link.sibling); }
+                let _ = self.cancel_with_reason(sibling_id, CancelReason::OcoSibling);
+            } else if let Some(sibling) = self.order_map.get_mut(&sibling_id) {
+                if allowed < sibling.remaining() {
+                    let visible = sibling.visible_remaining();
+                    sibling.amount = sibling.amount_executed + allowed;
+                    if sibling.display_quantity.is_some() { sibling.displayed = sibling.displayed.min(sibling.remaining()); }
+
+                    if let (false, false, Some(limit)) = (sibling.is_pending_stop(), sibling.midpoint, sibling.order_limit) {
+                        let (side, timestamp, quantity) = (sibling.side, self.current_time, sibling.visible_remaining());
+                        self.touched_levels.push((side, limit));
+                        self.adjust_level(side, limit, quantity - visible);
+                        self.events.publish(|sequence| OrderbookEvent::OrderReduced { sequence, timestamp, order_id: sibling_id, quantity });
+                    }
+                }
+            }
         }
     }
 
-    pub fn order_id(&self) -> i64 {
-        self.order_id
+    // Replaces the quote of the account with a new bid and ask, placed as good till cancel limit
+    // orders of the account. Both orders are validated, and checked against the opposite side
+    // without the previous quote when the QuotePolicy rejects crossing quotes, before the previous
+    // quote is cancelled, so a rejected quote leaves the previous one untouched.
+    pub fn submit_quote(&mut self, account_id: u64, bid_price: i64, bid_quantity: i64, ask_price: i64, ask_quantity: i64) -> Result<QuoteHandle, OrderbookError> {
+        self.tick();
+        let started = self.latency_start();
+        let result = self.replace_quote(account_id, (bid_price, bid_quantity), (ask_price, ask_quantity));
+        self.record_latency(started);
+        result
     }
-}
 
-struct Execution<'a> {
-    selling_order: &'a Order<'a>,
-    buying_order: &'a Order<'a>,
-    amount: i64,
-}
+    fn replace_quote(&mut self, account_id: u64, bid: (i64, i64), ask: (i64, i64)) -> Result<QuoteHandle, OrderbookError> {
+        if bid.0 >= ask.0 { return Err(self.reject(OrderbookError::InvalidQuote { bid_price: bid.0, ask_price: ask.0 })); }
+        let previous = self.quotes.get(&account_id).copied();
+        if self.config.quote_policy == QuotePolicy::RejectCrossing {
+            for (side, price, replaced) in [(Side::Buy, bid.0, previous.map(|quote| quote.ask_id)), (Side::Sell, ask.0, previous.map(|quote| quote.bid_id))] {
+                let Some(best_opposite) = self.quote_crosses(side, price, replaced) else { continue; };
+                return Err(self.reject(OrderbookError::QuoteWouldCross { side, price, best_opposite }));
+            }
+        }
 
-pub struct Security {
-    pub isin: String,
-    pub name: String,
-}
\ No newline at end of file
+        let mut bid_order = Order::new(Side::Buy, Some(Price(bid.0)), &self.security, Qty(bid.1), TimeInForce::GoodTillCancel).with_account(account_id);
+        let mut ask_order = Order::new(Side::Sell, Some(Price(ask.0)), &self.security, Qty(ask.1), TimeInForce::GoodTillCancel).with_account(account_id);
+        self.check_order(&mut bid_order).map_err(|error| self.reject(error))?;
+        self.check_order(&mut ask_order).map_err(|error| self.reject(error))?;
+        self.assign_order_id(&mut bid_order).map_err(|error| self.reject(error))?;
+        self.assign_order_id(&mut ask_order).map_err(|error| self.reject(error))?;
+        let (bid_id, ask_id) = (bid_order.order_id, ask_order.order_id);
+        self.log(JournalEntry::Quote { account_id, bid_id, ask_id, bid, ask })?;
+
+        if let Some(previous) = previous { self.remove_quote_orders(previous, CancelReason::QuoteReplaced); }
+        let quote_id = self.next_quote_id;
+        self.next_quote_id += 1;
+        self.quotes.insert(account_id, Quote { quote_id, bid_id, ask_id });
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::QuoteUpdated {
+            sequence, timestamp, account_id, quote_id, bid_order_id: bid_id, bid_price: bid.0, bid_quantity: bid.1, ask_order_id: ask_id, ask_price: ask.0, ask_quantity: ask.1,
+        });
+
+        let mut bid_report = self.submit_order(bid_order);
+        let ask_report = self.submit_order(ask_order);
+        bid_report.sync(self.order_map.get(&bid_id));
+        Ok(QuoteHandle { quote_id, account_id, bid: bid_report, ask: ask_report })
+    }
+
+    // The best opposite price a quote at `price` would trade with, leaving out the order of the
+    // quote it replaces. Parked market orders trade with any quote.
+    fn quote_crosses(&self, side: Side, price: i64, replaced: Option<i64>) -> Option<i64> {
+        let opposite = side.opposite();
+        let other = |order_id: &i64| Some(*order_id) != replaced;
+        if self.at_market_orders(opposite).iter().any(other) { return Some(self.current_market_price); }
+        let best_opposite = self.levels(opposite).find(|level| level.orders().any(|order| other(&order.order_id)))?.price;
+        (!side.improves(best_opposite, price)).then_some(best_opposite)
+    }
+
+    // Cancels whatever is still open of a quote, unless the account has replaced it since.
+    pub fn cancel_quote(&mut self, handle: &QuoteHandle) -> Result<(), OrderbookError> {
+        self.tick();
+        self.withdraw_quote(handle.account_id, handle.quote_id)
+    }
+
+    fn withdraw_quote(&mut self, account_id: u64, quote_id: i64) -> Result<(), OrderbookError> {
+        let Some(quote) = self.quotes.get(&account_id).copied().filter(|quote| quote.quote_id == quote_id) else { return Err(OrderbookError::UnknownQuote(quote_id)); };
+        self.log(JournalEntry::CancelQuote { account_id, quote_id })?;
+        self.quotes.remove(&account_id);
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::QuoteCancelled { sequence, timestamp, account_id, quote_id });
+        self.remove_quote_orders(quote, CancelReason::QuoteCancelled);
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+        Ok(())
+    }
+
+    // Takes the open orders of a quote off the book, the caller publishes the book update.
+    fn remove_quote_orders(&mut self, quote: Quote, reason: CancelReason) {
+        for order_id in [quote.bid_id, quote.ask_id] {
+            let Some(mut order) = self.unlink_order(order_id) else { continue; };
+            order.close(OrderState::Cancelled, reason);
+            self.sequence += 1;
+            self.notify_cancelled(order_id, reason);
+        }
+    }
+
+    // the bid and ask order ids of the current quote of an account
+    pub fn quote(&self, account_id: u64) -> Option<(i64, i64)> {
+        self.quotes.get(&account_id).map(|quote| (quote.bid_id, quote.ask_id))
+    }
+
+    // A post only order must never take liquidity. If its limit reaches the opposite side it is either
+    // rejected or moved one tick away from the opposite best price, depending on its policy.
+    fn apply_post_only(&self, order: &mut Order) -> Result<(), OrderbookError> {
+        let (Some(policy), Some(limit)) = (order.post_only, order.order_limit) else { return Ok(()); };
+        if order.is_pending_stop() { return Ok(()); }
+
+        // parked market orders of the opposite side trade against any limit
+        let opposite = order.side.opposite();
+        if !self.at_market_orders(opposite).is_empty() {
+            return Err(OrderbookError::RejectedPostOnlyWouldCross { limit, best_opposite: self.current_market_price });
+        }
+
+        let Some(best_opposite) = self.best_price(opposite) else { return Ok(()); };
+        if order.side.improves(best_opposite, limit) { return Ok(()); }
+
+        let repriced = match order.side {
+            Side::Buy => best_opposite - self.security.tick_size,
+            Side::Sell => best_opposite + self.security.tick_size,
+        };
+
+        match policy {
+            PostOnlyPolicy::Reprice if repriced > 0 => {
+                order.order_limit = Some(repriced);
+                Ok(())
+            },
+            _ => Err(OrderbookError::RejectedPostOnlyWouldCross { limit, best_opposite }),
+        }
+    }
+
+    // Matches an accepted order against the book and rests or cancels whatever is left of it.
+    // Outside continuous trading and in batch auctions nothing matches, orders that may rest wait
+    // for the auction. A trade outside the price bands stops the sweep, see PriceBands.
+    fn execute_order(&mut self, order: &mut Order) -> Vec<Execution> {
+        let continuous = self.session_state == SessionState::Continuous && !self.config.batch_auctions;
+        self.dynamic_reference = self.current_market_price;
+        // a fill or kill order is killed before anything in the book is touched
+        if order.time_in_force == TimeInForce::FillOrKill && (!continuous || self.available_liquidity(order, order.amount) < order.amount) {
+            order.close(OrderState::Killed, CancelReason::Killed);
+            self.notify_cancelled(order.order_id, CancelReason::Killed);
+            return Vec::new();
+        }
+
+        // an order with a minimum quantity does not take a smaller fill, but rests if nothing is on offer
+        if let (Some(min_quantity), true) = (order.min_quantity, continuous) {
+            let available = self.available_liquidity(order, min_quantity);
+            if available > 0 && available < min_quantity {
+                order.close(OrderState::Killed, CancelReason::Killed);
+                self.notify_cancelled(order.order_id, CancelReason::Killed);
+                return Vec::new();
+            }
+        }
+
+        let signal = if continuous && !order.midpoint { self.matching_signal(order) } else { MatchingSignal::NoOperation };
+        let executions = match signal {
+            MatchingSignal::BuyAtMarket | MatchingSignal::SellAtMarket => {
+                // try to match order directly
+                self.match_at_market(order)
+            },
+            MatchingSignal::NewHighestBid | MatchingSignal::NewLowestAsk => {
+                // the order reaches the opposite side, try to match before resting
+                self.match_limit_order(order)
+            },
+            // midpoint orders only meet other midpoint orders on arrival
+            MatchingSignal::NoOperation if continuous && order.midpoint => self.match_against_midpoint_orders(order),
+            MatchingSignal::NoOperation => {
+                // No matching possible
+                Vec::new()
+            },
+        };
+
+        if let Some((band, price)) = self.volatility_trigger.take() { self.interrupt(order.order_id, band, price); }
+        self.rest_order(order, &executions);
+        if let Some(reason) = order.cancel_reason { self.notify_cancelled(order.order_id, reason); }
+        while let Some(order_id) = self.met_cancelled.pop() { self.cancel_oco_sibling(order_id); }
+        if !self.oco_links.is_empty() { self.apply_oco_fills(&executions); }
+
+        if order.remaining() == 0 { self.filled_orders.insert(order.order_id, order.clone()); }
+        // the accounting listener, if any, booked every execution the moment it happened
+        self.record_executions(&executions);
+        executions
+    }
+
+    fn record_executions(&mut self, executions: &[Execution]) {
+        for execution in executions {
+            self.stats.record(execution.price, execution.amount);
+            if let Some(candles) = &mut self.candles { candles.record(self.current_time, execution.price, execution.amount); }
+        }
+        self.executions.extend(executions.iter().cloned());
+    }
+
+    // Buy stops are queued by ascending, sell stops by descending stop price, so the front of each
+    // queue is the next stop to trigger. Stops with the same price keep their arrival order.
+    fn insert_stop_order(&mut self, order: Order) {
+        let Some(stop_price) = order.stop_price else { return; };
+        let order_id = order.order_id;
+        let side = order.side;
+        self.order_map.insert(order_id, order);
+
+        let order_map = &self.order_map;
+        let queue = match side {
+            Side::Buy => &mut self.buy_stop_orders,
+            Side::Sell => &mut self.sell_stop_orders,
+        };
+        let index = queue.partition_point(|queued_id| {
+            order_map.get(queued_id).and_then(|queued| queued.stop_price).is_some_and(|queued_stop| !side.improves(queued_stop, stop_price))
+        });
+        queue.insert(index, order_id);
+    }
+
+    fn trailing_stop_price(side: Side, market_price: i64, offset: i64) -> i64 {
+        match side {
+            Side::Buy => market_price + offset,
+            Side::Sell => market_price - offset,
+        }
+    }
+
+    // Moves trailing stops after the market price changed and restores the order of the stop
+    // queues. The sort is stable, so stops at the same price keep their arrival order.
+    fn ratchet_trailing_stops(&mut self) {
+        for side in [Side::Buy, Side::Sell] {
+            let queue = match side {
+                Side::Buy => &mut self.buy_stop_orders,
+                Side::Sell => &mut self.sell_stop_orders,
+            };
+            let mut moved = false;
+
+            for order_id in queue.iter() {
+                let Some(order) = self.order_map.get_mut(order_id) else { continue; };
+                let (Some(offset), Some(stop_price)) = (order.trailing_offset, order.stop_price) else { continue; };
+                let trailed = Self::trailing_stop_price(side, self.current_market_price, offset);
+                // a buy stop only moves down, a sell stop only moves up
+                if side.improves(stop_price, trailed) {
+                    order.stop_price = Some(trailed);
+                    moved = true;
+                }
+            }
+
+            if moved {
+                let order_map = &self.order_map;
+                queue.make_contiguous().sort_by_key(|order_id| {
+                    let stop_price = order_map.get(order_id).and_then(|order| order.stop_price).unwrap_or(0);
+                    match side {
+                        Side::Buy => stop_price,
+                        Side::Sell => -stop_price,
+                    }
+                });
+            }
+        }
+    }
+
+    fn stop_triggered(&self, order: &Order) -> bool {
+        match (order.side, order.stop_price) {
+            (Side::Buy, Some(stop_price)) => self.current_market_price >= stop_price,
+            (Side::Sell, Some(stop_price)) => self.current_market_price <= stop_price,
+            (_, None) => false,
+        }
+    }
+
+    // Executions move the market price, which can trigger stops whose executions in turn trigger
+    // further stops. The cascade is resolved one stop at a time until no stop is triggered anymore.
+    // Triggers are checked against the trail levels from before the price change, only then are
+    // trailing stops moved along with the new price.
+    fn trigger_stop_orders(&mut self) {
+        loop {
+            let next_stop = [Side::Buy, Side::Sell].into_iter().find_map(|side| {
+                let queue = match side {
+                    Side::Buy => &self.buy_stop_orders,
+                    Side::Sell => &self.sell_stop_orders,
+                };
+                queue.front().copied().filter(|order_id| self.order_map.get(order_id).is_some_and(|order| self.stop_triggered(order))).map(|order_id| (side, order_id))
+            });
+            let Some((side, order_id)) = next_stop else {
+                self.ratchet_trailing_stops();
+                break;
+            };
+
+            match side {
+                Side::Buy => self.buy_stop_orders.pop_front(),
+                Side::Sell => self.sell_stop_orders.pop_front(),
+            };
+            let Some(mut order) = self.order_map.remove(&order_id) else { continue; };
+            if order.is_expired(self.current_time) {
+                self.notify_cancelled(order_id, CancelReason::Expired);
+                self.cancel_oco_sibling(order_id);
+                continue;
+            }
+            order.triggered = true;
+            self.execute_order(&mut order);
+        }
+    }
+
+    fn match_at_market(&mut self, order: &mut Order) -> Vec<Execution> {
+        // midpoint orders improve on the opposite best price, so they trade first
+        let mut executions = self.match_against_midpoint_orders(order);
+
+        // match with limit orders in order of the price and queue location on the opposite side
+        if self.volatility_trigger.is_none() { executions.append(&mut self.match_against_levels(order)); }
+        executions
+    }
+
+    fn match_limit_order(&mut self, order: &mut Order) -> Vec<Execution> {
+        let mut executions = self.match_against_midpoint_orders(order);
+
+        // then match with at market orders in order of the queue location on the opposite side
+        if self.volatility_trigger.is_none() { executions.append(&mut self.match_against_market_orders(order)); }
+
+        // then match with limit orders which are at or better than the limit of this one
+        if self.volatility_trigger.is_none() { executions.append(&mut self.match_against_levels(order)); }
+        executions
+    }
+
+    // Whatever is left of an order after matching either rests in the book or, if its time in force
+    // does not allow resting, is cancelled. The remainder of a market order is handled as its
+    // MarketRemainder says.
+    fn rest_order(&mut self, order: &mut Order, executions: &[Execution]) {
+        if order.remaining() == 0 || order.closed.is_some() { return; }
+        if self.reduce_only_cap(order) == Some(0) {
+            order.close(OrderState::Cancelled, CancelReason::ReduceOnly);
+            return;
+        }
+
+        match order.time_in_force {
+            TimeInForce::GoodTillCancel | TimeInForce::Day | TimeInForce::AtTheClose => {
+                if order.midpoint {
+                    order.timestamp = self.current_time;
+                    self.midpoint_orders_mut(order.side).push_back(order.order_id);
+                    self.order_map.insert(order.order_id, order.clone());
+                    return;
+                }
+                if order.order_limit.is_some() {
+                    self.insert_order(order.clone());
+                    return;
+                }
+
+                // market orders take part in the volatility auction whatever their remainder policy
+                let remainder = if self.session_state == SessionState::VolatilityAuction { MarketRemainder::RestAsMarket } else { order.market_remainder };
+                match (remainder, executions.last()) {
+                    (MarketRemainder::RestAsMarket, _) => {
+                        self.at_market_orders_mut(order.side).push_back(order.order_id);
+                        self.order_map.insert(order.order_id, order.clone());
+                    },
+                    (MarketRemainder::ConvertToLimit, Some(last_execution)) => {
+                        // the market order used up the opposite side, so its last price cannot cross anymore
+                        order.order_limit = Some(last_execution.price);
+                        self.insert_order(order.clone());
+                    },
+                    (MarketRemainder::ConvertToLimit, None) | (MarketRemainder::CancelRemainder, _) => order.close(OrderState::Cancelled, CancelReason::Unfilled),
+                }
+            },
+            TimeInForce::ImmediateOrCancel | TimeInForce::FillOrKill => order.close(OrderState::Cancelled, CancelReason::Unfilled),
+        }
+    }
+
+    // Sums up the quantity the order could execute against right now without modifying the book.
+    // The walk follows the matching order and stops as soon as `needed` is reached.
+    fn available_liquidity(&self, order: &Order, needed: i64) -> i64 {
+        let mut available = 0;
+        let self_trade = self.self_trade_policy_for(order);
+
+        for (price, resting_order) in self.resting_liquidity(order.side, order.order_limit) {
+            if self.price_bands.breach(price, self.band_reference, self.current_market_price).is_some() { return available; }
+            if order.order_limit.is_none() && !self.config.within_collar(order.side, price, self.current_market_price) { return available; }
+            // own orders are cancelled instead of traded, and only CancelOldest matches past them
+            if Self::is_self_trade(self_trade, order, resting_order) {
+                if self_trade == SelfTradePolicy::CancelOldest { continue; }
+                return available;
+            }
+            available += resting_order.remaining();
+            if available >= needed { return available; }
+        }
+
+        available
+    }
+
+    // The resting orders an incoming order of `side` with `limit` would trade against, in matching
+    // order, together with the price each would trade at. Parked market orders come first for
+    // limit orders, then the levels that do not lie beyond the limit.
+    fn resting_liquidity(&self, side: Side, limit: Option<i64>) -> impl Iterator<Item = (i64, &Order)> + '_ {
+        let opposite = side.opposite();
+        let at_market = limit.into_iter().flat_map(move |limit| {
+            self.at_market_orders(opposite).iter().filter_map(|order_id| self.order_map.get(order_id)).map(move |resting_order| (limit, resting_order))
+        });
+        let levels = self.ladder(opposite, limit)
+            .flat_map(|(price, level)| level.orders(&self.order_map).map(move |resting_order| (price, resting_order)));
+
+        // expired orders that were not purged yet never trade
+        at_market.chain(levels).filter(|(_, resting_order)| !resting_order.is_expired(self.current_time))
+    }
+
+    // a quote that would improve on the incoming limit lies beyond it
+    fn beyond_limit(side: Side, price: i64, limit: Option<i64>) -> bool {
+        limit.is_some_and(|limit| side.improves(price, limit))
+    }
+
+    // Simulates an order of `side` against the visible liquidity of the book without changing it.
+    // The hidden part of icebergs is not counted.
+    pub fn estimate_fill(&self, side: Side, amount: i64, limit: Option<i64>) -> FillEstimate {
+        let mut filled = 0;
+        let mut notional: i128 = 0;
+        let mut worst_price = None;
+
+        for (price, resting_order) in self.resting_liquidity(side, limit) {
+            if filled >= amount { break; }
+            let quantity = (amount - filled).min(resting_order.visible_remaining());
+            filled += quantity;
+            notional += price as i128 * quantity as i128;
+            worst_price = Some(price);
+        }
+
+        // the average is rounded down to whole price units
+        let average_price = if filled > 0 { Some((notional / filled as i128) as i64) } else { None };
+        FillEstimate { filled, average_price, worst_price, leftover: amount.max(0) - filled }
+    }
+
+    // Fills the incoming limit order against parked market orders of the opposite side in FIFO
+    // order. Market orders trade at the limit of the incoming order.
+    fn match_against_market_orders(&mut self, order: &mut Order) -> Vec<Execution> {
+        let mut executions = Vec::new();
+        let Some(price) = order.order_limit else { return executions; };
+        let opposite = order.side.opposite();
+        let self_trade = self.self_trade_policy_for(order);
+
+        while order.remaining() > 0 {
+            let (incoming_cap, resting_cap) = self.reduce_only_caps(order, self.at_market_orders(opposite).front().copied());
+            let queue = match opposite {
+                Side::Buy => &mut self.buy_at_market_orders,
+                Side::Sell => &mut self.sell_at_market_orders,
+            };
+            let Some(&resting_id) = queue.front() else { break; };
+
+            let Some(resting_order) = self.order_map.get_mut(&resting_id) else {
+                queue.pop_front();
+                continue;
+            };
+
+            // expired orders that were not purged yet never trade
+            if resting_order.is_expired(self.current_time) {
+                queue.pop_front();
+                self.order_map.remove(&resting_id);
+                self.notify_met_cancelled(resting_id, CancelReason::Expired);
+                continue;
+            }
+
+            // reduce only orders never build up a position, whatever cannot reduce it anymore is cancelled
+            if incoming_cap == Some(0) {
+                order.close(OrderState::Cancelled, CancelReason::ReduceOnly);
+                break;
+            }
+            if resting_cap == Some(0) {
+                queue.pop_front();
+                self.order_map.remove(&resting_id);
+                self.notify_met_cancelled(resting_id, CancelReason::ReduceOnly);
+                continue;
+            }
+
+            // parked market orders do not trade beyond their collar either
+            if !self.config.within_collar(opposite, price, self.dynamic_reference) { break; }
+
+            if Self::is_self_trade(self_trade, order, resting_order) {
+                if self_trade != SelfTradePolicy::CancelNewest {
+                    queue.pop_front();
+                    self.order_map.remove(&resting_id);
+                    self.notify_met_cancelled(resting_id, CancelReason::SelfTrade);
+                }
+                if self_trade == SelfTradePolicy::CancelOldest { continue; }
+                order.close(OrderState::Cancelled, CancelReason::SelfTrade);
+                break;
+            }
+
+            if let Some(band) = self.price_bands.breach(price, self.band_reference, self.dynamic_reference) {
+                self.volatility_trigger = Some((band, price));
+                break;
+            }
+
+            let resting_account = resting_order.account_id;
+            let amount = Self::fill(order, resting_order, incoming_cap.into_iter().chain(resting_cap).min());
+            if self.position_provider.is_some() { Self::track_position(&mut self.position_changes, order, resting_account, amount); }
+            let mut execution = Execution::between(order, resting_id, resting_account, price, amount);
+            execution.timestamp = self.current_time;
+            execution.trade_id = self.trade_tape.record(&execution, order.side, self.current_time);
+            (execution.maker_fee, execution.taker_fee) = self.fees.fees(price, amount);
+            self.current_market_price = price;
+
+            if resting_order.remaining() == 0 {
+                queue.pop_front();
+                if let Some(filled) = self.order_map.remove(&resting_id) { self.filled_orders.insert(resting_id, filled); }
+            }
+            self.notify_execution(&execution);
+            executions.push(execution);
+        }
+
+        executions
+    }
+
+    // Trades the incoming order with the midpoint orders of the opposite side in time priority, at
+    // the midpoint of the lit book taken again for every fill. Midpoint orders the midpoint is
+    // beyond the limit of are passed over and keep their place.
+    fn match_against_midpoint_orders(&mut self, order: &mut Order) -> Vec<Execution> {
+        let mut executions = Vec::new();
+        let opposite = order.side.opposite();
+        let self_trade = self.self_trade_policy_for(order);
+        let mut index = 0;
+
+        while order.remaining() > 0 {
+            let Some(price) = self.mid_price() else { break; };
+            if order.order_limit.is_some_and(|limit| order.side.improves(price, limit)) { break; }
+            let Some(&resting_id) = self.midpoint_orders(opposite).get(index) else { break; };
+            let (incoming_cap, resting_cap) = self.reduce_only_caps(order, Some(resting_id));
+            let queue = match opposite {
+                Side::Buy => &mut self.buy_midpoint_orders,
+                Side::Sell => &mut self.sell_midpoint_orders,
+            };
+
+            let Some(resting_order) = self.order_map.get_mut(&resting_id) else {
+                queue.remove(index);
+                continue;
+            };
+
+            // expired orders that were not purged yet never trade
+            if resting_order.is_expired(self.current_time) {
+                queue.remove(index);
+                self.order_map.remove(&resting_id);
+                self.notify_met_cancelled(resting_id, CancelReason::Expired);
+                continue;
+            }
+
+            if incoming_cap == Some(0) {
+                order.close(OrderState::Cancelled, CancelReason::ReduceOnly);
+                break;
+            }
+            if resting_cap == Some(0) {
+                queue.remove(index);
+                self.order_map.remove(&resting_id);
+                self.notify_met_cancelled(resting_id, CancelReason::ReduceOnly);
+                continue;
+            }
+
+            if resting_order.order_limit.is_some_and(|limit| opposite.improves(price, limit)) {
+                index += 1;
+                continue;
+            }
+
+            if Self::is_self_trade(self_trade, order, resting_order) {
+                if self_trade != SelfTradePolicy::CancelNewest {
+                    queue.remove(index);
+                    self.order_map.remove(&resting_id);
+                    self.notify_met_cancelled(resting_id, CancelReason::SelfTrade);
+                }
+                if self_trade == SelfTradePolicy::CancelOldest { continue; }
+                order.close(OrderState::Cancelled, CancelReason::SelfTrade);
+                break;
+            }
+
+            let resting_account = resting_order.account_id;
+            let amount = Self::fill(order, resting_order, incoming_cap.into_iter().chain(resting_cap).min());
+            if self.position_provider.is_some() { Self::track_position(&mut self.position_changes, order, resting_account, amount); }
+            let mut execution = Execution::between(order, resting_id, resting_account, price, amount);
+            execution.timestamp = self.current_time;
+            execution.trade_id = self.trade_tape.record(&execution, order.side, self.current_time);
+            (execution.maker_fee, execution.taker_fee) = self.fees.fees(price, amount);
+            self.current_market_price = price;
+
+            if resting_order.remaining() == 0 {
+                queue.remove(index);
+                if let Some(filled) = self.order_map.remove(&resting_id) { self.filled_orders.insert(resting_id, filled); }
+            }
+            self.notify_execution(&execution);
+            executions.push(execution);
+        }
+
+        executions
+    }
+
+    // Walks the limit levels of the opposite side, best price first, until the incoming order is
+    // filled, its limit is reached or the side is exhausted. Within a level the orders fill in
+    // queue order, or by the allocations of a pro rata plan, see MatchingAlgorithm.
+    fn match_against_levels(&mut self, order: &mut Order) -> Vec<Execution> {
+        let mut executions = Vec::new();
+        let opposite = order.side.opposite();
+        let self_trade = self.self_trade_policy_for(order);
+        let min_allocation = match self.config.matching_algorithm {
+            MatchingAlgorithm::PriceTimePriority => None,
+            MatchingAlgorithm::ProRata { min_allocation } => Some(min_allocation),
+        };
+        // stays empty in price time priority, where every order of the level may fill completely
+        let mut plan: VecDeque<(i64, i64)> = VecDeque::new();
+
+        while order.remaining() > 0 {
+            if let Some(min_allocation) = min_allocation.filter(|_| plan.is_empty()) { plan = self.pro_rata_plan(opposite, order.remaining(), min_allocation); }
+            let front_id = self.ladder(opposite, None).next().and_then(|(_, level)| self.order_map.get_at(level.front()?)).map(|resting_order| resting_order.order_id);
+            let next_id = plan.front().map(|&(order_id, _)| order_id).or(front_id);
+            let (incoming_cap, resting_cap) = self.reduce_only_caps(order, next_id);
+            let (levels, order_map) = match opposite {
+                Side::Buy => (&mut self.buy_levels, &mut self.order_map),
+                Side::Sell => (&mut self.sell_levels, &mut self.order_map),
+            };
+            let best = match opposite {
+                Side::Buy => levels.iter_mut().next_back(),
+                Side::Sell => levels.iter_mut().next(),
+            };
+            let Some((&level_price, level)) = best else { break; };
+            let Some(front) = level.front() else {
+                levels.remove(&level_price);
+                continue;
+            };
+            let (resting, allocation) = match plan.pop_front() {
+                Some((order_id, allocation)) => (order_map.handle(&order_id), allocation),
+                None => (Some(front), i64::MAX),
+            };
+            // an order of the plan that left the level since has nothing more to fill
+            let queued = |resting_order: &&mut Order| resting_order.queue.is_some() && resting_order.order_limit == Some(level_price);
+            let Some(resting) = resting else { continue; };
+            let Some(resting_order) = order_map.get_at_mut(resting).filter(queued) else { continue; };
+            let (resting_id, visible) = (resting_order.order_id, resting_order.visible_remaining());
+
+            // expired orders that were not purged yet never trade
+            if resting_order.is_expired(self.current_time) {
+                self.touched_levels.push((opposite, level_price));
+                level.remove(order_map, resting);
+                order_map.remove(&resting_id);
+                *self.number_limit_orders_mut(opposite) -= 1;
+                self.notify_met_cancelled(resting_id, CancelReason::Expired);
+                continue;
+            }
+            if allocation == 0 { continue; }
+
+            let price = resting_order.order_limit.unwrap_or(self.current_market_price);
+            if Self::beyond_limit(order.side, price, order.order_limit) { break; }
+            // a market order leaves whatever lies beyond its collar to its remainder policy
+            if order.order_limit.is_none() && !self.config.within_collar(order.side, price, self.dynamic_reference) { break; }
+
+            if incoming_cap == Some(0) {
+                order.close(OrderState::Cancelled, CancelReason::ReduceOnly);
+                break;
+            }
+            if resting_cap == Some(0) {
+                self.touched_levels.push((opposite, price));
+                level.remove(order_map, resting);
+                order_map.remove(&resting_id);
+                *self.number_limit_orders_mut(opposite) -= 1;
+                self.notify_met_cancelled(resting_id, CancelReason::ReduceOnly);
+                continue;
+            }
+
+            if Self::is_self_trade(self_trade, order, resting_order) {
+                if self_trade != SelfTradePolicy::CancelNewest {
+                    self.touched_levels.push((opposite, price));
+                    level.remove(order_map, resting);
+                    order_map.remove(&resting_id);
+                    *self.number_limit_orders_mut(opposite) -= 1;
+                    self.notify_met_cancelled(resting_id, CancelReason::SelfTrade);
+                }
+                if self_trade == SelfTradePolicy::CancelOldest { continue; }
+                order.close(OrderState::Cancelled, CancelReason::SelfTrade);
+                break;
+            }
+
+            if let Some(band) = self.price_bands.breach(price, self.band_reference, self.dynamic_reference) {
+                self.volatility_trigger = Some((band, price));
+                break;
+            }
+
+            let resting_account = resting_order.account_id;
+            let amount = Self::fill(order, resting_order, incoming_cap.into_iter().chain(resting_cap).chain([allocation]).min());
+            if self.position_provider.is_some() { Self::track_position(&mut self.position_changes, order, resting_account, amount); }
+            let mut execution = Execution::between(order, resting_id, resting_account, price, amount);
+            execution.timestamp = self.current_time;
+            execution.trade_id = self.trade_tape.record(&execution, order.side, self.current_time);
+            (execution.maker_fee, execution.taker_fee) = self.fees.fees(price, amount);
+            self.current_market_price = price;
+            self.touched_levels.push((opposite, price));
+            let mut refreshed = None;
+
+            if resting_order.remaining() == 0 {
+                level.quantity -= visible;
+                level.remove(order_map, resting);
+                if let Some(filled) = order_map.remove(&resting_id) { self.filled_orders.insert(resting_id, filled); }
+                *self.number_limit_orders_mut(opposite) -= 1;
+            } else {
+                if resting_order.visible_remaining() == 0 {
+                    // the next slice of an iceberg goes to the back of its level and loses time priority
+                    resting_order.replenish();
+                    resting_order.timestamp = self.current_time;
+                    refreshed = Some(resting_order.visible_remaining());
+                }
+                level.quantity += resting_order.visible_remaining() - visible;
+                if refreshed.is_some() {
+                    level.remove(order_map, resting);
+                    level.push_back(order_map, resting_id);
+                }
+            }
+            self.notify_execution(&execution);
+            executions.push(execution);
+
+            if let Some(quantity) = refreshed {
+                let timestamp = self.current_time;
+                self.events.publish(|sequence| OrderbookEvent::OrderAdded { sequence, timestamp, order_id: resting_id, side: opposite, price, quantity });
+            }
+        }
+
+        if let Some((price, level)) = self.best_level_mut(opposite) {
+            if level.is_empty() { self.price_levels_mut(opposite).remove(&price); }
+        }
+        executions
+    }
+
+    // Splits `quantity` over the orders of the best level of `side`, see MatchingAlgorithm::ProRata.
+    // The allocations come in queue order, orders that get nothing with 0.
+    fn pro_rata_plan(&self, side: Side, quantity: i64, min_allocation: i64) -> VecDeque<(i64, i64)> {
+        let Some((_, level)) = self.ladder(side, None).next() else { return VecDeque::new(); };
+        let sizes: Vec<(i64, i64)> = level.orders(&self.order_map).map(|resting_order| {
+            let size = if resting_order.is_expired(self.current_time) { 0 } else { resting_order.visible_remaining() };
+            (resting_order.order_id, size)
+        }).collect();
+        let total: i128 = sizes.iter().map(|&(_, size)| size as i128).sum();
+        if total == 0 { return sizes.into_iter().map(|(order_id, _)| (order_id, 0)).collect(); }
+
+        let quantity = (quantity as i128).min(total);
+        let mut plan: Vec<(i64, i64)> = sizes.iter().map(|&(order_id, size)| {
+            let allocation = (quantity * size as i128 / total) as i64;
+            (order_id, if allocation < min_allocation { 0 } else { allocation })
+        }).collect();
+
+        // what the floor rounding and the minimum left over goes to the largest orders, among
+        // orders of the same size to the earliest, as far as they have quantity left
+        let mut leftover = quantity as i64 - plan.iter().map(|&(_, allocation)| allocation).sum::<i64>();
+        let mut by_size: Vec<usize> = (0..sizes.len()).collect();
+        by_size.sort_by_key(|&index| std::cmp::Reverse(sizes[index].1));
+        for index in by_size {
+            if leftover == 0 { break; }
+            let extra = leftover.min(sizes[index].1 - plan[index].1);
+            plan[index].1 += extra;
+            leftover -= extra;
+        }
+        plan.into()
+    }
+
+    // What happens when the order meets a resting order of its own account, Allow for orders
+    // without an account.
+    fn self_trade_policy_for(&self, order: &Order) -> SelfTradePolicy {
+        let Some(account_id) = order.account_id else { return SelfTradePolicy::Allow; };
+        self.account_self_trade_policies.get(&account_id).copied().unwrap_or(self.self_trade_policy)
+    }
+
+    fn is_self_trade(policy: SelfTradePolicy, order: &Order, resting_order: &Order) -> bool {
+        policy != SelfTradePolicy::Allow && order.account_id.is_some() && resting_order.account_id == order.account_id
+    }
+
+    fn fill(order: &mut Order, resting_order: &mut Order, cap: Option<i64>) -> i64 {
+        // never execute more than is still open on either side, and only the visible slice of a resting iceberg
+        let visible = if resting_order.midpoint { resting_order.remaining() } else { resting_order.visible_remaining() };
+        let amount = order.remaining().min(visible).min(cap.unwrap_or(i64::MAX));
+        order.amount_executed += amount;
+        resting_order.amount_executed += amount;
+        if resting_order.display_quantity.is_some() { resting_order.displayed -= amount; }
+        amount
+    }
+
+    // How much a reduce only order may still execute: the position of its account, as reported by the
+    // provider and adjusted by the fills of the current call, as far as the order reduces it.
+    fn reduce_only_cap(&self, order: &Order) -> Option<i64> {
+        if !order.reduce_only { return None; }
+        let (Some(provider), Some(account_id)) = (&self.position_provider, order.account_id) else { return Some(0); };
+
+        let position = provider.position(account_id, &self.security.isin) + self.position_changes.get(&account_id).copied().unwrap_or(0);
+        match order.side {
+            Side::Buy => Some((-position).max(0)),
+            Side::Sell => Some(position.max(0)),
+        }
+    }
+
+    fn reduce_only_caps(&self, order: &Order, resting_id: Option<i64>) -> (Option<i64>, Option<i64>) {
+        if self.position_provider.is_none() { return (None, None); }
+        let resting_cap = resting_id.and_then(|resting_id| self.order_map.get(&resting_id)).and_then(|resting_order| self.reduce_only_cap(resting_order));
+        (self.reduce_only_cap(order), resting_cap)
+    }
+
+    // Keeps track of how the fills of the current call moved positions, the provider only learns
+    // about them once the executions were processed.
+    fn track_position(position_changes: &mut HashMap<u64, i64>, order: &Order, resting_account: Option<u64>, amount: i64) {
+        let (buyer, seller) = match order.side {
+            Side::Buy => (order.account_id, resting_account),
+            Side::Sell => (resting_account, order.account_id),
+        };
+        if let Some(buyer) = buyer { *position_changes.entry(buyer).or_insert(0) += amount; }
+        if let Some(seller) = seller { *position_changes.entry(seller).or_insert(0) -= amount; }
+    }
+
+    // Takes a queued order out of its level without searching the queue, the order stays in the
+    // order map.
+    fn remove_from_levels(&mut self, order_id: i64, side: Side, price: i64) {
+        self.touched_levels.push((side, price));
+        let (levels, order_map) = self.levels_and_orders(side);
+        let (Some(level), Some(handle)) = (levels.get_mut(&price), order_map.handle(&order_id)) else { return; };
+        if !level.remove(order_map, handle) { return; }
+        if level.is_empty() { levels.remove(&price); }
+        *self.number_limit_orders_mut(side) -= 1;
+    }
+
+    // Files the levels of a side again under the limit of their first order, once a corporate
+    // action moved the limits of the resting orders. Levels that end up at the same price merge,
+    // the orders of the level that was better before stay in front.
+    fn rekey_levels(&mut self, side: Side) {
+        let levels = std::mem::take(self.price_levels_mut(side));
+        let best_first: Box<dyn Iterator<Item = PriceLevel>> = match side {
+            Side::Buy => Box::new(levels.into_values().rev()),
+            Side::Sell => Box::new(levels.into_values()),
+        };
+        let mut rekeyed: BTreeMap<i64, PriceLevel> = BTreeMap::new();
+        for mut level in best_first {
+            let Some(price) = level.front().and_then(|front| self.order_map.get_at(front)).and_then(|order| order.order_limit) else { continue; };
+            level.quantity = self.level_quantity(&level);
+            rekeyed.entry(price).or_default().append(&mut self.order_map, level);
+        }
+        *self.price_levels_mut(side) = rekeyed;
+    }
+
+    // Without a clock time only advances when the caller says so, which keeps simulations
+    // deterministic. Expiry is checked against the latest time given here or read from the clock,
+    // in nanoseconds. The time never goes back, an earlier time leaves it where it is.
+    pub fn set_time(&mut self, now: Timestamp) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetTime { now })?;
+        self.current_time = self.current_time.max(now);
+        self.end_volatility_auction();
+        Ok(())
+    }
+
+    pub fn current_time(&self) -> Timestamp {
+        self.current_time
+    }
+
+    // Moves the book time to the time of the clock. A clock that went back leaves the time where
+    // it is, so orders keep their time priority. A time that cannot be journaled is not taken
+    // either, the book stays the one its journal rebuilds.
+    fn tick(&mut self) {
+        let Some(now) = self.clock.as_ref().map(|clock| clock.now()) else { return; };
+        if now > self.current_time { self.set_time(now).ok(); }
+    }
+
+    // Cancels every order whose expiry has been reached at `now` and returns their ids in ascending
+    // order. A `now` before the book time is taken as the book time, so no order comes back to life.
+    pub fn purge_expired(&mut self, now: u64) -> Result<Vec<i64>, OrderbookError> {
+        self.log(JournalEntry::PurgeExpired { now })?;
+        self.current_time = self.current_time.max(now);
+        self.end_volatility_auction();
+        let now = self.current_time;
+
+        let mut expired: Vec<i64> = self.order_map.values().filter(|order| order.is_expired(now)).map(|order| order.order_id).collect();
+        expired.sort_unstable();
+
+        for &order_id in &expired {
+            let _ = self.cancel_with_reason(order_id, CancelReason::Expired);
+        }
+
+        Ok(expired)
+    }
+
+    // Cancels every open order, resting, parked or waiting for its stop, in the order the ids were
+    // assigned and returns their ids. The book stays usable, but nothing of it is left to trade.
+    pub fn delist(&mut self) -> Result<Vec<i64>, OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::Delist)?;
+        let mut cancelled: Vec<i64> = self.order_map.keys().copied().collect();
+        cancelled.sort_unstable();
+
+        for &order_id in &cancelled {
+            // the oco sibling of an order cancelled before is already gone
+            let _ = self.cancel_with_reason(order_id, CancelReason::Delisted);
+        }
+
+        Ok(cancelled)
+    }
+
+    // Cancels every open order the filter selects, resting, parked or waiting for its stop, along
+    // with the oco siblings of those orders, and returns their ids in ascending order. Every order
+    // is reported with OrderCancelled, then one MassCancelled event follows. The levels are walked
+    // once and best and worst prices refreshed at the end instead of after every removal.
+    pub fn cancel_all(&mut self, filter: CancelFilter) -> Result<Vec<i64>, OrderbookError> {
+        self.tick();
+        self.cancel_all_with_reason(filter, CancelReason::MassCancel)
+    }
+
+    // The journal only records the filter, replaying it reports the orders with MassCancel.
+    pub(crate) fn cancel_all_with_reason(&mut self, filter: CancelFilter, reason: CancelReason) -> Result<Vec<i64>, OrderbookError> {
+        self.log(JournalEntry::CancelAll { filter })?;
+        let selected: HashSet<i64> = self.order_map.values().filter(|order| filter.matches(order)).map(|order| order.order_id).collect();
+        let siblings: HashSet<i64> = selected.iter().filter_map(|order_id| self.oco_links.get(order_id))
+            .map(|link| link.sibling).filter(|sibling| !selected.contains(sibling)).collect();
+        let removed = |order_id: &i64| selected.contains(order_id) || siblings.contains(order_id);
+
+        for side in [Side::Buy, Side::Sell] {
+            self.at_market_orders_mut(side).retain(|order_id| !removed(order_id));
+        }
+        self.buy_stop_orders.retain(|order_id| !removed(order_id));
+        self.sell_stop_orders.retain(|order_id| !removed(order_id));
+
+        let mut cancelled: Vec<i64> = selected.iter().chain(&siblings).copied().collect();
+        cancelled.sort_unstable();
+        for &order_id in &cancelled {
+            let Some(order) = self.order_map.get(&order_id) else { continue; };
+            if let (true, Some(limit)) = (order.queue.is_some(), order.order_limit) { self.remove_from_levels(order_id, order.side, limit); }
+            let Some(mut order) = self.order_map.remove(&order_id) else { continue; };
+            let reason = if siblings.contains(&order_id) { CancelReason::OcoSibling } else { reason };
+            order.close(OrderState::Cancelled, reason);
+            self.oco_links.remove(&order_id);
+            self.notify_cancelled(order_id, reason);
+        }
+
+        let (timestamp, count) = (self.current_time, cancelled.len());
+        self.events.publish(|sequence| OrderbookEvent::MassCancelled { sequence, timestamp, filter, count });
+        self.sequence += 1;
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+        Ok(cancelled)
+    }
+
+    // Empties the book for the next scenario without giving up its storage: every open order and
+    // quote is removed, the session goes back to continuous trading without halt or interruption,
+    // and the policy decides what else starts over. Settings, listeners, subscribers, clock and
+    // journal stay, and so do settlements not yet handed to the sink. The sequence moves on and a
+    // single BookCleared event is published. Returns the ids of the removed orders in ascending
+    // order.
+    pub fn clear(&mut self, policy: ClearPolicy) -> Result<Vec<i64>, OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::Clear { policy })?;
+        let mut removed: Vec<i64> = self.order_map.keys().copied().collect();
+        removed.sort_unstable();
+        if policy.cancels_orders {
+            for &order_id in &removed {
+                if let Some(order) = self.order_map.get_mut(&order_id) { order.close(OrderState::Cancelled, CancelReason::Cleared); }
+                self.notify_cancelled(order_id, CancelReason::Cleared);
+            }
+        }
+        self.clear_orders();
+
+        self.session_state = SessionState::default();
+        self.halt = None;
+        self.volatility_trigger = None;
+        self.interruption_ends_at = None;
+        self.position_changes.clear();
+        self.touched_levels.clear();
+        self.published_best = (None, None);
+        self.published_indicative = None;
+        if policy.resets_statistics {
+            self.current_market_price = self.starting_price;
+            self.band_reference = self.starting_price;
+            self.dynamic_reference = self.starting_price;
+            self.stats = SessionStats::new(self.starting_price);
+            self.session_start_trade = self.trade_tape.last_trade_id();
+            self.price_samples.clear();
+            if let Some(candles) = &mut self.candles { candles.clear(); }
+        }
+        if policy.resets_tape {
+            self.trade_tape.clear();
+            self.executions.clear();
+            self.busted_trades.clear();
+            self.unpriced_trades.clear();
+            self.filled_orders.clear();
+            self.session_start_trade = 0;
+        }
+        if policy.resets_order_ids {
+            self.last_order_id = 0;
+            self.order_ids.reset();
+            self.next_oco_link_id = 1;
+            self.next_quote_id = 1;
+        }
+
+        let (timestamp, count) = (self.current_time, removed.len());
+        self.events.publish(|sequence| OrderbookEvent::BookCleared { sequence, timestamp, policy, count });
+        self.sequence += 1;
+        #[cfg(debug_assertions)]
+        self.check_cleared(policy);
+        self.notify_book_update();
+        Ok(removed)
+    }
+
+    // Debug builds compare a cleared book with a new one for the same security and starting
+    // price. Apart from the capacity only sequence, time, journal and what the policy kept differ.
+    #[cfg(debug_assertions)]
+    fn check_cleared(&self, policy: ClearPolicy) {
+        let fresh = Orderbook::new(self.security.clone(), self.starting_price);
+        let mut expected = fresh.snapshot();
+        expected.sequence = self.sequence;
+        expected.current_time = self.current_time;
+        expected.journal_entries = self.journal.as_ref().map_or(0, |journal| journal.entries());
+        if !policy.resets_statistics {
+            expected.current_market_price = self.current_market_price;
+            expected.band_reference = Some(self.band_reference);
+        }
+        if !policy.resets_order_ids {
+            expected.last_order_id = self.last_order_id;
+            expected.next_oco_link_id = self.next_oco_link_id;
+            expected.next_quote_id = Some(self.next_quote_id);
+        }
+        debug_assert_eq!(self.snapshot(), expected, "the cleared book differs from a new one");
+        debug_assert!(self.order_map.is_empty() && self.quotes.is_empty() && self.oco_links.is_empty());
+        debug_assert_eq!(self.check_invariants(), Ok(()));
+        if policy.resets_statistics {
+            debug_assert_eq!((self.stats, self.dynamic_reference, self.price_samples.len()), (fresh.stats, fresh.dynamic_reference, 0));
+        }
+        if policy.resets_tape {
+            debug_assert_eq!((self.trade_tape.len(), self.trade_tape.last_trade_id(), self.executions.len()), (0, 0, 0));
+            debug_assert!(self.busted_trades.is_empty() && self.unpriced_trades.is_empty() && self.session_start_trade == 0);
+        }
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        self.session_state
+    }
+
+    // Starts the call phase before the open, orders are collected without matching.
+    pub fn pre_open(&mut self) -> Result<(), OrderbookError> {
+        self.tick();
+        self.enter(SessionState::PreOpen).map(|_| ())
+    }
+
+    // Uncrosses the orders collected since the last batch at a single price, see
+    // OrderbookConfig::with_batch_auctions. The owner of the book calls it at the pace of the
+    // batches. None outside batch auctions or outside continuous trading.
+    pub fn run_batch(&mut self) -> Result<Option<AuctionResult>, OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::RunBatch)?;
+        if !self.config.batch_auctions || self.session_state != SessionState::Continuous { return Ok(None); }
+        Ok(Some(self.batch()))
+    }
+
+    // Whatever is left of the orders rests for the next batch.
+    fn batch(&mut self) -> AuctionResult {
+        let result = self.run_auction();
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::AuctionUncrossed { sequence, timestamp, result });
+        self.trigger_stop_orders();
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+        result
+    }
+
+    // Starts continuous trading. Coming from any other state the orders collected so far go
+    // through the opening auction first, see uncross, and its result is returned.
+    pub fn open(&mut self) -> Result<Option<AuctionResult>, OrderbookError> {
+        self.tick();
+        self.enter(SessionState::Continuous)
+    }
+
+    // Stops matching, orders are collected for an auction.
+    pub fn start_auction(&mut self) -> Result<(), OrderbookError> {
+        self.tick();
+        self.enter(SessionState::Auction).map(|_| ())
+    }
+
+    // Starts the call phase of the closing auction. Resting orders take part in it unless they are
+    // continuous only, those are cancelled now. Orders at the close are accepted from now on.
+    pub fn start_closing_auction(&mut self) -> Result<(), OrderbookError> {
+        self.tick();
+        self.enter(SessionState::ClosingAuction).map(|_| ())
+    }
+
+    // Ends a call phase with its auction. The closing auction closes the book, see close, any
+    // other call phase goes on to continuous trading like open. None in continuous trading and
+    // once closed.
+    pub fn uncross(&mut self) -> Result<Option<AuctionResult>, OrderbookError> {
+        self.tick();
+        match self.session_state {
+            SessionState::PreOpen | SessionState::Auction | SessionState::VolatilityAuction => self.open(),
+            SessionState::ClosingAuction => self.close(),
+            SessionState::Continuous | SessionState::Closed | SessionState::Halted => Ok(None),
+        }
+    }
+
+    // Halts trading whatever state the book is in: new orders and amendments are rejected with
+    // OrderbookError::MarketHalted until it resumes, cancels are still accepted and nothing
+    // matches. A command the book is executing when the halt comes in completes first. Halting a
+    // halted book only changes the reason.
+    pub fn halt(&mut self, reason: HaltReason) -> Result<(), OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::Halt { reason })?;
+        if let Some(halt) = self.halt.as_mut().filter(|_| self.session_state == SessionState::Halted) {
+            halt.reason = reason;
+            self.notify_book_update();
+            return Ok(());
+        }
+        self.halt = Some(Halt { reason, halted_at: self.current_time, resumed_at: None, previous: self.session_state });
+        self.transition(SessionState::Halted);
+        Ok(())
+    }
+
+    // Ends a halt. The book goes back to the state it was halted in, or with `through_auction` to
+    // a call phase that re-establishes the price once it is uncrossed, see uncross.
+    pub fn resume(&mut self, through_auction: bool) -> Result<(), OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::Resume { through_auction })?;
+        if self.session_state != SessionState::Halted { return Ok(()); }
+        let Some(halt) = self.halt else { return Ok(()); };
+        self.transition(if through_auction { SessionState::Auction } else { halt.previous });
+        Ok(())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.session_state == SessionState::Halted
+    }
+
+    // The halt going on or, once resumed, the last one.
+    pub fn halt_status(&self) -> Option<Halt> {
+        self.halt
+    }
+
+    // Stops continuous trading after a trade at `price` would have left a price band. Orders are
+    // collected for the duration of the volatility auction, which then uncrosses, see set_time.
+    fn interrupt(&mut self, order_id: i64, band: PriceBand, price: i64) {
+        let (timestamp, reference_price, last_price) = (self.current_time, self.band_reference, self.dynamic_reference);
+        let ends_at = self.current_time.saturating_add(self.price_bands.auction_duration);
+        self.events.publish(|sequence| OrderbookEvent::VolatilityInterruption { sequence, timestamp, order_id, band, price, reference_price, last_price, ends_at });
+        self.transition(SessionState::VolatilityAuction);
+        self.interruption_ends_at = Some(ends_at);
+    }
+
+    fn end_volatility_auction(&mut self) {
+        if self.session_state != SessionState::VolatilityAuction { return; }
+        if self.interruption_ends_at.is_some_and(|ends_at| self.current_time < ends_at) { return; }
+        self.transition(SessionState::Continuous);
+    }
+
+    // When the volatility auction going on ends, None outside of one.
+    pub fn interruption_ends_at(&self) -> Option<u64> {
+        self.interruption_ends_at
+    }
+
+    // Interrupts continuous trading with a volatility auction when a trade would leave a band.
+    // No bands are enforced by default.
+    pub fn set_price_bands(&mut self, bands: PriceBands) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetPriceBands { bands })?;
+        self.price_bands = bands;
+        Ok(())
+    }
+
+    pub fn price_bands(&self) -> PriceBands {
+        self.price_bands
+    }
+
+    // The price the static band is measured from: the price of the last auction that traded, or
+    // the starting price of the session.
+    pub fn band_reference(&self) -> i64 {
+        self.band_reference
+    }
+
+    // The price of the last closing auction of the session.
+    pub fn closing_price(&self) -> Option<i64> {
+        self.stats.closing_auction.map(|result| result.price)
+    }
+
+    // Refuses new orders and amendments until the book opens again, cancels are still accepted
+    // and resting orders stay where they are. Closing during the closing auction runs it first:
+    // its price is the official closing price, see closing_price, and becomes the reference price
+    // of the next session, and the orders at the close it did not fill are cancelled.
+    pub fn close(&mut self) -> Result<Option<AuctionResult>, OrderbookError> {
+        self.tick();
+        self.enter(SessionState::Closed)
+    }
+
+    // A change of state the caller asked for, journaled before it is made. The changes that follow
+    // from other commands, like halts and volatility auctions, come back when those are replayed.
+    fn enter(&mut self, state: SessionState) -> Result<Option<AuctionResult>, OrderbookError> {
+        self.log(JournalEntry::SetSessionState { state })?;
+        Ok(self.transition(state))
+    }
+
+    // Every change of state is published as SessionChanged. Continuous trading starts with an
+    // auction over whatever the book collected while it was not matching, and so does the close
+    // after a closing auction.
+    fn transition(&mut self, state: SessionState) -> Option<AuctionResult> {
+        let previous = std::mem::replace(&mut self.session_state, state);
+        if previous == state { return None; }
+        self.sequence += 1;
+        let timestamp = self.current_time;
+        self.events.publish(|sequence| OrderbookEvent::SessionChanged { sequence, timestamp, previous, state });
+        // a halt keeps the volatility auction it interrupted for when trading resumes
+        if previous == SessionState::VolatilityAuction && state != SessionState::Halted { self.interruption_ends_at = None; }
+        if previous == SessionState::Halted {
+            if let Some(halt) = &mut self.halt { halt.resumed_at.get_or_insert(timestamp); }
+        }
+
+        if state == SessionState::ClosingAuction {
+            let mut excluded: Vec<i64> = self.order_map.values().filter(|order| order.continuous_only).map(|order| order.order_id).collect();
+            excluded.sort_unstable();
+            for order_id in excluded {
+                // the oco sibling of an order cancelled before is already gone
+                let _ = self.cancel_with_reason(order_id, CancelReason::ContinuousOnly);
+            }
+        }
+
+        let auction = match (previous, state) {
+            // continuous trading that was halted goes on without an auction, nothing traded in between
+            (SessionState::Halted, SessionState::Continuous) if self.halt.is_some_and(|halt| halt.previous == SessionState::Continuous) => None,
+            (_, SessionState::Continuous) => {
+                let result = self.run_auction();
+                self.events.publish(|sequence| OrderbookEvent::AuctionUncrossed { sequence, timestamp, result });
+                // the static band is measured from the last auction that traded
+                if result.volume > 0 { self.band_reference = result.price; }
+                self.match_crossed_orders();
+                self.trigger_stop_orders();
+                self.reprice_pegged_orders();
+                Some(result)
+            },
+            (SessionState::ClosingAuction, SessionState::Closed) => {
+                let result = self.run_auction();
+                self.events.publish(|sequence| OrderbookEvent::AuctionUncrossed { sequence, timestamp, result });
+                self.stats.closing_auction = Some(result);
+                let mut unfilled: Vec<i64> = self.order_map.values().filter(|order| order.time_in_force == TimeInForce::AtTheClose).map(|order| order.order_id).collect();
+                unfilled.sort_unstable();
+                for order_id in unfilled {
+                    let _ = self.cancel_with_reason(order_id, CancelReason::Unfilled);
+                }
+                Some(result)
+            },
+            _ => None,
+        };
+        self.notify_book_update();
+        auction
+    }
+
+    // The result an auction over the book would have right now, without trading anything. The
+    // reference price is the last price. Outside call phases the book does not cross, so the
+    // volume is 0 unless parked market orders face opposite limits.
+    pub fn indicative_auction_price(&self) -> AuctionResult {
+        let (bids, market_buy) = self.auction_interest(Side::Buy);
+        let (asks, market_sell) = self.auction_interest(Side::Sell);
+        auction::equilibrium(&bids, &asks, market_buy, market_sell, self.current_market_price)
+    }
+
+    // The indicative auction price with the imbalance by side, None outside call phases. While
+    // the book collects orders for an auction, including batch auctions, every change of it is
+    // published as OrderbookEvent::IndicativePrice.
+    pub fn indicative(&self) -> Option<IndicativePrice> {
+        self.in_call_phase().then(|| IndicativePrice::from(self.indicative_auction_price()))
+    }
+
+    // Whether orders are collected for an auction instead of matching as they arrive.
+    fn in_call_phase(&self) -> bool {
+        match self.session_state {
+            SessionState::Continuous => self.config.batch_auctions,
+            SessionState::Closed | SessionState::Halted => false,
+            _ => true,
+        }
+    }
+
+    // The quantity of a side per level best price first and the quantity of its parked market
+    // orders, hidden quantity included and expired orders left out.
+    fn auction_interest(&self, side: Side) -> (Vec<(i64, i64)>, i64) {
+        let open = |order: &Order| if order.is_expired(self.current_time) { 0 } else { order.remaining() };
+        let levels = self.ladder(side, None)
+            .map(|(price, level)| (price, level.orders(&self.order_map).map(open).sum::<i64>()))
+            .filter(|&(_, quantity)| quantity > 0).collect();
+        (levels, self.at_market_orders(side).iter().filter_map(|order_id| self.order_map.get(order_id)).map(open).sum())
+    }
+
+    // The orders of a side that trade in an auction at `price`, in priority: parked market orders
+    // in their queue, then the levels at or better than the price, best first and in queue order.
+    fn auction_queue(&self, side: Side, price: i64) -> Vec<i64> {
+        let at_market = self.at_market_orders(side).iter().filter_map(|order_id| self.order_map.get(order_id));
+        let levels = self.ladder(side, Some(price)).flat_map(|(_, level)| level.orders(&self.order_map));
+        at_market.chain(levels).filter(|order| !order.is_expired(self.current_time)).map(|order| order.order_id).collect()
+    }
+
+    // Trades everything that crosses at the price of indicative_auction_price, all at that one
+    // price and in price-time priority on both sides. Of each pair the order that came later is
+    // the aggressor. The hidden quantity of icebergs takes part, self trade prevention and reduce
+    // only caps do not apply.
+    fn run_auction(&mut self) -> AuctionResult {
+        self.position_changes.clear();
+        let result = self.indicative_auction_price();
+        if result.volume == 0 { return result; }
+
+        let (buys, sells) = (self.auction_queue(Side::Buy, result.price), self.auction_queue(Side::Sell, result.price));
+        let (mut buys, mut sells) = (buys.into_iter().peekable(), sells.into_iter().peekable());
+        let (mut left, mut executions, mut filled) = (result.volume, Vec::new(), Vec::new());
+        while left > 0 {
+            let (Some(&buy_id), Some(&sell_id)) = (buys.peek(), sells.peek()) else { break; };
+            let amount = self.order_map[&buy_id].remaining().min(self.order_map[&sell_id].remaining()).min(left);
+            for order_id in [buy_id, sell_id] {
+                let Some(order) = self.order_map.get_mut(&order_id) else { continue; };
+                let visible = order.visible_remaining();
+                order.amount_executed += amount;
+                if order.display_quantity.is_some() {
+                    order.displayed = order.displayed.min(order.remaining());
+                    if order.displayed == 0 { order.replenish(); }
+                }
+                if let (false, false, Some(limit)) = (order.is_pending_stop(), order.midpoint, order.order_limit) {
+                    let (side, change) = (order.side, order.visible_remaining() - visible);
+                    self.touched_levels.push((side, limit));
+                    self.adjust_level(side, limit, change);
+                }
+            }
+
+            let (incoming_id, resting_id) = if buy_id > sell_id { (buy_id, sell_id) } else { (sell_id, buy_id) };
+            let (incoming, resting_account) = (&self.order_map[&incoming_id], self.order_map[&resting_id].account_id);
+            let mut execution = Execution::between(incoming, resting_id, resting_account, result.price, amount);
+            execution.timestamp = self.current_time;
+            execution.trade_id = self.trade_tape.record(&execution, incoming.side, self.current_time);
+            (execution.maker_fee, execution.taker_fee) = self.fees.fees(result.price, amount);
+            left -= amount;
+
+            if self.order_map[&buy_id].remaining() == 0 {
+                filled.push(buy_id);
+                buys.next();
+            }
+            if self.order_map[&sell_id].remaining() == 0 {
+                filled.push(sell_id);
+                sells.next();
+            }
+            self.notify_execution(&execution);
+            executions.push(execution);
+        }
+
+        for order_id in filled {
+            if let Some(order) = self.unlink_order(order_id) { self.filled_orders.insert(order_id, order); }
+        }
+        self.current_market_price = result.price;
+        if !self.oco_links.is_empty() { self.apply_oco_fills(&executions); }
+        self.record_executions(&executions);
+        result
+    }
+
+    // Trades what the auction left crossed the way continuous trading would have: the orders of
+    // every level that crosses the opposite best price and the parked market orders facing
+    // opposite limits are taken out of their queues and submitted again in the order their ids
+    // were assigned. Only parked market orders can be left facing opposite limits after an
+    // auction, which never leaves both sides crossing at its price.
+    fn match_crossed_orders(&mut self) {
+        self.position_changes.clear();
+        let (best_bid, best_ask) = (self.best_bid(), self.best_ask());
+        let mut crossed: Vec<i64> = self.order_map.values().filter(|order| !order.is_pending_stop() && !order.midpoint).filter(|order| {
+            match (order.order_limit, order.side) {
+                (Some(limit), Side::Buy) => best_ask.is_some_and(|ask| limit >= ask) || !self.sell_at_market_orders.is_empty(),
+                (Some(limit), Side::Sell) => best_bid.is_some_and(|bid| limit <= bid) || !self.buy_at_market_orders.is_empty(),
+                (None, Side::Buy) => best_ask.is_some(),
+                (None, Side::Sell) => best_bid.is_some(),
+            }
+        }).map(|order| order.order_id).collect();
+        if crossed.is_empty() { return; }
+        crossed.sort_unstable();
+
+        let mut orders = Vec::with_capacity(crossed.len());
+        for order_id in crossed {
+            let Some(order) = self.unlink_order(order_id) else { continue; };
+            let timestamp = self.current_time;
+            if order.order_limit.is_some() { self.events.publish(|sequence| OrderbookEvent::OrderRemoved { sequence, timestamp, order_id }); }
+            orders.push(order);
+        }
+        for mut order in orders {
+            self.execute_order(&mut order);
+        }
+    }
+
+    // Applies a corporate action to the open orders of the book.
+    //
+    // A cash dividend with OpenOrderPolicy::Reprice moves the limits of all resting orders, and the
+    // caps of pegged ones, down by the dividend: levels keep their order and every order keeps its
+    // place in its queue. Orders whose limit or cap would round to below one tick, buys rounding
+    // down and sells up, are cancelled. Stops and orders without a limit are not touched, nor is
+    // the market price, so no stop triggers.
+    //
+    // A split always rescales the orders that OpenOrderPolicy::Cancel does not cancel, Keep
+    // applies to dividends only. See split_orders for the rounding. An action of another security
+    // is refused.
+    pub fn apply_corporate_action(&mut self, action: &CorporateAction, policy: OpenOrderPolicy) -> Result<AdjustedOrders, OrderbookError> {
+        self.tick();
+        if action.isin() != self.security.isin { return Err(OrderbookError::WrongSecurity); }
+        self.log(JournalEntry::CorporateAction { action: Box::new(action.clone()), policy })?;
+        let mut resting: Vec<i64> = self.order_map.values().filter(|order| !order.is_pending_stop() && order.order_limit.is_some()).map(|order| order.order_id).collect();
+        resting.sort_unstable();
+        let (timestamp, published) = (self.current_time, action.clone());
+        self.events.publish(|sequence| OrderbookEvent::CorporateAction { sequence, timestamp, action: published });
+
+        let dividend = match action.kind() {
+            CorporateActionKind::CashDividend { amount_per_share } => Some(amount_per_share),
+            CorporateActionKind::Split { .. } => None,
+        };
+        let mut adjusted = AdjustedOrders::default();
+        let cancelled: Vec<i64> = match (policy, dividend) {
+            (OpenOrderPolicy::Cancel, _) => resting.clone(),
+            (_, None) | (OpenOrderPolicy::Keep, _) => Vec::new(),
+            (OpenOrderPolicy::Reprice, Some(dividend)) => resting.iter().copied().filter(|order_id| {
+                let order = &self.order_map[order_id];
+                order.order_limit.into_iter().chain(order.peg_cap).any(|price| self.security.whole_ticks(price as i128 - dividend as i128, order.side == Side::Sell) < 1)
+            }).collect(),
+        };
+        for order_id in cancelled {
+            // the oco sibling of an order cancelled before is already gone
+            if self.cancel_with_reason(order_id, CancelReason::CorporateAction).is_ok() { adjusted.cancelled.push(order_id); }
+        }
+
+        match (action.kind(), dividend) {
+            (CorporateActionKind::Split { numerator, denominator }, _) => self.split_orders(numerator, denominator, &mut adjusted),
+            (_, Some(dividend)) if policy == OpenOrderPolicy::Reprice && dividend != 0 => {
+                for order_id in resting {
+                    let Some(order) = self.order_map.get_mut(&order_id) else { continue; };
+                    let Some(limit) = order.order_limit else { continue; };
+                    // a dividend that is not a multiple of the tick rounds limits to the passive side
+                    let buy = order.side == Side::Buy;
+                    let repriced = self.security.round_to_tick(limit as i128 - dividend as i128, !buy);
+                    order.order_limit = Some(repriced);
+                    if let Some(cap) = order.peg_cap { order.peg_cap = Some(self.security.round_to_tick(cap as i128 - dividend as i128, !buy)); }
+                    let side = order.side;
+                    if !order.midpoint { self.touched_levels.extend([(side, limit), (side, repriced)]); }
+                    adjusted.repriced.push(order_id);
+                }
+                self.rekey_levels(Side::Buy);
+                self.rekey_levels(Side::Sell);
+            },
+            _ => {},
+        }
+
+        self.sequence += 1;
+        self.notify_book_update();
+        Ok(adjusted)
+    }
+
+    // Turns every share into numerator / denominator shares, so prices are multiplied by
+    // denominator / numerator and quantities by numerator / denominator. Prices that fall between
+    // two ticks are rounded to the passive side: limits and pegged caps of buys down and of sells
+    // up, stop prices away from the market, buy stops up and sell stops down, so no order becomes
+    // more aggressive than it was. Offsets and the prices of the book round to the nearest tick,
+    // no price drops below one tick. Quantities are rounded down to whole lots, the amount and the
+    // executed quantity of an order each, and shown and minimum quantities keep at least one lot.
+    // Orders left without an open lot are cancelled. Ticks and lots are those of the security.
+    // Levels that end up at the same price merge, the orders of the level that was better before
+    // in front.
+    fn split_orders(&mut self, numerator: i64, denominator: i64, adjusted: &mut AdjustedOrders) {
+        let security = Arc::clone(&self.security);
+        let tick = security.tick_size as i128;
+        // the scaled price in ticks, rounded up or down
+        let price = |price: i64, up: bool| {
+            let scaled = price as i128 * denominator as i128;
+            let unit = numerator as i128 * tick;
+            let ticks = if up { (scaled + unit - 1).div_euclid(unit) } else { scaled.div_euclid(unit) };
+            security.round_to_tick(ticks * tick, false)
+        };
+        let nearest = |value: i64| ((((value as i128 * denominator as i128 * 2) + numerator as i128 * tick).div_euclid(2 * numerator as i128 * tick)) * tick).clamp(0, i64::MAX as i128) as i64;
+        let lot = security.lot_size as i128;
+        let quantity = |quantity: i64| (quantity as i128 * numerator as i128 / denominator as i128 / lot * lot).clamp(0, i64::MAX as i128) as i64;
+
+        let mut order_ids: Vec<i64> = self.order_map.keys().copied().collect();
+        order_ids.sort_unstable();
+        let mut emptied = Vec::new();
+        for &order_id in &order_ids {
+            let Some(order) = self.order_map.get_mut(&order_id) else { continue; };
+            let (buy, pending_stop) = (order.side == Side::Buy, order.is_pending_stop());
+            if let Some(limit) = order.order_limit {
+                order.order_limit = Some(price(limit, !buy));
+                if !pending_stop && !order.midpoint { self.touched_levels.extend([(order.side, limit), (order.side, price(limit, !buy))]); }
+            }
+            order.peg_cap = order.peg_cap.map(|cap| price(cap, !buy));
+            order.stop_price = order.stop_price.map(|stop| price(stop, buy));
+            order.trailing_offset = order.trailing_offset.map(|offset| nearest(offset).max(security.tick_size));
+            order.peg_offset = order.peg_offset.map(nearest);
+            order.amount = quantity(order.amount);
+            order.amount_executed = quantity(order.amount_executed);
+            order.min_quantity = order.min_quantity.map(|min_quantity| quantity(min_quantity).max(security.lot_size));
+            order.display_quantity = order.display_quantity.map(|display| quantity(display).max(security.lot_size));
+            order.displayed = quantity(order.displayed).min(order.remaining());
+            if order.display_quantity.is_some() && order.displayed == 0 { order.replenish(); }
+            if order.remaining() <= 0 { emptied.push(order_id); } else { adjusted.repriced.push(order_id); }
+        }
+        for link in self.oco_links.values_mut() {
+            link.amount = quantity(link.amount);
+            link.executed = quantity(link.executed);
+        }
+
+        self.rekey_levels(Side::Buy);
+        self.rekey_levels(Side::Sell);
+
+        for order_id in emptied {
+            if self.cancel_with_reason(order_id, CancelReason::CorporateAction).is_ok() { adjusted.cancelled.push(order_id); }
+        }
+        adjusted.repriced.retain(|order_id| self.order_map.contains_key(order_id));
+
+        let nearest = |value: i64| nearest(value).max(security.tick_size);
+        self.starting_price = nearest(self.starting_price);
+        self.current_market_price = nearest(self.current_market_price);
+        self.band_reference = nearest(self.band_reference);
+        let stats = &mut self.stats;
+        stats.starting_price = nearest(stats.starting_price);
+        stats.last_price = nearest(stats.last_price);
+        for stat in [&mut stats.open, &mut stats.high, &mut stats.low] { *stat = stat.map(nearest); }
+    }
+
+    // Closes the trading session: all day orders and orders at the close are cancelled, the session
+    // statistics start over and the closing price becomes the reference price of the next session.
+    // Good till cancel orders are not touched and keep their place in the queue.
+    pub fn end_of_session(&mut self) -> Result<SessionSummary, OrderbookError> {
+        self.tick();
+        self.log(JournalEntry::EndOfSession)?;
+        let mut cancelled_order_ids: Vec<i64> = self.order_map.values().filter(|order| matches!(order.time_in_force, TimeInForce::Day | TimeInForce::AtTheClose)).map(|order| order.order_id).collect();
+        cancelled_order_ids.sort_unstable();
+
+        for &order_id in &cancelled_order_ids {
+            let _ = self.cancel_with_reason(order_id, CancelReason::EndOfSession);
+        }
+
+        let summary = SessionSummary { cancelled_order_ids, reference_price: self.current_market_price, stats: self.stats() };
+
+        self.starting_price = self.current_market_price;
+        self.band_reference = self.starting_price;
+        self.stats = SessionStats::new(self.starting_price);
+        self.session_start_trade = self.trade_tape.last_trade_id();
+        self.filled_orders.clear();
+
+        Ok(summary)
+    }
+
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        let mut snapshot = DepthSnapshot::new();
+        self.depth_into(levels, &mut snapshot);
+        snapshot
+    }
+
+    // Fills a snapshot the caller keeps around, so repeated polling does not allocate once its
+    // buffers are large enough.
+    pub fn depth_into(&self, levels: usize, snapshot: &mut DepthSnapshot) {
+        for (side, buffer) in [(Side::Buy, &mut snapshot.bids), (Side::Sell, &mut snapshot.asks)] {
+            buffer.clear();
+            buffer.extend(self.depth_levels(side).take(levels));
+        }
+        snapshot.last_price = self.current_market_price;
+        snapshot.sequence = self.sequence;
+        snapshot.session_state = self.session_state;
+        snapshot.halt = self.halt.filter(|_| self.session_state == SessionState::Halted);
+        snapshot.checksum = book_checksum(&snapshot.bids, &snapshot.asks);
+    }
+
+    // The checksum of the top `levels` levels of each side, see market_data::book_checksum.
+    pub fn checksum(&self, levels: usize) -> u32 {
+        let bids: Vec<DepthLevel> = self.depth_levels(Side::Buy).take(levels).collect();
+        let asks: Vec<DepthLevel> = self.depth_levels(Side::Sell).take(levels).collect();
+        book_checksum(&bids, &asks)
+    }
+
+    // Aggregates the levels of a side best price first, counting visible quantity only.
+    fn depth_levels(&self, side: Side) -> impl Iterator<Item = DepthLevel> + '_ {
+        self.levels(side).map(|level| level.to_depth_level())
+    }
+
+    // The bid levels from the best to the worst bid, without copying anything out of the book.
+    pub fn bid_levels(&self) -> impl Iterator<Item = LevelRef<'_>> {
+        self.levels(Side::Buy)
+    }
+
+    // The ask levels from the best to the worst ask, without copying anything out of the book.
+    pub fn ask_levels(&self) -> impl Iterator<Item = LevelRef<'_>> {
+        self.levels(Side::Sell)
+    }
+
+    // The ladder ends at the worst price of the side, empty queues are skipped.
+    fn levels(&self, side: Side) -> impl Iterator<Item = LevelRef<'_>> {
+        self.ladder(side, None).map(move |(price, level)| LevelRef { price, quantity: level.quantity, order_count: level.len(), orders: level.orders(&self.order_map) })
+    }
+
+    // (bid quantity - ask quantity) / (bid quantity + ask quantity) over the top `levels` levels of
+    // each side. A one sided book gives 1.0 or -1.0, a book without quotes None.
+    pub fn imbalance(&self, levels: usize) -> Option<f64> {
+        // every level fits an i64, their sum may not
+        let bid_quantity: i128 = self.depth_levels(Side::Buy).take(levels).map(|level| level.quantity as i128).sum();
+        let ask_quantity: i128 = self.depth_levels(Side::Sell).take(levels).map(|level| level.quantity as i128).sum();
+        let total = bid_quantity + ask_quantity;
+        if total == 0 { return None; }
+
+        Some((bid_quantity - ask_quantity) as f64 / total as f64)
+    }
+
+    // The visible quantity resting on a side from the best price up to and including `up_to_price`,
+    // i64::MAX for more than that.
+    pub fn cumulative_depth(&self, side: Side, up_to_price: i64) -> i64 {
+        self.depth_levels(side).take_while(|level| !side.improves(up_to_price, level.price)).fold(0, |total: i64, level| total.saturating_add(level.quantity))
+    }
+
+    // The orders resting at one price, in the order they will be matched.
+    pub fn orders_at(&self, price: i64, side: Side) -> Vec<OrderView> {
+        self.price_levels(side).get(&price).map_or_else(Vec::new, |level| self.level_view(level))
+    }
+
+    pub fn full_book(&self) -> BookView {
+        let side_view = |side| self.ladder(side, None).flat_map(|(_, level)| self.level_view(level)).collect();
+        BookView { bids: side_view(Side::Buy), asks: side_view(Side::Sell) }
+    }
+
+    // how many orders of the account are open in the book, pending stops included
+    pub fn open_order_count(&self, account_id: u64) -> usize {
+        self.order_map.values().filter(|order| order.account_id == Some(account_id)).count()
+    }
+
+    // Every open order of the account by ascending id, resting, parked or waiting for its stop.
+    // Orders that do not rest in a level are shown at their limit, their stop price or the market
+    // price, in that order, with a queue position of 0.
+    pub fn open_orders(&self, account_id: u64) -> Vec<OrderView> {
+        let mut orders: Vec<&Order> = self.order_map.values().filter(|order| order.account_id == Some(account_id)).collect();
+        orders.sort_unstable_by_key(|order| order.order_id);
+        orders.into_iter().map(|order| {
+            let queue_position = self.queue_position(OrderId::from_raw(order.order_id));
+            OrderView {
+                order_id: order.order_id,
+                side: order.side,
+                price: queue_position.map_or(order.order_limit.or(order.stop_price).unwrap_or(self.current_market_price), |position| position.price),
+                remaining: order.remaining(),
+                visible: order.visible_remaining(),
+                queue_position: queue_position.map_or(0, |position| position.orders_ahead),
+                timestamp: order.timestamp,
+            }
+        }).collect()
+    }
+
+    fn level_view(&self, level: &PriceLevel) -> Vec<OrderView> {
+        level.orders(&self.order_map).enumerate().map(|(queue_position, order)| {
+            OrderView {
+                order_id: order.order_id,
+                side: order.side,
+                price: order.order_limit.unwrap_or(self.current_market_price),
+                remaining: order.remaining(),
+                visible: order.visible_remaining(),
+                queue_position,
+                timestamp: order.timestamp,
+            }
+        }).collect()
+    }
+
+    // Where a resting limit order waits: the orders ahead of it in its level and the quantity that
+    // has to trade before it, which includes every better priced level of its side. None for
+    // orders that are not resting in a level.
+    pub fn queue_position(&self, order_id: OrderId) -> Option<QueuePosition> {
+        let order_id = order_id.to_raw();
+        let order = self.order_map.get(&order_id)?;
+        if order.is_pending_stop() { return None; }
+        let price = order.order_limit?;
+        let level = self.price_levels(order.side).get(&price)?;
+
+        let orders_ahead = level.orders(&self.order_map).position(|queued| queued.order_id == order_id)?;
+        let better_levels: i64 = self.ladder(order.side, None).take_while(|&(level_price, _)| level_price != price)
+            .flat_map(|(_, level)| level.orders(&self.order_map)).map(Order::remaining).sum();
+        let same_level: i64 = level.orders(&self.order_map).take(orders_ahead).map(Order::remaining).sum();
+
+        Some(QueuePosition { price, orders_ahead, quantity_ahead: better_levels + same_level })
+    }
+
+    // Grows with every order placed, amended or cancelled.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    // Verifies that no empty levels exist, every queued order is resting at the price of its level
+    // and linked to its neighbours both ways, the cached quantities of the levels are up to date and
+    // the counters match the ladder.
+    pub fn check_invariants(&self) -> Result<(), String> {
+        let mut queued = 0;
+        for side in [Side::Buy, Side::Sell] {
+            let mut order_count = 0;
+
+            for (&price, level) in self.price_levels(side) {
+                if level.is_empty() { return Err(format!("Empty {:?} level at {} in the book", side, price)); }
+
+                let mut previous: Option<SlotHandle> = None;
+                let mut next = level.front();
+                let mut walked = 0;
+                while let Some(handle) = next {
+                    let Some(order) = self.order_map.get_at(handle) else { return Err(format!("The {:?} level at {} links to an order that is gone", side, price)); };
+                    if order.order_limit != Some(price) || order.side != side || order.remaining() <= 0 {
+                        return Err(format!("Order {} does not belong to the {:?} level at {}", order.order_id, side, price));
+                    }
+                    let Some(link) = order.queue.filter(|link| link.prev == previous) else {
+                        return Err(format!("Order {} is not linked to the order ahead of it at {}", order.order_id, price));
+                    };
+                    queued += 1;
+                    walked += 1;
+                    if queued > self.order_map.len() { return Err(format!("The {:?} level at {} is linked in a cycle", side, price)); }
+                    previous = Some(handle);
+                    next = link.next;
+                }
+                if previous != level.tail || walked != level.len() {
+                    return Err(format!("The {:?} level at {} counts {} orders but {} are linked", side, price, level.len(), walked));
+                }
+
+                let quantity = self.level_quantity(level);
+                if level.quantity != quantity {
+                    return Err(format!("The {:?} level at {} caches a quantity of {} but holds {}", side, price, level.quantity, quantity));
+                }
+
+                order_count += level.len();
+            }
+
+            let number_orders = match side {
+                Side::Buy => self.number_buy_limit_orders,
+                Side::Sell => self.number_sell_limit_orders,
+            };
+            if number_orders as usize != order_count {
+                return Err(format!("{} {:?} orders are counted but {} are queued", number_orders, side, order_count));
+            }
+
+            for order_id in self.midpoint_orders(side) {
+                if !self.order_map.get(order_id).is_some_and(|order| order.midpoint && order.side == side && order.remaining() > 0) {
+                    return Err(format!("Order {} does not belong to the {:?} midpoint orders", order_id, side));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // A new event stream. Events are buffered per subscriber, a receiver that is dropped is
+    // simply removed.
+    pub fn subscribe(&mut self) -> Receiver<OrderbookEvent> {
+        self.events.subscribe()
+    }
+
+    // A stream that buffers without limit, for consumers that are drained in step with the book
+    // like a replay. A consumer that falls behind makes the buffer grow instead of losing events.
+    pub fn subscribe_unbounded(&mut self) -> Receiver<OrderbookEvent> {
+        self.events.subscribe_unbounded()
+    }
+
+    // Publishes the full depth of the book as it is now to the readers of snapshot_reader. Only the
+    // level headers are copied, the orders behind them stay in the book, see order and full_book.
+    pub fn publish_snapshot(&mut self) -> Arc<DepthSnapshot> {
+        let snapshot = Arc::new(self.depth(usize::MAX));
+        match &mut self.snapshots {
+            Some(snapshots) => snapshots.publish(snapshot.clone()),
+            None => self.snapshots = Some(SnapshotPublisher::new(snapshot.clone())),
+        }
+        snapshot
+    }
+
+    // Lets other threads read the latest published snapshot while the book keeps matching. The
+    // first reader publishes the book as it is, if nothing was published before.
+    pub fn snapshot_reader(&mut self) -> SnapshotReader {
+        if self.snapshots.is_none() { self.publish_snapshot(); }
+        self.snapshots.as_ref().map(SnapshotPublisher::reader).expect("a snapshot was published")
+    }
+
+    // Publishes a snapshot by itself once the sequence moved on by `interval` since the last one,
+    // checked at the end of every call that changed the book. None leaves publishing to the owner.
+    pub fn set_snapshot_interval(&mut self, interval: Option<u64>) {
+        if self.snapshots.is_none() { self.publish_snapshot(); }
+        if let Some(snapshots) = &mut self.snapshots { snapshots.set_interval(interval); }
+    }
+
+    // Buffer size and overflow policy for subscriptions made from now on. The policy applies to all
+    // subscribers.
+    pub fn set_event_buffer(&mut self, capacity: usize, policy: OverflowPolicy) {
+        self.events.configure(capacity, policy);
+    }
+
+    // From now on every command is written to the journal before it is executed. Once a write
+    // fails the book refuses commands until another journal is set.
+    pub fn set_journal(&mut self, journal: Journal) {
+        self.journal = Some(journal);
+    }
+
+    pub fn journal(&self) -> Option<&Journal> {
+        self.journal.as_ref()
+    }
+
+    fn log(&mut self, entry: JournalEntry) -> Result<(), OrderbookError> {
+        match &mut self.journal {
+            Some(journal) => journal.append(&entry).map_err(OrderbookError::JournalWrite),
+            None => Ok(()),
+        }
+    }
+
+    // Rebuilds a book from its journal by replaying every logged command, then keeps logging to the
+    // same journal. Matching only depends on the commands and the book time they carry, so the
+    // result is the same book with the same order ids and queues. Security and starting price have
+    // to be the ones the journal was written with. Reduce only orders also depend on the positions
+    // of the position provider, which are not part of the journal.
+    pub fn recover(security: Arc<Security>, starting_price: i64, path: impl AsRef<Path>) -> Result<Orderbook, JournalError> {
+        let mut book = Orderbook::new(security, starting_price);
+        book.replay_journal(path.as_ref(), 0)?;
+        Ok(book)
+    }
+
+    // Fast restart: restores the snapshot and only replays the commands logged after it was taken.
+    pub fn recover_from_snapshot(security: Arc<Security>, snapshot: &BookSnapshot, path: impl AsRef<Path>) -> Result<Orderbook, JournalError> {
+        let mut book = Orderbook::new(security, snapshot.starting_price);
+        book.restore(snapshot).map_err(JournalError::Restore)?;
+        book.replay_journal(path.as_ref(), snapshot.journal_entries as usize)?;
+        Ok(book)
+    }
+
+    fn replay_journal(&mut self, path: &Path, skip: usize) -> Result<(), JournalError> {
+        let security = Arc::clone(&self.security);
+        let book = self;
+
+        for (entry, segment, offset) in journal::read_journal(path, &security)?.into_iter().skip(skip) {
+            let replayed = match entry {
+                JournalEntry::Place { order_id, order } => book.place_order(*order).is_ok_and(|report| report.order_id().to_raw() == order_id),
+                JournalEntry::PlaceOco { primary_id, secondary_id, orders } => book.place_oco(orders.0, orders.1)
+                    .is_ok_and(|report| report.primary().order_id().to_raw() == primary_id && report.secondary().order_id().to_raw() == secondary_id),
+                JournalEntry::Cancel { order_id } => book.cancel_with_reason(order_id, CancelReason::Requested).is_ok(),
+                JournalEntry::ForceCancel { order_id } => book.force_cancel(OrderId::from_raw(order_id)).is_ok(),
+                JournalEntry::CancelAll { filter } => book.cancel_all(filter).is_ok(),
+                JournalEntry::SetSessionState { state } => book.enter(state).is_ok(),
+                JournalEntry::SetSelfTradePolicy { account_id, policy } => match account_id {
+                    Some(account_id) => book.set_account_self_trade_policy(account_id, policy).is_ok(),
+                    None => book.set_self_trade_policy(policy).is_ok(),
+                },
+                JournalEntry::Amend { order_id, new_limit, new_amount } => book.amend_order(OrderId::from_raw(order_id), new_limit.map(Price), Qty(new_amount)).is_ok(),
+                JournalEntry::SetTime { now } => book.set_time(now).is_ok(),
+                JournalEntry::SetPriceBands { bands } => book.set_price_bands(bands).is_ok(),
+                JournalEntry::SetConfig { config } => book.set_config(config).is_ok(),
+                JournalEntry::RunBatch => book.run_batch().is_ok(),
+                JournalEntry::Halt { reason } => book.halt(reason).is_ok(),
+                JournalEntry::Resume { through_auction } => book.resume(through_auction).is_ok(),
+                JournalEntry::PurgeExpired { now } => book.purge_expired(now).is_ok(),
+                JournalEntry::EndOfSession => book.end_of_session().is_ok(),
+                JournalEntry::Quote { account_id, bid_id, ask_id, bid, ask } => book.submit_quote(account_id, bid.0, bid.1, ask.0, ask.1)
+                    .is_ok_and(|handle| handle.bid().order_id().to_raw() == bid_id && handle.ask().order_id().to_raw() == ask_id),
+                JournalEntry::CancelQuote { account_id, quote_id } => book.withdraw_quote(account_id, quote_id).is_ok(),
+                JournalEntry::BustTrade { trade_id } => book.bust_trade(trade_id).is_ok(),
+                JournalEntry::ReportTrade { buyer_account, seller_account, price, quantity, flags } => book.report_trade(buyer_account, seller_account, price, quantity, flags).is_ok(),
+                JournalEntry::SetOcoPolicy { policy } => book.set_oco_policy(policy).is_ok(),
+                JournalEntry::Clear { policy } => book.clear(policy).is_ok(),
+                JournalEntry::Delist => book.delist().is_ok(),
+                JournalEntry::CorporateAction { action, policy } => book.apply_corporate_action(&action, policy).is_ok(),
+                JournalEntry::SetMaxStopLimitGap { max_gap } => book.set_max_stop_limit_gap(max_gap).is_ok(),
+            };
+            if !replayed { return Err(JournalError::Diverged { segment, offset }); }
+        }
+
+        book.set_journal(Journal::open(path)?);
+        Ok(())
+    }
+
+    // Captures resting orders, their queues, the settings of the book and its trades, see BookSnapshot.
+    pub fn snapshot(&self) -> BookSnapshot {
+        let orders = |queue: &mut dyn Iterator<Item = &i64>| -> Vec<SnapshotOrder> {
+            queue.filter_map(|order_id| self.order_map.get(order_id)).map(Order::to_snapshot).collect()
+        };
+
+        let mut oco_links: Vec<SnapshotOcoLink> = self.oco_links.iter().map(|(&order_id, link)| {
+            SnapshotOcoLink { order_id, link_id: link.link_id, sibling: link.sibling, amount: link.amount, executed: link.executed }
+        }).collect();
+        oco_links.sort_unstable_by_key(|link| link.order_id);
+        let mut quotes: Vec<SnapshotQuote> = self.quotes.iter().map(|(&account_id, quote)| {
+            SnapshotQuote { account_id, quote_id: quote.quote_id, bid_order_id: quote.bid_id, ask_order_id: quote.ask_id }
+        }).collect();
+        quotes.sort_unstable_by_key(|quote| quote.account_id);
+        let mut account_self_trade_policies: Vec<(u64, SelfTradePolicy)> = self.account_self_trade_policies.iter().map(|(&account_id, &policy)| (account_id, policy)).collect();
+        account_self_trade_policies.sort_unstable_by_key(|&(account_id, _)| account_id);
+        let mut filled_orders: Vec<SnapshotOrder> = self.filled_orders.values().map(Order::to_snapshot).collect();
+        filled_orders.sort_unstable_by_key(|order| order.order_id);
+        let sorted = |trade_ids: &HashSet<u64>| {
+            let mut trade_ids: Vec<u64> = trade_ids.iter().copied().collect();
+            trade_ids.sort_unstable();
+            trade_ids
+        };
+
+        BookSnapshot {
+            isin: self.security.isin.clone(),
+            starting_price: self.starting_price,
+            current_market_price: self.current_market_price,
+            sequence: self.sequence,
+            current_time: self.current_time,
+            last_order_id: self.last_order_id,
+            next_oco_link_id: self.next_oco_link_id,
+            bids: self.ladder(Side::Buy, None).flat_map(|(_, level)| level.orders(&self.order_map)).map(Order::to_snapshot).collect(),
+            asks: self.ladder(Side::Sell, None).flat_map(|(_, level)| level.orders(&self.order_map)).map(Order::to_snapshot).collect(),
+            market_orders: orders(&mut self.buy_at_market_orders.iter().chain(&self.sell_at_market_orders)),
+            stop_orders: orders(&mut self.buy_stop_orders.iter().chain(&self.sell_stop_orders)),
+            midpoint_orders: orders(&mut self.buy_midpoint_orders.iter().chain(&self.sell_midpoint_orders)),
+            pegged_orders: self.pegged_orders.iter().copied().filter(|order_id| self.order_map.contains_key(order_id)).collect(),
+            oco_links,
+            journal_entries: self.journal.as_ref().map_or(0, |journal| journal.entries()),
+            session_state: self.session_state,
+            band_reference: Some(self.band_reference),
+            interruption_ends_at: self.interruption_ends_at,
+            halt: self.halt,
+            next_quote_id: Some(self.next_quote_id),
+            quotes,
+            config: self.config,
+            price_bands: self.price_bands,
+            dynamic_reference: Some(self.dynamic_reference),
+            oco_policy: self.oco_policy,
+            max_stop_limit_gap: self.max_stop_limit_gap,
+            self_trade_policy: self.self_trade_policy,
+            account_self_trade_policies,
+            stats: Some(self.stats),
+            executions: self.executions.clone(),
+            busted_trades: sorted(&self.busted_trades),
+            unpriced_trades: sorted(&self.unpriced_trades),
+            session_start_trade: self.session_start_trade,
+            filled_orders,
+            trades: self.trade_tape.last_n_trades(self.trade_tape.len()),
+            last_trade_id: self.trade_tape.last_trade_id(),
+        }
+    }
+
+    // Rebuilds the queues of a snapshot with the same matching priority. Only a book without any
+    // orders can be restored into, and a snapshot that does not make a consistent book leaves it
+    // empty.
+    pub fn restore(&mut self, snapshot: &BookSnapshot) -> Result<(), OrderbookError> {
+        if !self.order_map.is_empty() { return Err(OrderbookError::BookNotEmpty); }
+        if snapshot.isin != self.security.isin { return Err(OrderbookError::WrongSecurity); }
+
+        match self.restore_orders(snapshot) {
+            Ok(()) => {},
+            Err(reason) => {
+                self.clear_orders();
+                return Err(OrderbookError::InvalidSnapshot(reason));
+            },
+        }
+
+        self.starting_price = snapshot.starting_price;
+        self.current_market_price = snapshot.current_market_price;
+        self.sequence = snapshot.sequence;
+        self.current_time = snapshot.current_time;
+        self.session_state = snapshot.session_state;
+        self.band_reference = snapshot.band_reference.unwrap_or(snapshot.starting_price);
+        self.interruption_ends_at = snapshot.interruption_ends_at;
+        self.halt = snapshot.halt;
+        self.next_oco_link_id = snapshot.next_oco_link_id;
+        self.next_quote_id = snapshot.next_quote_id.unwrap_or(1);
+        self.last_order_id = snapshot.last_order_id;
+        self.order_ids.advance_past(snapshot.last_order_id);
+        self.config = snapshot.config;
+        self.price_bands = snapshot.price_bands;
+        self.dynamic_reference = snapshot.dynamic_reference.unwrap_or(snapshot.starting_price);
+        self.oco_policy = snapshot.oco_policy;
+        self.max_stop_limit_gap = snapshot.max_stop_limit_gap;
+        self.self_trade_policy = snapshot.self_trade_policy;
+        self.account_self_trade_policies = snapshot.account_self_trade_policies.iter().copied().collect();
+        self.stats = snapshot.stats.unwrap_or(SessionStats::new(snapshot.starting_price));
+        self.executions = snapshot.executions.clone();
+        self.busted_trades = snapshot.busted_trades.iter().copied().collect();
+        self.unpriced_trades = snapshot.unpriced_trades.iter().copied().collect();
+        self.session_start_trade = snapshot.session_start_trade;
+        self.filled_orders = snapshot.filled_orders.iter().map(|order| (order.order_id, Order::from_snapshot(order, &self.security))).collect();
+        self.trade_tape.restore(&snapshot.trades, snapshot.last_trade_id);
+        self.published_best = (self.best_bid(), self.best_ask());
+        Ok(())
+    }
+
+    fn restore_orders(&mut self, snapshot: &BookSnapshot) -> Result<(), SnapshotProblem> {
+        let all_orders = snapshot.bids.iter().chain(&snapshot.asks).chain(&snapshot.market_orders).chain(&snapshot.stop_orders).chain(&snapshot.midpoint_orders);
+        for restored in all_orders {
+            let order = Order::from_snapshot(restored, &self.security);
+            if order.remaining() <= 0 { return Err(SnapshotProblem::NothingLeft(order.order_id)); }
+            if self.order_map.insert(order.order_id, order).is_some() { return Err(SnapshotProblem::ListedTwice(restored.order_id)); }
+        }
+
+        for (side, orders) in [(Side::Buy, &snapshot.bids), (Side::Sell, &snapshot.asks)] {
+            for restored in orders {
+                let pending_stop = restored.stop_price.is_some() && !restored.triggered;
+                let Some(limit) = restored.order_limit.filter(|_| restored.side == side && !pending_stop) else {
+                    return Err(SnapshotProblem::NotLimitOrder { order_id: restored.order_id, side });
+                };
+                let (levels, order_map) = self.levels_and_orders(side);
+                let level = levels.entry(limit).or_default();
+                let visible = order_map.get(&restored.order_id).map_or(0, Order::visible_remaining);
+                if level.quantity.checked_add(visible).is_none() { return Err(SnapshotProblem::LevelFull(limit)); }
+                level.push_back(order_map, restored.order_id);
+                *self.number_limit_orders_mut(side) += 1;
+            }
+        }
+
+        for restored in &snapshot.market_orders {
+            if restored.order_limit.is_some() { return Err(SnapshotProblem::NotMarketOrder(restored.order_id)); }
+            self.at_market_orders_mut(restored.side).push_back(restored.order_id);
+        }
+
+        for restored in &snapshot.stop_orders {
+            if restored.stop_price.is_none() || restored.triggered { return Err(SnapshotProblem::NotPendingStop(restored.order_id)); }
+            match restored.side {
+                Side::Buy => self.buy_stop_orders.push_back(restored.order_id),
+                Side::Sell => self.sell_stop_orders.push_back(restored.order_id),
+            }
+        }
+
+        for restored in &snapshot.midpoint_orders {
+            if !restored.midpoint { return Err(SnapshotProblem::NotMidpointOrder(restored.order_id)); }
+            self.midpoint_orders_mut(restored.side).push_back(restored.order_id);
+        }
+
+        self.pegged_orders = snapshot.pegged_orders.clone();
+        for link in &snapshot.oco_links {
+            self.oco_links.insert(link.order_id, OcoLink { link_id: link.link_id, sibling: link.sibling, amount: link.amount, executed: link.executed });
+        }
+        for quote in &snapshot.quotes {
+            self.quotes.insert(quote.account_id, Quote { quote_id: quote.quote_id, bid_id: quote.bid_order_id, ask_id: quote.ask_order_id });
+        }
+
+        self.check_invariants().map_err(|_| SnapshotProblem::Inconsistent)
+    }
+
+    fn clear_orders(&mut self) {
+        self.order_map.clear();
+        self.buy_at_market_orders.clear();
+        self.sell_at_market_orders.clear();
+        self.buy_levels.clear();
+        self.sell_levels.clear();
+        self.number_buy_limit_orders = 0;
+        self.number_sell_limit_orders = 0;
+        self.buy_stop_orders.clear();
+        self.sell_stop_orders.clear();
+        self.buy_midpoint_orders.clear();
+        self.sell_midpoint_orders.clear();
+        self.pegged_orders.clear();
+        self.oco_links.clear();
+        self.quotes.clear();
+    }
+
+    // The listener is called synchronously from within the book, in the order things happen.
+    pub fn set_listener(&mut self, listener: Box<dyn ExecutionListener + Send>) {
+        self.listener = Some(listener);
+    }
+
+    // Where the exchange books the trades of the book, see Exchange::set_accounts. It is kept
+    // apart from the listener, so neither replaces the other.
+    pub(crate) fn set_accounting_listener(&mut self, accounting: Box<dyn ExecutionListener + Send>) {
+        self.accounting = Some(accounting);
+    }
+
+    // Metrics are called synchronously from within the book, see metrics::Metrics.
+    pub fn set_metrics(&mut self, metrics: Box<dyn Metrics + Send>) {
+        self.metrics = Some(metrics);
+    }
+
+    // The clock order latencies are measured with, the monotonic clock of the system by default.
+    pub fn set_latency_clock(&mut self, clock: Box<dyn LatencyClock + Send>) {
+        self.latency_clock = clock;
+    }
+
+    // The clock the book reads its time from at the start of every command, see tick. The time
+    // goes to the journal like a call to set_time, so a recovered book, which has no clock until
+    // it is given one, replays with the times the clock gave.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock + Send>) {
+        self.clock = Some(clock);
+        self.tick();
+    }
+
+    // The fees charged on every execution from now on, no fees by default.
+    pub fn set_fees(&mut self, fees: FeeRates) {
+        self.fees = fees;
+    }
+
+    pub fn fees(&self) -> FeeRates {
+        self.fees
+    }
+
+    // Settlement instructions are published to the sink at the end of every call that traded.
+    pub fn set_execution_sink(&mut self, sink: Box<dyn ExecutionSink + Send>) {
+        self.execution_sink = Some(sink);
+    }
+
+    // Offers the instructions the sink has not accepted yet once more. The book does this by
+    // itself after every call that traded, a caller only needs it to catch up while no trades
+    // happen.
+    pub fn flush_settlements(&mut self) -> Result<(), &SinkError> {
+        let Some(sink) = &mut self.execution_sink else { return Ok(()); };
+        if self.pending_settlements.is_empty() { return Ok(()); }
+        match sink.publish(&self.pending_settlements) {
+            Ok(()) => {
+                self.pending_settlements.clear();
+                self.sink_error = None;
+                Ok(())
+            },
+            Err(error) => Err(self.sink_error.insert(error)),
+        }
+    }
+
+    // Instructions waiting for the sink, the oldest first.
+    pub fn pending_settlements(&self) -> &[SettlementInstruction] {
+        &self.pending_settlements
+    }
+
+    // Why the last attempt to publish failed, None once a publish succeeded.
+    pub fn sink_error(&self) -> Option<&SinkError> {
+        self.sink_error.as_ref()
+    }
+
+    // Reduce only orders are rejected until a provider is set.
+    pub fn set_position_provider(&mut self, provider: Box<dyn PositionProvider + Send>) {
+        self.position_provider = Some(provider);
+    }
+
+    // Leaving batch auctions runs a last batch, so continuous trading starts from an uncrossed book.
+    pub fn set_config(&mut self, config: OrderbookConfig) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetConfig { config })?;
+        let last_batch = self.config.batch_auctions && !config.batch_auctions && self.session_state == SessionState::Continuous;
+        if last_batch { self.batch(); }
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> &OrderbookConfig {
+        &self.config
+    }
+
+    pub fn set_oco_policy(&mut self, policy: OcoPolicy) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetOcoPolicy { policy })?;
+        self.oco_policy = policy;
+        Ok(())
+    }
+
+    // What an incoming order does when it meets a resting order of its own account, for accounts
+    // without a policy of their own. Self trades are allowed by default.
+    pub fn set_self_trade_policy(&mut self, policy: SelfTradePolicy) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetSelfTradePolicy { account_id: None, policy })?;
+        self.self_trade_policy = policy;
+        Ok(())
+    }
+
+    pub fn set_account_self_trade_policy(&mut self, account_id: u64, policy: SelfTradePolicy) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetSelfTradePolicy { account_id: Some(account_id), policy })?;
+        self.account_self_trade_policies.insert(account_id, policy);
+        Ok(())
+    }
+
+    pub fn self_trade_policy(&self, account_id: u64) -> SelfTradePolicy {
+        self.account_self_trade_policies.get(&account_id).copied().unwrap_or(self.self_trade_policy)
+    }
+
+    // the link id and sibling order of an order that is one leg of an active oco pair
+    pub fn oco_link(&self, order_id: OrderId) -> Option<(i64, OrderId)> {
+        self.oco_links.get(&order_id.to_raw()).map(|link| (link.link_id, OrderId::from_raw(link.sibling)))
+    }
+
+    // Limits how far the limit of a stop limit order may lie on the unfavourable side of its stop
+    // price. No limit is enforced by default.
+    pub fn set_max_stop_limit_gap(&mut self, max_gap: Option<i64>) -> Result<(), OrderbookError> {
+        self.log(JournalEntry::SetMaxStopLimitGap { max_gap })?;
+        self.max_stop_limit_gap = max_gap;
+        Ok(())
+    }
+
+    pub fn security(&self) -> &Arc<Security> {
+        &self.security
+    }
+
+    pub fn starting_price(&self) -> i64 {
+        self.starting_price
+    }
+
+    pub fn current_market_price(&self) -> i64 {
+        self.current_market_price
+    }
+
+    pub fn best_bid(&self) -> Option<i64> {
+        self.best_price(Side::Buy)
+    }
+
+    pub fn best_ask(&self) -> Option<i64> {
+        self.best_price(Side::Sell)
+    }
+
+    pub fn spread(&self) -> Option<i64> {
+        Some(self.best_ask()? - self.best_bid()?)
+    }
+
+    // rounded down to whole price units
+    pub fn mid_price(&self) -> Option<i64> {
+        let (bid, ask) = (self.best_bid()?, self.best_ask()?);
+        Some(bid + (ask - bid).div_euclid(2))
+    }
+
+    // the price of the last execution, or the starting price before the first one
+    pub fn last_price(&self) -> i64 {
+        self.current_market_price
+    }
+
+    pub fn stats(&self) -> SessionStats {
+        self.stats
+    }
+
+    pub fn order_status(&self, order_id: OrderId) -> Option<OrderState> {
+        self.order_map.get(&order_id.to_raw()).map(|order| order.state())
+    }
+
+    pub fn order(&self, order_id: OrderId) -> Option<&Order> {
+        self.order_map.get(&order_id.to_raw())
+    }
+
+    pub fn executions(&self) -> &[Execution] {
+        &self.executions
+    }
+
+    // The trade bust_trade would reverse, an error if it is unknown or busted already.
+    pub fn bustable_trade(&self, trade_id: u64) -> Result<&Execution, OrderbookError> {
+        if self.busted_trades.contains(&trade_id) { return Err(OrderbookError::TradeAlreadyBusted(trade_id)); }
+        self.executions.iter().find(|execution| execution.trade_id == trade_id).ok_or(OrderbookError::UnknownTrade(trade_id))
+    }
+
+    // Reverses a trade of the book. The trade stays on the tape marked busted, the session
+    // statistics are recomputed without it and both orders get its quantity back: open orders keep
+    // their place, limit orders filled in this session rest again at the back of their level.
+    // Outside the call phases an order is not re-opened where it would cross the book, which is
+    // always the case for the aggressor once the resting order is back; the busted quantity is
+    // taken off its amount instead. Cancelled orders and filled orders that may not rest stay
+    // closed the same way.
+    pub fn bust_trade(&mut self, trade_id: u64) -> Result<Execution, OrderbookError> {
+        self.tick();
+        let execution = self.bustable_trade(trade_id)?.clone();
+        self.log(JournalEntry::BustTrade { trade_id })?;
+        self.sequence += 1;
+        let counts = |trade_id: &u64| !self.busted_trades.contains(trade_id) && !self.unpriced_trades.contains(trade_id);
+        let latest = self.executions.iter().rev().find(|execution| counts(&execution.trade_id)).map(|execution| execution.trade_id);
+        self.busted_trades.insert(trade_id);
+        self.trade_tape.bust(trade_id);
+        let timestamp = self.current_time;
+        let busted = execution.clone();
+        self.events.publish(|sequence| OrderbookEvent::TradeBusted { sequence, timestamp, execution: busted });
+
+        let (resting_id, aggressor_id) = match execution.aggressor {
+            Side::Buy => (execution.selling_order_id, execution.buying_order_id),
+            Side::Sell => (execution.buying_order_id, execution.selling_order_id),
+        };
+        for order_id in [resting_id, aggressor_id] { self.reopen(order_id, execution.amount); }
+
+        let mut stats = SessionStats::new(self.stats.starting_price);
+        stats.closing_auction = self.stats.closing_auction;
+        let counts = |trade_id: &u64| !self.busted_trades.contains(trade_id) && !self.unpriced_trades.contains(trade_id);
+        for execution in self.executions.iter().filter(|execution| execution.trade_id > self.session_start_trade && counts(&execution.trade_id)) {
+            stats.record(execution.price, execution.amount);
+        }
+        self.stats = stats;
+        if latest == Some(trade_id) { self.current_market_price = self.stats.last_price; }
+        self.reprice_pegged_orders();
+        self.notify_book_update();
+        Ok(execution)
+    }
+
+    // Records a trade agreed away from the book, a block trade for example, without touching its
+    // orders. The trade goes on the tape flagged off-book and to the listener, the settlement sink
+    // and the subscribers like any trade, with 0 for both order ids and no fees. Unless the flags
+    // say otherwise it does not move the market price or count in the session statistics, and its
+    // price has to be within the static price band around the reference price of the session.
+    pub fn report_trade(&mut self, buyer_account: u64, seller_account: u64, price: i64, quantity: i64, flags: ReportFlags) -> Result<Execution, OrderbookError> {
+        self.tick();
+        if quantity <= 0 { return Err(OrderbookError::InvalidAmount); }
+        if price <= 0 { return Err(OrderbookError::InvalidLimit); }
+        if price % self.security.tick_size != 0 { return Err(OrderbookError::PriceNotOnTick { price, tick_size: self.security.tick_size }); }
+        if quantity % self.security.lot_size != 0 { return Err(OrderbookError::QuantityNotInLots { quantity, lot_size: self.security.lot_size }); }
+        // a static band only, the price of a negotiated trade is not measured against the last trade
+        if !flags.negotiated_outside_band && self.price_bands.breach(price, self.band_reference, price).is_some() {
+            return Err(OrderbookError::OutsidePriceBand { price, reference_price: self.band_reference });
+        }
+        self.log(JournalEntry::ReportTrade { buyer_account, seller_account, price, quantity, flags })?;
+        self.sequence += 1;
+
+        let mut execution = Execution {
+            trade_id: 0,
+            selling_order_id: 0,
+            buying_order_id: 0,
+            selling_account_id: Some(seller_account),
+            buying_account_id: Some(buyer_account),
+            price,
+            amount: quantity,
+            aggressor: Side::Buy,
+            maker_fee: 0,
+            taker_fee: 0,
+            timestamp: self.current_time,
+        };
+        execution.trade_id = self.trade_tape.record_off_book(&execution, self.current_time);
+        self.notify_execution(&execution);
+        if flags.sets_last_price {
+            self.current_market_price = price;
+            self.record_executions(std::slice::from_ref(&execution));
+        } else {
+            self.unpriced_trades.insert(execution.trade_id);
+            self.executions.push(execution.clone());
+        }
+        self.notify_book_update();
+        Ok(execution)
+    }
+
+    pub fn is_busted(&self, trade_id: u64) -> bool {
+        self.busted_trades.contains(&trade_id)
+    }
+
+    // Gives the quantity of a busted trade back to one of its orders, see bust_trade.
+    fn reopen(&mut self, order_id: i64, quantity: i64) {
+        if let Some(order) = self.order_map.get(&order_id) {
+            let crosses = self.reopen_crosses(order);
+            let Some(order) = self.order_map.get_mut(&order_id) else { return; };
+            let visible = order.visible_remaining();
+            order.amount_executed -= quantity;
+            if crosses {
+                order.amount -= quantity;
+            } else if let (false, false, Some(limit)) = (order.is_pending_stop(), order.midpoint, order.order_limit) {
+                let (side, change) = (order.side, order.visible_remaining() - visible);
+                self.touched_levels.push((side, limit));
+                self.adjust_level(side, limit, change);
+            }
+            return;
+        }
+
+        let Some(mut order) = self.filled_orders.remove(&order_id) else { return; };
+        order.amount_executed -= quantity;
+        let may_rest = (order.order_limit.is_some() || order.midpoint) && matches!(order.time_in_force, TimeInForce::GoodTillCancel | TimeInForce::Day | TimeInForce::AtTheClose);
+        if !may_rest || self.reopen_crosses(&order) {
+            order.amount -= quantity;
+            self.filled_orders.insert(order_id, order);
+            return;
+        }
+        if order.midpoint {
+            order.timestamp = self.current_time;
+            self.midpoint_orders_mut(order.side).push_back(order_id);
+            self.order_map.insert(order_id, order);
+            return;
+        }
+        if order.peg_offset.is_some() { self.pegged_orders.push(order_id); }
+        self.insert_order(order);
+    }
+
+    fn reopen_crosses(&self, order: &Order) -> bool {
+        if self.in_call_phase() || order.midpoint { return false; }
+        match order.order_limit {
+            Some(limit) => self.quote_crosses(order.side, limit, Some(order.order_id)).is_some(),
+            None => self.best_price(order.side.opposite()).is_some() || !self.at_market_orders(order.side.opposite()).is_empty(),
+        }
+    }
+
+    pub fn trade_tape(&self) -> &TradeTape {
+        &self.trade_tape
+    }
+
+    pub fn vwap(&self, window: TradeWindow) -> Option<i64> {
+        self.trade_tape.vwap(window)
+    }
+
+    // Samples the last price for twap. The caller decides when to sample, typically in fixed
+    // intervals of engine time, so every sample carries the same weight.
+    pub fn record_sample(&mut self, now: u64) {
+        self.price_samples.push((now, self.current_market_price));
+    }
+
+    // Time weighted average of the samples taken at or after `since`, rounded down. None without
+    // samples in that range.
+    pub fn twap(&self, since: u64) -> Option<i64> {
+        let prices: Vec<i64> = self.price_samples.iter().filter(|(timestamp, _)| *timestamp >= since).map(|(_, price)| *price).collect();
+        if prices.is_empty() { return None; }
+
+        let total: i128 = prices.iter().map(|&price| price as i128).sum();
+        Some(total.div_euclid(prices.len() as i128) as i64)
+    }
+
+    // Attaches an aggregator that is fed with every execution from now on.
+    pub fn set_candle_aggregator(&mut self, candles: Option<CandleAggregator>) {
+        self.candles = candles;
+    }
+
+    pub fn candles(&self) -> Option<&CandleAggregator> {
+        self.candles.as_ref()
+    }
+
+    // How many levels per side the Display ladder shows, 10 by default.
+    pub fn set_display_levels(&mut self, levels: usize) {
+        self.display_levels = levels;
+    }
+
+    // Keeps only the latest `retention` trades on the tape, or all of them with None.
+    pub fn set_tape_retention(&mut self, retention: Option<usize>) {
+        self.trade_tape.set_retention(retention);
+    }
+}
+
+// Prints the ladder with the asks on top and the bids below, both from high to low prices, so
+// the best prices meet at the separator.
+impl fmt::Display for Orderbook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.security.isin, self.security.name)?;
+        writeln!(f, "{:>12} {:>12} {:>8}", "price", "quantity", "orders")?;
+
+        let asks: Vec<DepthLevel> = self.depth_levels(Side::Sell).take(self.display_levels).collect();
+        let price = |price: i64| self.security.to_display_price(price);
+        for level in asks.iter().rev() {
+            writeln!(f, "{:>12} {:>12} {:>8}", price(level.price), level.quantity, level.order_count)?;
+        }
+
+        let spread = self.spread().map_or_else(|| "-".to_string(), price);
+        writeln!(f, "---- last {} spread {} ----", price(self.current_market_price), spread)?;
+
+        for level in self.depth_levels(Side::Buy).take(self.display_levels) {
+            writeln!(f, "{:>12} {:>12} {:>8}", price(level.price), level.quantity, level.order_count)?;
+        }
+        Ok(())
+    }
+}
+
+enum MatchingSignal {
+    BuyAtMarket,
+    SellAtMarket,
+    NewHighestBid,
+    NewLowestAsk,
+    NoOperation
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+impl Side {
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+
+    // Whether `price` is a strictly better quote than `reference` on this side of the book:
+    // higher for bids, lower for asks.
+    fn improves(self, price: i64, reference: i64) -> bool {
+        match self {
+            Side::Buy => price > reference,
+            Side::Sell => price < reference,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimeInForce {
+    #[default]
+    GoodTillCancel,
+    // executes what it can immediately, the remainder is cancelled instead of resting
+    ImmediateOrCancel,
+    // executes the full amount immediately or not at all
+    FillOrKill,
+    // rests like good till cancel but is cancelled at the end of the trading session
+    Day,
+    // only accepted during the closing auction, whatever it leaves of the order is cancelled
+    AtTheClose,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarketRemainder {
+    // the remainder waits in the book as a market order for the next opposite limit order
+    #[default]
+    RestAsMarket,
+    // the remainder becomes a limit order at the last price the order traded at, or is cancelled
+    // if it did not trade at all
+    ConvertToLimit,
+    CancelRemainder,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PostOnlyPolicy {
+    // an order that would cross the opposite best price is rejected
+    Reject,
+    // an order that would cross is re-priced one tick behind the opposite best price
+    Reprice,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OcoPolicy {
+    // any fill of one leg cancels the other leg completely
+    #[default]
+    CancelOnFill,
+    // a partial fill of one leg shrinks the other leg by the same share of its amount
+    ReduceProportionally,
+}
+
+// What a quote does when one of its sides reaches the opposite side of the book.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum QuotePolicy {
+    // the whole quote is rejected and the previous quote of the account stays
+    #[default]
+    RejectCrossing,
+    // the crossing side trades like any limit order and rests with what is left
+    Trade,
+}
+
+// What the book does with orders. Books start in continuous trading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SessionState {
+    // orders are accepted but never matched, they accumulate for the opening auction
+    PreOpen,
+    #[default]
+    Continuous,
+    // the call phase of an auction during the day, orders are accepted but never matched
+    Auction,
+    // the call phase of the closing auction, which ends with Orderbook::uncross
+    ClosingAuction,
+    // continuous trading interrupted by a trade outside the price bands, see PriceBands
+    VolatilityAuction,
+    // trading is halted, see Orderbook::halt
+    Halted,
+    // new orders and amendments are rejected with OrderbookError::MarketClosed
+    Closed,
+}
+
+// Settings of a book that are not part of its orders. The defaults keep every protection off.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderbookConfig {
+    market_collar_bps: Option<i64>,
+    matching_algorithm: MatchingAlgorithm,
+    batch_auctions: bool,
+    quote_policy: QuotePolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    expected_orders: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    expected_levels_per_side: usize,
+}
+
+impl OrderbookConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // How far from the last price before the order a market order may trade, in basis points: a
+    // market buy up to the last price * (1 + bps / 10000), a market sell down to * (1 - bps /
+    // 10000). The sweep stops at the first level beyond, and the remainder is handled as its
+    // MarketRemainder says. Parked market orders are held to the collar around the last price.
+    pub fn with_market_collar(mut self, bps: i64) -> OrderbookConfig {
+        self.market_collar_bps = Some(bps);
+        self
+    }
+
+    pub fn market_collar_bps(&self) -> Option<i64> {
+        self.market_collar_bps
+    }
+
+    pub fn with_matching_algorithm(mut self, algorithm: MatchingAlgorithm) -> OrderbookConfig {
+        self.matching_algorithm = algorithm;
+        self
+    }
+
+    pub fn matching_algorithm(&self) -> MatchingAlgorithm {
+        self.matching_algorithm
+    }
+
+    // Frequent batch auctions instead of continuous matching: during continuous trading orders
+    // are collected without matching, like in a call phase, until Orderbook::run_batch uncrosses
+    // them. Orders that may not rest are cancelled on arrival, they cannot wait for a batch.
+    pub fn with_batch_auctions(mut self) -> OrderbookConfig {
+        self.batch_auctions = true;
+        self
+    }
+
+    pub fn batch_auctions(&self) -> bool {
+        self.batch_auctions
+    }
+
+    pub fn with_quote_policy(mut self, policy: QuotePolicy) -> OrderbookConfig {
+        self.quote_policy = policy;
+        self
+    }
+
+    pub fn quote_policy(&self) -> QuotePolicy {
+        self.quote_policy
+    }
+
+    // The open orders the book is sized for by Orderbook::with_config. The capacity hints are read
+    // only when the book is built and are not journaled.
+    pub fn with_expected_orders(mut self, orders: usize) -> OrderbookConfig {
+        self.expected_orders = orders;
+        self
+    }
+
+    pub fn expected_orders(&self) -> usize {
+        self.expected_orders
+    }
+
+    pub fn with_expected_levels_per_side(mut self, levels: usize) -> OrderbookConfig {
+        self.expected_levels_per_side = levels;
+        self
+    }
+
+    pub fn expected_levels_per_side(&self) -> usize {
+        self.expected_levels_per_side
+    }
+
+    // the furthest price from `reference` a market order of the side may trade at, rounded toward it
+    pub(crate) fn collar_price(&self, side: Side, reference: i64) -> Option<i64> {
+        let bps = self.market_collar_bps? as i128;
+        let collar = match side {
+            Side::Buy => (reference as i128 * (10_000 + bps)).div_euclid(10_000),
+            Side::Sell => (reference as i128 * (10_000 - bps) + 9_999).div_euclid(10_000),
+        };
+        Some(collar.clamp(0, i64::MAX as i128) as i64)
+    }
+
+    pub(crate) fn within_collar(&self, side: Side, price: i64, reference: i64) -> bool {
+        let Some(bps) = self.market_collar_bps else { return true; };
+        let (price, collar) = (price as i128 * 10_000, reference as i128 * bps as i128);
+        match side {
+            Side::Buy => price <= reference as i128 * 10_000 + collar,
+            Side::Sell => price >= reference as i128 * 10_000 - collar,
+        }
+    }
+}
+
+// How an incoming order is shared between the orders of a price level it reaches.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MatchingAlgorithm {
+    // the earliest order of the level fills first
+    #[default]
+    PriceTimePriority,
+    // every order of the level gets a share of the incoming quantity in proportion to its visible
+    // quantity, rounded down. Shares below min_allocation are dropped, and whatever is left over
+    // goes to the largest orders, the earliest first among orders of the same size. Orders keep
+    // their place in the queue.
+    ProRata { min_allocation: i64 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum HaltReason {
+    // news that is expected to move the price is about to be published
+    NewsPending,
+    // ordered by the regulator
+    Regulatory,
+    // a technical problem of the venue
+    Technical,
+    // any other reason of the operator of the exchange
+    Operator,
+}
+
+// A trading halt, from the time the book was halted until it resumed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Halt {
+    pub(crate) reason: HaltReason,
+    pub(crate) halted_at: u64,
+    pub(crate) resumed_at: Option<u64>,
+    // the state the book was halted in
+    pub(crate) previous: SessionState,
+}
+
+impl Halt {
+    pub fn reason(&self) -> HaltReason {
+        self.reason
+    }
+
+    pub fn halted_at(&self) -> u64 {
+        self.halted_at
+    }
+
+    // None while the halt lasts
+    pub fn resumed_at(&self) -> Option<u64> {
+        self.resumed_at
+    }
+
+    pub fn halted_in(&self) -> SessionState {
+        self.previous
+    }
+}
+
+// How far trades may move the price in continuous trading, in basis points. The static band is
+// measured from the band reference, see Orderbook::band_reference, the dynamic band from the last
+// price before the incoming order. A trade outside either band is not made: the incoming order
+// stops matching, whatever is left of it is parked, and the book enters a volatility auction
+// for `auction_duration`, in the time of Orderbook::set_time.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PriceBands {
+    static_bps: Option<i64>,
+    dynamic_bps: Option<i64>,
+    auction_duration: u64,
+}
+
+impl PriceBands {
+    pub fn new(auction_duration: u64) -> Self {
+        PriceBands { static_bps: None, dynamic_bps: None, auction_duration }
+    }
+
+    pub fn with_static_band(mut self, bps: i64) -> PriceBands {
+        self.static_bps = Some(bps);
+        self
+    }
+
+    pub fn with_dynamic_band(mut self, bps: i64) -> PriceBands {
+        self.dynamic_bps = Some(bps);
+        self
+    }
+
+    pub fn static_bps(&self) -> Option<i64> {
+        self.static_bps
+    }
+
+    pub fn dynamic_bps(&self) -> Option<i64> {
+        self.dynamic_bps
+    }
+
+    pub fn auction_duration(&self) -> u64 {
+        self.auction_duration
+    }
+
+    // The band a trade at `price` would leave, the static one if it left both.
+    pub(crate) fn breach(&self, price: i64, reference: i64, last_price: i64) -> Option<PriceBand> {
+        let outside = |from: i64, bps: Option<i64>| bps.is_some_and(|bps| (price as i128 - from as i128).abs() * 10_000 > from as i128 * bps as i128);
+        if outside(reference, self.static_bps) { return Some(PriceBand::Static); }
+        if outside(last_price, self.dynamic_bps) { return Some(PriceBand::Dynamic); }
+        None
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PriceBand {
+    Static,
+    Dynamic,
+}
+
+// How an off-book trade reported with Orderbook::report_trade is treated. Venues leave such
+// prints out of the official last price, and so does the book by default.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReportFlags {
+    sets_last_price: bool,
+    negotiated_outside_band: bool,
+}
+
+impl ReportFlags {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The trade sets the market price and counts in the session statistics like a trade of the book.
+    pub fn with_last_price(mut self) -> ReportFlags {
+        self.sets_last_price = true;
+        self
+    }
+
+    // The trade was negotiated at a price outside the static band, which it is allowed to be.
+    pub fn with_negotiated_outside_band(mut self) -> ReportFlags {
+        self.negotiated_outside_band = true;
+        self
+    }
+
+    pub fn sets_last_price(&self) -> bool {
+        self.sets_last_price
+    }
+
+    pub fn negotiated_outside_band(&self) -> bool {
+        self.negotiated_outside_band
+    }
+}
+
+// What Orderbook::clear does besides removing the open orders. By default the orders are dropped
+// without being reported and statistics, tape and order ids carry on, fresh resets all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClearPolicy {
+    cancels_orders: bool,
+    resets_statistics: bool,
+    resets_tape: bool,
+    resets_order_ids: bool,
+}
+
+impl ClearPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Leaves a book that cannot be told apart from a new one, apart from its sequence and time.
+    pub fn fresh() -> Self {
+        Self::new().with_statistics_reset().with_tape_reset().with_order_id_reset()
+    }
+
+    // Every order is reported to the listener and subscribers as cancelled with
+    // CancelReason::Cleared, in the order the ids were assigned.
+    pub fn with_cancellations(mut self) -> ClearPolicy {
+        self.cancels_orders = true;
+        self
+    }
+
+    // The session statistics, price samples and candles start over, and the market price and the
+    // references of the price bands go back to the starting price.
+    pub fn with_statistics_reset(mut self) -> ClearPolicy {
+        self.resets_statistics = true;
+        self
+    }
+
+    // The tape and the executions are forgotten and trade ids start at 1 again, so earlier trades
+    // can no longer be busted.
+    pub fn with_tape_reset(mut self) -> ClearPolicy {
+        self.resets_tape = true;
+        self
+    }
+
+    // Order, oco link and quote ids start at 1 again, see OrderIdGenerator::reset.
+    pub fn with_order_id_reset(mut self) -> ClearPolicy {
+        self.resets_order_ids = true;
+        self
+    }
+
+    pub fn cancels_orders(&self) -> bool {
+        self.cancels_orders
+    }
+
+    pub fn resets_statistics(&self) -> bool {
+        self.resets_statistics
+    }
+
+    pub fn resets_tape(&self) -> bool {
+        self.resets_tape
+    }
+
+    pub fn resets_order_ids(&self) -> bool {
+        self.resets_order_ids
+    }
+}
+
+// What happens when both sides of a match belong to the same account. The policy of the account
+// of the incoming order applies and is checked for every resting order the order meets, so one
+// sweep can cancel own orders and still trade with the orders of others.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SelfTradePolicy {
+    #[default]
+    Allow,
+    // the remainder of the incoming order is cancelled, the resting order stays
+    CancelNewest,
+    // the resting order is cancelled and the incoming order goes on to the next one
+    CancelOldest,
+    // the resting order and the remainder of the incoming order are cancelled
+    CancelBoth,
+}
+
+// Which open orders Orderbook::cancel_all cancels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CancelFilter {
+    All,
+    ByAccount(u64),
+    BySide(Side),
+    // orders with a limit between the two prices, both included, orders without a limit never match
+    ByPriceRange(i64, i64),
+}
+
+impl CancelFilter {
+    pub fn matches(&self, order: &Order) -> bool {
+        match *self {
+            CancelFilter::All => true,
+            CancelFilter::ByAccount(account_id) => order.account_id == Some(account_id),
+            CancelFilter::BySide(side) => order.side == side,
+            CancelFilter::ByPriceRange(low, high) => order.order_limit.is_some_and(|limit| low <= limit && limit <= high),
+        }
+    }
+}
+
+// The orders of the current quote of an account, keyed by the account.
+#[derive(Clone, Copy)]
+struct Quote {
+    quote_id: i64,
+    bid_id: i64,
+    ask_id: i64,
+}
+
+// The orders resting at one price in time priority and their visible quantity. The queue is a
+// list linked through the orders, see QueueLink, so orders leave it without a search.
+#[derive(Default)]
+struct PriceLevel {
+    head: Option<SlotHandle>,
+    tail: Option<SlotHandle>,
+    len: usize,
+    quantity: i64,
+}
+
+impl PriceLevel {
+    fn front(&self) -> Option<SlotHandle> {
+        self.head
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head.is_none()
+    }
+
+    fn orders<'a>(&self, order_map: &'a OrderSlab<Order>) -> LevelOrders<'a> {
+        LevelOrders { order_map, next: self.head }
+    }
+
+    // Queues an order of the order map behind the orders waiting already.
+    fn push_back(&mut self, order_map: &mut OrderSlab<Order>, order_id: i64) {
+        let Some(handle) = order_map.handle(&order_id) else { return; };
+        let Some(order) = order_map.get_at_mut(handle) else { return; };
+        order.queue = Some(QueueLink { prev: self.tail, next: None });
+        self.quantity += order.visible_remaining();
+        match queue_link(order_map, self.tail) {
+            Some(tail) => tail.next = Some(handle),
+            None => self.head = Some(handle),
+        }
+        self.tail = Some(handle);
+        self.len += 1;
+    }
+
+    // Takes an order out of the queue wherever it waits, false for orders that were not queued.
+    fn remove(&mut self, order_map: &mut OrderSlab<Order>, handle: SlotHandle) -> bool {
+        let Some(order) = order_map.get_at_mut(handle) else { return false; };
+        let Some(link) = order.queue.take() else { return false; };
+        self.quantity -= order.visible_remaining();
+        match queue_link(order_map, link.prev) {
+            Some(prev) => prev.next = link.next,
+            None => self.head = link.next,
+        }
+        match queue_link(order_map, link.next) {
+            Some(next) => next.prev = link.prev,
+            None => self.tail = link.prev,
+        }
+        self.len -= 1;
+        true
+    }
+
+    // Queues the orders of `other` behind the orders of the level.
+    fn append(&mut self, order_map: &mut OrderSlab<Order>, other: PriceLevel) {
+        let Some(head) = other.head else { return; };
+        match queue_link(order_map, self.tail) {
+            Some(tail) => tail.next = Some(head),
+            None => self.head = Some(head),
+        }
+        if let Some(link) = queue_link(order_map, Some(head)) { link.prev = self.tail; }
+        self.tail = other.tail;
+        self.len += other.len;
+        self.quantity += other.quantity;
+    }
+}
+
+// The orders ahead of and behind a resting order in the queue of its level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct QueueLink {
+    prev: Option<SlotHandle>,
+    next: Option<SlotHandle>,
+}
+
+fn queue_link(order_map: &mut OrderSlab<Order>, handle: Option<SlotHandle>) -> Option<&mut QueueLink> {
+    order_map.get_at_mut(handle?)?.queue.as_mut()
+}
+
+// The orders of a level in queue order.
+#[derive(Clone, Copy)]
+pub(crate) struct LevelOrders<'a> {
+    order_map: &'a OrderSlab<Order>,
+    next: Option<SlotHandle>,
+}
+
+impl<'a> Iterator for LevelOrders<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let order = self.order_map.get_at(self.next?)?;
+        self.next = order.queue.and_then(|link| link.next);
+        Some(order)
+    }
+}
+
+// The levels of one side of the book, best price first.
+struct Ladder<'a> {
+    range: btree_map::Range<'a, i64, PriceLevel>,
+    side: Side,
+}
+
+impl<'a> Iterator for Ladder<'a> {
+    type Item = (i64, &'a PriceLevel);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (&price, level) = match self.side {
+            Side::Buy => self.range.next_back(),
+            Side::Sell => self.range.next(),
+        }?;
+        Some((price, level))
+    }
+}
+
+// One leg of an oco pair, keyed by the order id of the leg.
+struct OcoLink {
+    link_id: i64,
+    sibling: i64,
+    amount: i64,
+    executed: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OrderState {
+    // a stop waiting for its trigger, with the stop price currently in effect
+    PendingTrigger { stop_price: i64 },
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Killed,
+}
+
+#[derive(Clone)]
+pub struct Order {
+    order_id: i64,
+    side: Side,
+    order_limit: Option<i64>,
+    stop_price: Option<i64>,
+    trailing_offset: Option<i64>,
+    peg_offset: Option<i64>,
+    peg_cap: Option<i64>,
+    min_quantity: Option<i64>,
+    market_remainder: MarketRemainder,
+    account_id: Option<u64>,
+    reduce_only: bool,
+    timestamp: u64,
+    triggered: bool,
+    display_quantity: Option<i64>,
+    displayed: i64,
+    post_only: Option<PostOnlyPolicy>,
+    expires_at: Option<u64>,
+    continuous_only: bool,
+    midpoint: bool,
+    client_order_id: Option<u64>,
+    // the neighbours of the order in the queue of its level while it rests in one
+    queue: Option<QueueLink>,
+    security: Arc<Security>,
+    amount: i64,
+    amount_executed: i64,
+    time_in_force: TimeInForce,
+    closed: Option<OrderState>,
+    cancel_reason: Option<CancelReason>,
+}
+
+impl fmt::Debug for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.order_limit {
+            Some(limit) => write!(f, "Order {{ id: {}, {:?} {} @ {}", self.order_id, self.side, self.remaining(), limit)?,
+            None => write!(f, "Order {{ id: {}, {:?} {} @ market", self.order_id, self.side, self.remaining())?,
+        }
+        if let Some(stop_price) = self.stop_price { write!(f, ", stop {}", stop_price)?; }
+        write!(f, " }}")
+    }
+}
+
+// Puts an order together attribute by attribute, starting from Order::buy or Order::sell:
+//
+//     Order::buy(&security).limit(Price(10_050)).quantity(Qty(100)).account(7).tif(TimeInForce::Day).build()?
+//
+// Without a limit the order is a market order, without a time in force good till cancel. build
+// checks everything a book would that does not depend on the state of the book, with the same
+// errors, so an order that builds is only rejected for reasons like a closed market, a crossing
+// post only price or an expiry in the past.
+#[derive(Clone, Debug)]
+pub struct OrderBuilder {
+    order: Order,
+}
+
+impl OrderBuilder {
+    // for a side only known at run time, Order::buy and Order::sell read better otherwise
+    pub fn new(side: Side, security: &Arc<Security>) -> Self {
+        OrderBuilder { order: Order::new(side, None, security, Qty(0), TimeInForce::default()) }
+    }
+
+    pub fn limit(mut self, limit: Price) -> OrderBuilder {
+        self.order.order_limit = Some(limit.get());
+        self
+    }
+
+    pub fn quantity(mut self, quantity: Qty) -> OrderBuilder {
+        self.order.amount = quantity.get();
+        self
+    }
+
+    pub fn tif(mut self, time_in_force: TimeInForce) -> OrderBuilder {
+        self.order.time_in_force = time_in_force;
+        self
+    }
+
+    pub fn account(self, account_id: u64) -> OrderBuilder {
+        self.map(|order| order.with_account(account_id))
+    }
+
+    // see Order::with_client_order_id
+    pub fn client_order_id(self, client_order_id: u64) -> OrderBuilder {
+        self.map(|order| order.with_client_order_id(client_order_id))
+    }
+
+    // see Order::with_expiry
+    pub fn expires_at(self, expires_at: u64) -> OrderBuilder {
+        self.map(|order| order.with_expiry(expires_at))
+    }
+
+    // see Order::with_stop_price
+    pub fn stop(self, stop_price: Price) -> OrderBuilder {
+        self.map(|order| order.with_stop_price(stop_price.get()))
+    }
+
+    // see Order::with_trailing_stop
+    pub fn trailing_stop(self, offset: i64) -> OrderBuilder {
+        self.map(|order| order.with_trailing_stop(offset))
+    }
+
+    // see Order::with_peg, the limit is the cap of the peg
+    pub fn peg(self, offset: i64) -> OrderBuilder {
+        self.map(|order| order.with_peg(offset))
+    }
+
+    // see Order::with_midpoint_peg
+    pub fn midpoint_peg(self) -> OrderBuilder {
+        self.map(Order::with_midpoint_peg)
+    }
+
+    // see Order::with_display_quantity
+    pub fn display_quantity(self, display_quantity: Qty) -> OrderBuilder {
+        self.map(|order| order.with_display_quantity(display_quantity.get()))
+    }
+
+    // see Order::with_min_quantity
+    pub fn min_quantity(self, min_quantity: Qty) -> OrderBuilder {
+        self.map(|order| order.with_min_quantity(min_quantity.get()))
+    }
+
+    // see Order::with_post_only
+    pub fn post_only(self, policy: PostOnlyPolicy) -> OrderBuilder {
+        self.map(|order| order.with_post_only(policy))
+    }
+
+    // see Order::with_reduce_only
+    pub fn reduce_only(self) -> OrderBuilder {
+        self.map(Order::with_reduce_only)
+    }
+
+    // see Order::with_continuous_only
+    pub fn continuous_only(self) -> OrderBuilder {
+        self.map(Order::with_continuous_only)
+    }
+
+    // see Order::with_market_remainder
+    pub fn market_remainder(self, market_remainder: MarketRemainder) -> OrderBuilder {
+        self.map(|order| order.with_market_remainder(market_remainder))
+    }
+
+    fn map(self, attribute: impl FnOnce(Order) -> Order) -> OrderBuilder {
+        OrderBuilder { order: attribute(self.order) }
+    }
+
+    pub fn build(self) -> Result<Order, OrderbookError> {
+        let order = self.order;
+        order.security.check_order_size(order.order_limit.or(order.stop_price), order.amount)?;
+        order.validate()?;
+        Ok(order)
+    }
+}
+
+// An order is written with the ISIN of its security in place of the shared Security. There is no
+// Deserialize: orders only come into being through Order::new and a book, snapshots carry
+// resting orders as SnapshotOrder.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Order {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut order = serializer.serialize_struct("Order", 15)?;
+        order.serialize_field("order_id", &self.order_id)?;
+        order.serialize_field("isin", &self.security.isin)?;
+        order.serialize_field("side", &self.side)?;
+        order.serialize_field("order_limit", &self.order_limit)?;
+        order.serialize_field("stop_price", &self.stop_price)?;
+        order.serialize_field("amount", &self.amount)?;
+        order.serialize_field("amount_executed", &self.amount_executed)?;
+        order.serialize_field("time_in_force", &self.time_in_force)?;
+        order.serialize_field("state", &self.state())?;
+        order.serialize_field("account_id", &self.account_id)?;
+        order.serialize_field("display_quantity", &self.display_quantity)?;
+        order.serialize_field("post_only", &self.post_only)?;
+        order.serialize_field("expires_at", &self.expires_at)?;
+        order.serialize_field("midpoint", &self.midpoint)?;
+        order.serialize_field("timestamp", &self.timestamp)?;
+        order.end()
+    }
+}
+
+impl Order {
+    fn to_snapshot(&self) -> SnapshotOrder {
+        SnapshotOrder {
+            order_id: self.order_id,
+            side: self.side,
+            order_limit: self.order_limit,
+            stop_price: self.stop_price,
+            trailing_offset: self.trailing_offset,
+            peg_offset: self.peg_offset,
+            peg_cap: self.peg_cap,
+            min_quantity: self.min_quantity,
+            market_remainder: self.market_remainder,
+            account_id: self.account_id,
+            reduce_only: self.reduce_only,
+            timestamp: self.timestamp,
+            triggered: self.triggered,
+            display_quantity: self.display_quantity,
+            displayed: self.displayed,
+            post_only: self.post_only,
+            expires_at: self.expires_at,
+            continuous_only: self.continuous_only,
+            midpoint: self.midpoint,
+            client_order_id: self.client_order_id,
+            amount: self.amount,
+            amount_executed: self.amount_executed,
+            time_in_force: self.time_in_force,
+        }
+    }
+
+    fn from_snapshot(restored: &SnapshotOrder, security: &Arc<Security>) -> Order {
+        Order {
+            order_id: restored.order_id,
+            side: restored.side,
+            order_limit: restored.order_limit,
+            stop_price: restored.stop_price,
+            trailing_offset: restored.trailing_offset,
+            peg_offset: restored.peg_offset,
+            peg_cap: restored.peg_cap,
+            min_quantity: restored.min_quantity,
+            market_remainder: restored.market_remainder,
+            account_id: restored.account_id,
+            reduce_only: restored.reduce_only,
+            timestamp: restored.timestamp,
+            triggered: restored.triggered,
+            display_quantity: restored.display_quantity,
+            displayed: restored.displayed,
+            post_only: restored.post_only,
+            expires_at: restored.expires_at,
+            continuous_only: restored.continuous_only,
+            midpoint: restored.midpoint,
+            client_order_id: restored.client_order_id,
+            queue: None,
+            security: Arc::clone(security),
+            amount: restored.amount,
+            amount_executed: restored.amount_executed,
+            time_in_force: restored.time_in_force,
+            closed: None,
+            cancel_reason: None,
+        }
+    }
+
+    pub fn new(side: Side, order_limit: Option<Price>, security: &Arc<Security>, amount: Qty, time_in_force: TimeInForce) -> Order {
+        Order {
+            order_id: -1,
+            side,
+            order_limit: order_limit.map(Price::get),
+            stop_price: None,
+            trailing_offset: None,
+            peg_offset: None,
+            peg_cap: None,
+            min_quantity: None,
+            market_remainder: MarketRemainder::default(),
+            account_id: None,
+            reduce_only: false,
+            timestamp: 0,
+            triggered: false,
+            display_quantity: None,
+            displayed: 0,
+            post_only: None,
+            expires_at: None,
+            continuous_only: false,
+            midpoint: false,
+            client_order_id: None,
+            queue: None,
+            security: Arc::clone(security),
+            amount: amount.get(),
+            amount_executed: 0,
+            time_in_force,
+            closed: None,
+            cancel_reason: None,
+        }
+    }
+
+    // Turns the order into a stop order. A buy stop triggers once the last traded price reaches or
+    // exceeds the stop price, a sell stop once it falls to or below it. On trigger the order is
+    // executed like a market order, or as a limit order if it carries a limit.
+    pub fn with_stop_price(mut self, stop_price: i64) -> Order {
+        self.stop_price = Some(stop_price);
+        self
+    }
+
+    // Turns the order into a trailing stop which keeps its stop price `offset` away from the last
+    // traded price. The stop only ever moves in favour of the order: up for sell stops while the
+    // market rises, down for buy stops while it falls.
+    pub fn with_trailing_stop(mut self, offset: i64) -> Order {
+        self.trailing_offset = Some(offset);
+        self
+    }
+
+    // Pegs a limit order to the best price of its own side, `offset` ticks less aggressive. The limit
+    // given to Order::new becomes the cap a pegged buy never exceeds, or the floor a pegged sell
+    // never goes below. Each time the peg moves the order loses its time priority.
+    pub fn with_peg(mut self, offset: i64) -> Order {
+        self.peg_offset = Some(offset);
+        self
+    }
+
+    // The order only starts executing on submission if at least `min_quantity` can be filled right
+    // away. Once it rests in the book it takes fills of any size.
+    pub fn with_min_quantity(mut self, min_quantity: i64) -> Order {
+        self.min_quantity = Some(min_quantity);
+        self
+    }
+
+    pub fn with_account(mut self, account_id: u64) -> Order {
+        self.account_id = Some(account_id);
+        self
+    }
+
+    // A reduce only order may only bring the position of its account closer to zero. It needs an
+    // account and is cut back, or cancelled, as soon as the position no longer covers it.
+    pub fn with_reduce_only(mut self) -> Order {
+        self.reduce_only = true;
+        self
+    }
+
+    // Keeps the order out of the closing auction, it is cancelled when the auction starts.
+    pub fn with_continuous_only(mut self) -> Order {
+        self.continuous_only = true;
+        self
+    }
+
+    pub fn is_continuous_only(&self) -> bool {
+        self.continuous_only
+    }
+
+    // Rests the order out of sight and trades it only at the midpoint of the lit book, against other
+    // midpoint orders and against incoming orders that reach the opposite best price, which trade
+    // with midpoint orders before anything else. A limit caps the midpoint a buy pays, or floors
+    // the one a sell takes. Midpoint orders trade in time priority, and not at all while a side of
+    // the lit book is empty. They never show in the depth and take no part in auctions.
+    pub fn with_midpoint_peg(mut self) -> Order {
+        self.midpoint = true;
+        self
+    }
+
+    pub fn is_midpoint(&self) -> bool {
+        self.midpoint
+    }
+
+    // Decides what happens to the part of a market order that found no liquidity.
+    pub fn with_market_remainder(mut self, market_remainder: MarketRemainder) -> Order {
+        self.market_remainder = market_remainder;
+        self
+    }
+
+    // Turns a limit order into an iceberg that only shows `display_quantity` in the book. Whenever
+    // the visible slice is filled the next one is taken from the hidden remainder.
+    pub fn with_display_quantity(mut self, display_quantity: i64) -> Order {
+        self.display_quantity = Some(display_quantity);
+        self
+    }
+
+    // Makes a limit order post only, so it only ever provides liquidity.
+    pub fn with_post_only(mut self, policy: PostOnlyPolicy) -> Order {
+        self.post_only = Some(policy);
+        self
+    }
+
+    // Makes the order good till date: once the book time reaches `expires_at` it no longer trades
+    // and is removed by the next purge.
+    pub fn with_expiry(mut self, expires_at: u64) -> Order {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    // The id the client gave the order. The book only keeps it, the exchange uses it to recognise
+    // an order sent twice, see Exchange::place_order.
+    pub fn with_client_order_id(mut self, client_order_id: u64) -> Order {
+        self.client_order_id = Some(client_order_id);
+        self
+    }
+
+    // A buy order to be put together with an OrderBuilder.
+    pub fn buy(security: &Arc<Security>) -> OrderBuilder {
+        OrderBuilder::new(Side::Buy, security)
+    }
+
+    pub fn sell(security: &Arc<Security>) -> OrderBuilder {
+        OrderBuilder::new(Side::Sell, security)
+    }
+
+    // Everything about the order that does not depend on the state of a book: positive amounts
+    // and prices on the tick and lot grid of the security, and attributes that go together. It
+    // holds for the order as built as well as for one the book pegged or trailed already.
+    pub(crate) fn validate(&self) -> Result<(), OrderbookError> {
+        if self.amount <= 0 { return Err(OrderbookError::InvalidAmount); }
+        if self.order_limit.is_some_and(|limit| limit <= 0) { return Err(OrderbookError::InvalidLimit); }
+
+        // the stop of a trailing stop follows the market price, only its offset has to be on the tick
+        let stop_price = self.stop_price.filter(|_| self.trailing_offset.is_none());
+        let tick_size = self.security.tick_size;
+        for price in [self.order_limit, self.peg_cap, self.peg_offset, self.trailing_offset, stop_price].into_iter().flatten() {
+            if price % tick_size != 0 { return Err(OrderbookError::PriceNotOnTick { price, tick_size }); }
+        }
+        let lot_size = self.security.lot_size;
+        for quantity in [Some(self.amount), self.display_quantity, self.min_quantity].into_iter().flatten() {
+            if quantity % lot_size != 0 { return Err(OrderbookError::QuantityNotInLots { quantity, lot_size }); }
+        }
+
+        if self.trailing_offset.is_some_and(|offset| offset <= 0) { return Err(OrderbookError::InvalidTrailingOffset); }
+        let stop = self.stop_price.is_some() || self.trailing_offset.is_some();
+        if let Some(offset) = self.peg_offset {
+            // the book turns the limit of a pegged order into its cap
+            if offset < 0 || self.order_limit.is_none() || stop { return Err(OrderbookError::InvalidPeg); }
+        }
+        if self.midpoint && (stop || self.peg_offset.is_some() || self.display_quantity.is_some() || self.post_only.is_some() || self.min_quantity.is_some()
+            || matches!(self.time_in_force, TimeInForce::FillOrKill | TimeInForce::AtTheClose)) {
+            return Err(OrderbookError::InvalidMidpointPeg);
+        }
+
+        if let Some(min_quantity) = self.min_quantity {
+            if min_quantity <= 0 || min_quantity > self.amount { return Err(OrderbookError::InvalidMinQuantity { min_quantity, amount: self.amount }); }
+        }
+        if let Some(display_quantity) = self.display_quantity {
+            if display_quantity <= 0 || display_quantity > self.amount || self.order_limit.is_none() {
+                return Err(OrderbookError::InvalidDisplayQuantity);
+            }
+        }
+        if self.stop_price.is_some_and(|stop_price| stop_price <= 0) { return Err(OrderbookError::InvalidStopPrice); }
+        Ok(())
+    }
+
+    pub fn order_id(&self) -> OrderId {
+        OrderId::from_raw(self.order_id)
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn order_limit(&self) -> Option<i64> {
+        self.order_limit
+    }
+
+    pub fn stop_price(&self) -> Option<i64> {
+        self.stop_price
+    }
+
+    pub fn trailing_offset(&self) -> Option<i64> {
+        self.trailing_offset
+    }
+
+    pub fn peg_offset(&self) -> Option<i64> {
+        self.peg_offset
+    }
+
+    // the cap or floor of a pegged order, its current price is order_limit
+    pub fn peg_cap(&self) -> Option<i64> {
+        self.peg_cap
+    }
+
+    pub fn is_pending_stop(&self) -> bool {
+        self.stop_price.is_some() && !self.triggered
+    }
+
+    pub fn security(&self) -> &Arc<Security> {
+        &self.security
+    }
+
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+
+    pub fn amount_executed(&self) -> i64 {
+        self.amount_executed
+    }
+
+    pub fn account_id(&self) -> Option<u64> {
+        self.account_id
+    }
+
+    pub fn client_order_id(&self) -> Option<u64> {
+        self.client_order_id
+    }
+
+    // the book time the order was accepted at, or last lost its time priority at
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn is_reduce_only(&self) -> bool {
+        self.reduce_only
+    }
+
+    pub fn market_remainder(&self) -> MarketRemainder {
+        self.market_remainder
+    }
+
+    pub fn min_quantity(&self) -> Option<i64> {
+        self.min_quantity
+    }
+
+    pub fn display_quantity(&self) -> Option<i64> {
+        self.display_quantity
+    }
+
+    // The part of the order that is shown in the book, which for icebergs is the open part of the
+    // current slice.
+    pub fn visible_remaining(&self) -> i64 {
+        if self.midpoint { return 0; }
+        match self.display_quantity {
+            Some(_) => self.displayed,
+            None => self.remaining(),
+        }
+    }
+
+    fn close(&mut self, state: OrderState, reason: CancelReason) {
+        self.closed = Some(state);
+        self.cancel_reason = Some(reason);
+    }
+
+    fn replenish(&mut self) {
+        if let Some(display_quantity) = self.display_quantity {
+            self.displayed = display_quantity.min(self.remaining());
+        }
+    }
+
+    pub fn post_only(&self) -> Option<PostOnlyPolicy> {
+        self.post_only
+    }
+
+    pub fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| now >= expires_at)
+    }
+
+    pub fn time_in_force(&self) -> TimeInForce {
+        self.time_in_force
+    }
+
+    pub fn remaining(&self) -> i64 {
+        self.amount - self.amount_executed
+    }
+
+    pub fn state(&self) -> OrderState {
+        if let Some(state) = self.closed { return state; }
+        if let (Some(stop_price), false) = (self.stop_price, self.triggered) { return OrderState::PendingTrigger { stop_price }; }
+
+        match self.amount_executed {
+            0 => OrderState::New,
+            executed if executed < self.amount => OrderState::PartiallyFilled,
+            _ => OrderState::Filled,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Execution {
+    trade_id: u64,
+    selling_order_id: i64,
+    buying_order_id: i64,
+    selling_account_id: Option<u64>,
+    buying_account_id: Option<u64>,
+    price: i64,
+    amount: i64,
+    // the side of the incoming order, which took liquidity
+    aggressor: Side,
+    // negative fees are rebates
+    maker_fee: i64,
+    taker_fee: i64,
+    // the book time of the trade
+    #[cfg_attr(feature = "serde", serde(default))]
+    timestamp: u64,
+}
+
+impl Execution {
+    fn between(incoming_order: &Order, resting_id: i64, resting_account: Option<u64>, price: i64, amount: i64) -> Self {
+        let (incoming_id, incoming_account) = (incoming_order.order_id, incoming_order.account_id);
+        match incoming_order.side {
+            Side::Buy => Execution { trade_id: 0, selling_order_id: resting_id, buying_order_id: incoming_id, selling_account_id: resting_account, buying_account_id: incoming_account, price, amount, aggressor: Side::Buy, maker_fee: 0, taker_fee: 0, timestamp: 0 },
+            Side::Sell => Execution { trade_id: 0, selling_order_id: incoming_id, buying_order_id: resting_id, selling_account_id: incoming_account, buying_account_id: resting_account, price, amount, aggressor: Side::Sell, maker_fee: 0, taker_fee: 0, timestamp: 0 },
+        }
+    }
+
+    // The same trade the other way round, the buyer sells to the seller and fees are paid back.
+    pub(crate) fn reversed(&self) -> Execution {
+        Execution {
+            trade_id: self.trade_id,
+            selling_order_id: self.buying_order_id,
+            buying_order_id: self.selling_order_id,
+            selling_account_id: self.buying_account_id,
+            buying_account_id: self.selling_account_id,
+            price: self.price,
+            amount: self.amount,
+            aggressor: self.aggressor.opposite(),
+            maker_fee: -self.maker_fee,
+            taker_fee: -self.taker_fee,
+            timestamp: self.timestamp,
+        }
+    }
+
+    // the id of the trade on the tape of the book
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn selling_order_id(&self) -> i64 {
+        self.selling_order_id
+    }
+
+    pub fn buying_order_id(&self) -> i64 {
+        self.buying_order_id
+    }
+
+    pub fn selling_account_id(&self) -> Option<u64> {
+        self.selling_account_id
+    }
+
+    pub fn buying_account_id(&self) -> Option<u64> {
+        self.buying_account_id
+    }
+
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn amount(&self) -> i64 {
+        self.amount
+    }
+
+    pub fn aggressor(&self) -> Side {
+        self.aggressor
+    }
+
+    pub fn maker_fee(&self) -> i64 {
+        self.maker_fee
+    }
+
+    pub fn taker_fee(&self) -> i64 {
+        self.taker_fee
+    }
+
+    pub fn buyer_fee(&self) -> i64 {
+        if self.aggressor == Side::Buy { self.taker_fee } else { self.maker_fee }
+    }
+
+    pub fn seller_fee(&self) -> i64 {
+        if self.aggressor == Side::Sell { self.taker_fee } else { self.maker_fee }
+    }
+}
+
+// What happened to an order within a single place_order call.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OrderReport {
+    order_id: i64,
+    filled: i64,
+    average_price: Option<i64>,
+    remaining: i64,
+    cancelled: i64,
+    state: OrderState,
+    executions: Vec<Execution>,
+}
+
+impl OrderReport {
+    fn new(order: &Order, executions: Vec<Execution>) -> Self {
+        let filled: i64 = executions.iter().map(|execution| execution.amount).sum();
+        let notional: i128 = executions.iter().map(|execution| execution.price as i128 * execution.amount as i128).sum();
+        // the average is rounded down to whole price units
+        let average_price = if filled > 0 { Some((notional / filled as i128) as i64) } else { None };
+
+        let (remaining, cancelled) = if order.closed.is_some() { (0, order.remaining()) } else { (order.remaining(), 0) };
+
+        OrderReport { order_id: order.order_id, filled, average_price, remaining, cancelled, state: order.state(), executions }
+    }
+
+    // Brings the report up to date with the order as it is in the book now. An order that left the
+    // book with quantity still open was cancelled in the meantime.
+    fn sync(&mut self, order: Option<&Order>) {
+        match order {
+            Some(order) => {
+                self.remaining = order.remaining();
+                self.state = order.state();
+            },
+            None if self.remaining > 0 => {
+                self.cancelled += self.remaining;
+                self.remaining = 0;
+                self.state = OrderState::Cancelled;
+            },
+            None => {},
+        }
+    }
+
+    pub fn order_id(&self) -> OrderId {
+        OrderId::from_raw(self.order_id)
+    }
+
+    pub fn filled(&self) -> Qty {
+        Qty(self.filled)
+    }
+
+    pub fn average_price(&self) -> Option<Price> {
+        self.average_price.map(Price)
+    }
+
+    pub fn remaining(&self) -> Qty {
+        Qty(self.remaining)
+    }
+
+    // open quantity that was cancelled instead of resting in the book
+    pub fn cancelled(&self) -> Qty {
+        Qty(self.cancelled)
+    }
+
+    pub fn state(&self) -> OrderState {
+        self.state
+    }
+
+    pub fn executions(&self) -> &[Execution] {
+        &self.executions
+    }
+
+    // What the order paid in fees over its executions, negative for a net rebate.
+    pub fn fees(&self) -> i64 {
+        self.executions.iter().map(|execution| if execution.buying_order_id == self.order_id { execution.buyer_fee() } else { execution.seller_fee() }).sum()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueuePosition {
+    price: i64,
+    orders_ahead: usize,
+    quantity_ahead: i64,
+}
+
+impl QueuePosition {
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    // orders ahead within the same price level
+    pub fn orders_ahead(&self) -> usize {
+        self.orders_ahead
+    }
+
+    // open quantity ahead at the same price and at all better prices
+    pub fn quantity_ahead(&self) -> i64 {
+        self.quantity_ahead
+    }
+}
+
+// What an order would get from the book right now, see Orderbook::estimate_fill.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FillEstimate {
+    filled: i64,
+    average_price: Option<i64>,
+    worst_price: Option<i64>,
+    leftover: i64,
+}
+
+impl FillEstimate {
+    pub fn filled(&self) -> i64 {
+        self.filled
+    }
+
+    pub fn average_price(&self) -> Option<i64> {
+        self.average_price
+    }
+
+    pub fn worst_price(&self) -> Option<i64> {
+        self.worst_price
+    }
+
+    pub fn leftover(&self) -> i64 {
+        self.leftover
+    }
+}
+
+// The outcome of placing an oco pair.
+#[derive(Clone, Debug)]
+pub struct OcoReport {
+    link_id: i64,
+    primary: OrderReport,
+    secondary: OrderReport,
+}
+
+impl OcoReport {
+    pub fn link_id(&self) -> i64 {
+        self.link_id
+    }
+
+    pub fn primary(&self) -> &OrderReport {
+        &self.primary
+    }
+
+    pub fn secondary(&self) -> &OrderReport {
+        &self.secondary
+    }
+}
+
+// The outcome of submitting a quote, needed to cancel it.
+#[derive(Clone, Debug)]
+pub struct QuoteHandle {
+    quote_id: i64,
+    account_id: u64,
+    bid: OrderReport,
+    ask: OrderReport,
+}
+
+impl QuoteHandle {
+    pub fn quote_id(&self) -> i64 {
+        self.quote_id
+    }
+
+    pub fn account_id(&self) -> u64 {
+        self.account_id
+    }
+
+    pub fn bid(&self) -> &OrderReport {
+        &self.bid
+    }
+
+    pub fn ask(&self) -> &OrderReport {
+        &self.ask
+    }
+}
+
+// What end_of_session did, for publishing the session close.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SessionSummary {
+    cancelled_order_ids: Vec<i64>,
+    reference_price: i64,
+    stats: SessionStats,
+}
+
+impl SessionSummary {
+    pub fn cancelled_order_ids(&self) -> &[i64] {
+        &self.cancelled_order_ids
+    }
+
+    // the closing price, which is the starting price of the next session
+    pub fn reference_price(&self) -> i64 {
+        self.reference_price
+    }
+
+    // the statistics of the session that just closed
+    pub fn stats(&self) -> &SessionStats {
+        &self.stats
+    }
+}
+
+// Prices are integers in units of 10^-price_decimals. Limits have to be multiples of the tick size
+// and quantities multiples of the lot size, both are 1 unless set. Orders above the maximum
+// quantity or notional are rejected, there is no maximum unless set.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(try_from = "SecurityFields"))]
+pub struct Security {
+    pub isin: String,
+    pub name: String,
+    tick_size: i64,
+    lot_size: i64,
+    price_decimals: u8,
+    max_order_quantity: Option<i64>,
+    max_order_notional: Option<i64>,
+}
+
+// A security as it is written, checked before it becomes a Security.
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct SecurityFields {
+    isin: String,
+    name: String,
+    #[serde(default = "Security::unit")]
+    tick_size: i64,
+    #[serde(default = "Security::unit")]
+    lot_size: i64,
+    #[serde(default)]
+    price_decimals: u8,
+    #[serde(default)]
+    max_order_quantity: Option<i64>,
+    #[serde(default)]
+    max_order_notional: Option<i64>,
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SecurityFields> for Security {
+    type Error = InvalidSecurity;
+
+    fn try_from(fields: SecurityFields) -> Result<Self, Self::Error> {
+        let SecurityFields { isin, name, tick_size, lot_size, price_decimals, max_order_quantity, max_order_notional } = fields;
+        Security::check_sizes(tick_size, lot_size)?;
+        Ok(Security { isin, name, tick_size, lot_size, price_decimals, max_order_quantity, max_order_notional })
+    }
+}
+
+impl Security {
+    pub fn new(isin: &str, name: &str) -> Self {
+        Security { isin: isin.to_string(), name: name.to_string(), tick_size: 1, lot_size: 1, price_decimals: 0, max_order_quantity: None, max_order_notional: None }
+    }
+
+    // Like new with the given sizes, which are refused instead of taken as 1 when below 1.
+    pub fn try_new(isin: &str, name: &str, tick_size: i64, lot_size: i64) -> Result<Self, InvalidSecurity> {
+        Security::check_sizes(tick_size, lot_size)?;
+        Ok(Security { tick_size, lot_size, ..Security::new(isin, name) })
+    }
+
+    fn check_sizes(tick_size: i64, lot_size: i64) -> Result<(), InvalidSecurity> {
+        if tick_size < 1 { return Err(InvalidSecurity::TickSize(tick_size)); }
+        if lot_size < 1 { return Err(InvalidSecurity::LotSize(lot_size)); }
+        Ok(())
+    }
+
+    // sizes below 1 are taken as 1
+    pub fn with_tick_size(mut self, tick_size: i64) -> Security {
+        self.tick_size = tick_size.max(1);
+        self
+    }
+
+    pub fn with_lot_size(mut self, lot_size: i64) -> Security {
+        self.lot_size = lot_size.max(1);
+        self
+    }
+
+    pub fn with_price_decimals(mut self, price_decimals: u8) -> Security {
+        self.price_decimals = price_decimals;
+        self
+    }
+
+    pub fn with_max_order_quantity(mut self, max_quantity: i64) -> Security {
+        self.max_order_quantity = Some(max_quantity);
+        self
+    }
+
+    // The most an order may be worth, limit * quantity. Orders without a limit are valued at their
+    // stop price, or else at the edge of the market collar of the book, or else at its last price.
+    pub fn with_max_order_notional(mut self, max_notional: i64) -> Security {
+        self.max_order_notional = Some(max_notional);
+        self
+    }
+
+    // the maximums of the security, or else the default ones
+    pub(crate) fn with_default_order_limits(mut self, max_quantity: Option<i64>, max_notional: Option<i64>) -> Security {
+        self.max_order_quantity = self.max_order_quantity.or(max_quantity);
+        self.max_order_notional = self.max_order_notional.or(max_notional);
+        self
+    }
+
+    // The maximum quantity and, for an order with a price, the maximum notional, computed in i128
+    // so that no order can overflow it.
+    pub(crate) fn check_order_size(&self, price: Option<i64>, amount: i64) -> Result<(), OrderbookError> {
+        if let Some(max_quantity) = self.max_order_quantity.filter(|&max_quantity| amount > max_quantity) {
+            return Err(OrderbookError::QuantityAboveMaximum { quantity: amount, max_quantity });
+        }
+        let (Some(price), Some(max_notional)) = (price, self.max_order_notional) else { return Ok(()); };
+        let notional = (Price(price) * Qty(amount)).get();
+        if notional > max_notional as i128 { return Err(OrderbookError::NotionalAboveMaximum { notional: notional.min(i64::MAX as i128) as i64, max_notional }); }
+        Ok(())
+    }
+
+    pub fn tick_size(&self) -> i64 {
+        self.tick_size
+    }
+
+    pub fn lot_size(&self) -> i64 {
+        self.lot_size
+    }
+
+    pub fn price_decimals(&self) -> u8 {
+        self.price_decimals
+    }
+
+    // The price as a decimal number with the decimals of the security, 10050 with 2 decimals is
+    // 100.50. Used wherever prices leave the engine as text.
+    pub fn to_display_price(&self, price: i64) -> String {
+        units::format_price(price, self.price_decimals)
+    }
+
+    // Reads a decimal price into price units. More decimals than the security has are rejected
+    // unless they are zeros, and so are prices that do not fit an i64.
+    pub fn parse_price(&self, text: &str) -> Result<i64, ParsePriceError> {
+        units::parse_price(text, self.price_decimals)
+    }
+
+    pub fn max_order_quantity(&self) -> Option<i64> {
+        self.max_order_quantity
+    }
+
+    pub fn max_order_notional(&self) -> Option<i64> {
+        self.max_order_notional
+    }
+
+    // The price rounded to a multiple of the tick, up or down, and never below one tick.
+    pub(crate) fn round_to_tick(&self, price: i128, up: bool) -> i64 {
+        let tick = self.tick_size as i128;
+        (self.whole_ticks(price, up) * tick).clamp(tick, i64::MAX as i128) as i64
+    }
+
+    // the price in ticks, rounded up or down, 0 or less for a price that rounds to below one tick
+    pub(crate) fn whole_ticks(&self, price: i128, up: bool) -> i128 {
+        let tick = self.tick_size as i128;
+        if up { (price + tick - 1).div_euclid(tick) } else { price.div_euclid(tick) }
+    }
+
+    #[cfg(feature = "serde")]
+    fn unit() -> i64 {
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> (Arc<Security>, Orderbook) {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let book = Orderbook::new(security.clone(), 100);
+        (security, book)
+    }
+
+    fn limit(security: &Arc<Security>, side: Side, price: i64, quantity: i64) -> Order {
+        OrderBuilder::new(side, security).limit(Price(price)).quantity(Qty(quantity)).build().unwrap()
+    }
+
+    // Counts the heap allocations of the thread that makes them, so tests running side by side do
+    // not see each other's.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+    }
+
+    unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            unsafe { std::alloc::System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+            unsafe { std::alloc::System.dealloc(ptr, layout) }
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            unsafe { std::alloc::System.realloc(ptr, layout, new_size) }
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    // runs `f` and returns its result with the number of allocations it made
+    fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(|count| count.get());
+        let result = f();
+        (result, ALLOCATIONS.with(|count| count.get()) - before)
+    }
+
+    #[test]
+    fn an_expired_stop_cancels_its_oco_sibling() {
+        let (security, mut book) = book();
+        let resting = limit(&security, Side::Buy, 90, 10);
+        let stop = OrderBuilder::new(Side::Buy, &security).limit(Price(111)).stop(Price(110)).quantity(Qty(10)).expires_at(50).build().unwrap();
+        let report = book.place_oco(resting, stop).unwrap();
+        let (resting_id, stop_id) = (report.primary().order_id(), report.secondary().order_id());
+
+        book.set_time(60).unwrap();
+        book.place_order(limit(&security, Side::Sell, 110, 1)).unwrap();
+        book.place_order(limit(&security, Side::Buy, 110, 1)).unwrap();
+
+        assert!(book.order(stop_id).is_none());
+        assert!(book.order(resting_id).is_none());
+        assert_eq!(book.oco_link(resting_id), None);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_expired_midpoint_leg_met_by_an_order_cancels_its_oco_sibling() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Buy, 90, 10)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 110, 10)).unwrap();
+        let midpoint = OrderBuilder::new(Side::Buy, &security).quantity(Qty(10)).midpoint_peg().expires_at(50).build().unwrap();
+        let report = book.place_oco(midpoint, limit(&security, Side::Buy, 80, 10)).unwrap();
+        let (midpoint_id, sibling_id) = (report.primary().order_id(), report.secondary().order_id());
+
+        book.set_time(60).unwrap();
+        let sell = OrderBuilder::new(Side::Sell, &security).quantity(Qty(5)).midpoint_peg().build().unwrap();
+        assert_eq!(book.place_order(sell).unwrap().filled(), Qty(0));
+
+        assert!(book.order(midpoint_id).is_none());
+        assert!(book.order(sibling_id).is_none());
+        assert_eq!(book.oco_link(sibling_id), None);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_expired_lit_leg_met_by_an_order_cancels_its_oco_sibling() {
+        let (security, mut book) = book();
+        let expiring = OrderBuilder::new(Side::Buy, &security).limit(Price(95)).quantity(Qty(10)).expires_at(50).build().unwrap();
+        let report = book.place_oco(expiring, limit(&security, Side::Buy, 80, 10)).unwrap();
+        let (expiring_id, sibling_id) = (report.primary().order_id(), report.secondary().order_id());
+
+        book.set_time(60).unwrap();
+        book.place_order(limit(&security, Side::Sell, 95, 5)).unwrap();
+
+        assert!(book.order(expiring_id).is_none());
+        assert!(book.order(sibling_id).is_none());
+        assert_eq!(book.best_bid(), None);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_stale_purge_does_not_move_the_time_back() {
+        let (security, mut book) = book();
+        let order = OrderBuilder::new(Side::Buy, &security).limit(Price(90)).quantity(Qty(10)).expires_at(50).build().unwrap();
+        let order_id = book.place_order(order).unwrap().order_id();
+
+        book.set_time(40).unwrap();
+        assert!(book.purge_expired(10).unwrap().is_empty());
+        assert_eq!(book.current_time(), 40);
+        book.set_time(20).unwrap();
+        assert_eq!(book.current_time(), 40);
+
+        assert!(book.purge_expired(30).unwrap().is_empty());
+        book.set_time(60).unwrap();
+        assert_eq!(book.purge_expired(55), Ok(vec![order_id.to_raw()]));
+        assert_eq!(book.current_time(), 60);
+    }
+
+    #[test]
+    fn sizes_below_one_are_refused() {
+        assert_eq!(Security::try_new("XS0000000001", "TEST", 0, 1), Err(InvalidSecurity::TickSize(0)));
+        assert_eq!(Security::try_new("XS0000000001", "TEST", 5, -1), Err(InvalidSecurity::LotSize(-1)));
+        let security = Security::try_new("XS0000000001", "TEST", 5, 10).unwrap();
+        assert_eq!((security.tick_size(), security.lot_size()), (5, 10));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn a_deserialized_security_is_checked() {
+        assert!(serde_json::from_str::<Security>(r#"{"isin":"XS0000000001","name":"TEST","tick_size":0}"#).is_err());
+        assert!(serde_json::from_str::<Security>(r#"{"isin":"XS0000000001","name":"TEST","lot_size":0}"#).is_err());
+
+        let security: Security = serde_json::from_str(r#"{"isin":"XS0000000001","name":"TEST"}"#).unwrap();
+        assert_eq!((security.tick_size(), security.lot_size()), (1, 1));
+        let security = Security::new("XS0000000001", "TEST").with_tick_size(5).with_price_decimals(2);
+        assert_eq!(serde_json::from_str::<Security>(&serde_json::to_string(&security).unwrap()).unwrap(), security);
+    }
+
+    #[test]
+    fn a_level_never_holds_more_than_an_i64() {
+        let (security, mut book) = book();
+        let large = i64::MAX / 2 + 1;
+        let first = book.place_order(limit(&security, Side::Buy, 90, large)).unwrap().order_id();
+        assert_eq!(book.place_order(limit(&security, Side::Buy, 90, large)).unwrap_err(), OrderbookError::LevelFull { price: 90 });
+        let second = book.place_order(limit(&security, Side::Buy, 89, large)).unwrap().order_id();
+
+        assert_eq!(book.amend_order(second, Some(Price(90)), Qty(large)).unwrap_err(), OrderbookError::LevelFull { price: 90 });
+        assert_eq!(book.amend_order(first, Some(Price(90)), Qty(i64::MAX)).unwrap().remaining(), Qty(i64::MAX));
+        assert_eq!(book.cumulative_depth(Side::Buy, 89), i64::MAX);
+        assert_eq!(book.imbalance(2), Some(1.0));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    fn journal_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("trade-city-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn a_command_that_cannot_be_journaled_changes_nothing() {
+        let dir = journal_dir("failed-journal");
+        let (_, mut book) = book();
+        // the first record rotates the segment, which fails once the directory is gone
+        book.set_journal(Journal::open(dir.join("book.journal")).unwrap().with_rotation(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(matches!(book.set_time(10), Err(OrderbookError::JournalWrite(_))));
+        assert_eq!(book.current_time(), 0);
+        assert!(book.set_price_bands(PriceBands::new(5).with_static_band(100)).is_err());
+        assert_eq!(book.price_bands(), PriceBands::default());
+        assert!(book.halt(HaltReason::Technical).is_err());
+        assert!(!book.is_halted());
+        assert!(book.set_max_stop_limit_gap(Some(5)).is_err());
+        assert!(book.purge_expired(20).is_err());
+        assert!(book.end_of_session().is_err());
+        assert_eq!((book.current_time(), book.session_state()), (0, SessionState::Continuous));
+    }
+
+    #[test]
+    fn a_corporate_action_that_cannot_be_journaled_changes_nothing() {
+        let dir = journal_dir("failed-action-journal");
+        let (security, mut book) = book();
+        let order_id = book.place_order(limit(&security, Side::Buy, 99, 10)).unwrap().order_id();
+        book.set_journal(Journal::open(dir.join("book.journal")).unwrap().with_rotation(1));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dividend = CorporateAction::new(1, "XS0000000001", CorporateActionKind::CashDividend { amount_per_share: 2 }, 0);
+        assert!(matches!(book.apply_corporate_action(&dividend, OpenOrderPolicy::Reprice), Err(OrderbookError::JournalWrite(_))));
+        let split = CorporateAction::new(2, "XS0000000001", CorporateActionKind::Split { numerator: 2, denominator: 1 }, 0);
+        assert!(book.apply_corporate_action(&split, OpenOrderPolicy::Reprice).is_err());
+        assert!(book.delist().is_err());
+        let order = book.order(order_id).unwrap();
+        assert_eq!((order.order_limit(), order.remaining()), (Some(99), 10));
+    }
+
+    #[test]
+    fn a_recovered_book_keeps_the_dividend_adjustments() {
+        let dir = journal_dir("dividend-journal");
+        let path = dir.join("book.journal");
+        let (security, mut book) = book();
+        book.set_journal(Journal::open(&path).unwrap());
+        book.place_order(limit(&security, Side::Buy, 99, 10)).unwrap();
+        book.place_order(limit(&security, Side::Buy, 2, 10)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 101, 10)).unwrap();
+        let other = CorporateAction::new(1, "XS0000000002", CorporateActionKind::CashDividend { amount_per_share: 2 }, 0);
+        assert_eq!(book.apply_corporate_action(&other, OpenOrderPolicy::Reprice), Err(OrderbookError::WrongSecurity));
+        let dividend = CorporateAction::new(2, "XS0000000001", CorporateActionKind::CashDividend { amount_per_share: 2 }, 0);
+        let adjusted = book.apply_corporate_action(&dividend, OpenOrderPolicy::Reprice).unwrap();
+        assert_eq!((adjusted.repriced(), adjusted.cancelled()), (&[1, 3][..], &[2][..]));
+        let adjusted_book = book.snapshot();
+        drop(book);
+
+        let recovered = Orderbook::recover(security, 100, &path).unwrap();
+        assert_eq!(recovered.snapshot(), adjusted_book);
+        assert_eq!((recovered.best_bid(), recovered.best_ask()), (Some(97), Some(99)));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_invalid_amendment_is_not_journaled() {
+        let dir = journal_dir("amend-journal");
+        let security = Arc::new(Security::new("XS0000000001", "TEST").with_lot_size(10));
+        let mut book = Orderbook::new(security.clone(), 100);
+        book.set_journal(Journal::open(dir.join("book.journal")).unwrap());
+        let order_id = book.place_order(limit(&security, Side::Buy, 99, 10)).unwrap().order_id();
+        let entries = book.journal().unwrap().entries();
+
+        let refused = book.amend_order(order_id, Some(Price(99)), Qty(15)).unwrap_err();
+        assert_eq!(refused, OrderbookError::QuantityNotInLots { quantity: 15, lot_size: 10 });
+        assert_eq!(book.journal().unwrap().entries(), entries);
+        book.amend_order(order_id, Some(Price(98)), Qty(20)).unwrap();
+        assert_eq!(book.journal().unwrap().entries(), entries + 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_snapshot_and_the_journal_after_it_rebuild_the_book() {
+        let dir = journal_dir("snapshot-journal");
+        let path = dir.join("book.journal");
+        let (security, mut book) = book();
+        book.set_journal(Journal::open(&path).unwrap());
+        let bands = PriceBands::new(50).with_static_band(1_000);
+        book.set_price_bands(bands).unwrap();
+        book.set_oco_policy(OcoPolicy::ReduceProportionally).unwrap();
+        book.set_account_self_trade_policy(7, SelfTradePolicy::CancelBoth).unwrap();
+        book.place_order(limit(&security, Side::Sell, 100, 10)).unwrap();
+        book.place_order(limit(&security, Side::Buy, 100, 4)).unwrap();
+        let snapshot = book.snapshot();
+        book.place_order(limit(&security, Side::Buy, 100, 3)).unwrap();
+        book.bust_trade(1).unwrap();
+        drop(book);
+
+        let recovered = Orderbook::recover_from_snapshot(security.clone(), &snapshot, &path).unwrap();
+        let replayed = Orderbook::recover(security, 100, &path).unwrap();
+        assert_eq!(recovered.snapshot(), replayed.snapshot());
+        assert_eq!(recovered.price_bands(), bands);
+        assert_eq!(recovered.self_trade_policy(7), SelfTradePolicy::CancelBoth);
+        let trade_ids: Vec<u64> = recovered.executions().iter().map(Execution::trade_id).collect();
+        assert_eq!(trade_ids, vec![1, 2]);
+        assert_eq!(recovered.bustable_trade(1), Err(OrderbookError::TradeAlreadyBusted(1)));
+        assert_eq!(recovered.stats(), replayed.stats());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_delisted_book_is_recovered_without_its_orders() {
+        let dir = journal_dir("delist-journal");
+        let path = dir.join("book.journal");
+        let (security, mut book) = book();
+        book.set_journal(Journal::open(&path).unwrap());
+        book.place_order(limit(&security, Side::Sell, 101, 10)).unwrap();
+        book.place_order(limit(&security, Side::Buy, 99, 5)).unwrap();
+        let snapshot = book.snapshot();
+        book.place_order(limit(&security, Side::Buy, 98, 1)).unwrap();
+        assert_eq!(book.delist().unwrap(), vec![1, 2, 3]);
+        let delisted = book.snapshot();
+        drop(book);
+
+        let recovered = Orderbook::recover_from_snapshot(security.clone(), &snapshot, &path).unwrap();
+        let replayed = Orderbook::recover(security, 100, &path).unwrap();
+        for book in [&recovered, &replayed] {
+            assert_eq!(book.snapshot().order_count(), 0);
+            assert_eq!((book.best_bid(), book.best_ask()), (None, None));
+        }
+        assert_eq!(replayed.snapshot(), delisted);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn every_order_gets_an_id_of_its_own() {
+        let (security, mut book) = book();
+        let mut order_ids = Vec::new();
+        for price in [95, 96, 97, 105, 104] {
+            let side = if price < 100 { Side::Buy } else { Side::Sell };
+            order_ids.push(book.place_order(limit(&security, side, price, 10)).unwrap().order_id());
+        }
+
+        let mut distinct = order_ids.clone();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert_eq!(distinct.len(), order_ids.len());
+        for (order_id, price) in order_ids.iter().zip([95, 96, 97, 105, 104]) {
+            assert_eq!(book.order(*order_id).and_then(Order::order_limit), Some(price));
+        }
+    }
+
+    struct SameId;
+
+    impl OrderIdGenerator for SameId {
+        fn next_order_id(&mut self) -> i64 {
+            1
+        }
+    }
+
+    #[test]
+    fn an_id_in_use_is_not_handed_out_again() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::with_order_ids(security.clone(), 100, Box::new(SameId));
+        book.place_order(limit(&security, Side::Buy, 95, 10)).unwrap();
+
+        let refused = book.place_order(limit(&security, Side::Buy, 96, 10)).unwrap_err();
+        assert_eq!(refused, OrderbookError::DuplicateOrderId(1));
+        assert_eq!(book.order(OrderId::from_raw(1)).and_then(Order::order_limit), Some(95));
+        assert_eq!(book.best_bid(), Some(95));
+    }
+
+    // best and worst bid, best and worst ask
+    fn extremes(book: &Orderbook) -> (Option<i64>, Option<i64>, Option<i64>, Option<i64>) {
+        (book.best_bid(), book.bid_levels().last().map(|level| level.price()), book.best_ask(), book.ask_levels().last().map(|level| level.price()))
+    }
+
+    #[test]
+    fn the_first_order_of_a_side_is_its_best_and_worst_price() {
+        for asks_first in [false, true] {
+            let (security, mut book) = book();
+            let (bid, ask) = (limit(&security, Side::Buy, 98, 10), limit(&security, Side::Sell, 102, 10));
+            let (first, second) = if asks_first { (ask, bid) } else { (bid, ask) };
+            book.place_order(first).unwrap();
+            book.place_order(second).unwrap();
+            assert_eq!(extremes(&book), (Some(98), Some(98), Some(102), Some(102)));
+
+            book.place_order(limit(&security, Side::Buy, 97, 10)).unwrap();
+            book.place_order(limit(&security, Side::Sell, 103, 10)).unwrap();
+            assert_eq!(extremes(&book), (Some(98), Some(97), Some(102), Some(103)));
+            assert_eq!(book.check_invariants(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn the_first_ask_of_a_book_trades_against_a_bid() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Buy, 100, 10)).unwrap();
+        let report = book.place_order(limit(&security, Side::Sell, 100, 4)).unwrap();
+
+        assert_eq!(report.filled(), Qty(4));
+        assert_eq!(extremes(&book), (Some(100), Some(100), None, None));
+    }
+
+    #[test]
+    fn bids_below_the_best_bid_are_laddered_by_price() {
+        let (security, mut book) = book();
+        for price in [100, 95, 98] { book.place_order(limit(&security, Side::Buy, price, 10)).unwrap(); }
+
+        let depth = book.depth(10);
+        let prices: Vec<i64> = depth.bids().iter().map(|level| level.price().get()).collect();
+        assert_eq!(prices, vec![100, 98, 95]);
+        let report = book.place_order(limit(&security, Side::Sell, 95, 30)).unwrap();
+        let fills: Vec<i64> = report.executions().iter().map(Execution::price).collect();
+        assert_eq!(fills, vec![100, 98, 95]);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_marketable_bid_trades_before_it_rests() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 101, 5)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 105, 5)).unwrap();
+        book.place_order(limit(&security, Side::Buy, 99, 5)).unwrap();
+
+        let report = book.place_order(limit(&security, Side::Buy, 103, 8)).unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(5), Qty(3)));
+        assert_eq!(report.executions()[0].price(), 101);
+        assert_eq!((book.best_bid(), book.best_ask()), (Some(103), Some(105)));
+        assert!(book.best_bid() < book.best_ask());
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn emptied_levels_leave_the_book() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 101, 5)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 102, 5)).unwrap();
+        let worst = book.place_order(limit(&security, Side::Sell, 103, 5)).unwrap().order_id();
+
+        book.place_order(limit(&security, Side::Buy, 101, 5)).unwrap();
+        assert_eq!(extremes(&book).2, Some(102));
+        book.cancel_order(worst, None).unwrap();
+        assert_eq!(extremes(&book), (None, None, Some(102), Some(102)));
+        assert_eq!((book.sell_levels.len(), book.buy_levels.len()), (1, 0));
+        assert_eq!(book.check_invariants(), Ok(()));
+
+        // the check finds a level nothing rests at
+        book.sell_levels.insert(110, PriceLevel::default());
+        assert!(book.check_invariants().is_err());
+    }
+
+    #[test]
+    fn rejections_come_as_typed_errors() {
+        let (security, mut book) = book();
+        let no_amount = OrderBuilder::new(Side::Buy, &security).limit(Price(100)).quantity(Qty(0)).build().unwrap_err();
+        assert_eq!(no_amount, OrderbookError::InvalidAmount);
+        assert_eq!(no_amount.to_string(), "Order amount must be greater than zero");
+        let no_limit = OrderBuilder::new(Side::Buy, &security).limit(Price(0)).quantity(Qty(1)).build().unwrap_err();
+        assert_eq!(no_limit, OrderbookError::InvalidLimit);
+
+        let other = Arc::new(Security::new("XS0000000002", "OTHER"));
+        assert_eq!(book.place_order(limit(&other, Side::Buy, 100, 1)).unwrap_err(), OrderbookError::WrongSecurity);
+        assert_eq!(book.cancel_order(OrderId::from_raw(42), None), Err(OrderbookError::UnknownOrder(42)));
+        assert_eq!(OrderbookError::UnknownOrder(42).to_string(), "Order 42 does not exist or is already filled");
+    }
+
+    fn with_minimum(security: &Arc<Security>, quantity: i64, min_quantity: i64) -> Result<Order, OrderbookError> {
+        OrderBuilder::new(Side::Buy, security).limit(Price(100)).quantity(Qty(quantity)).min_quantity(Qty(min_quantity)).build()
+    }
+
+    #[test]
+    fn an_order_takes_no_fill_below_its_minimum_quantity() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 100, 4)).unwrap();
+
+        let report = book.place_order(with_minimum(&security, 10, 5).unwrap()).unwrap();
+        assert_eq!((report.filled(), report.state()), (Qty(0), OrderState::Killed));
+        assert_eq!(book.ask_levels().next().map(|level| level.quantity()), Some(4));
+
+        let report = book.place_order(with_minimum(&security, 10, 4).unwrap()).unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(4), Qty(6)));
+        // resting, it takes fills of any size
+        book.place_order(limit(&security, Side::Sell, 100, 1)).unwrap();
+        assert_eq!(book.order(report.order_id()).map(Order::amount_executed), Some(5));
+
+        let refused = with_minimum(&security, 10, 11).unwrap_err();
+        assert_eq!(refused, OrderbookError::InvalidMinQuantity { min_quantity: 11, amount: 10 });
+    }
+
+    fn top(book: &Orderbook) -> (Option<i64>, Option<i64>, Option<i64>, Option<i64>, i64) {
+        (book.best_bid(), book.best_ask(), book.spread(), book.mid_price(), book.last_price())
+    }
+
+    #[test]
+    fn the_top_of_the_book_is_none_for_an_empty_side() {
+        let (security, mut book) = book();
+        assert_eq!(top(&book), (None, None, None, None, 100));
+
+        book.place_order(limit(&security, Side::Buy, 98, 10)).unwrap();
+        assert_eq!(top(&book), (Some(98), None, None, None, 100));
+
+        book.place_order(limit(&security, Side::Sell, 103, 10)).unwrap();
+        assert_eq!(top(&book), (Some(98), Some(103), Some(5), Some(100), 100));
+
+        book.place_order(limit(&security, Side::Sell, 98, 10)).unwrap();
+        assert_eq!(top(&book), (None, Some(103), None, None, 98));
+    }
+
+    #[test]
+    fn the_market_collar_stops_a_sweep_after_two_levels() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let sweep = |config: OrderbookConfig, remainder| {
+            let mut book = Orderbook::new(security.clone(), 100);
+            book.set_config(config).unwrap();
+            for price in 100..104 {
+                book.place_order(OrderBuilder::new(Side::Sell, &security).limit(Price(price)).quantity(Qty(5)).build().unwrap()).unwrap();
+            }
+            let report = book.place_order(OrderBuilder::new(Side::Buy, &security).quantity(Qty(20)).market_remainder(remainder).build().unwrap()).unwrap();
+            (book, report)
+        };
+        let levels = |book: &Orderbook| {
+            let depth = book.depth(usize::MAX);
+            let side = |levels: &[DepthLevel]| levels.iter().map(|level| (level.price().get(), level.quantity().get())).collect::<Vec<_>>();
+            (side(depth.bids()), side(depth.asks()))
+        };
+
+        // 150 basis points above the last price of 100 is 101.5
+        let collared = OrderbookConfig::new().with_market_collar(150);
+        let (book, report) = sweep(collared, MarketRemainder::CancelRemainder);
+        assert_eq!(report.filled(), Qty(10));
+        assert_eq!(book.executions().iter().map(Execution::price).collect::<Vec<_>>(), vec![100, 101]);
+        assert_eq!(levels(&book), (vec![], vec![(102, 5), (103, 5)]));
+
+        let (book, report) = sweep(collared, MarketRemainder::ConvertToLimit);
+        assert_eq!(report.filled(), Qty(10));
+        assert_eq!(levels(&book), (vec![(101, 10)], vec![(102, 5), (103, 5)]));
+
+        let (book, report) = sweep(OrderbookConfig::new(), MarketRemainder::CancelRemainder);
+        assert_eq!(report.filled(), Qty(20));
+        assert_eq!(levels(&book), (vec![], vec![]));
+    }
+
+    #[test]
+    fn a_sized_book_places_resting_orders_without_allocating() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let config = OrderbookConfig::new().with_expected_orders(2_048).with_expected_levels_per_side(16);
+        let mut book = Orderbook::with_config(security.clone(), 100, config, Box::new(crate::matching::clock::ManualClock::new(0)));
+        // the levels are there before the session, like the ones a busy book quotes all day
+        for price in 90..100 {
+            book.place_order(limit(&security, Side::Buy, price, 1)).unwrap();
+            book.place_order(limit(&security, Side::Sell, price + 11, 1)).unwrap();
+        }
+
+        let orders: Vec<Order> = (0..2_000).map(|i| limit(&security, [Side::Buy, Side::Sell][i % 2], [95, 105][i % 2], 1)).collect();
+        let (placed, count) = allocations(|| {
+            let mut placed = 0;
+            for order in orders {
+                if book.place_order(order).is_ok() { placed += 1; }
+            }
+            placed
+        });
+        assert_eq!(placed, 2_000);
+        assert_eq!(count, 0);
+        assert_eq!(allocations(|| Vec::<u8>::with_capacity(1)).1, 1);
+
+        // a burst beyond what the config expected
+        book.reserve_additional(2_000);
+        let orders: Vec<Order> = (0..2_000).map(|_| limit(&security, Side::Buy, 99, 1)).collect();
+        let (_, count) = allocations(|| orders.into_iter().for_each(|order| { book.place_order(order).unwrap(); }));
+        assert_eq!(count, 0);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_rejected_order_does_not_allocate() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let other = Arc::new(Security::new("XS0000000002", "OTHER"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        book.set_time(100).unwrap();
+        book.place_order(limit(&security, Side::Sell, 105, 10)).unwrap();
+        let rejected = |i: usize| match i % 4 {
+            0 => OrderBuilder::new(Side::Buy, &security).limit(Price(100)).quantity(Qty(1)).expires_at(50).build().unwrap(),
+            1 => OrderBuilder::new(Side::Buy, &security).limit(Price(105)).quantity(Qty(1)).post_only(PostOnlyPolicy::Reject).build().unwrap(),
+            2 => limit(&other, Side::Buy, 100, 1),
+            _ => OrderBuilder::new(Side::Buy, &security).limit(Price(100)).quantity(Qty(1)).account(1).reduce_only().build().unwrap(),
+        };
+        let orders: Vec<Order> = (0..1_000).map(rejected).collect();
+        let mut errors = Vec::with_capacity(2_000);
+
+        let (_, count) = allocations(|| {
+            for order in orders {
+                errors.push(book.place_order(order).unwrap_err());
+            }
+            for _ in 0..1_000 {
+                errors.push(book.cancel_order(OrderId::from_raw(999), None).unwrap_err());
+            }
+        });
+        assert_eq!(count, 0);
+        assert_eq!(errors.len(), 2_000);
+        assert_eq!(errors[0], OrderbookError::OrderExpired);
+        assert_eq!(errors[1], OrderbookError::RejectedPostOnlyWouldCross { limit: 105, best_opposite: 105 });
+        assert_eq!(errors[2], OrderbookError::WrongSecurity);
+        assert_eq!(errors[3], OrderbookError::ReduceOnlyUnavailable);
+        assert_eq!(errors[1_999], OrderbookError::UnknownOrder(999));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_split_and_its_reverse_restore_the_book() {
+        let dir = journal_dir("split-journal");
+        let path = dir.join("book.journal");
+        let (security, mut book) = book();
+        book.set_journal(Journal::open(&path).unwrap());
+        for (side, price, quantity) in [(Side::Buy, 98, 10), (Side::Buy, 96, 4), (Side::Buy, 99, 3), (Side::Sell, 102, 7), (Side::Sell, 104, 5)] {
+            book.place_order(limit(&security, side, price, quantity)).unwrap();
+        }
+        let before = book.depth(10);
+
+        let split = CorporateAction::new(1, "XS0000000001", CorporateActionKind::Split { numerator: 2, denominator: 1 }, 0);
+        let adjusted = book.apply_corporate_action(&split, OpenOrderPolicy::Reprice).unwrap();
+        assert_eq!((adjusted.repriced().len(), adjusted.cancelled().len()), (5, 0));
+        let levels = |book: &Orderbook| {
+            let depth = book.depth(10);
+            let side = |levels: &[DepthLevel]| levels.iter().map(|level| (level.price().get(), level.quantity().get())).collect::<Vec<_>>();
+            (side(depth.bids()), side(depth.asks()))
+        };
+        // the bid at 99 lands between two ticks and goes down to 49 with the bids at 98
+        assert_eq!(levels(&book), (vec![(49, 26), (48, 8)], vec![(51, 14), (52, 10)]));
+        assert_eq!(book.last_price(), 50);
+
+        let reverse = CorporateAction::new(2, "XS0000000001", CorporateActionKind::Split { numerator: 1, denominator: 2 }, 0);
+        book.apply_corporate_action(&reverse, OpenOrderPolicy::Reprice).unwrap();
+        assert_eq!(book.depth(10).asks(), before.asks());
+        assert_eq!(levels(&book).0, vec![(98, 13), (96, 4)]);
+        assert_eq!(book.last_price(), 100);
+        assert_eq!(book.check_invariants(), Ok(()));
+        let restored = book.snapshot();
+        drop(book);
+
+        let recovered = Orderbook::recover(security, 100, &path).unwrap();
+        assert_eq!(recovered.snapshot(), restored);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_dividend_cancels_the_orders_it_would_take_below_one_tick() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST").with_tick_size(5));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let lowest = book.place_order(limit(&security, Side::Buy, 5, 10)).unwrap().order_id();
+        let second = book.place_order(limit(&security, Side::Buy, 10, 10)).unwrap().order_id();
+        let ask = book.place_order(limit(&security, Side::Sell, 20, 10)).unwrap().order_id();
+
+        // 4 is less than a tick: the buy at one tick would round down to 0, the one at two ticks to
+        // one tick, and the sell rounds back up to where it was
+        let dividend = CorporateAction::new(1, "XS0000000001", CorporateActionKind::CashDividend { amount_per_share: 4 }, 0);
+        let adjusted = book.apply_corporate_action(&dividend, OpenOrderPolicy::Reprice).unwrap();
+        assert_eq!(adjusted.cancelled(), &[lowest.to_raw()][..]);
+        assert_eq!(book.order(second).and_then(Order::order_limit), Some(5));
+        assert_eq!(book.order(ask).and_then(Order::order_limit), Some(20));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_split_keeps_quantities_in_whole_lots() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST").with_lot_size(10));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let iceberg = OrderBuilder::new(Side::Buy, &security).limit(Price(90)).quantity(Qty(100)).display_quantity(Qty(20)).build().unwrap();
+        let iceberg = book.place_order(iceberg).unwrap().order_id();
+        let small = book.place_order(limit(&security, Side::Buy, 80, 10)).unwrap().order_id();
+        book.place_order(limit(&security, Side::Sell, 90, 30)).unwrap();
+
+        // 2 for 3: the 100 of the iceberg become 66.7 and the 30 it traded 20, the 10 of the small
+        // order 6.7
+        let split = CorporateAction::new(1, "XS0000000001", CorporateActionKind::Split { numerator: 2, denominator: 3 }, 0);
+        let adjusted = book.apply_corporate_action(&split, OpenOrderPolicy::Reprice).unwrap();
+        assert_eq!(adjusted.cancelled(), &[small.to_raw()][..]);
+        let order = book.order(iceberg).unwrap();
+        assert_eq!((order.amount_executed(), order.remaining(), order.display_quantity()), (20, 40, Some(10)));
+        assert!(order.validate().is_ok());
+        assert_eq!(book.depth(1).bids()[0].quantity(), Qty(10));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_id_whose_slot_was_recycled_is_unknown() {
+        let (security, mut book) = book();
+        let cancelled = book.place_order(limit(&security, Side::Buy, 90, 10)).unwrap().order_id();
+        book.cancel_order(cancelled, None).unwrap();
+        let filled = book.place_order(limit(&security, Side::Buy, 91, 10)).unwrap().order_id();
+        book.place_order(limit(&security, Side::Sell, 91, 10)).unwrap();
+        let recycled = book.place_order(limit(&security, Side::Buy, 92, 10)).unwrap().order_id();
+
+        for order_id in [cancelled, filled] {
+            assert_eq!(book.cancel_order(order_id, None), Err(OrderbookError::UnknownOrder(order_id.to_raw())));
+            assert_eq!(book.amend_order(order_id, Some(Price(93)), Qty(5)).err(), Some(OrderbookError::UnknownOrder(order_id.to_raw())));
+        }
+        assert_eq!(book.order(recycled).map(|order| order.remaining()), Some(10));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_bid_fills_partly_exactly_or_clears_the_ask_side() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 101, 10)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 102, 10)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 103, 10)).unwrap();
+
+        // partial: the bid is filled, the ask it met keeps the rest
+        let report = book.place_order(limit(&security, Side::Buy, 101, 4)).unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(4), Qty(0)));
+        assert_eq!(book.depth(1).asks()[0].quantity().get(), 6);
+
+        // exact: the bid takes the rest of the level and nothing of it rests
+        let report = book.place_order(limit(&security, Side::Buy, 101, 6)).unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(6), Qty(0)));
+        assert_eq!(book.best_ask(), Some(102));
+        assert_eq!(book.best_bid(), None);
+
+        // sweep: the bid takes both levels left and rests with what they could not fill
+        let report = book.place_order(limit(&security, Side::Buy, 103, 25)).unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(20), Qty(5)));
+        assert_eq!(report.executions().len(), 2);
+        assert!(book.depth(10).asks().is_empty());
+        assert_eq!((book.best_ask(), book.best_bid()), (None, Some(103)));
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn an_ioc_bid_against_a_thin_ask_side_cancels_its_rest() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 101, 3)).unwrap();
+        book.place_order(limit(&security, Side::Sell, 102, 4)).unwrap();
+
+        let bid = OrderBuilder::new(Side::Buy, &security).limit(Price(102)).quantity(Qty(10)).tif(TimeInForce::ImmediateOrCancel).build().unwrap();
+        let report = book.place_order(bid).unwrap();
+
+        assert_eq!((report.filled(), report.remaining(), report.cancelled()), (Qty(7), Qty(0), Qty(3)));
+        assert_eq!(report.state(), OrderState::Cancelled);
+        assert_eq!(book.best_bid(), None);
+        assert!(book.depth(10).bids().is_empty());
+        assert_eq!(book.best_ask(), None);
+    }
+
+    #[test]
+    fn a_post_only_bid_never_takes_the_ask() {
+        let (security, mut book) = book();
+        book.place_order(limit(&security, Side::Sell, 105, 10)).unwrap();
+        let post_only = |price, policy| OrderBuilder::new(Side::Buy, &security).limit(Price(price)).quantity(Qty(10)).post_only(policy).build().unwrap();
+
+        // one tick behind the touch it rests, at the touch or through it it is rejected
+        let resting = book.place_order(post_only(104, PostOnlyPolicy::Reject)).unwrap();
+        assert_eq!((resting.filled(), resting.remaining()), (Qty(0), Qty(10)));
+        for price in [105, 107] {
+            assert_eq!(book.place_order(post_only(price, PostOnlyPolicy::Reject)).err(), Some(OrderbookError::RejectedPostOnlyWouldCross { limit: price, best_opposite: 105 }));
+        }
+
+        // repriced one tick behind the ask, whether it touched or crossed
+        for price in [105, 107] {
+            let report = book.place_order(post_only(price, PostOnlyPolicy::Reprice)).unwrap();
+            assert!(report.executions().is_empty());
+            assert_eq!(book.order(report.order_id()).map(|order| order.order_limit), Some(Some(104)));
+        }
+        assert_eq!(book.depth(1).bids()[0].quantity().get(), 30);
+        assert_eq!(book.depth(1).asks()[0].quantity().get(), 10);
+    }
+
+    #[test]
+    fn an_amend_down_keeps_the_queue_position_and_a_price_change_loses_it() {
+        let (security, mut book) = book();
+        let [first, second, third] = [0; 3].map(|_| book.place_order(limit(&security, Side::Buy, 100, 10)).unwrap().order_id());
+        let position = |book: &Orderbook, order_id| book.queue_position(order_id).map(|position| (position.price(), position.orders_ahead(), position.quantity_ahead()));
+        assert_eq!(position(&book, second), Some((100, 1, 10)));
+
+        book.amend_order(second, Some(Price(100)), Qty(5)).unwrap();
+        assert_eq!(position(&book, second), Some((100, 1, 10)));
+        assert_eq!(position(&book, third), Some((100, 2, 15)));
+
+        // moved away and back, the order queues behind everything at the price
+        book.amend_order(first, Some(Price(99)), Qty(10)).unwrap();
+        assert_eq!(position(&book, first), Some((99, 0, 15)));
+        assert_eq!(position(&book, second), Some((100, 0, 0)));
+        book.amend_order(first, Some(Price(100)), Qty(10)).unwrap();
+        assert_eq!(position(&book, first), Some((100, 2, 15)));
+    }
+
+    struct FixedPosition(i64);
+
+    impl PositionProvider for FixedPosition {
+        fn position(&self, _account_id: u64, _isin: &str) -> i64 {
+            self.0
+        }
+    }
+
+    #[test]
+    fn two_reduce_only_orders_do_not_reduce_a_position_twice() {
+        let (security, mut book) = book();
+        book.set_position_provider(Box::new(FixedPosition(10)));
+        let events = book.subscribe_unbounded();
+        let reduce_only = |quantity| OrderBuilder::new(Side::Sell, &security).limit(Price(101)).quantity(Qty(quantity)).account(1).reduce_only().build().unwrap();
+        let first = book.place_order(reduce_only(6)).unwrap().order_id();
+        let second = book.place_order(reduce_only(6)).unwrap().order_id();
+
+        // the first takes 6 of the position, the second is capped to the 4 left and loses its rest
+        let report = book.place_order(limit(&security, Side::Buy, 101, 12)).unwrap();
+        let amounts: Vec<(i64, i64)> = report.executions().iter().map(|execution| (execution.selling_order_id, execution.amount)).collect();
+        assert_eq!(amounts, [(first.to_raw(), 6), (second.to_raw(), 4)]);
+        assert_eq!((report.filled(), report.remaining()), (Qty(10), Qty(2)));
+        assert!(book.order(second).is_none());
+        let cancelled: Vec<(i64, CancelReason)> = events.try_iter().filter_map(|event| match event {
+            OrderbookEvent::OrderCancelled { order_id, reason, .. } => Some((order_id, reason)),
+            _ => None,
+        }).collect();
+        assert_eq!(cancelled, [(second.to_raw(), CancelReason::ReduceOnly)]);
+        assert_eq!(book.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn a_halt_lets_the_match_in_progress_complete_and_refuses_what_comes_after() {
+        let (security, mut book) = book();
+        for price in 101..=110 { book.place_order(limit(&security, Side::Sell, price, 10)).unwrap(); }
+        let stop = OrderBuilder::new(Side::Buy, &security).stop(Price(105)).quantity(Qty(10)).build().unwrap();
+        let stop_id = book.place_order(stop).unwrap().order_id();
+        let handle = crate::matching::handle::OrderbookHandle::spawn(book);
+
+        // sent ahead of the halt, the sweep and the stop it triggers both trade in full
+        let sweep = handle.send_place_order(limit(&security, Side::Buy, 105, 50));
+        let halted = handle.execute(|book| book.halt(HaltReason::Regulatory));
+        let late = handle.send_place_order(limit(&security, Side::Buy, 110, 10));
+        let resting = handle.send_place_order(limit(&security, Side::Sell, 120, 10));
+
+        let report = sweep.wait().unwrap().unwrap();
+        assert_eq!((report.filled(), report.remaining()), (Qty(50), Qty(0)));
+        assert_eq!(halted.wait().unwrap(), Ok(()));
+        assert_eq!(late.wait().unwrap().err(), Some(OrderbookError::MarketHalted));
+        assert_eq!(resting.wait().unwrap().err(), Some(OrderbookError::MarketHalted));
+
+        let stop_fills = move |book: &mut Orderbook| book.executions().iter().filter(|execution| execution.buying_order_id == stop_id.to_raw()).map(|execution| (execution.price, execution.amount)).collect::<Vec<_>>();
+        let (stop_fills, best_ask, halted) = handle.execute(move |book| (stop_fills(book), book.best_ask(), book.is_halted())).wait().unwrap();
+        assert_eq!((stop_fills, best_ask, halted), (vec![(106, 10)], Some(107), true));
+        assert_eq!(handle.depth(10).unwrap().asks().len(), 4);
+    }
+
+    #[test]
+    fn a_pro_rata_level_allocates_the_whole_aggressor_in_minimum_shares() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let sizes = [60, 30, 10];
+        let fills = |quantity| {
+            let config = OrderbookConfig::default().with_matching_algorithm(MatchingAlgorithm::ProRata { min_allocation: 10 });
+            let mut book = Orderbook::with_config(security.clone(), 100, config, Box::new(crate::matching::clock::ManualClock::new(0)));
+            let resting: Vec<i64> = sizes.iter().map(|&size| book.place_order(limit(&security, Side::Sell, 101, size)).unwrap().order_id().to_raw()).collect();
+            book.place_order(limit(&security, Side::Sell, 102, 50)).unwrap();
+            let report = book.place_order(limit(&security, Side::Buy, 102, quantity)).unwrap();
+            let filled = |order_id| report.executions().iter().filter(|execution| execution.selling_order_id == order_id).map(|execution| execution.amount).sum::<i64>();
+            (report.filled().get(), resting.iter().map(|&order_id| filled(order_id)).collect::<Vec<i64>>())
+        };
+
+        // shares of 30, 15 and 5, the last is below the minimum and goes to the largest order instead
+        assert_eq!(fills(50), (50, vec![35, 15, 0]));
+        assert_eq!(fills(120), (120, vec![60, 30, 10]));
+        for quantity in 1..=100 {
+            let (filled, allocations) = fills(quantity);
+            assert_eq!((filled, allocations.iter().sum::<i64>()), (quantity, quantity));
+            // a share below the minimum only takes what the larger orders had no room left for
+            for (index, &allocation) in allocations.iter().enumerate() {
+                if allocation == 0 || allocation >= 10 { continue; }
+                assert!((0..index).all(|larger| allocations[larger] == sizes[larger]), "{} gets {:?}", quantity, allocations);
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use std::sync::{Arc, Mutex};
+
+// Gives the book read access to the positions held by accounts, which reduce only orders must
+// never increase. Positions are signed, short positions are negative.
+pub trait PositionProvider {
+    fn position(&self, account_id: u64, isin: &str) -> i64;
+}
+
+// Lets the registry the positions come from be shared with the code that updates it.
+impl<T: PositionProvider> PositionProvider for Arc<Mutex<T>> {
+    fn position(&self, account_id: u64, isin: &str) -> i64 {
+        self.lock().map_or(0, |provider| provider.position(account_id, isin))
+    }
+}
@@ -0,0 +1,102 @@
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use super::market_data::DepthSnapshot;
+
+// Holds the latest published depth of a book. Readers bump the reference count of the current
+// snapshot without ever waiting for the book. They announce themselves on the reader count of the
+// current epoch first. Publishing swaps the snapshot in, moves on to the next epoch and waits
+// for the readers of the last one before it lets go of the old snapshot. Readers hold their count
+// for a handful of instructions only.
+struct SnapshotCell {
+    current: AtomicPtr<DepthSnapshot>,
+    epoch: AtomicUsize,
+    readers: [AtomicUsize; 2],
+}
+
+impl SnapshotCell {
+    fn new(snapshot: Arc<DepthSnapshot>) -> Self {
+        SnapshotCell { current: AtomicPtr::new(Arc::into_raw(snapshot) as *mut _), epoch: AtomicUsize::new(0), readers: [AtomicUsize::new(0), AtomicUsize::new(0)] }
+    }
+
+    fn load(&self) -> Arc<DepthSnapshot> {
+        let readers = loop {
+            let epoch = self.epoch.load(Ordering::SeqCst);
+            let readers = &self.readers[epoch & 1];
+            readers.fetch_add(1, Ordering::SeqCst);
+            if self.epoch.load(Ordering::SeqCst) == epoch { break readers; }
+            readers.fetch_sub(1, Ordering::SeqCst);
+        };
+        let current = self.current.load(Ordering::SeqCst);
+        // the publisher keeps the snapshot alive until the readers of this epoch are done
+        let snapshot = unsafe {
+            Arc::increment_strong_count(current);
+            Arc::from_raw(current)
+        };
+        readers.fetch_sub(1, Ordering::SeqCst);
+        snapshot
+    }
+
+    // Only ever called by the one publisher of the cell.
+    fn store(&self, snapshot: Arc<DepthSnapshot>) {
+        let previous = self.current.swap(Arc::into_raw(snapshot) as *mut _, Ordering::SeqCst);
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst);
+        while self.readers[epoch & 1].load(Ordering::SeqCst) != 0 { thread::yield_now(); }
+        drop(unsafe { Arc::from_raw(previous) });
+    }
+}
+
+impl Drop for SnapshotCell {
+    fn drop(&mut self) {
+        let current = std::mem::replace(self.current.get_mut(), ptr::null_mut());
+        drop(unsafe { Arc::from_raw(current) });
+    }
+}
+
+// A view on the latest depth a book published, for threads other than the one matching on it.
+// Every snapshot is one consistent state of the book, all its levels taken at the same sequence,
+// but it is as old as the last call to Orderbook::publish_snapshot.
+#[derive(Clone)]
+pub struct SnapshotReader {
+    cell: Arc<SnapshotCell>,
+}
+
+impl SnapshotReader {
+    pub fn latest(&self) -> Arc<DepthSnapshot> {
+        self.cell.load()
+    }
+}
+
+// The publishing side, owned by the book. With an interval the book publishes by itself after
+// every `interval` changes of its sequence.
+pub(crate) struct SnapshotPublisher {
+    cell: Arc<SnapshotCell>,
+    interval: Option<u64>,
+    published_sequence: u64,
+}
+
+impl SnapshotPublisher {
+    pub(crate) fn new(snapshot: Arc<DepthSnapshot>) -> Self {
+        let published_sequence = snapshot.sequence();
+        SnapshotPublisher { cell: Arc::new(SnapshotCell::new(snapshot)), interval: None, published_sequence }
+    }
+
+    pub(crate) fn reader(&self) -> SnapshotReader {
+        SnapshotReader { cell: self.cell.clone() }
+    }
+
+    pub(crate) fn publish(&mut self, snapshot: Arc<DepthSnapshot>) {
+        self.published_sequence = snapshot.sequence();
+        self.cell.store(snapshot);
+    }
+
+    pub(crate) fn set_interval(&mut self, interval: Option<u64>) {
+        self.interval = interval.filter(|&interval| interval > 0);
+    }
+
+    pub(crate) fn is_due(&self, sequence: u64) -> bool {
+        self.interval.is_some_and(|interval| sequence.wrapping_sub(self.published_sequence) >= interval)
+    }
+}
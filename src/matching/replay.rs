@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::sync::mpsc::Receiver;
+
+use super::error::OrderbookError;
+use super::events::OrderbookEvent;
+use super::orderbook::{Order, OrderReport, Orderbook};
+use super::units::{OrderId, Price, Qty};
+
+// One recorded command. Every command carries the time it was given at, the session moves the
+// clock of the book to it before the command is applied.
+#[derive(Clone, Debug)]
+pub enum Command {
+    // the book is chosen by the security of the order
+    Place { timestamp: u64, order: Box<Order> },
+    // on behalf of the account, see Orderbook::cancel_order
+    Cancel { timestamp: u64, isin: String, order_id: OrderId, account_id: Option<u64> },
+    Amend { timestamp: u64, isin: String, order_id: OrderId, new_limit: Option<Price>, new_amount: Qty },
+}
+
+impl Command {
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Command::Place { timestamp, .. } => *timestamp,
+            Command::Cancel { timestamp, .. } => *timestamp,
+            Command::Amend { timestamp, .. } => *timestamp,
+        }
+    }
+
+    pub fn isin(&self) -> &str {
+        match self {
+            Command::Place { order, .. } => &order.security().isin,
+            Command::Cancel { isin, .. } => isin,
+            Command::Amend { isin, .. } => isin,
+        }
+    }
+}
+
+// What the book answered to a command.
+#[derive(Clone, Debug)]
+pub enum ReplayOutcome {
+    Placed(Result<OrderReport, OrderbookError>),
+    Cancelled(Result<(), OrderbookError>),
+    Amended(Result<OrderReport, OrderbookError>),
+    // no book of the session trades the security, the command was skipped
+    UnknownSecurity,
+}
+
+#[derive(Clone, Debug)]
+pub struct ReplayStep {
+    pub(crate) index: usize,
+    pub(crate) timestamp: u64,
+    pub(crate) isin: String,
+    pub(crate) outcome: ReplayOutcome,
+}
+
+impl ReplayStep {
+    // position of the command in the log, counting from zero
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn outcome(&self) -> &ReplayOutcome {
+        &self.outcome
+    }
+}
+
+struct ReplayBook {
+    book: Orderbook,
+    receiver: Receiver<OrderbookEvent>,
+    events: Vec<OrderbookEvent>,
+}
+
+// Drives books through a recorded command log, one command at a time. The books have no clock
+// and no randomness of their own, so replaying the same log against books in the same starting
+// state gives the same snapshots and event streams every time. Between steps the books can be
+// inspected freely.
+pub struct ReplaySession {
+    commands: Peekable<Box<dyn Iterator<Item = Command>>>,
+    books: BTreeMap<String, ReplayBook>,
+    steps: usize,
+}
+
+impl ReplaySession {
+    pub fn new(commands: impl Iterator<Item = Command> + 'static) -> ReplaySession {
+        let commands: Box<dyn Iterator<Item = Command>> = Box::new(commands);
+        ReplaySession {
+            commands: commands.peekable(),
+            books: BTreeMap::new(),
+            steps: 0,
+        }
+    }
+
+    // Adds a book for the commands on its security and starts recording its events. A book added
+    // for a security that already has one replaces it.
+    pub fn add_book(&mut self, mut book: Orderbook) {
+        let receiver = book.subscribe_unbounded();
+        let isin = book.security().isin.clone();
+        self.books.insert(isin, ReplayBook { book, receiver, events: Vec::new() });
+    }
+
+    pub fn with_book(mut self, book: Orderbook) -> ReplaySession {
+        self.add_book(book);
+        self
+    }
+
+    // Applies the next command of the log, or returns None once the log is exhausted.
+    pub fn step_one(&mut self) -> Option<ReplayStep> {
+        let command = self.commands.next()?;
+        let index = self.steps;
+        self.steps += 1;
+        let timestamp = command.timestamp();
+        let isin = command.isin().to_string();
+        let outcome = match self.books.get_mut(&isin) {
+            Some(replay_book) => {
+                let book = &mut replay_book.book;
+                // a book that cannot journal the time of the command does not run it either
+                let outcome = match command {
+                    Command::Place { order, .. } => ReplayOutcome::Placed(book.set_time(timestamp).and_then(|_| book.place_order(*order))),
+                    Command::Cancel { order_id, account_id, .. } => ReplayOutcome::Cancelled(book.set_time(timestamp).and_then(|_| book.cancel_order(order_id, account_id))),
+                    Command::Amend { order_id, new_limit, new_amount, .. } => ReplayOutcome::Amended(book.set_time(timestamp).and_then(|_| book.amend_order(order_id, new_limit, new_amount))),
+                };
+                replay_book.events.extend(replay_book.receiver.try_iter());
+                outcome
+            },
+            None => ReplayOutcome::UnknownSecurity,
+        };
+        Some(ReplayStep { index, timestamp, isin, outcome })
+    }
+
+    // Applies every command with a timestamp up to and including `timestamp` and returns what the
+    // books answered. The log is expected in time order, replay stops at the first later command.
+    pub fn advance_until(&mut self, timestamp: u64) -> Vec<ReplayStep> {
+        let mut steps = Vec::new();
+        while self.commands.peek().is_some_and(|command| command.timestamp() <= timestamp) {
+            if let Some(step) = self.step_one() {
+                steps.push(step);
+            }
+        }
+        steps
+    }
+
+    // Applies the rest of the log.
+    pub fn run_to_end(&mut self) -> Vec<ReplayStep> {
+        let mut steps = Vec::new();
+        while let Some(step) = self.step_one() {
+            steps.push(step);
+        }
+        steps
+    }
+
+    // timestamp of the command the next step applies
+    pub fn next_timestamp(&mut self) -> Option<u64> {
+        self.commands.peek().map(Command::timestamp)
+    }
+
+    pub fn is_finished(&mut self) -> bool {
+        self.commands.peek().is_none()
+    }
+
+    // number of commands applied so far
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn book(&self, isin: &str) -> Option<&Orderbook> {
+        self.books.get(isin).map(|replay_book| &replay_book.book)
+    }
+
+    // every event the book published since it was added, in sequence order
+    pub fn events(&self, isin: &str) -> &[OrderbookEvent] {
+        self.books.get(isin).map(|replay_book| replay_book.events.as_slice()).unwrap_or(&[])
+    }
+
+    // Ends the session and hands back the books.
+    pub fn into_books(self) -> Vec<Orderbook> {
+        self.books.into_values().map(|replay_book| replay_book.book).collect()
+    }
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::io;
+#[cfg(feature = "serde")]
+use std::io::Write;
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// What clearing needs to know about one trade.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SettlementInstruction {
+    pub(crate) trade_id: u64,
+    pub(crate) isin: String,
+    pub(crate) buyer_account: Option<u64>,
+    pub(crate) seller_account: Option<u64>,
+    pub(crate) buying_order_id: i64,
+    pub(crate) selling_order_id: i64,
+    pub(crate) quantity: i64,
+    pub(crate) price: i64,
+    pub(crate) buyer_fee: i64,
+    pub(crate) seller_fee: i64,
+    pub(crate) timestamp: u64,
+}
+
+impl SettlementInstruction {
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn buyer_account(&self) -> Option<u64> {
+        self.buyer_account
+    }
+
+    pub fn seller_account(&self) -> Option<u64> {
+        self.seller_account
+    }
+
+    pub fn buying_order_id(&self) -> i64 {
+        self.buying_order_id
+    }
+
+    pub fn selling_order_id(&self) -> i64 {
+        self.selling_order_id
+    }
+
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn buyer_fee(&self) -> i64 {
+        self.buyer_fee
+    }
+
+    pub fn seller_fee(&self) -> i64 {
+        self.seller_fee
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+#[derive(Debug)]
+pub enum SinkError {
+    Io(io::Error),
+    // the downstream system refused the batch or cannot be reached right now
+    Unavailable(String),
+}
+
+impl fmt::Display for SinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SinkError::Io(error) => write!(f, "Execution sink i/o failed: {}", error),
+            SinkError::Unavailable(reason) => write!(f, "Execution sink unavailable: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+impl From<io::Error> for SinkError {
+    fn from(error: io::Error) -> Self {
+        SinkError::Io(error)
+    }
+}
+
+// Receives the trades of every call that matched, as one batch in execution order. A batch the
+// sink fails to publish stays with the book and is offered again, together with the trades of
+// later calls, until publishing succeeds, so a sink sees every trade at least once. A sink that
+// fails halfway through a batch sees its first part again, the trade id tells repeats apart.
+pub trait ExecutionSink {
+    fn publish(&mut self, batch: &[SettlementInstruction]) -> Result<(), SinkError>;
+}
+
+// Lets the caller keep a handle on a sink that is owned by the book.
+impl<T: ExecutionSink> ExecutionSink for Arc<Mutex<T>> {
+    fn publish(&mut self, batch: &[SettlementInstruction]) -> Result<(), SinkError> {
+        match self.lock() {
+            Ok(mut sink) => sink.publish(batch),
+            Err(_) => Err(SinkError::Unavailable("sink lock poisoned".to_string())),
+        }
+    }
+}
+
+// Keeps every published batch in memory. A sink set unavailable fails every publish, to try
+// what the book does when clearing is down.
+#[derive(Clone, Debug, Default)]
+pub struct VecSink {
+    batches: Vec<Vec<SettlementInstruction>>,
+    unavailable: bool,
+}
+
+impl VecSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn batches(&self) -> &[Vec<SettlementInstruction>] {
+        &self.batches
+    }
+
+    pub fn instructions(&self) -> impl Iterator<Item = &SettlementInstruction> {
+        self.batches.iter().flatten()
+    }
+
+    pub fn set_unavailable(&mut self, unavailable: bool) {
+        self.unavailable = unavailable;
+    }
+
+    pub fn clear(&mut self) {
+        self.batches.clear();
+    }
+}
+
+impl ExecutionSink for VecSink {
+    fn publish(&mut self, batch: &[SettlementInstruction]) -> Result<(), SinkError> {
+        if self.unavailable { return Err(SinkError::Unavailable("sink set unavailable".to_string())); }
+        self.batches.push(batch.to_vec());
+        Ok(())
+    }
+}
+
+// Appends one json object per instruction and line, and flushes after every batch.
+#[cfg(feature = "serde")]
+pub struct FileSink {
+    file: io::BufWriter<std::fs::File>,
+}
+
+#[cfg(feature = "serde")]
+impl FileSink {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<FileSink> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(FileSink { file: io::BufWriter::new(file) })
+    }
+}
+
+#[cfg(feature = "serde")]
+impl ExecutionSink for FileSink {
+    fn publish(&mut self, batch: &[SettlementInstruction]) -> Result<(), SinkError> {
+        for instruction in batch {
+            serde_json::to_writer(&mut self.file, instruction).map_err(io::Error::from)?;
+            self.file.write_all(b"\n")?;
+        }
+        self.file.flush()?;
+        Ok(())
+    }
+}
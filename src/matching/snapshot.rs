@@ -0,0 +1,298 @@
+use super::market_data::SessionStats;
+use super::orderbook::{Execution, Halt, MarketRemainder, OcoPolicy, OrderbookConfig, PostOnlyPolicy, PriceBands, SelfTradePolicy, SessionState, Side, TimeInForce};
+use super::trade_tape::Trade;
+
+// The full matching state of a book at one point in time. Orders are listed in matching priority:
+// bids and asks best level first and in queue order within a level, parked market orders and
+// stops in the order of their queues. The settings the journal records and the trades a bust may
+// reverse are part of it, so replaying the journal after a snapshot ends in the same book as
+// replaying all of it. Candles and twap samples are market data and not part of a snapshot. Like
+// every data type of the crate it serializes with the field names as declared, which are kept
+// stable.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookSnapshot {
+    pub(crate) isin: String,
+    pub(crate) starting_price: i64,
+    pub(crate) current_market_price: i64,
+    pub(crate) sequence: u64,
+    pub(crate) current_time: u64,
+    pub(crate) last_order_id: i64,
+    pub(crate) next_oco_link_id: i64,
+    pub(crate) bids: Vec<SnapshotOrder>,
+    pub(crate) asks: Vec<SnapshotOrder>,
+    pub(crate) market_orders: Vec<SnapshotOrder>,
+    pub(crate) stop_orders: Vec<SnapshotOrder>,
+    // midpoint orders by side, buys first, each in time priority
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) midpoint_orders: Vec<SnapshotOrder>,
+    // pegged orders in the order they are re-priced
+    pub(crate) pegged_orders: Vec<i64>,
+    pub(crate) oco_links: Vec<SnapshotOcoLink>,
+    // commands in the journal when the snapshot was taken, recovery replays the ones after them
+    pub(crate) journal_entries: u64,
+    // snapshots taken before books had session states restore into continuous trading
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) session_state: SessionState,
+    // the starting price for snapshots taken before books had price bands
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) band_reference: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) interruption_ends_at: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) halt: Option<Halt>,
+    // the quotes of accounts by the current quote id, the ids of snapshots taken before books had
+    // quotes start at 1
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) next_quote_id: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) quotes: Vec<SnapshotQuote>,
+    // snapshots taken before they held the settings of the book restore the defaults
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) config: OrderbookConfig,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) price_bands: PriceBands,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) dynamic_reference: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) oco_policy: OcoPolicy,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) max_stop_limit_gap: Option<i64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) self_trade_policy: SelfTradePolicy,
+    // by account id
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) account_self_trade_policies: Vec<(u64, SelfTradePolicy)>,
+    // the statistics of the session, started afresh for snapshots taken before they held them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) stats: Option<SessionStats>,
+    // every execution of the book, busted and off-book trades by id
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) executions: Vec<Execution>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) busted_trades: Vec<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) unpriced_trades: Vec<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) session_start_trade: u64,
+    // limit orders filled in this session by id, a bust re-opens them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) filled_orders: Vec<SnapshotOrder>,
+    // the trades the tape retains, oldest first, and the id of the last trade of the book
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) trades: Vec<Trade>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) last_trade_id: u64,
+}
+
+impl BookSnapshot {
+    pub fn isin(&self) -> &str {
+        &self.isin
+    }
+
+    pub fn starting_price(&self) -> i64 {
+        self.starting_price
+    }
+
+    pub fn current_market_price(&self) -> i64 {
+        self.current_market_price
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub fn current_time(&self) -> u64 {
+        self.current_time
+    }
+
+    pub fn bids(&self) -> &[SnapshotOrder] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[SnapshotOrder] {
+        &self.asks
+    }
+
+    pub fn market_orders(&self) -> &[SnapshotOrder] {
+        &self.market_orders
+    }
+
+    pub fn stop_orders(&self) -> &[SnapshotOrder] {
+        &self.stop_orders
+    }
+
+    pub fn journal_entries(&self) -> u64 {
+        self.journal_entries
+    }
+
+    pub fn session_state(&self) -> SessionState {
+        self.session_state
+    }
+
+    pub fn order_count(&self) -> usize {
+        self.bids.len() + self.asks.len() + self.market_orders.len() + self.stop_orders.len() + self.midpoint_orders.len()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl BookSnapshot {
+    pub fn to_json(&self) -> Result<String, serde_json::Error> {
+        serde_json::to_string(self)
+    }
+
+    pub fn from_json(json: &str) -> Result<BookSnapshot, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+
+    // compact binary form, for fast restarts
+    pub fn to_bytes(&self) -> Result<Vec<u8>, bincode::Error> {
+        bincode::serialize(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<BookSnapshot, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+}
+
+// A resting order with everything matching needs to continue with it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotOrder {
+    pub(crate) order_id: i64,
+    pub(crate) side: Side,
+    pub(crate) order_limit: Option<i64>,
+    pub(crate) stop_price: Option<i64>,
+    pub(crate) trailing_offset: Option<i64>,
+    pub(crate) peg_offset: Option<i64>,
+    pub(crate) peg_cap: Option<i64>,
+    pub(crate) min_quantity: Option<i64>,
+    pub(crate) market_remainder: MarketRemainder,
+    pub(crate) account_id: Option<u64>,
+    pub(crate) reduce_only: bool,
+    pub(crate) timestamp: u64,
+    pub(crate) triggered: bool,
+    pub(crate) display_quantity: Option<i64>,
+    pub(crate) displayed: i64,
+    pub(crate) post_only: Option<PostOnlyPolicy>,
+    pub(crate) expires_at: Option<u64>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) continuous_only: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) midpoint: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub(crate) client_order_id: Option<u64>,
+    pub(crate) amount: i64,
+    pub(crate) amount_executed: i64,
+    pub(crate) time_in_force: TimeInForce,
+}
+
+impl SnapshotOrder {
+    pub fn order_id(&self) -> i64 {
+        self.order_id
+    }
+
+    pub fn side(&self) -> Side {
+        self.side
+    }
+
+    pub fn order_limit(&self) -> Option<i64> {
+        self.order_limit
+    }
+
+    pub fn remaining(&self) -> i64 {
+        self.amount - self.amount_executed
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotOcoLink {
+    pub(crate) order_id: i64,
+    pub(crate) link_id: i64,
+    pub(crate) sibling: i64,
+    pub(crate) amount: i64,
+    pub(crate) executed: i64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SnapshotQuote {
+    pub(crate) account_id: u64,
+    pub(crate) quote_id: i64,
+    pub(crate) bid_order_id: i64,
+    pub(crate) ask_order_id: i64,
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use std::sync::Arc;
+
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::matching::events::OrderbookEvent;
+    use crate::matching::orderbook::{OrderBuilder, Orderbook, Security};
+    use crate::matching::units::{Price, Qty};
+
+    fn round_trip<T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug>(value: &T) {
+        assert_eq!(&serde_json::from_str::<T>(&serde_json::to_string(value).unwrap()).unwrap(), value);
+        assert_eq!(&bincode::deserialize::<T>(&bincode::serialize(value).unwrap()).unwrap(), value);
+    }
+
+    fn traded_book() -> (Orderbook, Vec<OrderbookEvent>) {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let events = book.subscribe_unbounded();
+        let limit = |side, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity)).build().unwrap();
+        book.place_order(limit(Side::Sell, 101, 5)).unwrap();
+        book.place_order(limit(Side::Sell, 102, 5)).unwrap();
+        let bid = book.place_order(limit(Side::Buy, 99, 3)).unwrap().order_id();
+        book.place_order(limit(Side::Buy, 101, 2)).unwrap();
+        book.cancel_order(bid, None).unwrap();
+        book.place_order(limit(Side::Buy, 98, 4)).unwrap();
+        let other = Arc::new(Security::new("XS0000000002", "OTHER"));
+        assert!(book.place_order(OrderBuilder::new(Side::Buy, &other).limit(Price(98)).quantity(Qty(1)).build().unwrap()).is_err());
+        let events = events.try_iter().collect();
+        (book, events)
+    }
+
+    #[test]
+    fn the_data_types_round_trip_through_json_and_bincode() {
+        let (book, events) = traded_book();
+        assert!(events.iter().any(|event| matches!(event, OrderbookEvent::Trade { .. })));
+        assert!(events.iter().any(|event| matches!(event, OrderbookEvent::OrderCancelled { .. })));
+        assert!(events.iter().any(|event| matches!(event, OrderbookEvent::OrderRejected { .. })));
+        for event in &events {
+            round_trip(event);
+        }
+        round_trip(&book.executions()[0]);
+        round_trip(&book.depth(10));
+        round_trip(&book.full_book().bids()[0]);
+        round_trip(&book.security().as_ref().clone());
+
+        let snapshot = book.snapshot();
+        round_trip(&snapshot);
+        assert_eq!(BookSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap(), snapshot);
+        assert_eq!(BookSnapshot::from_bytes(&snapshot.to_bytes().unwrap()).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn the_field_names_are_the_declared_ones() {
+        let (book, events) = traded_book();
+        let accepted = serde_json::to_value(&events[0]).unwrap();
+        assert_eq!(accepted, serde_json::json!({"OrderAccepted": {"sequence": 1, "timestamp": 0, "order_id": 1}}));
+
+        let snapshot = serde_json::to_value(book.snapshot()).unwrap();
+        assert_eq!(snapshot["isin"], "XS0000000001");
+        assert_eq!(snapshot["bids"][0]["order_id"], 5);
+        assert_eq!(snapshot["bids"][0]["amount"], 4);
+        let level = serde_json::to_value(book.depth(1).asks()[0]).unwrap();
+        assert_eq!(level, serde_json::json!({"price": 101, "quantity": 3, "order_count": 1}));
+    }
+}
@@ -0,0 +1,274 @@
+use std::cell::{Cell, UnsafeCell};
+use std::fmt;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{self, AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, Thread};
+
+use super::error::OrderbookError;
+use super::orderbook::{Order, OrderReport, Orderbook, Side, TimeInForce};
+use super::units::{OrderId, Price, Qty};
+
+// A command for the book on the other end of a queue. Commands are plain values, the security of
+// a placed order is the one of the book that applies it, so sending one never allocates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Place { side: Side, limit: Option<Price>, amount: Qty, time_in_force: TimeInForce, account_id: Option<u64> },
+    Cancel { order_id: OrderId, account_id: Option<u64> },
+    Amend { order_id: OrderId, limit: Option<Price>, amount: Qty },
+}
+
+impl Command {
+    // Runs the command against the book. Cancels have no report to give.
+    pub fn apply(self, book: &mut Orderbook) -> Result<Option<OrderReport>, OrderbookError> {
+        match self {
+            Command::Place { side, limit, amount, time_in_force, account_id } => {
+                let mut order = Order::new(side, limit, book.security(), amount, time_in_force);
+                if let Some(account_id) = account_id { order = order.with_account(account_id); }
+                book.place_order(order).map(Some)
+            },
+            Command::Cancel { order_id, account_id } => book.cancel_order(order_id, account_id).map(|_| None),
+            Command::Amend { order_id, limit, amount } => book.amend_order(order_id, limit, amount).map(Some),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrySendError {
+    // the ring is full, the consumer has not caught up yet
+    Full(Command),
+    // the consumer is gone
+    Disconnected(Command),
+}
+
+impl fmt::Display for TrySendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "The command queue is full"),
+            TrySendError::Disconnected(_) => write!(f, "The consumer of the command queue is gone"),
+        }
+    }
+}
+
+impl std::error::Error for TrySendError {}
+
+// How a consumer waits for the next command: spinning on the ring, which answers fastest but keeps
+// a core busy, or parking its thread until the producer sends something.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WaitStrategy {
+    #[default]
+    BusyPoll,
+    Park,
+}
+
+const SPINS_BEFORE_YIELD: u32 = 128;
+
+// keeps the two indices on cache lines of their own, so producer and consumer do not contend
+#[repr(align(64))]
+struct CachePadded(AtomicUsize);
+
+struct Ring {
+    slots: Box<[UnsafeCell<MaybeUninit<Command>>]>,
+    mask: usize,
+    // the next slot the consumer reads, only written by the consumer
+    head: CachePadded,
+    // the next slot the producer writes, only written by the producer
+    tail: CachePadded,
+    closed: AtomicBool,
+    parked: AtomicBool,
+    consumer: Mutex<Option<Thread>>,
+}
+
+// Slots between head and tail belong to the consumer, all others to the producer, and each side
+// only moves its own index after it is done with a slot.
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    fn wake_consumer(&self) {
+        if !self.parked.load(Ordering::Relaxed) || !self.parked.swap(false, Ordering::SeqCst) { return; }
+        self.unpark_consumer();
+    }
+
+    fn unpark_consumer(&self) {
+        if let Some(consumer) = self.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref() { consumer.unpark(); }
+    }
+}
+
+// A bounded queue from one producer thread to the thread that owns a book. The capacity is
+// rounded up to a power of two.
+pub fn channel(capacity: usize) -> (Producer, Consumer) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let ring = Arc::new(Ring {
+        slots: (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect(),
+        mask: capacity - 1,
+        head: CachePadded(AtomicUsize::new(0)),
+        tail: CachePadded(AtomicUsize::new(0)),
+        closed: AtomicBool::new(false),
+        parked: AtomicBool::new(false),
+        consumer: Mutex::new(None),
+    });
+    (Producer { ring: ring.clone(), head: Cell::new(0) }, Consumer { ring, tail: Cell::new(0) })
+}
+
+// Either end may move to another thread, but not be shared between two. Each end keeps the last
+// index it saw of the other one and only reads the shared index again when that one runs out, so
+// the two threads rarely touch the same cache line.
+pub struct Producer {
+    ring: Arc<Ring>,
+    head: Cell<usize>,
+}
+
+impl Producer {
+    // Never blocks: a full ring hands the command back right away.
+    pub fn try_send(&self, command: Command) -> Result<(), TrySendError> {
+        if self.ring.closed.load(Ordering::Acquire) { return Err(TrySendError::Disconnected(command)); }
+        let tail = self.ring.tail.0.load(Ordering::Relaxed);
+        if tail.wrapping_sub(self.head.get()) > self.ring.mask {
+            self.head.set(self.ring.head.0.load(Ordering::Acquire));
+            if tail.wrapping_sub(self.head.get()) > self.ring.mask { return Err(TrySendError::Full(command)); }
+        }
+
+        unsafe { (*self.ring.slots[tail & self.ring.mask].get()).write(command); }
+        self.ring.tail.0.store(tail.wrapping_add(1), Ordering::Release);
+        // pairs with the fence of a consumer going to sleep, one of the two sees the other
+        atomic::fence(Ordering::SeqCst);
+        self.ring.wake_consumer();
+        Ok(())
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.ring.mask + 1
+    }
+}
+
+impl Drop for Producer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::SeqCst);
+        self.ring.unpark_consumer();
+    }
+}
+
+pub struct Consumer {
+    ring: Arc<Ring>,
+    tail: Cell<usize>,
+}
+
+impl Consumer {
+    // The next command if one is queued.
+    pub fn try_recv(&self) -> Option<Command> {
+        let head = self.ring.head.0.load(Ordering::Relaxed);
+        if head == self.tail.get() {
+            self.tail.set(self.ring.tail.0.load(Ordering::Acquire));
+            if head == self.tail.get() { return None; }
+        }
+
+        let command = unsafe { (*self.ring.slots[head & self.ring.mask].get()).assume_init_read() };
+        self.ring.head.0.store(head.wrapping_add(1), Ordering::Release);
+        Some(command)
+    }
+
+    // Waits for the next command, None once the producer is gone and every command it sent was
+    // received.
+    pub fn recv(&self, wait: WaitStrategy) -> Option<Command> {
+        let mut spins = 0u32;
+        loop {
+            if let Some(command) = self.try_recv() { return Some(command); }
+            if self.ring.closed.load(Ordering::Acquire) { return self.try_recv(); }
+
+            match wait {
+                // the thread stays runnable, but gives the core up now and then in case the
+                // producer shares it
+                WaitStrategy::BusyPoll => {
+                    spins = spins.wrapping_add(1);
+                    if spins.is_multiple_of(SPINS_BEFORE_YIELD) { thread::yield_now(); } else { std::hint::spin_loop(); }
+                },
+                WaitStrategy::Park => {
+                    *self.ring.consumer.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(thread::current());
+                    self.ring.parked.store(true, Ordering::SeqCst);
+                    atomic::fence(Ordering::SeqCst);
+                    // a command sent before the flag was up would otherwise wait for the next one
+                    let empty = self.ring.head.0.load(Ordering::Relaxed) == self.ring.tail.0.load(Ordering::Acquire);
+                    if empty && !self.ring.closed.load(Ordering::SeqCst) { thread::park(); }
+                    self.ring.parked.store(false, Ordering::SeqCst);
+                },
+            }
+        }
+    }
+
+    // Applies every command to the book until the producer is gone, passing each result on.
+    pub fn run(&self, book: &mut Orderbook, wait: WaitStrategy, mut on_result: impl FnMut(Command, Result<Option<OrderReport>, OrderbookError>)) {
+        while let Some(command) = self.recv(wait) {
+            on_result(command, command.apply(book));
+        }
+    }
+}
+
+impl Drop for Consumer {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cancel(order_id: i64) -> Command {
+        Command::Cancel { order_id: OrderId::from_raw(order_id), account_id: None }
+    }
+
+    #[test]
+    fn a_full_ring_takes_commands_again_once_one_is_received() {
+        let (producer, consumer) = channel(3);
+        assert_eq!(producer.capacity(), 4);
+        for order_id in 1..=4 { producer.try_send(cancel(order_id)).unwrap(); }
+        assert_eq!(producer.try_send(cancel(5)), Err(TrySendError::Full(cancel(5))));
+
+        assert_eq!(consumer.try_recv(), Some(cancel(1)));
+        producer.try_send(cancel(5)).unwrap();
+        assert_eq!(producer.try_send(cancel(6)), Err(TrySendError::Full(cancel(6))));
+    }
+
+    #[test]
+    fn sending_to_a_dropped_consumer_is_refused() {
+        let (producer, consumer) = channel(4);
+        producer.try_send(cancel(1)).unwrap();
+        drop(consumer);
+        assert_eq!(producer.try_send(cancel(2)), Err(TrySendError::Disconnected(cancel(2))));
+    }
+
+    #[test]
+    fn the_consumer_drains_the_ring_after_the_producer_is_gone() {
+        let (producer, consumer) = channel(8);
+        for order_id in 1..=5 { producer.try_send(cancel(order_id)).unwrap(); }
+        drop(producer);
+
+        assert_eq!(consumer.recv(WaitStrategy::BusyPoll), Some(cancel(1)));
+        let rest: Vec<Command> = std::iter::from_fn(|| consumer.recv(WaitStrategy::Park)).collect();
+        assert_eq!(rest, (2..=5).map(cancel).collect::<Vec<_>>());
+        assert_eq!(consumer.recv(WaitStrategy::BusyPoll), None);
+    }
+
+    #[test]
+    fn a_parked_consumer_gets_every_command_in_order() {
+        const COMMANDS: i64 = 100_000;
+        let (producer, consumer) = channel(16);
+        let sender = thread::spawn(move || {
+            for order_id in 1..=COMMANDS {
+                let mut command = cancel(order_id);
+                while let Err(TrySendError::Full(full)) = producer.try_send(command) {
+                    command = full;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut expected = 1;
+        while let Some(command) = consumer.recv(WaitStrategy::Park) {
+            assert_eq!(command, cancel(expected));
+            expected += 1;
+        }
+        sender.join().unwrap();
+        assert_eq!(expected, COMMANDS + 1);
+    }
+}
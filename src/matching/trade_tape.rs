@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::io::{self, Write};
+
+use super::csv_export::{write_row, PriceFormat};
+use super::orderbook::{Execution, Security, Side};
+
+// A single print on the tape. Trade ids start at 1 and increase by one per execution, also when
+// older trades have already been dropped from a bounded tape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trade {
+    trade_id: u64,
+    buying_order_id: i64,
+    selling_order_id: i64,
+    price: i64,
+    quantity: i64,
+    aggressor: Side,
+    timestamp: u64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    busted: bool,
+    #[cfg_attr(feature = "serde", serde(default))]
+    off_book: bool,
+}
+
+impl Trade {
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn buying_order_id(&self) -> i64 {
+        self.buying_order_id
+    }
+
+    pub fn selling_order_id(&self) -> i64 {
+        self.selling_order_id
+    }
+
+    pub fn price(&self) -> i64 {
+        self.price
+    }
+
+    pub fn quantity(&self) -> i64 {
+        self.quantity
+    }
+
+    // the side of the incoming order that took liquidity
+    pub fn aggressor(&self) -> Side {
+        self.aggressor
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    // a busted trade was reversed, see Orderbook::bust_trade
+    pub fn is_busted(&self) -> bool {
+        self.busted
+    }
+
+    // an off-book trade was reported to the book instead of matched in it, see
+    // Orderbook::report_trade. Its aggressor is always Buy.
+    pub fn is_off_book(&self) -> bool {
+        self.off_book
+    }
+}
+
+// Selects the trades a statistic is computed over. Only trades still retained on the tape count.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TradeWindow {
+    LastTrades(usize),
+    // the trades after the one with the given id
+    SinceTrade(u64),
+    // the trades at or after the given engine timestamp
+    SinceTimestamp(u64),
+}
+
+// Records every execution of a book in fill order. With a retention only the latest trades are
+// kept, otherwise the tape grows without bound. Busted trades stay on the tape with their flag
+// set, but statistics leave them out.
+pub struct TradeTape {
+    trades: VecDeque<Trade>,
+    retention: Option<usize>,
+    next_trade_id: u64,
+    // the security of the book the tape belongs to, for exports
+    isin: String,
+    price_decimals: u8,
+}
+
+impl TradeTape {
+    pub fn new(retention: Option<usize>) -> Self {
+        TradeTape { trades: VecDeque::new(), retention, next_trade_id: 1, isin: String::new(), price_decimals: 0 }
+    }
+
+    pub(crate) fn for_security(security: &Security) -> Self {
+        TradeTape { isin: security.isin.clone(), price_decimals: security.price_decimals(), ..Self::default() }
+    }
+
+    // Writes the retained trades as CSV with a header row, oldest first.
+    pub fn write_csv<W: Write>(&self, mut w: W, prices: PriceFormat) -> io::Result<()> {
+        write_row(&mut w, &["trade_id", "timestamp", "isin", "price", "quantity", "aggressor", "buying_order_id", "selling_order_id", "busted", "off_book"])?;
+        for trade in &self.trades {
+            let aggressor = match trade.aggressor {
+                Side::Buy => "buy",
+                Side::Sell => "sell",
+            };
+            write_row(&mut w, &[
+                &trade.trade_id.to_string(),
+                &trade.timestamp.to_string(),
+                &self.isin,
+                &prices.format_in(self.price_decimals, trade.price),
+                &trade.quantity.to_string(),
+                aggressor,
+                &trade.buying_order_id.to_string(),
+                &trade.selling_order_id.to_string(),
+                &trade.busted.to_string(),
+                &trade.off_book.to_string(),
+            ])?;
+        }
+        w.flush()
+    }
+
+    pub(crate) fn record(&mut self, execution: &Execution, aggressor: Side, timestamp: u64) -> u64 {
+        let trade_id = self.next_trade_id;
+        self.next_trade_id += 1;
+
+        if self.retention == Some(0) { return trade_id; }
+        if self.retention.is_some_and(|retention| self.trades.len() >= retention) { self.trades.pop_front(); }
+
+        self.trades.push_back(Trade {
+            trade_id,
+            buying_order_id: execution.buying_order_id(),
+            selling_order_id: execution.selling_order_id(),
+            price: execution.price(),
+            quantity: execution.amount(),
+            aggressor,
+            timestamp,
+            busted: false,
+            off_book: false,
+        });
+        trade_id
+    }
+
+    pub(crate) fn record_off_book(&mut self, execution: &Execution, timestamp: u64) -> u64 {
+        let trade_id = self.record(execution, execution.aggressor(), timestamp);
+        if let Some(trade) = self.trades.back_mut().filter(|trade| trade.trade_id == trade_id) { trade.off_book = true; }
+        trade_id
+    }
+
+    pub(crate) fn bust(&mut self, trade_id: u64) {
+        let index = self.trades.partition_point(|trade| trade.trade_id < trade_id);
+        if let Some(trade) = self.trades.get_mut(index).filter(|trade| trade.trade_id == trade_id) { trade.busted = true; }
+    }
+
+    // room for `additional` more trades, never more than the retention keeps
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        let additional = self.retention.map_or(additional, |retention| additional.min(retention.saturating_sub(self.trades.len())));
+        self.trades.reserve(additional);
+    }
+
+    // Forgets every trade, the next one is trade 1 again. The buffer is kept.
+    pub(crate) fn clear(&mut self) {
+        self.trades.clear();
+        self.next_trade_id = 1;
+    }
+
+    // Takes the trades of a snapshot, oldest first, as far as the retention keeps them. The next
+    // trade gets the id after `last_trade_id`.
+    pub(crate) fn restore(&mut self, trades: &[Trade], last_trade_id: u64) {
+        self.trades = trades.iter().copied().collect();
+        self.next_trade_id = last_trade_id + 1;
+        self.set_retention(self.retention);
+    }
+
+    pub fn set_retention(&mut self, retention: Option<usize>) {
+        self.retention = retention;
+        if let Some(retention) = retention {
+            while self.trades.len() > retention { self.trades.pop_front(); }
+        }
+    }
+
+    // all retained trades with an id greater than `trade_id`, oldest first
+    pub fn trades_since(&self, trade_id: u64) -> Vec<Trade> {
+        let start = self.trades.partition_point(|trade| trade.trade_id <= trade_id);
+        self.trades.range(start..).copied().collect()
+    }
+
+    // the latest `n` trades, oldest first
+    pub fn last_n_trades(&self, n: usize) -> Vec<Trade> {
+        self.trades.range(self.trades.len().saturating_sub(n)..).copied().collect()
+    }
+
+    pub fn window(&self, window: TradeWindow) -> Vec<Trade> {
+        match window {
+            TradeWindow::LastTrades(n) => self.last_n_trades(n),
+            TradeWindow::SinceTrade(trade_id) => self.trades_since(trade_id),
+            TradeWindow::SinceTimestamp(timestamp) => self.trades.iter().filter(|trade| trade.timestamp >= timestamp).copied().collect(),
+        }
+    }
+
+    // Volume weighted average price of the window, rounded down to whole price units like the
+    // average price of an order report. None if the window holds no trades.
+    pub fn vwap(&self, window: TradeWindow) -> Option<i64> {
+        let mut trades = self.window(window);
+        trades.retain(|trade| !trade.busted);
+        let volume: i128 = trades.iter().map(|trade| trade.quantity as i128).sum();
+        if volume == 0 { return None; }
+
+        let notional: i128 = trades.iter().map(|trade| trade.price as i128 * trade.quantity as i128).sum();
+        Some(notional.div_euclid(volume) as i64)
+    }
+
+    pub fn last_trade_id(&self) -> u64 {
+        self.next_trade_id - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.trades.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.trades.is_empty()
+    }
+}
+
+impl Default for TradeTape {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::matching::orderbook::{OrderBuilder, Orderbook};
+    use crate::matching::units::{Price, Qty};
+
+    #[test]
+    fn the_tape_holds_the_trades_in_fill_order() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        let limit = |side, price, quantity| OrderBuilder::new(side, &security).limit(Price(price)).quantity(Qty(quantity)).build().unwrap();
+        book.place_order(limit(Side::Sell, 101, 5)).unwrap();
+        book.place_order(limit(Side::Sell, 102, 5)).unwrap();
+        book.place_order(limit(Side::Buy, 99, 3)).unwrap();
+
+        book.place_order(OrderBuilder::new(Side::Buy, &security).quantity(Qty(7)).build().unwrap()).unwrap();
+        book.place_order(limit(Side::Sell, 99, 3)).unwrap();
+        book.place_order(limit(Side::Buy, 102, 3)).unwrap();
+
+        let tape = book.trade_tape().last_n_trades(10);
+        let printed: Vec<(u64, i64, i64, Side)> = tape.iter().map(|trade| (trade.trade_id(), trade.price(), trade.quantity(), trade.aggressor())).collect();
+        assert_eq!(printed, vec![(1, 101, 5, Side::Buy), (2, 102, 2, Side::Buy), (3, 99, 3, Side::Sell), (4, 102, 3, Side::Buy)]);
+        let filled: Vec<(u64, i64)> = book.executions().iter().map(|execution| (execution.trade_id(), execution.price())).collect();
+        assert_eq!(filled, tape.iter().map(|trade| (trade.trade_id(), trade.price())).collect::<Vec<_>>());
+        assert_eq!(book.trade_tape().trades_since(2).len(), 2);
+    }
+
+    #[test]
+    fn a_bounded_tape_keeps_counting_trade_ids() {
+        let security = Arc::new(Security::new("XS0000000001", "TEST"));
+        let mut book = Orderbook::new(security.clone(), 100);
+        book.set_tape_retention(Some(2));
+        for _ in 0..3 {
+            book.place_order(OrderBuilder::new(Side::Sell, &security).limit(Price(100)).quantity(Qty(1)).build().unwrap()).unwrap();
+            book.place_order(OrderBuilder::new(Side::Buy, &security).limit(Price(100)).quantity(Qty(1)).build().unwrap()).unwrap();
+        }
+
+        let trade_ids: Vec<u64> = book.trade_tape().last_n_trades(10).iter().map(Trade::trade_id).collect();
+        assert_eq!(trade_ids, vec![2, 3]);
+        assert_eq!(book.trade_tape().last_trade_id(), 3);
+    }
+}
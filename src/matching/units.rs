@@ -0,0 +1,238 @@
+use std::fmt;
+use std::num::TryFromIntError;
+use std::ops::Mul;
+
+use super::error::ParsePriceError;
+
+// A price in the smallest unit of the security, see Security::with_price_decimals. Prices only
+// come together with quantities, to a notional, or with other prices through checked arithmetic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Price(pub i64);
+
+// A quantity of the security, in units of the security and not in lots.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Qty(pub i64);
+
+// Price times quantity, wide enough that no order can overflow it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct Notional(pub i128);
+
+// The id a book gave an order. Ids start at 1, the book keeps them as i64.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(transparent))]
+pub struct OrderId(pub u64);
+
+impl Price {
+    pub fn get(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Price) -> Option<Price> {
+        self.0.checked_add(other.0).map(Price)
+    }
+
+    pub fn checked_sub(self, other: Price) -> Option<Price> {
+        self.0.checked_sub(other.0).map(Price)
+    }
+
+    pub fn is_on_tick(self, tick_size: i64) -> bool {
+        self.0 % tick_size == 0
+    }
+
+    // the nearest price on the tick at or below, at or above the price
+    pub fn round_down_to_tick(self, tick_size: i64) -> Price {
+        Price(self.0.div_euclid(tick_size) * tick_size)
+    }
+
+    pub fn round_up_to_tick(self, tick_size: i64) -> Price {
+        let down = self.round_down_to_tick(tick_size);
+        if down == self { down } else { Price(down.0 + tick_size) }
+    }
+}
+
+impl Qty {
+    pub fn get(self) -> i64 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Qty) -> Option<Qty> {
+        self.0.checked_add(other.0).map(Qty)
+    }
+
+    pub fn checked_sub(self, other: Qty) -> Option<Qty> {
+        self.0.checked_sub(other.0).map(Qty)
+    }
+
+    pub fn is_in_lots(self, lot_size: i64) -> bool {
+        self.0 % lot_size == 0
+    }
+}
+
+impl Notional {
+    pub fn get(self) -> i128 {
+        self.0
+    }
+
+    pub fn checked_add(self, other: Notional) -> Option<Notional> {
+        self.0.checked_add(other.0).map(Notional)
+    }
+
+    pub fn checked_sub(self, other: Notional) -> Option<Notional> {
+        self.0.checked_sub(other.0).map(Notional)
+    }
+}
+
+impl OrderId {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    // An id beyond the ids of the book is looked up as one that does not exist.
+    pub(crate) fn to_raw(self) -> i64 {
+        i64::try_from(self.0).unwrap_or(i64::MAX)
+    }
+
+    // only for ids the book handed out, which are positive
+    pub(crate) fn from_raw(order_id: i64) -> OrderId {
+        OrderId(order_id as u64)
+    }
+}
+
+// Places the decimal point `decimals` digits from the right, so 10050 with 2 decimals is 100.50.
+// Every price has an exact representation, nothing is rounded.
+pub(crate) fn format_price(price: i64, decimals: u8) -> String {
+    if decimals == 0 { return price.to_string(); }
+
+    let digits = format!("{:0>width$}", price.unsigned_abs(), width = decimals as usize + 1);
+    let (units, fraction) = digits.split_at(digits.len() - decimals as usize);
+    format!("{}{}.{}", if price < 0 { "-" } else { "" }, units, fraction)
+}
+
+// The inverse of format_price. Zeros beyond the decimals are accepted, any other digit there is
+// an error instead of being rounded away.
+pub(crate) fn parse_price(text: &str, decimals: u8) -> Result<i64, ParsePriceError> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    let sign_length = text.len() - digits.len();
+    if digits.is_empty() { return Err(ParsePriceError::Empty); }
+
+    let point = digits.find('.');
+    if let Some(offset) = digits.bytes().enumerate().position(|(offset, byte)| !byte.is_ascii_digit() && Some(offset) != point) {
+        return Err(ParsePriceError::InvalidCharacter(sign_length + offset));
+    }
+    let (units, fraction) = match point {
+        Some(point) => (&digits[..point], &digits[point + 1..]),
+        None => (digits, ""),
+    };
+    if units.is_empty() || (point.is_some() && fraction.is_empty()) { return Err(ParsePriceError::InvalidCharacter(sign_length + point.unwrap_or(0))); }
+
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize { return Err(ParsePriceError::TooManyDecimals { decimals }); }
+    let accumulate = |value: i128, digits: &str| digits.bytes().try_fold(value, |value, digit| value.checked_mul(10)?.checked_add((digit - b'0') as i128));
+    let value = accumulate(0, units)
+        .and_then(|value| accumulate(value, fraction))
+        .and_then(|value| value.checked_mul(10i128.checked_pow((decimals as usize - fraction.len()) as u32)?))
+        .ok_or(ParsePriceError::Overflow)?;
+    i64::try_from(if sign_length > 0 { -value } else { value }).map_err(|_| ParsePriceError::Overflow)
+}
+
+impl Mul<Qty> for Price {
+    type Output = Notional;
+
+    fn mul(self, quantity: Qty) -> Notional {
+        Notional(self.0 as i128 * quantity.0 as i128)
+    }
+}
+
+impl Mul<Price> for Qty {
+    type Output = Notional;
+
+    fn mul(self, price: Price) -> Notional {
+        price * self
+    }
+}
+
+impl From<i64> for Price {
+    fn from(price: i64) -> Self {
+        Price(price)
+    }
+}
+
+impl From<Price> for i64 {
+    fn from(price: Price) -> Self {
+        price.0
+    }
+}
+
+impl From<i64> for Qty {
+    fn from(quantity: i64) -> Self {
+        Qty(quantity)
+    }
+}
+
+impl From<Qty> for i64 {
+    fn from(quantity: Qty) -> Self {
+        quantity.0
+    }
+}
+
+impl From<Notional> for i128 {
+    fn from(notional: Notional) -> Self {
+        notional.0
+    }
+}
+
+impl From<u64> for OrderId {
+    fn from(order_id: u64) -> Self {
+        OrderId(order_id)
+    }
+}
+
+impl From<OrderId> for u64 {
+    fn from(order_id: OrderId) -> Self {
+        order_id.0
+    }
+}
+
+// ids from places that keep them as i64, like events and trades
+impl TryFrom<i64> for OrderId {
+    type Error = TryFromIntError;
+
+    fn try_from(order_id: i64) -> Result<Self, Self::Error> {
+        u64::try_from(order_id).map(OrderId)
+    }
+}
+
+impl TryFrom<OrderId> for i64 {
+    type Error = TryFromIntError;
+
+    fn try_from(order_id: OrderId) -> Result<Self, Self::Error> {
+        i64::try_from(order_id.0)
+    }
+}
+
+impl fmt::Display for Price {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Qty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for Notional {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for OrderId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}